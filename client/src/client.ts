@@ -26,6 +26,7 @@ interface ListParams {
   prefix?: string;
   start?: string;
   end?: string;
+  cursor?: string;
   limit?: number;
 }
 
@@ -67,6 +68,7 @@ export class WitKvClient {
     if (params?.prefix !== undefined) searchParams.set('prefix', params.prefix);
     if (params?.start !== undefined) searchParams.set('start', params.start);
     if (params?.end !== undefined) searchParams.set('end', params.end);
+    if (params?.cursor !== undefined) searchParams.set('cursor', params.cursor);
     if (params?.limit !== undefined) searchParams.set('limit', String(params.limit));
     const queryString = searchParams.toString();
     return queryString ? `${basePath}?${queryString}` : basePath;
@@ -113,13 +115,13 @@ export class WitKvClient {
    *
    * @param keyspace - Keyspace name
    * @param key - Key to set
-   * @param value - Value to store (Wave text format)
+   * @param value - Value to store: Wave text, or binary-export bytes as returned by a binary `get`
    * @param options - Operation options
    */
   async set(
     keyspace: string,
     key: string,
-    value: string,
+    value: string | ArrayBuffer | Uint8Array,
     options?: OperationOptions
   ): Promise<void> {
     const db = options?.database ?? this.defaultDatabase;
@@ -129,7 +131,7 @@ export class WitKvClient {
       {
         method: 'PUT',
         headers: {
-          'Content-Type': MIME_WASM_WAVE,
+          'Content-Type': typeof value === 'string' ? MIME_WASM_WAVE : MIME_OCTET_STREAM,
         },
         body: value,
       }
@@ -209,7 +211,8 @@ export class WitKvClient {
     if (format === 'binary') {
       return result as ArrayBuffer;
     }
-    return { keys: this.parseKeyList(result as string) };
+    const wave = result as string;
+    return { keys: this.parseKeyList(wave), next_cursor: this.parseNextCursor(wave) };
   }
 
   /**
@@ -372,6 +375,95 @@ export class WitKvClient {
     return { databases: names.map(name => ({ name })) };
   }
 
+  /**
+   * List the indexed fields of a keyspace.
+   *
+   * @param keyspace - Keyspace name
+   * @param options - Operation options
+   * @returns Indexed field paths
+   */
+  async listIndexes(keyspace: string, options?: OperationOptions): Promise<string[]> {
+    const db = options?.database ?? this.defaultDatabase;
+
+    const response = await fetch(
+      `${this.baseUrl}/api/v1/db/${encodeURIComponent(db)}/indexes/${encodeURIComponent(keyspace)}`
+    );
+
+    if (!response.ok) {
+      throw await this.parseError(response);
+    }
+
+    const body: { fields: string[] } = await response.json();
+    return body.fields;
+  }
+
+  /**
+   * Index a record field, e.g. `email` or `address.city`.
+   *
+   * @param keyspace - Keyspace name
+   * @param field - Field path
+   * @param options - Operation options
+   */
+  async createIndex(keyspace: string, field: string, options?: OperationOptions): Promise<void> {
+    await this.indexRequest('PUT', keyspace, field, options);
+  }
+
+  /**
+   * Drop an index.
+   *
+   * @param keyspace - Keyspace name
+   * @param field - Indexed field path
+   * @param options - Operation options
+   */
+  async dropIndex(keyspace: string, field: string, options?: OperationOptions): Promise<void> {
+    await this.indexRequest('DELETE', keyspace, field, options);
+  }
+
+  /**
+   * List the keys whose indexed field equals a value.
+   *
+   * @param keyspace - Keyspace name
+   * @param field - Indexed field path
+   * @param value - WAVE-encoded field value, e.g. `"alice@example.com"`
+   * @param options - Get options
+   * @returns Array of key names (wave format) or ArrayBuffer (binary format)
+   */
+  async lookup(
+    keyspace: string,
+    field: string,
+    value: string,
+    options?: GetOptions
+  ): Promise<string[] | ArrayBuffer> {
+    const db = options?.database ?? this.defaultDatabase;
+    const format = options?.format ?? 'wave';
+    const params = new URLSearchParams({ value });
+    const url = `${this.baseUrl}/api/v1/db/${encodeURIComponent(db)}/indexes/${encodeURIComponent(keyspace)}/${encodeURIComponent(field)}?${params.toString()}`;
+
+    const result = await this.fetchWithFormat(url, format);
+    if (format === 'binary') {
+      return result as ArrayBuffer;
+    }
+    return this.parseKeyList(result as string);
+  }
+
+  private async indexRequest(
+    method: 'PUT' | 'DELETE',
+    keyspace: string,
+    field: string,
+    options?: OperationOptions
+  ): Promise<void> {
+    const db = options?.database ?? this.defaultDatabase;
+
+    const response = await fetch(
+      `${this.baseUrl}/api/v1/db/${encodeURIComponent(db)}/indexes/${encodeURIComponent(keyspace)}/${encodeURIComponent(field)}`,
+      { method }
+    );
+
+    if (!response.ok) {
+      throw await this.parseError(response);
+    }
+  }
+
   /**
    * Check server health.
    *
@@ -612,12 +704,12 @@ export class WitKvClient {
 
   /**
    * Parse a key-list WAVE format string.
-   * Format: {keys: ["key1", "key2"]}
+   * Format: {keys: ["key1", "key2"], next-cursor: none}
    */
   private parseKeyList(wave: string): string[] {
     // Simple WAVE parser for key-list format
     // Match the keys array content
-    const match = wave.match(/\{keys:\s*\[(.*?)\]\}/s);
+    const match = wave.match(/\{keys:\s*\[(.*?)\],\s*next-cursor:/s);
     if (!match) {
       return [];
     }
@@ -639,6 +731,15 @@ export class WitKvClient {
     return keys;
   }
 
+  /**
+   * Parse the next-cursor of a key-list WAVE format string.
+   * Cursors are hex strings, so they never need unescaping.
+   */
+  private parseNextCursor(wave: string): string | undefined {
+    const match = wave.match(/next-cursor:\s*some\("([0-9a-f]*)"\)\}$/);
+    return match ? match[1] : undefined;
+  }
+
   /**
    * Parse a keyspace-list WAVE format string.
    * Format: {keyspaces: [{name: "...", ...}, ...]}
@@ -36,6 +36,7 @@ export interface TypeMetadata {
  */
 export interface KeyList {
   keys: string[];
+  next_cursor?: string;
 }
 
 /**
@@ -66,6 +67,8 @@ export interface ListOptions {
   prefix?: string;
   start?: string;
   end?: string;
+  /** `next_cursor` of the previous page */
+  cursor?: string;
   limit?: number;
 }
 
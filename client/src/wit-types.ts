@@ -16,7 +16,7 @@ export interface SemanticVersion {
 
 /**
  * Stored value envelope - wraps the actual value with metadata.
- * WIT: record stored-value { version: u8, type-version: semantic-version, value: list<u8>, memory: option<list<u8>> }
+ * WIT: record stored-value { version: u8, type-version: semantic-version, value: list<u8>, memory: option<list<u8>>, revision: u64 }
  */
 export interface StoredValue {
   /** Format version for future compatibility */
@@ -27,6 +27,8 @@ export interface StoredValue {
   value: Uint8Array;
   /** Linear memory bytes (for variable-length types: strings, lists) */
   memory?: Uint8Array;
+  /** Store revision of the last write (0 for values written before revisions) */
+  revision: number;
 }
 
 /**
@@ -64,11 +66,13 @@ export interface KeyspaceMetadata {
 
 /**
  * List of keys in a keyspace.
- * WIT: record key-list { keys: list<string> }
+ * WIT: record key-list { keys: list<string>, next-cursor: option<string> }
  */
 export interface KeyList {
   /** The keys in the keyspace */
   keys: string[];
+  /** Opaque cursor for the next page, if the listing stopped at its limit */
+  nextCursor?: string;
 }
 
 /**
@@ -0,0 +1,575 @@
+//! Conversion functions between wasm_wave::Value and JSON.
+//!
+//! The mapping is directed by the value's `WaveType`, so it is lossless and
+//! every JSON document converts back to the value it was produced from:
+//!
+//! | WIT type | JSON |
+//! |----------|------|
+//! | `bool` | `true` / `false` |
+//! | `u8`..`u32`, `s8`..`s32` | number |
+//! | `u64`, `s64` | number, or string when outside ±(2^53 - 1) |
+//! | `f32`, `f64` | number, or `"NaN"`, `"Infinity"`, `"-Infinity"` |
+//! | `char`, `string` | string |
+//! | `list<T>`, `tuple<...>` | array |
+//! | `record` | object keyed by field name, in declaration order |
+//! | `variant` | `{"tag": "case"}` or `{"tag": "case", "value": payload}` |
+//! | `enum` | case name string |
+//! | `option<T>` | `null` for `none`, the payload for `some` (`{"some": payload}` if `T` is itself an option) |
+//! | `result<T, E>` | `{"ok": payload}` or `{"err": payload}`, `null` without a payload |
+//! | `flags` | array of the set flag names |
+//!
+//! When reading JSON, 64-bit integers are accepted as numbers or strings, and
+//! record fields of `option` type may be left out for `none`.
+
+use std::borrow::Cow;
+
+use serde_json::{Map, Number, Value as Json};
+use thiserror::Error;
+use wasm_wave::value::{Type as WaveType, Value};
+use wasm_wave::wasm::{WasmType, WasmTypeKind, WasmValue};
+
+/// Largest integer every JSON implementation reads exactly (2^53 - 1).
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Error converting between a WAVE value and JSON.
+#[derive(Error, Debug)]
+#[error("{message} at {path}")]
+pub struct JsonConvertError {
+    /// Location of the offending value, such as `$.address.city` or `$.tags[2]`.
+    pub path: String,
+    /// What was wrong with it.
+    pub message: String,
+}
+
+/// Convert a wasm_wave::Value of type `wave_type` to JSON.
+pub fn wave_to_json(value: &Value, wave_type: &WaveType) -> Result<Json, JsonConvertError> {
+    to_json(value, wave_type, &mut Path::root())
+}
+
+/// Convert JSON to a wasm_wave::Value of type `wave_type`.
+pub fn json_to_wave(json: &Json, wave_type: &WaveType) -> Result<Value, JsonConvertError> {
+    from_json(json, wave_type, &mut Path::root())
+}
+
+/// Location within the converted value, for error messages.
+struct Path(String);
+
+impl Path {
+    fn root() -> Self {
+        Path("$".to_string())
+    }
+
+    /// Run `f` with `segment` appended to the path.
+    fn with<T>(&mut self, segment: std::fmt::Arguments<'_>, f: impl FnOnce(&mut Self) -> T) -> T {
+        let len = self.0.len();
+        self.0.push_str(&segment.to_string());
+        let result = f(self);
+        self.0.truncate(len);
+        result
+    }
+
+    fn error(&self, message: impl Into<String>) -> JsonConvertError {
+        JsonConvertError {
+            path: self.0.clone(),
+            message: message.into(),
+        }
+    }
+}
+
+fn to_json(value: &Value, ty: &WaveType, path: &mut Path) -> Result<Json, JsonConvertError> {
+    let json = match ty.kind() {
+        WasmTypeKind::Bool => Json::Bool(value.unwrap_bool()),
+        WasmTypeKind::S8 => Json::from(value.unwrap_s8()),
+        WasmTypeKind::S16 => Json::from(value.unwrap_s16()),
+        WasmTypeKind::S32 => Json::from(value.unwrap_s32()),
+        WasmTypeKind::U8 => Json::from(value.unwrap_u8()),
+        WasmTypeKind::U16 => Json::from(value.unwrap_u16()),
+        WasmTypeKind::U32 => Json::from(value.unwrap_u32()),
+        WasmTypeKind::S64 => {
+            let v = value.unwrap_s64();
+            if v.unsigned_abs() <= MAX_SAFE_INTEGER {
+                Json::from(v)
+            } else {
+                Json::String(v.to_string())
+            }
+        }
+        WasmTypeKind::U64 => {
+            let v = value.unwrap_u64();
+            if v <= MAX_SAFE_INTEGER {
+                Json::from(v)
+            } else {
+                Json::String(v.to_string())
+            }
+        }
+        WasmTypeKind::F32 => float_to_json(f64::from(value.unwrap_f32())),
+        WasmTypeKind::F64 => float_to_json(value.unwrap_f64()),
+        WasmTypeKind::Char => Json::String(value.unwrap_char().to_string()),
+        WasmTypeKind::String => Json::String(value.unwrap_string().into_owned()),
+        WasmTypeKind::List => {
+            let elem_ty = ty
+                .list_element_type()
+                .ok_or_else(|| path.error("expected list type"))?;
+            let elements = value
+                .unwrap_list()
+                .enumerate()
+                .map(|(i, elem)| {
+                    path.with(format_args!("[{}]", i), |p| to_json(&elem, &elem_ty, p))
+                })
+                .collect::<Result<_, _>>()?;
+            Json::Array(elements)
+        }
+        WasmTypeKind::Record => {
+            let field_types: Vec<_> = ty.record_fields().collect();
+            let mut object = Map::new();
+            for (name, field) in value.unwrap_record() {
+                let field_ty = field_types
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, ty)| ty)
+                    .ok_or_else(|| path.error(format!("field '{}' not found in type", name)))?;
+                let json =
+                    path.with(format_args!(".{}", name), |p| to_json(&field, field_ty, p))?;
+                object.insert(name.into_owned(), json);
+            }
+            Json::Object(object)
+        }
+        WasmTypeKind::Tuple => {
+            let elements = value
+                .unwrap_tuple()
+                .zip(ty.tuple_element_types())
+                .enumerate()
+                .map(|(i, (elem, elem_ty))| {
+                    path.with(format_args!("[{}]", i), |p| to_json(&elem, &elem_ty, p))
+                })
+                .collect::<Result<_, _>>()?;
+            Json::Array(elements)
+        }
+        WasmTypeKind::Variant => {
+            let (case, payload) = value.unwrap_variant();
+            let payload_ty = ty
+                .variant_cases()
+                .find(|(name, _)| *name == case)
+                .ok_or_else(|| path.error(format!("variant case '{}' not found in type", case)))?
+                .1;
+            let mut object = Map::new();
+            object.insert("tag".to_string(), Json::String(case.to_string()));
+            if let (Some(payload), Some(payload_ty)) = (payload, payload_ty) {
+                let json = path.with(format_args!(".{}", case), |p| {
+                    to_json(&payload, &payload_ty, p)
+                })?;
+                object.insert("value".to_string(), json);
+            }
+            Json::Object(object)
+        }
+        WasmTypeKind::Enum => Json::String(value.unwrap_enum().into_owned()),
+        WasmTypeKind::Option => {
+            let some_ty = ty
+                .option_some_type()
+                .ok_or_else(|| path.error("expected option type"))?;
+            match value.unwrap_option() {
+                None => Json::Null,
+                Some(inner) => {
+                    let json = to_json(&inner, &some_ty, path)?;
+                    if some_ty.kind() == WasmTypeKind::Option {
+                        tagged("some", json)
+                    } else {
+                        json
+                    }
+                }
+            }
+        }
+        WasmTypeKind::Result => {
+            let (ok_ty, err_ty) = ty
+                .result_types()
+                .ok_or_else(|| path.error("expected result type"))?;
+            let (tag, payload, payload_ty) = match value.unwrap_result() {
+                Ok(payload) => ("ok", payload, ok_ty),
+                Err(payload) => ("err", payload, err_ty),
+            };
+            let json = match (payload, payload_ty) {
+                (Some(payload), Some(payload_ty)) => path.with(format_args!(".{}", tag), |p| {
+                    to_json(&payload, &payload_ty, p)
+                })?,
+                _ => Json::Null,
+            };
+            tagged(tag, json)
+        }
+        WasmTypeKind::Flags => Json::Array(
+            value
+                .unwrap_flags()
+                .map(|flag| Json::String(flag.into_owned()))
+                .collect(),
+        ),
+        kind => return Err(path.error(format!("unsupported type {}", kind))),
+    };
+    Ok(json)
+}
+
+fn from_json(json: &Json, ty: &WaveType, path: &mut Path) -> Result<Value, JsonConvertError> {
+    let value = match ty.kind() {
+        WasmTypeKind::Bool => Value::make_bool(
+            json.as_bool()
+                .ok_or_else(|| mismatch(path, "a boolean", json))?,
+        ),
+        WasmTypeKind::S8 => Value::make_s8(int_from_json(json, path)?),
+        WasmTypeKind::S16 => Value::make_s16(int_from_json(json, path)?),
+        WasmTypeKind::S32 => Value::make_s32(int_from_json(json, path)?),
+        WasmTypeKind::S64 => Value::make_s64(int_from_json(json, path)?),
+        WasmTypeKind::U8 => Value::make_u8(int_from_json(json, path)?),
+        WasmTypeKind::U16 => Value::make_u16(int_from_json(json, path)?),
+        WasmTypeKind::U32 => Value::make_u32(int_from_json(json, path)?),
+        WasmTypeKind::U64 => Value::make_u64(int_from_json(json, path)?),
+        // Narrowing is exact for every f32 written by `wave_to_json`
+        WasmTypeKind::F32 => Value::make_f32(float_from_json(json, path)? as f32),
+        WasmTypeKind::F64 => Value::make_f64(float_from_json(json, path)?),
+        WasmTypeKind::Char => {
+            let s = json
+                .as_str()
+                .ok_or_else(|| mismatch(path, "a character", json))?;
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Value::make_char(c),
+                _ => return Err(mismatch(path, "a single character", json)),
+            }
+        }
+        WasmTypeKind::String => Value::make_string(Cow::Borrowed(
+            json.as_str()
+                .ok_or_else(|| mismatch(path, "a string", json))?,
+        )),
+        WasmTypeKind::List => {
+            let elem_ty = ty
+                .list_element_type()
+                .ok_or_else(|| path.error("expected list type"))?;
+            let elements = json
+                .as_array()
+                .ok_or_else(|| mismatch(path, "an array", json))?
+                .iter()
+                .enumerate()
+                .map(|(i, elem)| {
+                    path.with(format_args!("[{}]", i), |p| from_json(elem, &elem_ty, p))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::make_list(ty, elements).map_err(|e| path.error(e.to_string()))?
+        }
+        WasmTypeKind::Record => {
+            let object = json
+                .as_object()
+                .ok_or_else(|| mismatch(path, "an object", json))?;
+            let field_types: Vec<_> = ty.record_fields().collect();
+            if let Some(unknown) = object
+                .keys()
+                .find(|key| !field_types.iter().any(|(name, _)| name == key.as_str()))
+            {
+                return Err(path.error(format!("unknown field '{}'", unknown)));
+            }
+            let mut fields = Vec::with_capacity(field_types.len());
+            for (name, field_ty) in &field_types {
+                let value = match object.get(name.as_ref()) {
+                    Some(field) => {
+                        path.with(format_args!(".{}", name), |p| from_json(field, field_ty, p))?
+                    }
+                    None if field_ty.kind() == WasmTypeKind::Option => {
+                        Value::make_option(field_ty, None).map_err(|e| path.error(e.to_string()))?
+                    }
+                    None => return Err(path.error(format!("missing field '{}'", name))),
+                };
+                fields.push((name.as_ref(), value));
+            }
+            Value::make_record(ty, fields).map_err(|e| path.error(e.to_string()))?
+        }
+        WasmTypeKind::Tuple => {
+            let array = json
+                .as_array()
+                .ok_or_else(|| mismatch(path, "an array", json))?;
+            let elem_types: Vec<_> = ty.tuple_element_types().collect();
+            if array.len() != elem_types.len() {
+                return Err(mismatch(
+                    path,
+                    &format!("an array of {} elements", elem_types.len()),
+                    json,
+                ));
+            }
+            let elements = array
+                .iter()
+                .zip(&elem_types)
+                .enumerate()
+                .map(|(i, (elem, elem_ty))| {
+                    path.with(format_args!("[{}]", i), |p| from_json(elem, elem_ty, p))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::make_tuple(ty, elements).map_err(|e| path.error(e.to_string()))?
+        }
+        WasmTypeKind::Variant => {
+            let object = json
+                .as_object()
+                .ok_or_else(|| mismatch(path, "an object with a \"tag\"", json))?;
+            if let Some(unknown) = object.keys().find(|key| *key != "tag" && *key != "value") {
+                return Err(path.error(format!("unknown variant key '{}'", unknown)));
+            }
+            let case = object
+                .get("tag")
+                .and_then(Json::as_str)
+                .ok_or_else(|| path.error("missing variant \"tag\""))?;
+            let payload_ty = ty
+                .variant_cases()
+                .find(|(name, _)| name == case)
+                .ok_or_else(|| path.error(format!("unknown variant case '{}'", case)))?
+                .1;
+            let payload = match (object.get("value"), payload_ty) {
+                (Some(payload), Some(payload_ty)) => {
+                    Some(path.with(format_args!(".{}", case), |p| {
+                        from_json(payload, &payload_ty, p)
+                    })?)
+                }
+                (None, Some(_)) => {
+                    return Err(path.error(format!("variant case '{}' needs a \"value\"", case)));
+                }
+                (None | Some(Json::Null), None) => None,
+                (Some(_), None) => {
+                    return Err(path.error(format!("variant case '{}' has no payload", case)));
+                }
+            };
+            Value::make_variant(ty, case, payload).map_err(|e| path.error(e.to_string()))?
+        }
+        WasmTypeKind::Enum => {
+            let case = json
+                .as_str()
+                .ok_or_else(|| mismatch(path, "an enum case name", json))?;
+            Value::make_enum(ty, case).map_err(|e| path.error(e.to_string()))?
+        }
+        WasmTypeKind::Option => {
+            let some_ty = ty
+                .option_some_type()
+                .ok_or_else(|| path.error("expected option type"))?;
+            let inner = match json {
+                Json::Null => None,
+                json if some_ty.kind() == WasmTypeKind::Option => {
+                    let payload = untag(json, &["some"], path)?.1;
+                    Some(from_json(payload, &some_ty, path)?)
+                }
+                json => Some(from_json(json, &some_ty, path)?),
+            };
+            Value::make_option(ty, inner).map_err(|e| path.error(e.to_string()))?
+        }
+        WasmTypeKind::Result => {
+            let (ok_ty, err_ty) = ty
+                .result_types()
+                .ok_or_else(|| path.error("expected result type"))?;
+            let (tag, payload) = untag(json, &["ok", "err"], path)?;
+            let payload_ty = if tag == "ok" { ok_ty } else { err_ty };
+            let payload = match (payload, payload_ty) {
+                (payload, Some(payload_ty)) => Some(path.with(format_args!(".{}", tag), |p| {
+                    from_json(payload, &payload_ty, p)
+                })?),
+                (Json::Null, None) => None,
+                (_, None) => return Err(path.error(format!("result {} has no payload", tag))),
+            };
+            let result = if tag == "ok" {
+                Ok(payload)
+            } else {
+                Err(payload)
+            };
+            Value::make_result(ty, result).map_err(|e| path.error(e.to_string()))?
+        }
+        WasmTypeKind::Flags => {
+            let flags = json
+                .as_array()
+                .ok_or_else(|| mismatch(path, "an array of flag names", json))?
+                .iter()
+                .map(|flag| {
+                    flag.as_str()
+                        .ok_or_else(|| mismatch(path, "an array of flag names", json))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::make_flags(ty, flags).map_err(|e| path.error(e.to_string()))?
+        }
+        kind => return Err(path.error(format!("unsupported type {}", kind))),
+    };
+    Ok(value)
+}
+
+/// A single-key object `{tag: payload}`.
+fn tagged(tag: &str, payload: Json) -> Json {
+    let mut object = Map::new();
+    object.insert(tag.to_string(), payload);
+    Json::Object(object)
+}
+
+/// The key and payload of a single-key object whose key is one of `tags`.
+fn untag<'a>(
+    json: &'a Json,
+    tags: &[&str],
+    path: &Path,
+) -> Result<(&'a str, &'a Json), JsonConvertError> {
+    let expected = || {
+        let keys: Vec<_> = tags.iter().map(|tag| format!("\"{}\"", tag)).collect();
+        path.error(format!(
+            "expected an object with one key of {}, got {}",
+            keys.join(", "),
+            describe(json)
+        ))
+    };
+    let object = json.as_object().ok_or_else(expected)?;
+    let mut entries = object.iter();
+    match (entries.next(), entries.next()) {
+        (Some((tag, payload)), None) if tags.contains(&tag.as_str()) => Ok((tag, payload)),
+        _ => Err(expected()),
+    }
+}
+
+fn float_to_json(v: f64) -> Json {
+    match Number::from_f64(v) {
+        Some(number) => Json::Number(number),
+        None if v.is_nan() => Json::String("NaN".to_string()),
+        None if v > 0.0 => Json::String("Infinity".to_string()),
+        None => Json::String("-Infinity".to_string()),
+    }
+}
+
+fn float_from_json(json: &Json, path: &Path) -> Result<f64, JsonConvertError> {
+    match json {
+        Json::Number(number) => number
+            .as_f64()
+            .ok_or_else(|| path.error(format!("{} is not a float", number))),
+        Json::String(s) if s == "NaN" => Ok(f64::NAN),
+        Json::String(s) if s == "Infinity" => Ok(f64::INFINITY),
+        Json::String(s) if s == "-Infinity" => Ok(f64::NEG_INFINITY),
+        json => Err(path.error(format!("expected a number, got {}", describe(json)))),
+    }
+}
+
+/// An integer from a JSON number or a string of digits, checked against the range of `T`.
+fn int_from_json<T>(json: &Json, path: &Path) -> Result<T, JsonConvertError>
+where
+    T: TryFrom<i64> + TryFrom<u64> + std::str::FromStr,
+{
+    let out_of_range = || path.error(format!("{} is out of range", json));
+    match json {
+        Json::Number(number) => {
+            if let Some(v) = number.as_u64() {
+                T::try_from(v).map_err(|_| out_of_range())
+            } else if let Some(v) = number.as_i64() {
+                T::try_from(v).map_err(|_| out_of_range())
+            } else {
+                Err(path.error(format!("expected an integer, got {}", number)))
+            }
+        }
+        Json::String(s) => s
+            .parse()
+            .map_err(|_| path.error(format!("expected an integer, got \"{}\"", s))),
+        json => Err(path.error(format!("expected an integer, got {}", describe(json)))),
+    }
+}
+
+fn mismatch(path: &Path, expected: &str, json: &Json) -> JsonConvertError {
+    path.error(format!("expected {}, got {}", expected, describe(json)))
+}
+
+/// Short description of a JSON value for error messages.
+fn describe(json: &Json) -> &'static str {
+    match json {
+        Json::Null => "null",
+        Json::Bool(_) => "a boolean",
+        Json::Number(_) => "a number",
+        Json::String(_) => "a string",
+        Json::Array(_) => "an array",
+        Json::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wit_parser::Resolve;
+
+    fn wave_type(wit: &str, name: &str) -> WaveType {
+        let mut resolve = Resolve::new();
+        resolve.push_str("test.wit", wit).unwrap();
+        let (id, _) = resolve
+            .types
+            .iter()
+            .find(|(_, def)| def.name.as_deref() == Some(name))
+            .unwrap();
+        wasm_wave::value::resolve_wit_type(&resolve, id).unwrap()
+    }
+
+    const WIT: &str = r#"
+        package test:json;
+        interface types {
+            variant shape { circle(f32), square(u32), empty }
+            enum color { red, green }
+            flags perms { read, write }
+            record item {
+                name: string,
+                big: u64,
+                small: s64,
+                ratio: f64,
+                initial: char,
+                shape: shape,
+                color: color,
+                perms: perms,
+                note: option<string>,
+                nested: option<option<u8>>,
+                outcome: result<u32, string>,
+                done: result,
+                pair: tuple<u8, string>,
+                scores: list<s32>,
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_roundtrip() {
+        let ty = wave_type(WIT, "item");
+        let wave = r#"{name: "a\"b", big: 18446744073709551615, small: -5, ratio: nan, initial: 'x', shape: circle(1.5), color: green, perms: {read, write}, note: none, nested: some(none), outcome: err("no"), done: ok, pair: (1, "one"), scores: [1, -2]}"#;
+        let value: Value = wasm_wave::from_str(&ty, wave).unwrap();
+
+        let json = wave_to_json(&value, &ty).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "name": "a\"b",
+                "big": "18446744073709551615",
+                "small": -5,
+                "ratio": "NaN",
+                "initial": "x",
+                "shape": {"tag": "circle", "value": 1.5},
+                "color": "green",
+                "perms": ["read", "write"],
+                "note": null,
+                "nested": {"some": null},
+                "outcome": {"err": "no"},
+                "done": {"ok": null},
+                "pair": [1, "one"],
+                "scores": [1, -2],
+            })
+        );
+
+        let back = json_to_wave(&json, &ty).unwrap();
+        assert_eq!(
+            wasm_wave::to_string(&back).unwrap(),
+            wasm_wave::to_string(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let ty = wave_type(WIT, "shape");
+        assert!(json_to_wave(&json!({"tag": "empty"}), &ty).is_ok());
+        assert!(json_to_wave(&json!({"tag": "square", "value": "7"}), &ty).is_ok());
+
+        let err = json_to_wave(&json!({"tag": "square", "value": -1}), &ty).unwrap_err();
+        assert_eq!(err.path, "$.square");
+        assert!(json_to_wave(&json!({"tag": "square"}), &ty).is_err());
+        assert!(json_to_wave(&json!({"tag": "hexagon"}), &ty).is_err());
+        assert!(json_to_wave(&json!("circle"), &ty).is_err());
+
+        let ty = wave_type(WIT, "item");
+        let err = json_to_wave(&json!({"name": "a", "bogus": 1}), &ty).unwrap_err();
+        assert_eq!(err.message, "unknown field 'bogus'");
+        let err = json_to_wave(&json!({"name": "a"}), &ty).unwrap_err();
+        assert_eq!(err.message, "missing field 'big'");
+    }
+}
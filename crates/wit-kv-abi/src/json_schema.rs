@@ -0,0 +1,336 @@
+//! JSON Schema for the JSON mapping of WIT values.
+//!
+//! [`json_schema`] describes the JSON documents accepted by
+//! [`json_to_wave`](crate::json_to_wave) for a WIT type, following the
+//! mapping of [`wave_to_json`](crate::wave_to_json). Schemas use JSON Schema
+//! 2020-12, the dialect of OpenAPI 3.1, and are fully inlined: WIT types
+//! cannot be recursive, so no `$ref`s are needed and a schema can be embedded
+//! anywhere. Named WIT types get a `title`, and WIT doc comments become
+//! `description`s.
+
+use serde_json::{Map, Value as Json, json};
+use wit_parser::{Docs, Resolve, Type, TypeDefKind};
+
+use crate::json_convert::JsonConvertError;
+
+/// Largest integer every JSON implementation reads exactly (2^53 - 1).
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// JSON Schema of the JSON form of values of type `ty`.
+///
+/// Returns an error naming the offending location for types that have no
+/// JSON form, such as resources, maps, futures and streams.
+pub fn json_schema(resolve: &Resolve, ty: &Type) -> Result<Json, JsonConvertError> {
+    schema(resolve, ty, &mut "$".to_string())
+}
+
+fn schema(resolve: &Resolve, ty: &Type, path: &mut String) -> Result<Json, JsonConvertError> {
+    let schema = match ty {
+        Type::Bool => json!({"type": "boolean"}),
+        Type::U8 => integer(u8::MIN, u8::MAX),
+        Type::U16 => integer(u16::MIN, u16::MAX),
+        Type::U32 => integer(u32::MIN, u32::MAX),
+        Type::S8 => integer(i8::MIN, i8::MAX),
+        Type::S16 => integer(i16::MIN, i16::MAX),
+        Type::S32 => integer(i32::MIN, i32::MAX),
+        Type::U64 => json!({
+            "anyOf": [
+                integer(u64::MIN, u64::MAX),
+                {"type": "string", "pattern": "^[0-9]+$"},
+            ],
+            "description": format!("Numbers above {} are written as strings", MAX_SAFE_INTEGER),
+        }),
+        Type::S64 => json!({
+            "anyOf": [
+                integer(i64::MIN, i64::MAX),
+                {"type": "string", "pattern": "^-?[0-9]+$"},
+            ],
+            "description": format!(
+                "Numbers outside ±{} are written as strings",
+                MAX_SAFE_INTEGER
+            ),
+        }),
+        Type::F32 | Type::F64 => json!({
+            "anyOf": [
+                {"type": "number"},
+                {"enum": ["NaN", "Infinity", "-Infinity"]},
+            ],
+        }),
+        Type::Char => json!({"type": "string", "minLength": 1, "maxLength": 1}),
+        Type::String => json!({"type": "string"}),
+        Type::ErrorContext => return Err(unsupported(path, "error-context")),
+        Type::Id(id) => {
+            let def = resolve
+                .types
+                .get(*id)
+                .ok_or_else(|| error(path, "type not found in resolve"))?;
+            let mut schema = typedef_schema(resolve, &def.kind, path)?;
+            if let Some(name) = &def.name {
+                annotate(&mut schema, Some(name), &def.docs);
+            }
+            schema
+        }
+    };
+    Ok(schema)
+}
+
+fn typedef_schema(
+    resolve: &Resolve,
+    kind: &TypeDefKind,
+    path: &mut String,
+) -> Result<Json, JsonConvertError> {
+    let schema = match kind {
+        TypeDefKind::Type(ty) => schema(resolve, ty, path)?,
+        TypeDefKind::Record(record) => {
+            let mut properties = Map::new();
+            let mut required = Vec::new();
+            for field in &record.fields {
+                let mut field_schema = nested(path, &format!(".{}", field.name), |p| {
+                    schema(resolve, &field.ty, p)
+                })?;
+                annotate(&mut field_schema, None, &field.docs);
+                properties.insert(field.name.clone(), field_schema);
+                // Fields of option type may be left out for none
+                if !is_option(resolve, &field.ty) {
+                    required.push(Json::String(field.name.clone()));
+                }
+            }
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+                "additionalProperties": false,
+            })
+        }
+        TypeDefKind::Tuple(tuple) => {
+            let items = tuple
+                .types
+                .iter()
+                .enumerate()
+                .map(|(i, ty)| nested(path, &format!("[{}]", i), |p| schema(resolve, ty, p)))
+                .collect::<Result<Vec<_>, _>>()?;
+            json!({
+                "type": "array",
+                "prefixItems": items,
+                "minItems": tuple.types.len(),
+                "maxItems": tuple.types.len(),
+            })
+        }
+        TypeDefKind::List(elem) => json!({
+            "type": "array",
+            "items": nested(path, "[]", |p| schema(resolve, elem, p))?,
+        }),
+        TypeDefKind::FixedSizeList(elem, len) => json!({
+            "type": "array",
+            "items": nested(path, "[]", |p| schema(resolve, elem, p))?,
+            "minItems": len,
+            "maxItems": len,
+        }),
+        TypeDefKind::Variant(variant) => {
+            let cases = variant
+                .cases
+                .iter()
+                .map(|case| {
+                    let (value, required) = match &case.ty {
+                        Some(ty) => (
+                            nested(path, &format!(".{}", case.name), |p| schema(resolve, ty, p))?,
+                            json!(["tag", "value"]),
+                        ),
+                        None => (json!({"type": "null"}), json!(["tag"])),
+                    };
+                    let mut case_schema = json!({
+                        "type": "object",
+                        "properties": {"tag": {"const": case.name}, "value": value},
+                        "required": required,
+                        "additionalProperties": false,
+                    });
+                    annotate(&mut case_schema, None, &case.docs);
+                    Ok(case_schema)
+                })
+                .collect::<Result<Vec<_>, JsonConvertError>>()?;
+            json!({"oneOf": cases})
+        }
+        TypeDefKind::Enum(enum_) => {
+            let names: Vec<_> = enum_.cases.iter().map(|case| case.name.as_str()).collect();
+            json!({"type": "string", "enum": names})
+        }
+        TypeDefKind::Flags(flags) => {
+            let names: Vec<_> = flags.flags.iter().map(|flag| flag.name.as_str()).collect();
+            json!({
+                "type": "array",
+                "items": {"type": "string", "enum": names},
+                "uniqueItems": true,
+            })
+        }
+        TypeDefKind::Option(inner) => {
+            let some = schema(resolve, inner, path)?;
+            // A nested option is tagged, or its none would be ambiguous
+            let some = if is_option(resolve, inner) {
+                tagged("some", some)
+            } else {
+                some
+            };
+            json!({"anyOf": [{"type": "null"}, some]})
+        }
+        TypeDefKind::Result(result) => {
+            let ok = match &result.ok {
+                Some(ty) => nested(path, ".ok", |p| schema(resolve, ty, p))?,
+                None => json!({"type": "null"}),
+            };
+            let err = match &result.err {
+                Some(ty) => nested(path, ".err", |p| schema(resolve, ty, p))?,
+                None => json!({"type": "null"}),
+            };
+            json!({"oneOf": [tagged("ok", ok), tagged("err", err)]})
+        }
+        TypeDefKind::Resource | TypeDefKind::Handle(_) => {
+            return Err(unsupported(path, "resource"));
+        }
+        TypeDefKind::Map(_, _) => return Err(unsupported(path, "map")),
+        TypeDefKind::Future(_) => return Err(unsupported(path, "future")),
+        TypeDefKind::Stream(_) => return Err(unsupported(path, "stream")),
+        TypeDefKind::Unknown => return Err(unsupported(path, "unknown")),
+    };
+    Ok(schema)
+}
+
+/// Whether `ty` is an option, looking through type aliases.
+fn is_option(resolve: &Resolve, ty: &Type) -> bool {
+    let Type::Id(id) = ty else {
+        return false;
+    };
+    match resolve.types.get(*id).map(|def| &def.kind) {
+        Some(TypeDefKind::Option(_)) => true,
+        Some(TypeDefKind::Type(alias)) => is_option(resolve, alias),
+        _ => false,
+    }
+}
+
+fn integer<T: Into<Json>>(min: T, max: T) -> Json {
+    json!({"type": "integer", "minimum": min.into(), "maximum": max.into()})
+}
+
+/// Schema of a single-key object `{tag: payload}`.
+fn tagged(tag: &str, payload: Json) -> Json {
+    json!({
+        "type": "object",
+        "properties": {tag: payload},
+        "required": [tag],
+        "additionalProperties": false,
+    })
+}
+
+/// Add a `title` and the doc comment as `description` to `schema`.
+///
+/// Named types keep the description of their definition; a field's doc
+/// comment replaces it, since it says more about that use of the type.
+fn annotate(schema: &mut Json, title: Option<&str>, docs: &Docs) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+    if let Some(title) = title {
+        object.insert("title".to_string(), Json::String(title.to_string()));
+    }
+    if let Some(docs) = &docs.contents {
+        object.insert(
+            "description".to_string(),
+            Json::String(docs.trim().to_string()),
+        );
+    }
+}
+
+/// Run `f` with `segment` appended to `path`.
+fn nested<T>(path: &mut String, segment: &str, f: impl FnOnce(&mut String) -> T) -> T {
+    let len = path.len();
+    path.push_str(segment);
+    let result = f(path);
+    path.truncate(len);
+    result
+}
+
+fn unsupported(path: &str, kind: &str) -> JsonConvertError {
+    error(path, format!("{} types have no JSON form", kind))
+}
+
+fn error(path: &str, message: impl Into<String>) -> JsonConvertError {
+    JsonConvertError {
+        path: path.to_string(),
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn type_schema(wit: &str, name: &str) -> Result<Json, JsonConvertError> {
+        let mut resolve = Resolve::new();
+        resolve.push_str("test.wit", wit).unwrap();
+        let (id, _) = resolve
+            .types
+            .iter()
+            .find(|(_, def)| def.name.as_deref() == Some(name))
+            .unwrap();
+        json_schema(&resolve, &Type::Id(id))
+    }
+
+    #[test]
+    fn test_record_schema() {
+        let wit = r#"
+            package test:schema;
+            interface types {
+                /// A shape to draw
+                variant shape { circle(f32), empty }
+                record item {
+                    /// Display name
+                    name: string,
+                    shape: shape,
+                    note: option<string>,
+                    nested: option<option<u8>>,
+                    done: result<_, string>,
+                }
+            }
+        "#;
+        let schema = type_schema(wit, "item").unwrap();
+        let at = |pointer: &str| schema.pointer(pointer).cloned();
+
+        assert_eq!(at("/title"), Some(json!("item")));
+        assert_eq!(at("/required"), Some(json!(["name", "shape", "done"])));
+        assert_eq!(at("/additionalProperties"), Some(json!(false)));
+        assert_eq!(
+            at("/properties/name"),
+            Some(json!({"type": "string", "description": "Display name"}))
+        );
+        assert_eq!(at("/properties/shape/title"), Some(json!("shape")));
+        assert_eq!(
+            at("/properties/shape/description"),
+            Some(json!("A shape to draw"))
+        );
+        assert_eq!(
+            at("/properties/shape/oneOf/1/properties/tag"),
+            Some(json!({"const": "empty"}))
+        );
+        assert_eq!(
+            at("/properties/nested/anyOf/1/required"),
+            Some(json!(["some"]))
+        );
+        assert_eq!(
+            at("/properties/done/oneOf/0/properties/ok"),
+            Some(json!({"type": "null"}))
+        );
+    }
+
+    #[test]
+    fn test_unsupported_type() {
+        let wit = r#"
+            package test:schema;
+            interface types {
+                resource blob;
+                record holder { items: list<own<blob>> }
+            }
+        "#;
+        let err = type_schema(wit, "holder").unwrap_err();
+        assert_eq!(err.path, "$.items[]");
+    }
+}
@@ -13,6 +13,8 @@
 //! - `val_lower`: Direct wasmtime Val lowering (requires `val` feature)
 //! - `val_lift`: Direct wasmtime Val lifting (requires `val` feature)
 //! - `val_convert`: Conversions between wasmtime Val and wasm_wave Value (requires `val` feature)
+//! - `json_convert`: Lossless conversions between wasm_wave Value and JSON (requires `json` feature)
+//! - `json_schema`: JSON Schema of the JSON form of WIT types (requires `json` feature)
 //!
 //! # Convenience Types
 //!
@@ -22,6 +24,10 @@
 
 mod buffer;
 mod error;
+#[cfg(feature = "json")]
+mod json_convert;
+#[cfg(feature = "json")]
+mod json_schema;
 mod memory;
 #[cfg(feature = "val")]
 mod val_convert;
@@ -35,6 +41,10 @@ mod wave_lower;
 pub use error::CanonicalAbiError;
 pub use memory::LinearMemory;
 
+#[cfg(feature = "json")]
+pub use json_convert::{JsonConvertError, json_to_wave, wave_to_json};
+#[cfg(feature = "json")]
+pub use json_schema::json_schema;
 #[cfg(feature = "val")]
 pub use val_convert::{ValConvertError, val_to_wave, wave_to_val};
 
@@ -63,7 +73,25 @@ use wit_parser::{Resolve, SizeAlign, Type};
 /// ```
 pub struct CanonicalAbi<'a> {
     pub(crate) resolve: &'a Resolve,
-    pub(crate) sizes: SizeAlign,
+    pub(crate) sizes: Sizes<'a>,
+}
+
+/// Size and alignment information, computed by [`CanonicalAbi::new`] or
+/// borrowed from the caller by [`CanonicalAbi::with_sizes`].
+pub(crate) enum Sizes<'a> {
+    Owned(SizeAlign),
+    Borrowed(&'a SizeAlign),
+}
+
+impl std::ops::Deref for Sizes<'_> {
+    type Target = SizeAlign;
+
+    fn deref(&self) -> &SizeAlign {
+        match self {
+            Sizes::Owned(sizes) => sizes,
+            Sizes::Borrowed(sizes) => sizes,
+        }
+    }
 }
 
 impl<'a> CanonicalAbi<'a> {
@@ -74,7 +102,36 @@ impl<'a> CanonicalAbi<'a> {
     pub fn new(resolve: &'a Resolve) -> Self {
         let mut sizes = SizeAlign::default();
         sizes.fill(resolve);
-        Self { resolve, sizes }
+        Self {
+            resolve,
+            sizes: Sizes::Owned(sizes),
+        }
+    }
+
+    /// Create a CanonicalAbi instance from size and alignment information
+    /// that was already computed for `resolve`.
+    ///
+    /// Use this to avoid recomputing sizes when the same resolve is used
+    /// many times. `sizes` must have been filled from this same resolve.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wit_parser::SizeAlign;
+    /// use wit_kv_abi::CanonicalAbi;
+    ///
+    /// let mut sizes = SizeAlign::default();
+    /// sizes.fill(&resolve);
+    /// for bytes in values {
+    ///     let abi = CanonicalAbi::with_sizes(&resolve, &sizes);
+    ///     let (value, _) = abi.lift(bytes, &wit_ty, &wave_ty)?;
+    /// }
+    /// ```
+    pub fn with_sizes(resolve: &'a Resolve, sizes: &'a SizeAlign) -> Self {
+        Self {
+            resolve,
+            sizes: Sizes::Borrowed(sizes),
+        }
     }
 
     /// Encode a WAVE value to canonical ABI format.
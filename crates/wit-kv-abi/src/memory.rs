@@ -106,6 +106,26 @@ impl LinearMemory {
             })
     }
 
+    /// Read the bytes of `len` consecutive elements of `elem_size` bytes each.
+    ///
+    /// Fails if the whole range is not inside memory, so callers can trust
+    /// `len` before allocating for the elements.
+    pub fn read_list(
+        &self,
+        ptr: u32,
+        len: u32,
+        elem_size: usize,
+    ) -> Result<&[u8], CanonicalAbiError> {
+        let invalid = || CanonicalAbiError::InvalidMemoryPointer {
+            ptr,
+            len,
+            memory_size: self.data.len(),
+        };
+        let size = (len as usize).checked_mul(elem_size).ok_or_else(invalid)?;
+        let end = (ptr as usize).checked_add(size).ok_or_else(invalid)?;
+        self.data.get(ptr as usize..end).ok_or_else(invalid)
+    }
+
     /// Get the raw bytes of the linear memory.
     pub fn as_bytes(&self) -> &[u8] {
         &self.data
@@ -310,10 +310,12 @@ impl CanonicalAbi<'_> {
                     }
                 });
 
+                // Bounds-check the whole list before allocating for it
+                let list_bytes = memory.read_list(ptr, len, elem_size)?;
+
                 let mut elements: Vec<Val> = Vec::with_capacity(len as usize);
                 for i in 0..len as usize {
-                    let elem_offset = ptr as usize + i * elem_size;
-                    let elem_bytes = memory.read(elem_offset as u32, elem_size as u32)?;
+                    let elem_bytes = read_slice(list_bytes, i * elem_size, elem_size)?;
                     let (elem_val, _) =
                         self.lift_val_from(elem_bytes, elem_ty, inner_val_ty.as_ref(), 0, memory)?;
                     elements.push(elem_val);
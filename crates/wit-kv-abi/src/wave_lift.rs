@@ -520,14 +520,16 @@ impl CanonicalAbi<'_> {
                         let elem_size = self.sizes.size(elem_ty).size_wasm32();
 
                         // Read each element from linear memory
+                        let list_bytes = mem.read_list(ptr, len, elem_size)?;
                         let mut elements: Vec<Value> = Vec::new();
                         for i in 0..len as usize {
-                            let elem_offset = ptr as usize + i * elem_size;
-                            let elem_bytes = mem.read(elem_offset as u32, elem_size as u32)?;
-
-                            // Lift element from linear memory bytes
-                            let (elem_val, _) =
-                                self.lift_from(elem_bytes, elem_ty, &wave_elem_ty, 0, Some(mem))?;
+                            let (elem_val, _) = self.lift_from(
+                                list_bytes,
+                                elem_ty,
+                                &wave_elem_ty,
+                                i * elem_size,
+                                Some(mem),
+                            )?;
                             elements.push(elem_val);
                         }
 
@@ -1,13 +1,19 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter};
 use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
 
-use wit_kv::kv::{BinaryExport, KvError, KvStore};
-use wit_kv::wasm::{TypedRunner, WasmError};
+use wit_kv::kv::{
+    BinaryExport, DEFAULT_IMPORT_BATCH_SIZE, DEFAULT_MAP_BATCH_SIZE, ImportStats, KvError, KvStore,
+    ModuleKind, ModuleRegistration, ScanOptions, TransferFormat,
+};
+use wit_kv::wasm::{ComponentCache, ExecutionLimits, TypedRunner, WasmError};
 use wit_kv::{
-    CanonicalAbi, CanonicalAbiError, LinearMemory, Resolve, Type, TypeId, ValConvertError, Value,
-    find_first_named_type, find_type_by_name, resolve_wit_type, val_to_wave, wave_from_str,
-    wave_to_string,
+    CanonicalAbi, CanonicalAbiError, JsonConvertError, LinearMemory, Resolve, Type, TypeId,
+    ValConvertError, Value, WaveType, find_first_named_type, find_type_by_name, json_to_wave,
+    resolve_wit_type, val_to_wave, wave_from_str, wave_to_json, wave_to_string,
 };
 
 /// CLI-specific errors.
@@ -29,6 +35,10 @@ pub enum AppError {
     #[error("WAVE writing error: {0}")]
     WaveWrite(String),
 
+    /// JSON parsing or conversion error
+    #[error("JSON error: {0}")]
+    Json(String),
+
     /// Type not found
     #[error("Type not found: {0}")]
     TypeNotFound(String),
@@ -48,6 +58,18 @@ pub enum AppError {
     /// Keyspace not found
     #[error("Keyspace '{0}' not found")]
     KeyspaceNotFound(String),
+
+    /// Invalid line in a batch file
+    #[error("Batch line {line}: {message}")]
+    InvalidBatch { line: usize, message: String },
+
+    /// Registered module of the wrong kind
+    #[error("Module '{id}' is a {actual}, expected a {expected}")]
+    ModuleKindMismatch {
+        id: String,
+        expected: &'static str,
+        actual: &'static str,
+    },
 }
 
 impl From<KvError> for AppError {
@@ -74,6 +96,12 @@ impl From<ValConvertError> for AppError {
     }
 }
 
+impl From<JsonConvertError> for AppError {
+    fn from(e: JsonConvertError) -> Self {
+        Self::Json(e.to_string())
+    }
+}
+
 impl From<anyhow::Error> for AppError {
     fn from(e: anyhow::Error) -> Self {
         Self::Library(wit_kv::Error::WitParse(e))
@@ -88,6 +116,149 @@ struct Cli {
     command: Commands,
 }
 
+/// Text encoding of values read and printed by the CLI.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ValueFormat {
+    /// WAVE text
+    #[default]
+    Wave,
+    /// JSON, in the mapping described in the README
+    Json,
+}
+
+/// Resource limits of a WebAssembly Component run by the CLI (unlimited by default).
+#[derive(Args)]
+struct LimitArgs {
+    /// Fuel (roughly, executed instructions) a single call may consume
+    #[arg(long)]
+    fuel: Option<u64>,
+
+    /// Fuel all calls may consume together
+    #[arg(long)]
+    job_fuel: Option<u64>,
+
+    /// Milliseconds a single call may take
+    #[arg(long)]
+    timeout_ms: Option<u64>,
+
+    /// Milliseconds the whole operation may take
+    #[arg(long)]
+    job_timeout_ms: Option<u64>,
+
+    /// Maximum size in bytes of the component's linear memory
+    #[arg(long)]
+    max_memory: Option<usize>,
+
+    /// Maximum number of elements of the component's tables
+    #[arg(long)]
+    max_table_elements: Option<usize>,
+}
+
+/// The WebAssembly Component run by a map or reduce: a module file, or a
+/// module registered in the store.
+#[derive(Args)]
+struct ModuleArgs {
+    /// Path to the WebAssembly Component module (.wasm)
+    #[arg(long, required_unless_present = "module_id", requires = "module_wit")]
+    module: Option<PathBuf>,
+
+    /// WIT file defining the component's types
+    #[arg(long, requires = "module")]
+    module_wit: Option<PathBuf>,
+
+    /// Id of a module registered with `module add`, instead of --module and --module-wit
+    #[arg(long, conflicts_with_all = ["module", "module_wit"])]
+    module_id: Option<String>,
+}
+
+impl ModuleArgs {
+    /// Build a runner of the module, which must be a `kind` module if registered.
+    ///
+    /// The types of a registered module default to those it was registered
+    /// with, and its compiled component is kept in the store's module cache.
+    fn runner(
+        &self,
+        store: &KvStore,
+        kind: ModuleKind,
+        input_type: Option<&str>,
+        output_type: Option<&str>,
+        limits: ExecutionLimits,
+    ) -> Result<TypedRunner, AppError> {
+        let mut builder = TypedRunner::builder().limits(limits);
+        let registration = match &self.module_id {
+            Some(id) => {
+                let registration = store
+                    .get_module(id)?
+                    .ok_or_else(|| KvError::ModuleNotFound(id.clone()))?;
+                if registration.kind != kind {
+                    return Err(AppError::ModuleKindMismatch {
+                        id: id.clone(),
+                        expected: kind.as_str(),
+                        actual: registration.kind.as_str(),
+                    });
+                }
+                let bytes = store
+                    .get_module_component(id)?
+                    .ok_or_else(|| KvError::ModuleNotFound(id.clone()))?;
+                builder = builder
+                    .component_bytes(bytes)
+                    .wit_text(&registration.wit_definition)
+                    .component_cache(ComponentCache::new(store.module_cache_dir()), id);
+                Some(registration)
+            }
+            None => {
+                if let (Some(module), Some(module_wit)) = (&self.module, &self.module_wit) {
+                    builder = builder.component(module).wit(module_wit);
+                }
+                None
+            }
+        };
+
+        if let Some(input_type) =
+            input_type.or(registration.as_ref().map(|r| r.input_type.as_str()))
+        {
+            builder = builder.input_type(input_type);
+        }
+        if let Some(output_type) =
+            output_type.or(registration.as_ref().map(|r| r.output_type.as_str()))
+        {
+            builder = builder.output_type(output_type);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Kind of a registered module.
+#[derive(Clone, Copy, ValueEnum)]
+enum ModuleKindArg {
+    /// Exports `filter` and `transform`, run by `map`
+    Mapper,
+    /// Exports `init-state` and `reduce`, run by `reduce`
+    Reducer,
+}
+
+impl From<ModuleKindArg> for ModuleKind {
+    fn from(kind: ModuleKindArg) -> Self {
+        match kind {
+            ModuleKindArg::Mapper => ModuleKind::Mapper,
+            ModuleKindArg::Reducer => ModuleKind::Reducer,
+        }
+    }
+}
+
+impl LimitArgs {
+    fn execution_limits(&self) -> ExecutionLimits {
+        ExecutionLimits {
+            call_fuel: self.fuel,
+            job_fuel: self.job_fuel,
+            call_timeout: self.timeout_ms.map(Duration::from_millis),
+            job_timeout: self.job_timeout_ms.map(Duration::from_millis),
+            max_memory: self.max_memory,
+            max_table_elements: self.max_table_elements,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Lower a WAVE-encoded value to binary using canonical ABI
@@ -104,6 +275,10 @@ enum Commands {
         #[arg(short, long)]
         value: String,
 
+        /// Encoding of --value
+        #[arg(long, value_enum, default_value_t)]
+        format: ValueFormat,
+
         /// Output file for the binary data
         #[arg(short, long)]
         output: PathBuf,
@@ -126,6 +301,10 @@ enum Commands {
         /// Output file for WAVE representation (stdout if not specified)
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        /// Encoding of the output
+        #[arg(long, value_enum, default_value_t)]
+        format: ValueFormat,
     },
 
     /// Initialize a new key-value store
@@ -148,10 +327,14 @@ enum Commands {
         #[arg(short = 't', long)]
         type_name: Option<String>,
 
-        /// Overwrite existing type definition
+        /// Replace an existing type definition (the type version is bumped)
         #[arg(long)]
         force: bool,
 
+        /// Accept breaking type changes when replacing a type
+        #[arg(long, requires = "force")]
+        allow_breaking: bool,
+
         /// Store path
         #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
         path: PathBuf,
@@ -192,6 +375,87 @@ enum Commands {
         path: PathBuf,
     },
 
+    /// Set or clear the default time-to-live of values in a keyspace
+    SetDefaultTtl {
+        /// Name of the keyspace
+        keyspace: String,
+
+        /// Time-to-live in seconds
+        #[arg(required_unless_present = "clear")]
+        seconds: Option<u64>,
+
+        /// Remove the default, so values without a TTL never expire
+        #[arg(long, conflicts_with = "seconds")]
+        clear: bool,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Remove expired values from every keyspace
+    PurgeExpired {
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Write a consistent backup archive of the whole store
+    Backup {
+        /// Archive file to write (replaced if it exists)
+        archive: PathBuf,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Create a new store from a backup archive
+    Restore {
+        /// Archive file written by `backup`
+        archive: PathBuf,
+
+        /// Path of the new store (must not exist)
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Index a record field so keys can be looked up by its value
+    CreateIndex {
+        /// Name of the keyspace
+        keyspace: String,
+
+        /// Field to index, e.g. `email` or `address.city`
+        field: String,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Drop a field index
+    DropIndex {
+        /// Name of the keyspace
+        keyspace: String,
+
+        /// Indexed field
+        field: String,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// List the indexed fields of a keyspace
+    ListIndexes {
+        /// Name of the keyspace
+        keyspace: String,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
     /// Set a value in a keyspace
     Set {
         /// Name of the keyspace
@@ -208,6 +472,26 @@ enum Commands {
         #[arg(long, group = "input")]
         file: Option<PathBuf>,
 
+        /// The file holds canonical ABI binary (binary-export WIT type), as written by `get --binary`
+        #[arg(long, requires = "file")]
+        binary: bool,
+
+        /// Encoding of --value or --file
+        #[arg(long, value_enum, default_value_t, conflicts_with = "binary")]
+        format: ValueFormat,
+
+        /// Only write if the key currently has this revision
+        #[arg(long, conflicts_with = "if_absent")]
+        if_revision: Option<u64>,
+
+        /// Only write if the key does not exist
+        #[arg(long)]
+        if_absent: bool,
+
+        /// Expire the value after this many seconds, instead of the keyspace's default TTL
+        #[arg(long)]
+        ttl: Option<u64>,
+
         /// Store path
         #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
         path: PathBuf,
@@ -225,6 +509,18 @@ enum Commands {
         #[arg(long)]
         binary: bool,
 
+        /// Print the value's revision instead of the value
+        #[arg(long, conflicts_with = "binary")]
+        revision: bool,
+
+        /// Only output these fields, e.g. --fields name,address.city
+        #[arg(long, value_delimiter = ',', conflicts_with = "revision")]
+        fields: Option<Vec<String>>,
+
+        /// Encoding of the output
+        #[arg(long, value_enum, default_value_t, conflicts_with_all = ["binary", "revision"])]
+        format: ValueFormat,
+
         /// Store path
         #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
         path: PathBuf,
@@ -238,6 +534,65 @@ enum Commands {
         /// Key for the value
         key: String,
 
+        /// Only delete if the key currently has this revision
+        #[arg(long)]
+        if_revision: Option<u64>,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Apply many writes atomically from a file of operations
+    ///
+    /// Each line is `set<TAB>keyspace<TAB>key<TAB>value` or
+    /// `delete<TAB>keyspace<TAB>key`. Blank lines and lines starting with `#`
+    /// are ignored. Either every operation is applied or none is.
+    Batch {
+        /// File with one operation per line (`-` reads from stdin)
+        #[arg(long)]
+        file: PathBuf,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Import `key<TAB>wave-value` lines into a keyspace, committing in batches
+    Import {
+        /// Name of the keyspace
+        keyspace: String,
+
+        /// File to import (`-` reads from stdin)
+        #[arg(long)]
+        file: PathBuf,
+
+        /// The file holds binary frames, as written by `export --binary`
+        #[arg(long)]
+        binary: bool,
+
+        /// Values committed per batch
+        #[arg(long, default_value_t = DEFAULT_IMPORT_BATCH_SIZE)]
+        batch_size: usize,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Export every value of a keyspace as `key<TAB>wave-value` lines
+    Export {
+        /// Name of the keyspace
+        keyspace: String,
+
+        /// Output file (stdout if not specified)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Write length-prefixed keys and binary-export records instead
+        #[arg(long)]
+        binary: bool,
+
         /// Store path
         #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
         path: PathBuf,
@@ -252,6 +607,10 @@ enum Commands {
         #[arg(long)]
         prefix: Option<String>,
 
+        /// Continue from the cursor printed by a previous page
+        #[arg(long)]
+        cursor: Option<String>,
+
         /// Maximum number of keys to return
         #[arg(long)]
         limit: Option<usize>,
@@ -261,22 +620,76 @@ enum Commands {
         path: PathBuf,
     },
 
-    /// Map values using a typed WebAssembly Component (actual WIT types, not binary-export)
-    Map {
+    /// Print keys and values in a keyspace, one tab-separated pair per line
+    Scan {
         /// Name of the keyspace
         keyspace: String,
 
-        /// Path to the WebAssembly Component module (.wasm)
+        /// Filter keys by prefix
         #[arg(long)]
-        module: PathBuf,
+        prefix: Option<String>,
 
-        /// WIT file defining the component's types
+        /// Start key (inclusive)
         #[arg(long)]
-        module_wit: PathBuf,
+        start: Option<String>,
 
-        /// Name of the input type in module_wit
+        /// End key (exclusive)
         #[arg(long)]
-        input_type: String,
+        end: Option<String>,
+
+        /// Resume after this key (the last key printed by a previous scan)
+        #[arg(long)]
+        after: Option<String>,
+
+        /// Scan from the highest key down
+        #[arg(long)]
+        reverse: bool,
+
+        /// Maximum number of values to print
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Only print values matching a predicate, e.g. 'score > 100 and tier is gold'
+        #[arg(long = "where", allow_hyphen_values = true)]
+        filter: Option<String>,
+
+        /// Only print these fields of each value, e.g. --fields name,address.city
+        #[arg(long, value_delimiter = ',')]
+        fields: Option<Vec<String>>,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// List the keys whose indexed field has a value
+    Lookup {
+        /// Name of the keyspace
+        keyspace: String,
+
+        /// Indexed field
+        field: String,
+
+        /// WAVE-encoded field value, e.g. '"alice@example.com"'
+        #[arg(long, allow_hyphen_values = true)]
+        value: String,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Map values using a typed WebAssembly Component (actual WIT types, not binary-export)
+    Map {
+        /// Name of the keyspace
+        keyspace: String,
+
+        #[command(flatten)]
+        module: ModuleArgs,
+
+        /// Name of the input type in module_wit
+        #[arg(long, required_unless_present = "module_id")]
+        input_type: Option<String>,
 
         /// Name of the output type (defaults to input type)
         #[arg(long)]
@@ -302,6 +715,18 @@ enum Commands {
         #[arg(long)]
         limit: Option<usize>,
 
+        /// Write the transformed values into this keyspace, under their keys,
+        /// instead of printing them. Its type must match the output type.
+        #[arg(long, value_name = "KEYSPACE")]
+        into: Option<String>,
+
+        /// Values committed per batch when writing into a keyspace
+        #[arg(long, requires = "into", default_value_t = DEFAULT_MAP_BATCH_SIZE)]
+        batch_size: usize,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
         /// Store path
         #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
         path: PathBuf,
@@ -312,21 +737,16 @@ enum Commands {
         /// Name of the keyspace
         keyspace: String,
 
-        /// Path to the WebAssembly Component module (.wasm)
-        #[arg(long)]
-        module: PathBuf,
-
-        /// WIT file defining the component's types
-        #[arg(long)]
-        module_wit: PathBuf,
+        #[command(flatten)]
+        module: ModuleArgs,
 
         /// Name of the input/value type in module_wit
-        #[arg(long)]
-        input_type: String,
+        #[arg(long, required_unless_present = "module_id")]
+        input_type: Option<String>,
 
         /// Name of the state type in module_wit
-        #[arg(long)]
-        state_type: String,
+        #[arg(long, required_unless_present = "module_id")]
+        state_type: Option<String>,
 
         /// Filter keys by prefix
         #[arg(long)]
@@ -344,6 +764,109 @@ enum Commands {
         #[arg(long)]
         limit: Option<usize>,
 
+        #[command(flatten)]
+        limits: LimitArgs,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Migrate every value in a keyspace to a new type using a WebAssembly Component
+    ///
+    /// The component must export `migrate: func(old: T0) -> T1`. All values are
+    /// migrated before anything is written, then the new values and type are
+    /// committed atomically.
+    Migrate {
+        /// Name of the keyspace
+        keyspace: String,
+
+        /// Path to the WebAssembly Component module (.wasm)
+        #[arg(long)]
+        module: PathBuf,
+
+        /// WIT file defining the component's types (becomes the keyspace's type definition)
+        #[arg(long)]
+        module_wit: PathBuf,
+
+        /// Name of the current keyspace type in module_wit
+        #[arg(long)]
+        input_type: String,
+
+        /// Name of the new keyspace type in module_wit
+        #[arg(long)]
+        output_type: String,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Manage WebAssembly Components registered in the store, so `map` and
+    /// `reduce` can run them with --module-id
+    Module {
+        #[command(subcommand)]
+        command: ModuleCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModuleCommand {
+    /// Register a module under an id, after checking that it compiles and
+    /// exports the functions of its kind
+    Add {
+        /// Id of the module (ASCII letters, digits, `-` and `_`)
+        id: String,
+
+        /// Path to the WebAssembly Component module (.wasm)
+        #[arg(long)]
+        module: PathBuf,
+
+        /// WIT file defining the component's types
+        #[arg(long)]
+        module_wit: PathBuf,
+
+        /// Whether the module maps or reduces
+        #[arg(long, value_enum)]
+        kind: ModuleKindArg,
+
+        /// Name of the input type in module_wit
+        #[arg(long)]
+        input_type: String,
+
+        /// Name of the output type (mappers, defaults to the input type) or
+        /// of the state type (reducers) in module_wit
+        #[arg(long, required_if_eq("kind", "reducer"))]
+        output_type: Option<String>,
+
+        /// Human-readable name (defaults to the id)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Replace a module already registered under the id
+        #[arg(long)]
+        force: bool,
+
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// List registered modules
+    List {
+        /// Store path
+        #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
+        path: PathBuf,
+    },
+
+    /// Remove a registered module
+    Rm {
+        /// Id of the module
+        id: String,
+
         /// Store path
         #[arg(long, default_value = ".wit-kv", env = "WIT_KV_PATH")]
         path: PathBuf,
@@ -382,6 +905,33 @@ fn get_error_hint(err: &AppError) -> Option<&'static str> {
         | AppError::KeyspaceNotFound(_) => {
             Some("Run 'wit-kv set-type <KEYSPACE> --wit <FILE>' to register a type first")
         }
+        AppError::Library(wit_kv::Error::Kv(KvError::IncompatibleTypeChange { .. })) => Some(
+            "Use --allow-breaking to accept the change; values written with the old type will no longer be readable",
+        ),
+        AppError::Library(wit_kv::Error::Kv(KvError::MigrationFailed { .. })) => {
+            Some("No values were changed; fix the migrate function and run the migration again")
+        }
+        AppError::Library(wit_kv::Error::Kv(
+            KvError::RevisionConflict { .. } | KvError::ValueMissing { .. },
+        )) => Some(
+            "Nothing was written; use 'wit-kv get <KEYSPACE> <KEY> --revision' to see the key's current revision",
+        ),
+        AppError::Library(wit_kv::Error::Kv(KvError::IndexNotFound { .. })) => {
+            Some("Run 'wit-kv create-index <KEYSPACE> <FIELD>' to index the field first")
+        }
+        AppError::Library(wit_kv::Error::Kv(KvError::InvalidIndex { .. })) => Some(
+            "Use 'wit-kv get-type <KEYSPACE>' to see the fields; drop indexes on fields a new type removes",
+        ),
+        AppError::Library(wit_kv::Error::Kv(KvError::InvalidQuery(_))) => Some(
+            "Use 'wit-kv get-type <KEYSPACE>' to see the fields; values are WAVE, e.g. name == \"alice\"",
+        ),
+        AppError::Library(wit_kv::Error::Kv(KvError::ModuleNotFound(_)))
+        | AppError::ModuleKindMismatch { .. } => {
+            Some("Use 'wit-kv module list' to see the registered modules and their kinds")
+        }
+        AppError::Library(wit_kv::Error::Kv(KvError::ModuleExists(_))) => {
+            Some("Use --force to replace the registered module")
+        }
         AppError::KeyNotFound { .. } => Some("Use 'wit-kv list <KEYSPACE>' to see available keys"),
         AppError::TypeNotFound(_) => Some(
             "Use --type-name to specify the exact type, or check the WIT file for available types",
@@ -393,6 +943,9 @@ fn get_error_hint(err: &AppError) -> Option<&'static str> {
         AppError::MissingValueInput => {
             Some("Provide a value with --value '{...}' or from a file with --file path.wave")
         }
+        AppError::InvalidBatch { .. } => Some(
+            "Nothing was written. Each line must be 'set<TAB>keyspace<TAB>key<TAB>value' or 'delete<TAB>keyspace<TAB>key'",
+        ),
         _ => None,
     }
 }
@@ -414,14 +967,14 @@ fn run(cli: Cli) -> Result<(), AppError> {
             wit,
             type_name,
             value,
+            format,
             output,
         } => {
             let (resolve, type_id) = load_wit_type(&wit, type_name.as_deref())?;
             let wave_type = resolve_wit_type(&resolve, type_id)
                 .map_err(|e| AppError::WaveParse(e.to_string()))?;
 
-            let parsed_value: Value = wave_from_str(&wave_type, &value)
-                .map_err(|e| AppError::WaveParse(e.to_string()))?;
+            let parsed_value = parse_value(&value, &wave_type, format)?;
 
             let abi = CanonicalAbi::new(&resolve);
             let ty = Type::Id(type_id);
@@ -459,6 +1012,7 @@ fn run(cli: Cli) -> Result<(), AppError> {
             type_name,
             input,
             output,
+            format,
         } => {
             let (resolve, type_id) = load_wit_type(&wit, type_name.as_deref())?;
             let wave_type = resolve_wit_type(&resolve, type_id)
@@ -476,16 +1030,20 @@ fn run(cli: Cli) -> Result<(), AppError> {
             let (val, _bytes_read) = abi.lift_to_val(&export.buffer, &ty, None, &memory)?;
             let value = val_to_wave(&val, &wave_type)?;
 
-            let wave_str =
-                wave_to_string(&value).map_err(|e| AppError::WaveWrite(e.to_string()))?;
+            let text = match format {
+                ValueFormat::Wave => {
+                    wave_to_string(&value).map_err(|e| AppError::WaveWrite(e.to_string()))?
+                }
+                ValueFormat::Json => wave_to_json(&value, &wave_type)?.to_string(),
+            };
 
             match output {
                 Some(path) => {
-                    std::fs::write(&path, &wave_str)?;
+                    std::fs::write(&path, &text)?;
                     println!("Lifted value to {}", path.display());
                 }
                 None => {
-                    println!("{}", wave_str);
+                    println!("{}", text);
                 }
             }
             Ok(())
@@ -501,13 +1059,15 @@ fn run(cli: Cli) -> Result<(), AppError> {
             wit,
             type_name,
             force,
+            allow_breaking,
             path,
         } => {
             let store = KvStore::open(&path)?;
-            let metadata = store.set_type(&keyspace, &wit, type_name.as_deref(), force)?;
+            let metadata =
+                store.set_type(&keyspace, &wit, type_name.as_deref(), force, allow_breaking)?;
             println!(
-                "Registered type '{}' for keyspace '{}' ({})",
-                metadata.type_name, keyspace, metadata.qualified_name
+                "Registered type '{}' for keyspace '{}' ({}, version {})",
+                metadata.type_name, keyspace, metadata.qualified_name, metadata.type_version
             );
             Ok(())
         }
@@ -557,44 +1117,178 @@ fn run(cli: Cli) -> Result<(), AppError> {
                 println!("No types registered");
             } else {
                 for metadata in types {
+                    let ttl = metadata
+                        .default_ttl
+                        .map(|ttl| format!(", default TTL {}s", ttl.as_secs()))
+                        .unwrap_or_default();
                     println!(
-                        "{}: {} (version {})",
-                        metadata.name, metadata.qualified_name, metadata.type_version
+                        "{}: {} (version {}{})",
+                        metadata.name, metadata.qualified_name, metadata.type_version, ttl
                     );
                 }
             }
             Ok(())
         }
+        Commands::SetDefaultTtl {
+            keyspace,
+            seconds,
+            clear: _,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            store.set_default_ttl(&keyspace, seconds.map(Duration::from_secs))?;
+            match seconds {
+                Some(seconds) => println!(
+                    "Values in keyspace '{}' now expire after {}s by default",
+                    keyspace, seconds
+                ),
+                None => println!("Cleared the default TTL of keyspace '{}'", keyspace),
+            }
+            Ok(())
+        }
+        Commands::PurgeExpired { path } => {
+            let store = KvStore::open(&path)?;
+            let purged = store.purge_expired()?;
+            println!("Purged {} expired value(s)", purged);
+            Ok(())
+        }
+        Commands::Backup { archive, path } => {
+            let store = KvStore::open(&path)?;
+            let info = store.snapshot_to(&archive)?;
+            println!(
+                "Backed up {} value(s) in {} keyspace(s) at revision {} to {}",
+                info.values,
+                info.keyspaces,
+                info.revision,
+                archive.display()
+            );
+            Ok(())
+        }
+        Commands::Restore { archive, path } => {
+            let (_store, info) = KvStore::restore(&archive, &path)?;
+            println!(
+                "Restored {} value(s) in {} keyspace(s) at revision {} to {}",
+                info.values,
+                info.keyspaces,
+                info.revision,
+                path.display()
+            );
+            Ok(())
+        }
+        Commands::CreateIndex {
+            keyspace,
+            field,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            store.create_index(&keyspace, &field)?;
+            println!("Created index on '{}' in keyspace '{}'", field, keyspace);
+            Ok(())
+        }
+        Commands::DropIndex {
+            keyspace,
+            field,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            store.drop_index(&keyspace, &field)?;
+            println!("Dropped index on '{}' in keyspace '{}'", field, keyspace);
+            Ok(())
+        }
+        Commands::ListIndexes { keyspace, path } => {
+            let store = KvStore::open(&path)?;
+            let fields = store.list_indexes(&keyspace)?;
+            if fields.is_empty() {
+                println!("No indexes");
+            } else {
+                for field in fields {
+                    println!("{}", field);
+                }
+            }
+            Ok(())
+        }
         Commands::Set {
             keyspace,
             key,
             value,
             file,
+            binary,
+            format,
+            if_revision,
+            if_absent,
+            ttl,
             path,
         } => {
             let store = KvStore::open(&path)?;
-            let wave_value = match (value, file) {
-                (Some(v), None) => v,
-                (None, Some(f)) => std::fs::read_to_string(f)?,
-                (None, None) => {
-                    return Err(AppError::MissingValueInput);
+            let mut batch = store.batch();
+            if let (true, Some(file)) = (binary, &file) {
+                let export = BinaryExport::decode_from_bytes(&std::fs::read(file)?)?;
+                match if_revision {
+                    Some(expected) => batch.set_raw_if(&keyspace, &key, &export, expected)?,
+                    None if if_absent => batch.set_raw_if_absent(&keyspace, &key, &export)?,
+                    None => batch.set_raw(&keyspace, &key, &export)?,
                 }
-                // Clap group ensures mutual exclusivity, but handle gracefully
-                (Some(v), Some(_)) => v,
-            };
-            store.set(&keyspace, &key, &wave_value)?;
-            println!("Set '{}' in keyspace '{}'", key, keyspace);
+            } else {
+                let wave_value = match (value, file) {
+                    (Some(v), None) => v,
+                    (None, Some(f)) => std::fs::read_to_string(f)?,
+                    (None, None) => {
+                        return Err(AppError::MissingValueInput);
+                    }
+                    // Clap group ensures mutual exclusivity, but handle gracefully
+                    (Some(v), Some(_)) => v,
+                };
+                match format {
+                    ValueFormat::Wave => match if_revision {
+                        Some(expected) => batch.set_if(&keyspace, &key, &wave_value, expected)?,
+                        None if if_absent => batch.set_if_absent(&keyspace, &key, &wave_value)?,
+                        None => batch.set(&keyspace, &key, &wave_value)?,
+                    },
+                    ValueFormat::Json => {
+                        let json = parse_json(&wave_value)?;
+                        match if_revision {
+                            Some(expected) => {
+                                batch.set_json_if(&keyspace, &key, &json, expected)?
+                            }
+                            None if if_absent => {
+                                batch.set_json_if_absent(&keyspace, &key, &json)?
+                            }
+                            None => batch.set_json(&keyspace, &key, &json)?,
+                        }
+                    }
+                }
+            }
+            if let Some(ttl) = ttl {
+                batch.set_ttl(&keyspace, &key, Some(Duration::from_secs(ttl)))?;
+            }
+            let revision = batch.commit()?;
+            println!(
+                "Set '{}' in keyspace '{}' (revision {})",
+                key, keyspace, revision
+            );
             Ok(())
         }
         Commands::Get {
             keyspace,
             key,
             binary,
+            revision,
+            fields,
+            format,
             path,
         } => {
             let store = KvStore::open(&path)?;
-            if binary {
-                match store.get_raw(&keyspace, &key)? {
+            if revision {
+                match store.get_with_revision(&keyspace, &key)? {
+                    Some((_, revision)) => println!("{}", revision),
+                    None => return Err(AppError::KeyNotFound { keyspace, key }),
+                }
+            } else if binary {
+                let stored = match &fields {
+                    Some(fields) => store.get_raw_projected(&keyspace, &key, fields)?,
+                    None => store.get_raw(&keyspace, &key)?,
+                };
+                match stored {
                     Some(stored) => {
                         // Export using binary-export WIT type (buffer + memory)
                         let export = BinaryExport::from_stored_owned(stored);
@@ -606,8 +1300,23 @@ fn run(cli: Cli) -> Result<(), AppError> {
                         return Err(AppError::KeyNotFound { keyspace, key });
                     }
                 }
+            } else if format == ValueFormat::Json {
+                let value = match &fields {
+                    Some(fields) => {
+                        store.get_projected_json_with_revision(&keyspace, &key, fields)?
+                    }
+                    None => store.get_json_with_revision(&keyspace, &key)?,
+                };
+                match value {
+                    Some((json, _)) => println!("{}", json),
+                    None => return Err(AppError::KeyNotFound { keyspace, key }),
+                }
             } else {
-                match store.get(&keyspace, &key)? {
+                let value = match &fields {
+                    Some(fields) => store.get_projected(&keyspace, &key, fields)?,
+                    None => store.get(&keyspace, &key)?,
+                };
+                match value {
                     Some(wave_str) => {
                         println!("{}", wave_str);
                     }
@@ -621,34 +1330,180 @@ fn run(cli: Cli) -> Result<(), AppError> {
         Commands::Delete {
             keyspace,
             key,
+            if_revision,
             path,
         } => {
             let store = KvStore::open(&path)?;
-            store.delete(&keyspace, &key)?;
+            match if_revision {
+                Some(expected) => store.delete_if(&keyspace, &key, expected)?,
+                None => store.delete(&keyspace, &key)?,
+            }
             println!("Deleted '{}' from keyspace '{}'", key, keyspace);
             Ok(())
         }
+        Commands::Batch { file, path } => {
+            let store = KvStore::open(&path)?;
+            let input = if file.as_os_str() == "-" {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                std::fs::read_to_string(&file)?
+            };
+
+            let mut batch = store.batch();
+            let (mut sets, mut deletes) = (0usize, 0usize);
+            for (index, line) in input.lines().enumerate() {
+                let line_number = index + 1;
+                if line.trim().is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let invalid = |message: String| AppError::InvalidBatch {
+                    line: line_number,
+                    message,
+                };
+
+                let result = match line.splitn(4, '\t').collect::<Vec<_>>().as_slice() {
+                    ["set", keyspace, key, value] => {
+                        sets += 1;
+                        batch.set(keyspace, key, value)
+                    }
+                    ["delete", keyspace, key] => {
+                        deletes += 1;
+                        batch.delete(keyspace, key)
+                    }
+                    [op, ..] => {
+                        return Err(invalid(format!("invalid '{}' operation", op)));
+                    }
+                    [] => continue,
+                };
+                result.map_err(|e| invalid(e.to_string()))?;
+            }
+
+            let written = batch.len();
+            let revision = batch.commit()?;
+            println!(
+                "Committed batch: {} set, {} deleted ({} keys written, revision {})",
+                sets, deletes, written, revision
+            );
+            Ok(())
+        }
+        Commands::Import {
+            keyspace,
+            file,
+            binary,
+            batch_size,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            let reader: Box<dyn BufRead> = if file.as_os_str() == "-" {
+                Box::new(std::io::stdin().lock())
+            } else {
+                Box::new(BufReader::new(File::open(&file)?))
+            };
+            let stats = store.import(&keyspace, transfer_format(binary), reader, batch_size)?;
+            print_import_summary(&stats, binary);
+            Ok(())
+        }
+        Commands::Export {
+            keyspace,
+            output,
+            binary,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            let count = match &output {
+                Some(output) => store.export(
+                    &keyspace,
+                    transfer_format(binary),
+                    BufWriter::new(File::create(output)?),
+                )?,
+                None => store.export(
+                    &keyspace,
+                    transfer_format(binary),
+                    BufWriter::new(std::io::stdout().lock()),
+                )?,
+            };
+            eprintln!("Exported {} values", count);
+            Ok(())
+        }
         Commands::List {
             keyspace,
             prefix,
+            cursor,
             limit,
             path,
         } => {
             let store = KvStore::open(&path)?;
-            let keys = store.list(&keyspace, prefix.as_deref(), None, None, limit)?;
-            if keys.is_empty() {
+            let page = store.list(
+                &keyspace,
+                prefix.as_deref(),
+                None,
+                None,
+                cursor.as_deref(),
+                limit,
+            )?;
+            if page.keys.is_empty() {
                 println!("No keys found");
             } else {
-                for key in keys {
+                for key in page.keys {
                     println!("{}", key);
                 }
             }
+            // On stderr so piped key lists stay clean
+            if let Some(next) = page.next_cursor {
+                eprintln!("More keys: --cursor {}", next);
+            }
+            Ok(())
+        }
+        Commands::Scan {
+            keyspace,
+            prefix,
+            start,
+            end,
+            after,
+            reverse,
+            limit,
+            filter,
+            fields,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            let options = ScanOptions {
+                prefix,
+                start,
+                end,
+                after,
+                reverse,
+                limit,
+            };
+            let mut scan = match filter {
+                Some(filter) => store.query(&keyspace, &filter, &options)?,
+                None => store.scan(&keyspace, &options)?,
+            };
+            if let Some(fields) = &fields {
+                scan = scan.project(fields)?;
+            }
+            let mut stdout = std::io::stdout().lock();
+            for entry in scan.wave() {
+                let (key, value) = entry?;
+                writeln!(stdout, "{}\t{}", key, value)?;
+            }
+            Ok(())
+        }
+        Commands::Lookup {
+            keyspace,
+            field,
+            value,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            for key in store.lookup_by_index(&keyspace, &field, &value)? {
+                println!("{}", key);
+            }
             Ok(())
         }
         Commands::Map {
             keyspace,
             module,
-            module_wit,
             input_type,
             output_type,
             key,
@@ -656,15 +1511,36 @@ fn run(cli: Cli) -> Result<(), AppError> {
             start,
             end,
             limit,
+            into,
+            batch_size,
+            limits,
             path,
         } => {
             let store = KvStore::open(&path)?;
-            let mut runner =
-                TypedRunner::new(&module, &module_wit, &input_type, output_type.as_deref())?;
+            let mut runner = module.runner(
+                &store,
+                ModuleKind::Mapper,
+                input_type.as_deref(),
+                output_type.as_deref(),
+                limits.execution_limits(),
+            )?;
             let metadata = store
                 .get_type(&keyspace)?
                 .ok_or_else(|| AppError::TypeNotFound(keyspace.clone()))?;
 
+            // Transformed values are stored as they are, so the target
+            // keyspace must have the module's output type
+            if let Some(into) = &into {
+                let target = store
+                    .get_type(into)?
+                    .ok_or_else(|| AppError::KeyspaceNotFound(into.clone()))?;
+                let (_, _, target_type) = wit_kv::load_wit_type_from_string(
+                    &target.wit_definition,
+                    Some(&target.type_name),
+                )?;
+                runner.check_output_type(&target_type)?;
+            }
+
             let keys = collect_keys(
                 &store,
                 &keyspace,
@@ -675,6 +1551,7 @@ fn run(cli: Cli) -> Result<(), AppError> {
                 limit,
             )?;
             let mut stats = ProcessingStats::new();
+            let mut batch = store.batch();
 
             for k in keys {
                 match store.get_raw(&keyspace, &k)? {
@@ -683,42 +1560,71 @@ fn run(cli: Cli) -> Result<(), AppError> {
                             Ok(true) => {
                                 match runner.call_transform(&stored, metadata.type_version) {
                                     Ok(result) => {
-                                        match runner.stored_to_wave_string(&result) {
-                                            Ok(wave_str) => println!("{}: {}", k, wave_str),
-                                            Err(e) => eprintln!("{}: <decode error: {}>", k, e),
+                                        if let Some(into) = &into {
+                                            match batch.set_stored(into, &k, result) {
+                                                Ok(()) => stats.transformed += 1,
+                                                Err(e) => {
+                                                    stats.add_error(&k, format!("write: {}", e))
+                                                }
+                                            }
+                                        } else {
+                                            match runner.stored_to_wave_string(&result) {
+                                                Ok(wave_str) => println!("{}: {}", k, wave_str),
+                                                Err(e) => {
+                                                    eprintln!("{}: <decode error: {}>", k, e)
+                                                }
+                                            }
+                                            stats.transformed += 1;
                                         }
-                                        stats.transformed += 1;
                                     }
+                                    Err(e) if e.ends_job() => return Err(e.into()),
                                     Err(e) => stats.add_error(&k, format!("transform: {}", e)),
                                 }
                             }
                             Ok(false) => stats.filtered += 1,
+                            Err(e) if e.ends_job() => return Err(e.into()),
                             Err(e) => stats.add_error(&k, format!("filter: {}", e)),
                         }
                         stats.processed += 1;
                     }
                     None => stats.add_error(&k, "not found".to_string()),
                 }
+                if batch.len() >= batch_size {
+                    let full = std::mem::replace(&mut batch, store.batch());
+                    let count = full.len();
+                    full.commit()?;
+                    stats.written += count;
+                }
+            }
+            if !batch.is_empty() {
+                let count = batch.len();
+                batch.commit()?;
+                stats.written += count;
             }
 
-            stats.print_map_summary();
+            stats.print_map_summary(into.as_deref());
             Ok(())
         }
         Commands::Reduce {
             keyspace,
             module,
-            module_wit,
             input_type,
             state_type,
             prefix,
             start,
             end,
             limit,
+            limits,
             path,
         } => {
             let store = KvStore::open(&path)?;
-            let mut runner =
-                TypedRunner::new(&module, &module_wit, &input_type, Some(&state_type))?;
+            let mut runner = module.runner(
+                &store,
+                ModuleKind::Reducer,
+                input_type.as_deref(),
+                state_type.as_deref(),
+                limits.execution_limits(),
+            )?;
             let metadata = store
                 .get_type(&keyspace)?
                 .ok_or_else(|| AppError::TypeNotFound(keyspace.clone()))?;
@@ -743,6 +1649,7 @@ fn run(cli: Cli) -> Result<(), AppError> {
                                 state = new_state;
                                 stats.processed += 1;
                             }
+                            Err(e) if e.ends_job() => return Err(e.into()),
                             Err(e) => stats.add_error(&k, format!("reduce: {}", e)),
                         }
                     }
@@ -758,9 +1665,169 @@ fn run(cli: Cli) -> Result<(), AppError> {
             stats.print_reduce_summary();
             Ok(())
         }
+        Commands::Migrate {
+            keyspace,
+            module,
+            module_wit,
+            input_type,
+            output_type,
+            limits,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            let previous = store
+                .get_type(&keyspace)?
+                .ok_or_else(|| AppError::KeyspaceNotFound(keyspace.clone()))?;
+            let (_, _, keyspace_type) = wit_kv::load_wit_type_from_string(
+                &previous.wit_definition,
+                Some(&previous.type_name),
+            )?;
+
+            let mut runner = TypedRunner::builder()
+                .component(&module)
+                .wit(&module_wit)
+                .input_type(&input_type)
+                .output_type(&output_type)
+                .limits(limits.execution_limits())
+                .build()?;
+            runner.check_input_type(&keyspace_type)?;
+
+            let mut migrated = 0u32;
+            let metadata = store.migrate(
+                &keyspace,
+                &module_wit,
+                Some(&output_type),
+                |_, value, type_version| {
+                    migrated += 1;
+                    runner.call_migrate(value, type_version)
+                },
+            )?;
+
+            println!(
+                "Migrated {} values in keyspace '{}' to type '{}' (version {} -> {})",
+                migrated,
+                keyspace,
+                metadata.type_name,
+                previous.type_version,
+                metadata.type_version
+            );
+            Ok(())
+        }
+        Commands::Module { command } => run_module(command),
     }
 }
 
+fn run_module(command: ModuleCommand) -> Result<(), AppError> {
+    match command {
+        ModuleCommand::Add {
+            id,
+            module,
+            module_wit,
+            kind,
+            input_type,
+            output_type,
+            name,
+            force,
+            path,
+        } => {
+            let store = KvStore::open(&path)?;
+            let kind = ModuleKind::from(kind);
+            let output_type = output_type.unwrap_or_else(|| input_type.clone());
+            ModuleRegistration::validate_id(&id)?;
+            if !force && store.get_module(&id)?.is_some() {
+                return Err(KvError::ModuleExists(id).into());
+            }
+
+            let bytes = std::fs::read(&module)?;
+            let registration = ModuleRegistration::new(
+                id.clone(),
+                name.unwrap_or_else(|| id.clone()),
+                std::fs::read_to_string(&module_wit)?,
+                input_type,
+                output_type,
+                kind,
+            );
+
+            // Compiling a new module also fills the module cache, so the first
+            // run is fast. A replaced module keeps its compiled component until
+            // the new one has passed, so it is compiled outside the cache.
+            let cache = ComponentCache::new(store.module_cache_dir());
+            let replacing = store.get_module(&id)?.is_some();
+            let mut builder = TypedRunner::builder()
+                .component_bytes(bytes.clone())
+                .wit_text(&registration.wit_definition)
+                .input_type(&registration.input_type)
+                .output_type(&registration.output_type);
+            if !replacing {
+                builder = builder.component_cache(cache.clone(), &id);
+            }
+            let validated = builder
+                .build()
+                .and_then(|mut runner| runner.check_exports(kind.exports()));
+            if let Err(e) = validated {
+                // Leave no compiled component of a module that is not registered
+                if !replacing {
+                    cache.remove(&id)?;
+                }
+                return Err(e.into());
+            }
+
+            store.register_module(&registration, &bytes, force)?;
+            if replacing {
+                cache.remove(&id)?;
+            }
+            println!(
+                "Registered {} '{}' ({} -> {})",
+                kind.as_str(),
+                id,
+                registration.input_type,
+                registration.output_type
+            );
+            Ok(())
+        }
+        ModuleCommand::List { path } => {
+            let store = KvStore::open(&path)?;
+            let modules = store.list_modules()?;
+            if modules.is_empty() {
+                println!("No modules registered");
+            } else {
+                for module in modules {
+                    println!(
+                        "{}: {} {} ({} -> {})",
+                        module.id,
+                        module.kind.as_str(),
+                        module.name,
+                        module.input_type,
+                        module.output_type
+                    );
+                }
+            }
+            Ok(())
+        }
+        ModuleCommand::Rm { id, path } => {
+            let store = KvStore::open(&path)?;
+            store.delete_module(&id)?;
+            ComponentCache::new(store.module_cache_dir()).remove(&id)?;
+            println!("Removed module '{}'", id);
+            Ok(())
+        }
+    }
+}
+
+/// Parse a value given on the command line in `format`.
+fn parse_value(text: &str, wave_type: &WaveType, format: ValueFormat) -> Result<Value, AppError> {
+    match format {
+        ValueFormat::Wave => {
+            wave_from_str(wave_type, text).map_err(|e| AppError::WaveParse(e.to_string()))
+        }
+        ValueFormat::Json => Ok(json_to_wave(&parse_json(text)?, wave_type)?),
+    }
+}
+
+fn parse_json(text: &str) -> Result<serde_json::Value, AppError> {
+    serde_json::from_str(text).map_err(|e| AppError::Json(e.to_string()))
+}
+
 fn load_wit_type(
     wit_path: &PathBuf,
     type_name: Option<&str>,
@@ -790,7 +1857,40 @@ fn collect_keys(
     if let Some(k) = key {
         Ok(vec![k])
     } else {
-        Ok(store.list(keyspace, prefix, start, end, limit)?)
+        Ok(store.list(keyspace, prefix, start, end, None, limit)?.keys)
+    }
+}
+
+fn transfer_format(binary: bool) -> TransferFormat {
+    if binary {
+        TransferFormat::Frames
+    } else {
+        TransferFormat::Lines
+    }
+}
+
+fn print_import_summary(stats: &ImportStats, binary: bool) {
+    eprintln!(
+        "Imported {} values, {} errors{}",
+        stats.imported,
+        stats.failed,
+        stats
+            .revision
+            .map(|revision| format!(" (revision {})", revision))
+            .unwrap_or_default()
+    );
+    let entry = if binary { "entry" } else { "line" };
+    for error in &stats.errors {
+        match &error.key {
+            Some(key) => eprintln!(
+                "  Error at {} {} ('{}'): {}",
+                entry, error.entry, key, error.message
+            ),
+            None => eprintln!("  Error at {} {}: {}", entry, error.entry, error.message),
+        }
+    }
+    if stats.failed > stats.errors.len() {
+        eprintln!("  ... and {} more", stats.failed - stats.errors.len());
     }
 }
 
@@ -799,6 +1899,7 @@ struct ProcessingStats {
     processed: usize,
     transformed: usize,
     filtered: usize,
+    written: usize,
     errors: Vec<(String, String)>,
 }
 
@@ -808,6 +1909,7 @@ impl ProcessingStats {
             processed: 0,
             transformed: 0,
             filtered: 0,
+            written: 0,
             errors: Vec::new(),
         }
     }
@@ -816,7 +1918,7 @@ impl ProcessingStats {
         self.errors.push((key.to_string(), error));
     }
 
-    fn print_map_summary(&self) {
+    fn print_map_summary(&self, into: Option<&str>) {
         eprintln!(
             "Processed {} keys: {} transformed, {} filtered out, {} errors",
             self.processed,
@@ -824,6 +1926,9 @@ impl ProcessingStats {
             self.filtered,
             self.errors.len()
         );
+        if let Some(into) = into {
+            eprintln!("Wrote {} values into keyspace '{}'", self.written, into);
+        }
         for (k, err) in &self.errors {
             eprintln!("  Error for '{}': {}", k, err);
         }
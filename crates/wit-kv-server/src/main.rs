@@ -2,7 +2,7 @@
 
 mod server;
 
-use axum::Router;
+use axum::{Router, http::header};
 use clap::Parser;
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -61,6 +61,9 @@ fn build_cors_layer(config: &CorsConfig) -> CorsLayer {
         .collect();
     cors = cors.allow_headers(headers);
 
+    // Let browser clients read revisions for conditional writes
+    cors = cors.expose_headers([header::ETAG]);
+
     // Configure credentials
     if config.allow_credentials {
         cors = cors.allow_credentials(true);
@@ -90,9 +93,10 @@ async fn main() -> anyhow::Result<()> {
 
     // Create application state
     let state = AppState::from_config(&config)?;
+    state.spawn_purge_tasks(&config);
 
     // Build router with API routes
-    let mut app = router(state);
+    let mut app = router(state.clone());
 
     // Add static file serving if configured
     if let Some(static_path) = &config.server.static_path {
@@ -124,7 +128,10 @@ async fn main() -> anyhow::Result<()> {
 
     // Run server with graceful shutdown
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            state.shut_down();
+        })
         .await?;
 
     tracing::info!("Server shutdown complete");
@@ -2,6 +2,10 @@
 
 use serde::Deserialize;
 use std::path::Path;
+use std::time::Duration;
+
+use wit_kv::kv::Durability;
+use wit_kv::wasm::ExecutionLimits;
 
 /// Server configuration loaded from TOML file.
 #[derive(Debug, Deserialize)]
@@ -28,6 +32,22 @@ pub struct ServerConfig {
     /// Optional path to serve static files from.
     /// Files are served from the root path after API endpoints.
     pub static_path: Option<String>,
+    /// Map, reduce and migrate jobs run at once across all databases (at least 1).
+    /// Requests beyond it get `503 SERVER_BUSY`.
+    #[serde(default = "default_max_concurrent_jobs")]
+    pub max_concurrent_jobs: usize,
+    /// Change feeds streamed at once across all databases (at least 1).
+    /// Requests beyond it get `503 SERVER_BUSY`.
+    #[serde(default = "default_max_watchers")]
+    pub max_watchers: usize,
+}
+
+fn default_max_concurrent_jobs() -> usize {
+    4
+}
+
+fn default_max_watchers() -> usize {
+    64
 }
 
 /// CORS (Cross-Origin Resource Sharing) configuration.
@@ -44,7 +64,7 @@ pub struct CorsConfig {
     /// Allowed HTTP methods. Default: ["GET", "POST", "PUT", "DELETE", "OPTIONS"]
     #[serde(default = "default_methods")]
     pub allow_methods: Vec<String>,
-    /// Allowed headers. Default: ["Content-Type", "Accept", "Authorization"]
+    /// Allowed headers. Default: ["Content-Type", "Accept", "Authorization", "If-Match", "If-None-Match", "X-TTL"]
     #[serde(default = "default_headers")]
     pub allow_headers: Vec<String>,
     /// Whether to allow credentials (cookies, authorization headers).
@@ -83,6 +103,9 @@ fn default_headers() -> Vec<String> {
         "Content-Type".to_string(),
         "Accept".to_string(),
         "Authorization".to_string(),
+        "If-Match".to_string(),
+        "If-None-Match".to_string(),
+        "X-TTL".to_string(),
     ]
 }
 
@@ -97,6 +120,139 @@ pub struct DatabaseConfig {
     pub name: String,
     /// Path to the database directory.
     pub path: String,
+    /// Write values back in the current type version when they are read
+    /// with an older one.
+    #[serde(default)]
+    pub upgrade_on_read: bool,
+    /// When value writes are fsynced: "sync" (every write), "interval" or "buffer".
+    #[serde(default)]
+    pub durability: DurabilityMode,
+    /// Sync interval in milliseconds for `durability = "interval"`.
+    #[serde(default = "default_sync_interval_ms")]
+    pub sync_interval_ms: u64,
+    /// Interval in milliseconds at which expired values are purged (0 disables purging).
+    #[serde(default = "default_purge_interval_ms")]
+    pub purge_interval_ms: u64,
+    /// Changes kept per keyspace for watchers to catch up on.
+    #[serde(default = "default_change_retention")]
+    pub change_retention: usize,
+    /// Directory that `POST /backup` writes archives to (backups are disabled if unset).
+    pub backup_dir: Option<String>,
+    /// Resource limits of uploaded map, reduce and migrate modules.
+    #[serde(default)]
+    pub limits: LimitsConfig,
+}
+
+impl DatabaseConfig {
+    /// The store durability selected by this configuration.
+    pub fn durability(&self) -> Durability {
+        match self.durability {
+            DurabilityMode::Sync => Durability::SyncAll,
+            DurabilityMode::Interval => {
+                Durability::Interval(Duration::from_millis(self.sync_interval_ms))
+            }
+            DurabilityMode::Buffer => Durability::Buffer,
+        }
+    }
+
+    /// How often expired values are purged, or `None` if they are not.
+    pub fn purge_interval(&self) -> Option<Duration> {
+        (self.purge_interval_ms > 0).then(|| Duration::from_millis(self.purge_interval_ms))
+    }
+}
+
+/// Resource limits of WebAssembly modules run against a database.
+///
+/// A limit of 0 disables it.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LimitsConfig {
+    /// Fuel (roughly, executed instructions) a single call may consume.
+    #[serde(default)]
+    pub call_fuel: u64,
+    /// Fuel all calls of one operation may consume together.
+    #[serde(default)]
+    pub job_fuel: u64,
+    /// Milliseconds a single call may take.
+    #[serde(default = "default_call_timeout_ms")]
+    pub call_timeout_ms: u64,
+    /// Milliseconds one operation may take.
+    #[serde(default = "default_job_timeout_ms")]
+    pub job_timeout_ms: u64,
+    /// Maximum size in bytes of a module's linear memory.
+    #[serde(default = "default_max_memory_bytes")]
+    pub max_memory_bytes: usize,
+    /// Maximum number of elements of a module's tables.
+    #[serde(default = "default_max_table_elements")]
+    pub max_table_elements: usize,
+}
+
+impl LimitsConfig {
+    /// The execution limits selected by this configuration.
+    pub fn execution_limits(&self) -> ExecutionLimits {
+        let millis = |ms: u64| (ms > 0).then(|| Duration::from_millis(ms));
+        ExecutionLimits {
+            call_fuel: (self.call_fuel > 0).then_some(self.call_fuel),
+            job_fuel: (self.job_fuel > 0).then_some(self.job_fuel),
+            call_timeout: millis(self.call_timeout_ms),
+            job_timeout: millis(self.job_timeout_ms),
+            max_memory: (self.max_memory_bytes > 0).then_some(self.max_memory_bytes),
+            max_table_elements: (self.max_table_elements > 0).then_some(self.max_table_elements),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            call_fuel: 0,
+            job_fuel: 0,
+            call_timeout_ms: default_call_timeout_ms(),
+            job_timeout_ms: default_job_timeout_ms(),
+            max_memory_bytes: default_max_memory_bytes(),
+            max_table_elements: default_max_table_elements(),
+        }
+    }
+}
+
+fn default_call_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_job_timeout_ms() -> u64 {
+    300_000
+}
+
+fn default_max_memory_bytes() -> usize {
+    64 << 20 // 64 MiB
+}
+
+fn default_max_table_elements() -> usize {
+    10_000
+}
+
+/// Database durability mode.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DurabilityMode {
+    /// fsync after every write.
+    #[default]
+    Sync,
+    /// fsync in the background every `sync_interval_ms`.
+    Interval,
+    /// Leave writes in OS buffers until the database is closed.
+    Buffer,
+}
+
+fn default_sync_interval_ms() -> u64 {
+    1000
+}
+
+fn default_purge_interval_ms() -> u64 {
+    60_000
+}
+
+fn default_change_retention() -> usize {
+    10_000
 }
 
 /// Logging configuration.
@@ -222,6 +378,17 @@ path = ".wit-kv"
 [[databases]]
 name = "archive"
 path = "/var/lib/wit-kv/archive"
+upgrade_on_read = true
+durability = "interval"
+sync_interval_ms = 250
+purge_interval_ms = 0
+change_retention = 500
+backup_dir = "/var/backups/wit-kv"
+
+[databases.limits]
+call_fuel = 1000000
+job_timeout_ms = 0
+max_memory_bytes = 1048576
 "#;
         let config = Config::parse(toml).unwrap();
         assert_eq!(config.server.bind, "127.0.0.1");
@@ -229,9 +396,55 @@ path = "/var/lib/wit-kv/archive"
         assert_eq!(config.databases.len(), 2);
         assert_eq!(config.databases.first().unwrap().name, "default");
         assert_eq!(config.databases.get(1).unwrap().name, "archive");
+        assert!(!config.databases.first().unwrap().upgrade_on_read);
+        assert!(config.databases.get(1).unwrap().upgrade_on_read);
+        assert_eq!(
+            config.databases.first().unwrap().durability(),
+            Durability::SyncAll
+        );
+        assert_eq!(
+            config.databases.get(1).unwrap().durability(),
+            Durability::Interval(Duration::from_millis(250))
+        );
+        assert_eq!(
+            config.databases.first().unwrap().purge_interval(),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(config.databases.get(1).unwrap().purge_interval(), None);
+        assert_eq!(config.databases.first().unwrap().change_retention, 10_000);
+        assert_eq!(config.databases.get(1).unwrap().change_retention, 500);
+        assert!(config.databases.first().unwrap().backup_dir.is_none());
+        assert_eq!(
+            config.databases.get(1).unwrap().backup_dir.as_deref(),
+            Some("/var/backups/wit-kv")
+        );
+        assert_eq!(
+            config.databases.first().unwrap().limits.execution_limits(),
+            ExecutionLimits {
+                call_fuel: None,
+                job_fuel: None,
+                call_timeout: Some(Duration::from_secs(5)),
+                job_timeout: Some(Duration::from_secs(300)),
+                max_memory: Some(64 << 20),
+                max_table_elements: Some(10_000),
+            }
+        );
+        assert_eq!(
+            config.databases.get(1).unwrap().limits.execution_limits(),
+            ExecutionLimits {
+                call_fuel: Some(1_000_000),
+                job_fuel: None,
+                call_timeout: Some(Duration::from_secs(5)),
+                job_timeout: None,
+                max_memory: Some(1 << 20),
+                max_table_elements: Some(10_000),
+            }
+        );
         // Defaults
         assert!(!config.cors.enabled);
         assert!(config.server.static_path.is_none());
+        assert_eq!(config.server.max_concurrent_jobs, 4);
+        assert_eq!(config.server.max_watchers, 64);
     }
 
     #[test]
@@ -242,6 +455,8 @@ path = "/var/lib/wit-kv/archive"
 bind = "0.0.0.0"
 port = 3000
 static_path = "./public"
+max_concurrent_jobs = 1
+max_watchers = 2
 
 [cors]
 enabled = true
@@ -259,6 +474,8 @@ path = "./data"
         assert_eq!(config.server.bind, "0.0.0.0");
         assert_eq!(config.server.port, 3000);
         assert_eq!(config.server.static_path, Some("./public".to_string()));
+        assert_eq!(config.server.max_concurrent_jobs, 1);
+        assert_eq!(config.server.max_watchers, 2);
 
         assert!(config.cors.enabled);
         assert_eq!(config.cors.allow_origins.len(), 2);
@@ -278,7 +495,7 @@ path = "./data"
         assert!(!config.enabled);
         assert!(config.allow_origins.is_empty());
         assert_eq!(config.allow_methods.len(), 5); // GET, POST, PUT, DELETE, OPTIONS
-        assert_eq!(config.allow_headers.len(), 3); // Content-Type, Accept, Authorization
+        assert_eq!(config.allow_headers.len(), 6); // Content-Type, Accept, Authorization, If-Match, If-None-Match, X-TTL
         assert!(!config.allow_credentials);
         assert_eq!(config.max_age, 3600);
     }
@@ -1,4 +1,4 @@
-//! Content-type negotiation for Wave text and binary formats.
+//! Content-type negotiation for Wave text, JSON and binary formats.
 
 use axum::{
     body::Bytes,
@@ -15,6 +15,12 @@ pub const MIME_WASM_WAVE: &str = "application/x-wasm-wave";
 /// MIME type for binary format.
 pub const MIME_OCTET_STREAM: &str = "application/octet-stream";
 
+/// MIME type for JSON.
+pub const MIME_JSON: &str = "application/json";
+
+/// MIME type for newline-delimited JSON, used by streamed JSON responses.
+pub const MIME_NDJSON: &str = "application/x-ndjson";
+
 /// MIME type for plain text (also accepts Wave).
 pub const MIME_TEXT_PLAIN: &str = "text/plain";
 
@@ -26,6 +32,8 @@ pub enum ContentFormat {
     Wave,
     /// Binary canonical ABI format.
     Binary,
+    /// JSON, type-directed by the keyspace type.
+    Json,
 }
 
 impl ContentFormat {
@@ -40,17 +48,20 @@ impl ContentFormat {
         match mime {
             MIME_WASM_WAVE | MIME_TEXT_PLAIN | "" => Ok(ContentFormat::Wave),
             MIME_OCTET_STREAM => Ok(ContentFormat::Binary),
+            MIME_JSON => Ok(ContentFormat::Json),
             _ => Err(ApiError::unsupported_media_type(mime)),
         }
     }
 
     /// Parse content format from Accept header value.
     pub fn from_accept(accept: &str) -> Self {
-        // Simple parsing - check for binary first, otherwise default to Wave
+        // Simple parsing - check for binary first, then JSON, otherwise default to Wave
         let accept_lower = accept.to_lowercase();
 
         if accept_lower.contains(MIME_OCTET_STREAM) {
             ContentFormat::Binary
+        } else if accept_lower.contains(MIME_JSON) || accept_lower.contains(MIME_NDJSON) {
+            ContentFormat::Json
         } else {
             ContentFormat::Wave
         }
@@ -61,6 +72,7 @@ impl ContentFormat {
         match self {
             ContentFormat::Wave => HeaderValue::from_static(MIME_WASM_WAVE),
             ContentFormat::Binary => HeaderValue::from_static(MIME_OCTET_STREAM),
+            ContentFormat::Json => HeaderValue::from_static(MIME_JSON),
         }
     }
 }
@@ -134,6 +146,11 @@ impl FormatResponse {
     pub fn binary(body: impl Into<Bytes>) -> Self {
         Self::new(ContentFormat::Binary, body)
     }
+
+    /// Create a JSON response.
+    pub fn json(value: &serde_json::Value) -> Self {
+        Self::new(ContentFormat::Json, value.to_string())
+    }
 }
 
 impl IntoResponse for FormatResponse {
@@ -2,7 +2,7 @@
 
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
@@ -11,6 +11,9 @@ use tracing::{debug, error};
 use wit_kv::kv::KvError;
 use wit_kv::wasm::WasmError;
 
+/// Seconds a client is asked to wait before retrying a request refused as busy.
+const BUSY_RETRY_AFTER_SECS: u16 = 1;
+
 /// API error response body.
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
@@ -33,6 +36,8 @@ pub struct ApiError {
     pub code: &'static str,
     pub message: String,
     pub details: Option<serde_json::Value>,
+    /// Seconds to send in a `Retry-After` header.
+    pub retry_after: Option<u16>,
 }
 
 impl ApiError {
@@ -43,6 +48,7 @@ impl ApiError {
             code,
             message: message.into(),
             details: None,
+            retry_after: None,
         }
     }
 
@@ -93,6 +99,11 @@ impl ApiError {
         Self::new(StatusCode::BAD_REQUEST, "INVALID_WAVE_FORMAT", message)
     }
 
+    /// Invalid JSON value error.
+    pub fn invalid_json(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "INVALID_JSON", message)
+    }
+
     /// Invalid binary format error.
     pub fn invalid_binary_format(message: impl Into<String>) -> Self {
         Self::new(StatusCode::BAD_REQUEST, "INVALID_BINARY_FORMAT", message)
@@ -134,6 +145,69 @@ impl ApiError {
     pub fn wasm_error(message: impl Into<String>) -> Self {
         Self::new(StatusCode::BAD_REQUEST, "WASM_ERROR", message)
     }
+
+    /// All job slots are taken.
+    pub fn server_busy() -> Self {
+        let mut err = Self::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "SERVER_BUSY",
+            "Too many jobs are running, retry later",
+        )
+        .with_details(serde_json::json!({ "retry_after": BUSY_RETRY_AFTER_SECS }));
+        err.retry_after = Some(BUSY_RETRY_AFTER_SECS);
+        err
+    }
+
+    /// All change feed slots are taken.
+    pub fn watchers_busy() -> Self {
+        let mut err = Self::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "SERVER_BUSY",
+            "Too many changes are being watched, retry later",
+        )
+        .with_details(serde_json::json!({ "retry_after": BUSY_RETRY_AFTER_SECS }));
+        err.retry_after = Some(BUSY_RETRY_AFTER_SECS);
+        err
+    }
+
+    /// Job not found error.
+    pub fn job_not_found(id: &str) -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "JOB_NOT_FOUND",
+            format!("Job '{}' not found", id),
+        )
+        .with_details(serde_json::json!({ "id": id }))
+    }
+
+    /// The result of a job that did not complete was requested.
+    pub fn job_not_completed(id: &str, status: impl Serialize) -> Self {
+        Self::new(
+            StatusCode::CONFLICT,
+            "JOB_NOT_COMPLETED",
+            format!("Job '{}' has no result", id),
+        )
+        .with_details(serde_json::json!({ "id": id, "status": status }))
+    }
+
+    /// A registered module of the wrong kind was referenced.
+    pub fn module_kind_mismatch(id: &str, expected: &str, actual: &str) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "MODULE_KIND_MISMATCH",
+            format!("Module '{}' is a {}, expected a {}", id, actual, expected),
+        )
+        .with_details(serde_json::json!({ "id": id, "expected": expected, "actual": actual }))
+    }
+
+    /// A module hit one of its execution limits.
+    pub fn limit_exceeded(
+        code: &'static str,
+        message: impl Into<String>,
+        details: serde_json::Value,
+    ) -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, code, message).with_details(details)
+    }
 }
 
 impl IntoResponse for ApiError {
@@ -162,7 +236,13 @@ impl IntoResponse for ApiError {
                 details: self.details,
             },
         };
-        (self.status, Json(body)).into_response()
+        let mut response = (self.status, Json(body)).into_response();
+        if let Some(seconds) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, seconds.into());
+        }
+        response
     }
 }
 
@@ -182,6 +262,11 @@ impl From<KvError> for ApiError {
                 "KEYSPACE_EXISTS",
                 format!("Keyspace '{}' already exists", keyspace),
             ),
+            KvError::InvalidKeyspaceName(_) => Self::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_KEYSPACE_NAME",
+                err.to_string(),
+            ),
             KvError::KeyNotFound(key) => Self::new(
                 StatusCode::NOT_FOUND,
                 "KEY_NOT_FOUND",
@@ -205,13 +290,99 @@ impl From<KvError> for ApiError {
                     current.patch
                 ),
             ),
+            KvError::IncompatibleTypeChange { keyspace, changes } => Self::new(
+                StatusCode::CONFLICT,
+                "INCOMPATIBLE_TYPE_CHANGE",
+                format!(
+                    "Breaking type change for keyspace '{}': {} (use allow_breaking=true to accept)",
+                    keyspace, changes
+                ),
+            ),
+            KvError::ProjectionFailed { .. } => {
+                Self::new(StatusCode::CONFLICT, "PROJECTION_FAILED", err.to_string())
+            }
+            KvError::MigrationFailed { .. } => Self::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "MIGRATION_FAILED",
+                err.to_string(),
+            ),
+            KvError::RevisionConflict {
+                key,
+                expected,
+                actual,
+            } => Self::new(
+                StatusCode::PRECONDITION_FAILED,
+                "PRECONDITION_FAILED",
+                err.to_string(),
+            )
+            .with_details(serde_json::json!({
+                "key": key,
+                "expected_revision": expected,
+                "current_revision": actual,
+            })),
+            KvError::ValueMissing { key } => Self::new(
+                StatusCode::PRECONDITION_FAILED,
+                "PRECONDITION_FAILED",
+                err.to_string(),
+            )
+            .with_details(serde_json::json!({
+                "key": key,
+                "expected_revision": "*",
+                "current_revision": null,
+            })),
             KvError::WaveParse(msg) => Self::invalid_wave_format(msg.clone()),
             KvError::NotInitialized(path) => Self::new(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "DATABASE_NOT_INITIALIZED",
                 format!("Database at '{}' is not initialized", path),
             ),
+            KvError::IndexNotFound { keyspace, field } => {
+                Self::new(StatusCode::NOT_FOUND, "INDEX_NOT_FOUND", err.to_string())
+                    .with_details(serde_json::json!({ "keyspace": keyspace, "field": field }))
+            }
+            KvError::IndexExists { keyspace, field } => {
+                Self::new(StatusCode::CONFLICT, "INDEX_EXISTS", err.to_string())
+                    .with_details(serde_json::json!({ "keyspace": keyspace, "field": field }))
+            }
+            KvError::InvalidIndex { field, .. } => {
+                Self::new(StatusCode::BAD_REQUEST, "INVALID_INDEX", err.to_string())
+                    .with_details(serde_json::json!({ "field": field }))
+            }
+            KvError::InvalidQuery(_) => {
+                Self::new(StatusCode::BAD_REQUEST, "INVALID_QUERY", err.to_string())
+            }
+            KvError::InvalidProjection(_) => Self::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_PROJECTION",
+                err.to_string(),
+            ),
+            KvError::ChangesTrimmed { oldest, .. } => {
+                Self::new(StatusCode::GONE, "CHANGES_TRIMMED", err.to_string())
+                    .with_details(serde_json::json!({ "oldest_seq": oldest }))
+            }
+            KvError::ChangesAhead { next, .. } => {
+                Self::new(StatusCode::BAD_REQUEST, "CHANGES_AHEAD", err.to_string())
+                    .with_details(serde_json::json!({ "next_seq": next }))
+            }
+            KvError::ModuleNotFound(id) => {
+                Self::new(StatusCode::NOT_FOUND, "MODULE_NOT_FOUND", err.to_string())
+                    .with_details(serde_json::json!({ "id": id }))
+            }
+            KvError::ModuleExists(id) => {
+                Self::new(StatusCode::CONFLICT, "MODULE_EXISTS", err.to_string())
+                    .with_details(serde_json::json!({ "id": id }))
+            }
+            KvError::InvalidModuleId(_) => Self::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_MODULE_ID",
+                err.to_string(),
+            ),
+            KvError::InvalidCursor(_) => {
+                Self::new(StatusCode::BAD_REQUEST, "INVALID_CURSOR", err.to_string())
+            }
+            KvError::InvalidJson(msg) => Self::invalid_json(msg.clone()),
             KvError::InvalidFormat(msg) => Self::invalid_binary_format(msg.clone()),
+            KvError::InvalidValue { .. } => Self::invalid_binary_format(err.to_string()),
             _ => Self::internal(err.to_string()),
         }
     }
@@ -236,6 +407,31 @@ impl From<WasmError> for ApiError {
                 Self::wasm_error(format!("Invalid return type: expected {}", expected))
             }
             WasmError::Trap(msg) => Self::wasm_error(format!("WASM execution error: {}", msg)),
+            WasmError::FuelExhausted { scope, limit } => Self::limit_exceeded(
+                "FUEL_EXHAUSTED",
+                err.to_string(),
+                serde_json::json!({ "scope": scope.to_string(), "limit": limit }),
+            ),
+            WasmError::DeadlineExceeded { scope, limit } => Self::limit_exceeded(
+                "DEADLINE_EXCEEDED",
+                err.to_string(),
+                serde_json::json!({ "scope": scope.to_string(), "limit_ms": limit.as_millis() }),
+            ),
+            WasmError::MemoryLimitExceeded { limit, requested } => Self::limit_exceeded(
+                "MEMORY_LIMIT_EXCEEDED",
+                err.to_string(),
+                serde_json::json!({ "limit": limit, "requested": requested }),
+            ),
+            WasmError::TableLimitExceeded { limit, requested } => Self::limit_exceeded(
+                "TABLE_LIMIT_EXCEEDED",
+                err.to_string(),
+                serde_json::json!({ "limit": limit, "requested": requested }),
+            ),
+            WasmError::Cancelled => Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "JOB_CANCELLED",
+                err.to_string(),
+            ),
             WasmError::TypeMismatch { keyspace_type } => {
                 Self::wasm_error(format!("Type mismatch: {}", keyspace_type))
             }
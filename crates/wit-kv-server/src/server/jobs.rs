@@ -0,0 +1,686 @@
+//! Running map, reduce and migrate jobs off the async executor.
+//!
+//! A synchronous request runs its job with [`run_job`] while the client
+//! waits. Map and reduce jobs can also be started in the background with
+//! [`submit_job`]: each gets a [`JobRecord`], persisted in the database it
+//! runs against and updated as the job moves through its [`JobStatus`]es,
+//! which clients poll by id.
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::{Span, debug, info, warn};
+
+use wit_kv::kv::KvStore;
+
+use super::{error::ApiError, state::AppState};
+
+/// Run `job` on the blocking thread pool, in one of the server's job slots.
+///
+/// Fails with `503 SERVER_BUSY` right away if all slots are taken. `job` is
+/// given a cancel flag that is set if the returned future is dropped before
+/// the job finished, which axum does when the client disconnects. The slot
+/// stays taken until the job has actually stopped.
+pub async fn run_job<T, F>(state: &AppState, job: F) -> Result<T, ApiError>
+where
+    F: FnOnce(Arc<AtomicBool>) -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    let slot = state.try_acquire_job_slot()?;
+    let cancel = Arc::new(AtomicBool::new(false));
+    let mut guard = CancelOnDrop(Some(Arc::clone(&cancel)));
+
+    let span = Span::current();
+    let result = tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let _slot = slot;
+        job(cancel)
+    })
+    .await;
+
+    guard.0 = None;
+    result.map_err(|e| ApiError::internal(format!("Job failed: {}", e)))?
+}
+
+/// Sets the cancel flag of a job when dropped, unless the job has finished.
+struct CancelOnDrop(Option<Arc<AtomicBool>>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(cancel) = &self.0 {
+            debug!("request dropped, cancelling job");
+            cancel.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Kind of a background job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    Map,
+    Reduce,
+}
+
+/// Where a background job is in its life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for a job slot.
+    Queued,
+    /// Running in a job slot.
+    Running,
+    /// Finished; the result is available.
+    Completed,
+    /// Stopped by an error.
+    Failed,
+    /// Stopped on request.
+    Cancelled,
+    /// The server stopped while the job was queued or running.
+    Interrupted,
+}
+
+impl JobStatus {
+    /// Whether the job has stopped, for good.
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+/// Counts of what a job has done so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    /// Number of values processed
+    pub processed: u32,
+    /// Number of values transformed (map jobs only)
+    pub transformed: u32,
+    /// Number of keys that failed
+    pub errors: u32,
+}
+
+/// Progress of a running job, updated by the job as it goes.
+#[derive(Debug, Default)]
+pub struct JobProgress {
+    processed: AtomicU32,
+    transformed: AtomicU32,
+    errors: AtomicU32,
+}
+
+impl JobProgress {
+    /// Record the job's counts so far.
+    pub fn update(&self, processed: u32, transformed: u32, errors: usize) {
+        self.processed.store(processed, Ordering::Relaxed);
+        self.transformed.store(transformed, Ordering::Relaxed);
+        self.errors
+            .store(u32::try_from(errors).unwrap_or(u32::MAX), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Progress {
+        Progress {
+            processed: self.processed.load(Ordering::Relaxed),
+            transformed: self.transformed.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The error that stopped a failed job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobError {
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl From<ApiError> for JobError {
+    fn from(err: ApiError) -> Self {
+        Self {
+            code: err.code.to_string(),
+            message: err.message,
+            details: err.details,
+        }
+    }
+}
+
+/// Record of a background job, persisted as JSON in the job records of the
+/// database it runs against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub kind: JobKind,
+    pub database: String,
+    pub keyspace: String,
+    pub status: JobStatus,
+    /// Unix time in milliseconds at which the job was submitted
+    pub submitted_at: u64,
+    /// Unix time in milliseconds at which the job got a slot
+    pub started_at: Option<u64>,
+    /// Unix time in milliseconds at which the job stopped
+    pub finished_at: Option<u64>,
+    pub progress: Progress,
+    /// Why the job failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<JobError>,
+    /// Result of a completed job, as returned by the synchronous endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+
+impl JobRecord {
+    /// The record without its result, which can be large.
+    pub fn summary(mut self) -> Self {
+        self.result = None;
+        self
+    }
+
+    fn save(&self, store: &KvStore) -> Result<(), ApiError> {
+        let json = serde_json::to_vec(self)
+            .map_err(|e| ApiError::internal(format!("Failed to encode job record: {}", e)))?;
+        store.put_job_record(&self.id, &json)?;
+        Ok(())
+    }
+
+    fn load(bytes: &[u8]) -> Result<Self, ApiError> {
+        serde_json::from_slice(bytes)
+            .map_err(|e| ApiError::internal(format!("Invalid job record: {}", e)))
+    }
+}
+
+/// Background jobs that have not finished yet.
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    active: Arc<Mutex<HashMap<String, Arc<ActiveJob>>>>,
+}
+
+/// A queued or running background job.
+struct ActiveJob {
+    record: Mutex<JobRecord>,
+    progress: JobProgress,
+    cancel: Arc<AtomicBool>,
+    /// Wakes the job if it is cancelled while queued.
+    cancelled: Notify,
+}
+
+impl ActiveJob {
+    fn record(&self) -> JobRecord {
+        let mut record = self
+            .record
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if record.status == JobStatus::Running {
+            record.progress = self.progress.get();
+        }
+        record
+    }
+
+    /// Move the job to `status`, and persist its record.
+    ///
+    /// Persisting syncs the database, so this is only called from the
+    /// blocking thread pool.
+    fn transition(
+        &self,
+        store: &KvStore,
+        status: JobStatus,
+        update: impl FnOnce(&mut JobRecord),
+    ) -> JobRecord {
+        let record = {
+            let mut record = self.record.lock().unwrap_or_else(PoisonError::into_inner);
+            record.status = status;
+            record.progress = self.progress.get();
+            update(&mut record);
+            record.clone()
+        };
+        if let Err(e) = record.save(store) {
+            warn!(job = %record.id, error = %e.message, "failed to persist job record");
+        }
+        record
+    }
+}
+
+impl JobRegistry {
+    /// Stop every queued and running job, so the server can exit.
+    pub fn stop_all(&self) {
+        for job in self.lock().values() {
+            job.cancel.store(true, Ordering::Relaxed);
+            job.cancelled.notify_one();
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<ActiveJob>> {
+        self.lock().get(id).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<ActiveJob>>> {
+        self.active.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Submit a job to run in the background against `keyspace` of `database`.
+///
+/// The job is queued until one of the server's job slots is free, then
+/// `job` runs on the blocking thread pool. It is given a cancel flag, set by
+/// [`cancel_job`], and a [`JobProgress`] to report its counts on. Its result
+/// is kept in the job record. Returns the record of the queued job.
+pub async fn submit_job<F>(
+    state: &AppState,
+    kind: JobKind,
+    database: &str,
+    keyspace: &str,
+    job: F,
+) -> Result<JobRecord, ApiError>
+where
+    F: FnOnce(Arc<AtomicBool>, &JobProgress) -> Result<serde_json::Value, ApiError>
+        + Send
+        + 'static,
+{
+    let record = JobRecord {
+        id: new_job_id(),
+        kind,
+        database: database.to_string(),
+        keyspace: keyspace.to_string(),
+        status: JobStatus::Queued,
+        submitted_at: now_millis(),
+        started_at: None,
+        finished_at: None,
+        progress: Progress::default(),
+        error: None,
+        result: None,
+    };
+    let queued = record.clone();
+    with_store(state, database, move |store| queued.save(store)).await?;
+
+    let active = Arc::new(ActiveJob {
+        record: Mutex::new(record.clone()),
+        progress: JobProgress::default(),
+        cancel: Arc::new(AtomicBool::new(false)),
+        cancelled: Notify::new(),
+    });
+    state
+        .jobs()
+        .lock()
+        .insert(record.id.clone(), Arc::clone(&active));
+    info!(job = %record.id, ?kind, "job queued");
+
+    let state = state.clone();
+    let id = record.id.clone();
+    let database = database.to_string();
+    let span = Span::current();
+    tokio::spawn(async move {
+        let (status, result) = run_queued(&state, &database, &active, span, job).await;
+        let finished = Arc::clone(&active);
+        let saved = with_store(&state, &database, move |store| {
+            finished.transition(store, status, |record| {
+                record.finished_at = Some(now_millis());
+                match result {
+                    Ok(value) => record.result = value,
+                    Err(e) => record.error = Some(e.into()),
+                }
+            });
+            Ok(())
+        })
+        .await;
+        if let Err(e) = saved {
+            warn!(job = %id, error = %e.message, "failed to persist job record");
+        }
+        state.jobs().lock().remove(&id);
+        info!(job = %id, ?status, "job finished");
+    });
+
+    Ok(record)
+}
+
+/// Wait for a slot and run a queued job, returning its final status and
+/// its result or error.
+async fn run_queued<F>(
+    state: &AppState,
+    database: &str,
+    active: &Arc<ActiveJob>,
+    span: Span,
+    job: F,
+) -> (JobStatus, Result<Option<serde_json::Value>, ApiError>)
+where
+    F: FnOnce(Arc<AtomicBool>, &JobProgress) -> Result<serde_json::Value, ApiError>
+        + Send
+        + 'static,
+{
+    let slot = tokio::select! {
+        slot = state.acquire_job_slot() => slot,
+        () = active.cancelled.notified() => None,
+    };
+    let Some(slot) = slot else {
+        return (stopped_status(state), Ok(None));
+    };
+
+    let running = Arc::clone(active);
+    let job_state = state.clone();
+    let database = database.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let _slot = slot;
+        let store = job_state.get_database(&database)?;
+        running.transition(store, JobStatus::Running, |record| {
+            record.started_at = Some(now_millis());
+        });
+        job(Arc::clone(&running.cancel), &running.progress)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Job failed: {}", e)))
+    .and_then(|result| result);
+
+    match result {
+        Ok(value) => (JobStatus::Completed, Ok(Some(value))),
+        Err(_) if active.cancel.load(Ordering::Relaxed) => (stopped_status(state), Ok(None)),
+        Err(e) => (JobStatus::Failed, Err(e)),
+    }
+}
+
+/// Status of a job stopped through its cancel flag: jobs stopped by a
+/// shutdown were not cancelled by anyone.
+fn stopped_status(state: &AppState) -> JobStatus {
+    if state.is_shutting_down() {
+        JobStatus::Interrupted
+    } else {
+        JobStatus::Cancelled
+    }
+}
+
+/// Run `f` against the store of `database` on the blocking thread pool:
+/// writing a job record syncs the database.
+async fn with_store<T, F>(state: &AppState, database: &str, f: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&KvStore) -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    let state = state.clone();
+    let database = database.to_string();
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        f(state.get_database(&database)?)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Job record write failed: {}", e)))?
+}
+
+/// The record of a job, with the progress of a running job up to now.
+pub fn find_job(state: &AppState, id: &str) -> Result<JobRecord, ApiError> {
+    if let Some(active) = state.jobs().get(id) {
+        return Ok(active.record());
+    }
+    for name in state.database_names() {
+        if let Some(bytes) = state.get_database(name)?.get_job_record(id)? {
+            return JobRecord::load(&bytes);
+        }
+    }
+    Err(ApiError::job_not_found(id))
+}
+
+/// Records of every job run against a database, oldest first.
+pub fn list_jobs(state: &AppState, database: &str) -> Result<Vec<JobRecord>, ApiError> {
+    let mut records = Vec::new();
+    for (id, bytes) in state.get_database(database)?.job_records()? {
+        match state.jobs().get(&id) {
+            Some(active) => records.push(active.record()),
+            None => records.push(JobRecord::load(&bytes)?),
+        }
+    }
+    Ok(records)
+}
+
+/// Stop a queued or running job, or remove the record of a finished one.
+///
+/// A running job stops at its next check of the cancel flag, so the
+/// returned record of an active job may still show it running.
+/// Returns `None` once a finished job's record is removed.
+pub async fn cancel_job(state: &AppState, id: &str) -> Result<Option<JobRecord>, ApiError> {
+    if let Some(active) = state.jobs().get(id) {
+        active.cancel.store(true, Ordering::Relaxed);
+        active.cancelled.notify_one();
+        info!(job = %id, "job cancellation requested");
+        return Ok(Some(active.record()));
+    }
+    let record = find_job(state, id)?;
+    let deleted = id.to_string();
+    with_store(state, &record.database, move |store| {
+        Ok(store.delete_job_record(&deleted)?)
+    })
+    .await?;
+    info!(job = %id, "job record deleted");
+    Ok(None)
+}
+
+/// Mark the jobs a previous run of the server left queued or running as
+/// interrupted.
+pub fn interrupt_unfinished_jobs(store: &KvStore) -> Result<usize, ApiError> {
+    let mut interrupted = 0;
+    for (_, bytes) in store.job_records()? {
+        let mut record = JobRecord::load(&bytes)?;
+        if record.status.is_finished() {
+            continue;
+        }
+        record.status = JobStatus::Interrupted;
+        record.finished_at = Some(now_millis());
+        record.save(store)?;
+        interrupted += 1;
+    }
+    Ok(interrupted)
+}
+
+/// A new job id: the submission time followed by random bits, so ids sort
+/// roughly by age.
+fn new_job_id() -> String {
+    // Every RandomState is seeded differently, which is all the randomness needed here
+    let now = now_millis();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(now);
+    format!("{:012x}{:08x}", now, hasher.finish() as u32)
+}
+
+/// Current Unix time in milliseconds.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::server::Config;
+
+    fn state(dir: &std::path::Path) -> AppState {
+        let config = Config::parse(&format!(
+            r#"
+[server]
+bind = "127.0.0.1"
+port = 0
+max_concurrent_jobs = 1
+
+[[databases]]
+name = "default"
+path = "{}"
+"#,
+            dir.join("db").display()
+        ))
+        .unwrap();
+        AppState::from_config(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_busy_when_slots_taken() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let running = tokio::spawn({
+            let state = state.clone();
+            async move {
+                run_job(&state, move |_| {
+                    let _ = started_tx.send(());
+                    let _ = release_rx.recv();
+                    Ok(1)
+                })
+                .await
+            }
+        });
+        started_rx.await.unwrap();
+
+        let err = run_job(&state, |_| Ok(2)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.code, "SERVER_BUSY");
+        assert_eq!(err.retry_after, Some(1));
+
+        release_tx.send(()).unwrap();
+        assert_eq!(running.await.unwrap().unwrap(), 1);
+        assert_eq!(run_job(&state, |_| Ok(3)).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+
+        let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+        let job = run_job(&state, move |cancel| {
+            while !cancel.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let _ = cancel_tx.send(());
+            Ok(())
+        });
+        // Dropping the request future stops the job, which then frees its slot
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), job)
+                .await
+                .is_err()
+        );
+        cancel_rx.await.unwrap();
+        // The slot is freed once the blocking thread has returned
+        let slot = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(slot) = state.try_acquire_job_slot() {
+                    return slot;
+                }
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+        drop(slot);
+        assert!(run_job(&state, |_| Ok(())).await.is_ok());
+    }
+
+    /// Poll a job until it has finished.
+    async fn wait_for(state: &AppState, id: &str) -> JobRecord {
+        loop {
+            let record = find_job(state, id).unwrap();
+            if record.status.is_finished() {
+                return record;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_background_job_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let record = submit_job(
+            &state,
+            JobKind::Map,
+            "default",
+            "users",
+            move |_, progress| {
+                progress.update(2, 1, 1);
+                let _ = release_rx.recv();
+                Ok(serde_json::json!({"processed": 2}))
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(record.status, JobStatus::Queued);
+
+        // Progress shows while the job runs
+        while find_job(&state, &record.id).unwrap().progress.processed == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let running = find_job(&state, &record.id).unwrap();
+        assert_eq!(running.status, JobStatus::Running);
+        assert_eq!(running.progress.errors, 1);
+
+        release_tx.send(()).unwrap();
+        let done = wait_for(&state, &record.id).await;
+        assert_eq!(done.status, JobStatus::Completed);
+        assert_eq!(done.result, Some(serde_json::json!({"processed": 2})));
+        assert!(done.finished_at.is_some());
+        assert_eq!(list_jobs(&state, "default").unwrap().len(), 1);
+
+        // Cancelling a finished job deletes its record
+        assert!(cancel_job(&state, &record.id).await.unwrap().is_none());
+        assert_eq!(
+            find_job(&state, &record.id).unwrap_err().code,
+            "JOB_NOT_FOUND"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_interrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+
+        // The only slot is taken, so the second job stays queued
+        let running = submit_job(&state, JobKind::Reduce, "default", "users", |cancel, _| {
+            while !cancel.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Err(ApiError::from(wit_kv::wasm::WasmError::Cancelled))
+        })
+        .await
+        .unwrap();
+        let queued = submit_job(&state, JobKind::Map, "default", "users", |_, _| {
+            Ok(serde_json::Value::Null)
+        })
+        .await
+        .unwrap();
+
+        assert!(cancel_job(&state, &queued.id).await.unwrap().is_some());
+        assert_eq!(
+            wait_for(&state, &queued.id).await.status,
+            JobStatus::Cancelled
+        );
+        cancel_job(&state, &running.id).await.unwrap();
+        let cancelled = wait_for(&state, &running.id).await;
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        assert!(cancelled.error.is_none());
+
+        // A job left running by a previous server run is interrupted on open
+        let store = state.get_database("default").unwrap();
+        let mut stale = cancelled.clone();
+        stale.id = "stale".to_string();
+        stale.status = JobStatus::Running;
+        stale.save(store).unwrap();
+        assert_eq!(interrupt_unfinished_jobs(store).unwrap(), 1);
+        assert_eq!(
+            find_job(&state, "stale").unwrap().status,
+            JobStatus::Interrupted
+        );
+        assert_eq!(interrupt_unfinished_jobs(store).unwrap(), 0);
+    }
+}
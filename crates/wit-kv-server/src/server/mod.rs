@@ -7,6 +7,7 @@
 mod config;
 mod content;
 mod error;
+mod jobs;
 mod logging;
 mod routes;
 mod state;
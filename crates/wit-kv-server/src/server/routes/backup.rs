@@ -0,0 +1,88 @@
+//! Backup handlers.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::Serialize;
+use tracing::{Span, debug, info, instrument};
+
+use super::super::{error::ApiError, state::AppState};
+
+/// Result of a backup.
+#[derive(Debug, Serialize)]
+pub struct BackupResult {
+    /// Path of the archive on the server
+    pub path: String,
+    /// Store revision the archive was taken at
+    pub revision: u64,
+    /// Unix time in milliseconds at which the archive was taken
+    pub created_at: u64,
+    /// Number of keyspaces in the archive
+    pub keyspaces: usize,
+    /// Number of values in the archive
+    pub values: u64,
+}
+
+/// Write a backup archive of a database to its configured `backup_dir`.
+///
+/// The archive is named `{database}-{unix_ms}.wkvb` and is taken from a
+/// consistent snapshot while the database keeps serving reads and writes
+/// (see [`wit_kv::kv::KvStore::snapshot_to`]). Returns `403 BACKUP_DISABLED`
+/// if the database has no `backup_dir`.
+#[instrument(skip(state), fields(database = %database))]
+pub async fn create_backup(
+    State(state): State<AppState>,
+    Path(database): Path<String>,
+) -> Result<(StatusCode, Json<BackupResult>), ApiError> {
+    state.get_database(&database)?;
+    let dir = state.backup_dir(&database).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::FORBIDDEN,
+            "BACKUP_DISABLED",
+            format!("No backup_dir is configured for database '{}'", database),
+        )
+    })?;
+    std::fs::create_dir_all(dir)
+        .map_err(|e| ApiError::internal(format!("Failed to create backup directory: {}", e)))?;
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let path = dir.join(format!("{}-{}.wkvb", database, millis));
+    debug!(path = %path.display(), "starting backup");
+
+    // Reading every value blocks on disk, so it runs off the async executor
+    let span = Span::current();
+    let archive = path.clone();
+    let backup = tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        state
+            .get_database(&database)?
+            .snapshot_to(&archive)
+            .map_err(ApiError::from)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Backup task failed: {}", e)))??;
+
+    info!(
+        path = %path.display(),
+        revision = backup.revision,
+        values = backup.values,
+        "backup written"
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(BackupResult {
+            path: path.display().to_string(),
+            revision: backup.revision,
+            created_at: backup.created_at,
+            keyspaces: backup.keyspaces,
+            values: backup.values,
+        }),
+    ))
+}
@@ -0,0 +1,118 @@
+//! Atomic batch write handlers.
+
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, instrument};
+
+use super::super::{error::ApiError, state::AppState};
+
+/// A batch of write operations, applied atomically.
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+/// A single write operation in a batch.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOperation {
+    /// Set a WAVE-encoded value
+    Set {
+        keyspace: String,
+        key: String,
+        value: String,
+        /// Only write if the key currently has this revision
+        #[serde(default)]
+        if_revision: Option<u64>,
+        /// Only write if the key does not exist
+        #[serde(default)]
+        if_absent: bool,
+        /// Expire the value after this many seconds instead of the keyspace default
+        #[serde(default)]
+        ttl: Option<u64>,
+    },
+    /// Delete a key
+    Delete {
+        keyspace: String,
+        key: String,
+        /// Only delete if the key currently has this revision
+        #[serde(default)]
+        if_revision: Option<u64>,
+    },
+}
+
+/// Result of a batch write.
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    /// Number of operations in the request
+    pub operations: usize,
+    /// Number of distinct keys written (later operations on a key replace earlier ones)
+    pub written: usize,
+    /// Revision assigned to every written value
+    pub revision: u64,
+}
+
+/// Apply a batch of writes, possibly across several keyspaces.
+///
+/// Every value is type-checked before anything is written; either all
+/// operations are committed (with a single fsync) or none is. Errors carry
+/// the index of the failing operation in `details.operation`; a failed
+/// `if_revision` or `if_absent` condition returns 412 and writes nothing.
+#[instrument(skip(state, request), fields(database = %database, operations = request.operations.len()))]
+pub async fn write_batch(
+    State(state): State<AppState>,
+    Path(database): Path<String>,
+    Json(request): Json<BatchRequest>,
+) -> Result<Json<BatchResult>, ApiError> {
+    debug!("applying batch");
+
+    let store = state.get_database(&database)?;
+    let operations = request.operations.len();
+
+    let mut batch = store.batch();
+    for (index, operation) in request.operations.iter().enumerate() {
+        let result = match operation {
+            BatchOperation::Set {
+                keyspace,
+                key,
+                value,
+                if_revision,
+                if_absent,
+                ttl,
+            } => match if_revision {
+                Some(expected) => batch.set_if(keyspace, key, value, *expected),
+                None if *if_absent => batch.set_if_absent(keyspace, key, value),
+                None => batch.set(keyspace, key, value),
+            }
+            .and_then(|()| match ttl {
+                Some(ttl) => batch.set_ttl(keyspace, key, Some(Duration::from_secs(*ttl))),
+                None => Ok(()),
+            }),
+            BatchOperation::Delete {
+                keyspace,
+                key,
+                if_revision,
+            } => match if_revision {
+                Some(expected) => batch.delete_if(keyspace, key, *expected),
+                None => batch.delete(keyspace, key),
+            },
+        };
+        result.map_err(|e| {
+            ApiError::from(e).with_details(serde_json::json!({ "operation": index }))
+        })?;
+    }
+    let written = batch.len();
+    let revision = batch.commit()?;
+
+    info!(written, revision, "batch committed");
+    Ok(Json(BatchResult {
+        operations,
+        written,
+        revision,
+    }))
+}
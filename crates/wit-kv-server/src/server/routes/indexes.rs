@@ -0,0 +1,107 @@
+//! Secondary index handlers.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, instrument};
+
+use wit_kv::kv::KeyList;
+
+use super::super::{
+    content::{AcceptFormat, ContentFormat, FormatResponse},
+    error::ApiError,
+    state::AppState,
+};
+
+/// Query parameters for looking up keys by index.
+#[derive(Debug, Deserialize)]
+pub struct LookupQuery {
+    /// WAVE-encoded field value, e.g. `"alice@example.com"` for a string field
+    pub value: String,
+}
+
+/// Indexed fields of a keyspace.
+#[derive(Debug, Serialize)]
+pub struct IndexListResponse {
+    pub fields: Vec<String>,
+}
+
+/// List the indexed fields of a keyspace.
+#[instrument(skip(state), fields(database = %database, keyspace = %keyspace))]
+pub async fn list_indexes(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+) -> Result<Json<IndexListResponse>, ApiError> {
+    debug!("listing indexes");
+
+    let store = state.get_database(&database)?;
+    let fields = store.list_indexes(&keyspace)?;
+
+    info!(count = fields.len(), "listed indexes");
+    Ok(Json(IndexListResponse { fields }))
+}
+
+/// Create an index on a field, indexing the existing values.
+#[instrument(skip(state), fields(database = %database, keyspace = %keyspace, field = %field))]
+pub async fn create_index(
+    State(state): State<AppState>,
+    Path((database, keyspace, field)): Path<(String, String, String)>,
+) -> Result<StatusCode, ApiError> {
+    debug!("creating index");
+
+    let store = state.get_database(&database)?;
+    store.create_index(&keyspace, &field)?;
+
+    info!("index created");
+    Ok(StatusCode::CREATED)
+}
+
+/// Drop an index.
+#[instrument(skip(state), fields(database = %database, keyspace = %keyspace, field = %field))]
+pub async fn drop_index(
+    State(state): State<AppState>,
+    Path((database, keyspace, field)): Path<(String, String, String)>,
+) -> Result<StatusCode, ApiError> {
+    debug!("dropping index");
+
+    let store = state.get_database(&database)?;
+    store.drop_index(&keyspace, &field)?;
+
+    info!("index dropped");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List the keys whose indexed field equals the `value` query parameter.
+#[instrument(skip(state, query, format), fields(database = %database, keyspace = %keyspace, field = %field))]
+pub async fn lookup(
+    State(state): State<AppState>,
+    Path((database, keyspace, field)): Path<(String, String, String)>,
+    Query(query): Query<LookupQuery>,
+    AcceptFormat(format): AcceptFormat,
+) -> Result<Response, ApiError> {
+    debug!(value = %query.value, "looking up by index");
+
+    let store = state.get_database(&database)?;
+    let key_list = KeyList::new(store.lookup_by_index(&keyspace, &field, &query.value)?);
+
+    info!(count = key_list.keys.len(), "looked up by index");
+
+    match format {
+        ContentFormat::Wave => Ok(FormatResponse::wave(key_list.to_wave()).into_response()),
+        ContentFormat::Json => Ok(FormatResponse::json(&key_list.to_json()?).into_response()),
+        ContentFormat::Binary => {
+            let (buffer, memory) = key_list
+                .encode()
+                .map_err(|e| ApiError::internal(e.to_string()))?;
+
+            let mut bytes = buffer;
+            bytes.extend(memory);
+
+            Ok(FormatResponse::binary(bytes).into_response())
+        }
+    }
+}
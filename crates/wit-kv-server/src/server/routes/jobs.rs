@@ -0,0 +1,173 @@
+//! Background map/reduce job handlers.
+
+use axum::{
+    Json,
+    extract::{Multipart, Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, instrument};
+
+use super::super::{
+    error::ApiError,
+    jobs::{self, JobKind, JobStatus},
+    state::AppState,
+};
+use super::mapreduce::{MapConfig, ReduceConfig, extract_module_multipart, run_map, run_reduce};
+
+/// Query parameters for submitting a job.
+#[derive(Debug, Deserialize)]
+pub struct SubmitQuery {
+    /// `map` or `reduce`
+    pub kind: JobKind,
+    /// Keyspace the job runs over
+    pub keyspace: String,
+}
+
+/// Submit a map or reduce job to run in the background.
+///
+/// Takes the same multipart/form-data body as the synchronous map and reduce
+/// endpoints, with the kind of job and its keyspace as query parameters.
+/// Responds `202 Accepted` with the job record, whose `id` is then polled
+/// at `/api/v1/jobs/{id}`. The job waits for a job slot rather than being
+/// refused while the server is busy.
+#[instrument(skip(state, query, multipart), fields(database = %database, kind = ?query.kind, keyspace = %query.keyspace))]
+pub async fn submit_job(
+    State(state): State<AppState>,
+    Path(database): Path<String>,
+    Query(query): Query<SubmitQuery>,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    debug!("submitting job");
+
+    let store = state.get_database(&database)?;
+    if store.get_type(&query.keyspace)?.is_none() {
+        return Err(ApiError::keyspace_not_found(&database, &query.keyspace));
+    }
+
+    let job_state = state.clone();
+    let db = database.clone();
+    let keyspace = query.keyspace.clone();
+    let record = match query.kind {
+        JobKind::Map => {
+            let (module_bytes, config): (_, MapConfig) =
+                extract_module_multipart(&mut multipart).await?;
+            if let Some(output_keyspace) = &config.output_keyspace
+                && store.get_type(output_keyspace)?.is_none()
+            {
+                return Err(ApiError::keyspace_not_found(&database, output_keyspace));
+            }
+            jobs::submit_job(
+                &state,
+                query.kind,
+                &database,
+                &query.keyspace,
+                move |cancel, progress| {
+                    let result = run_map(
+                        &job_state,
+                        &db,
+                        &keyspace,
+                        module_bytes,
+                        &config,
+                        cancel,
+                        progress,
+                    )?;
+                    Ok(json!(result))
+                },
+            )
+            .await?
+        }
+        JobKind::Reduce => {
+            let (module_bytes, config): (_, ReduceConfig) =
+                extract_module_multipart(&mut multipart).await?;
+            jobs::submit_job(
+                &state,
+                query.kind,
+                &database,
+                &query.keyspace,
+                move |cancel, progress| {
+                    let result = run_reduce(
+                        &job_state,
+                        &db,
+                        &keyspace,
+                        module_bytes,
+                        &config,
+                        cancel,
+                        progress,
+                    )?;
+                    Ok(json!(result))
+                },
+            )
+            .await?
+        }
+    };
+
+    info!(job = %record.id, "job submitted");
+    let location = format!("/api/v1/jobs/{}", record.id);
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, location)],
+        Json(record),
+    )
+        .into_response())
+}
+
+/// List the jobs of a database, oldest first, without their results.
+#[instrument(skip(state), fields(database = %database))]
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Path(database): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let records: Vec<_> = jobs::list_jobs(&state, &database)?
+        .into_iter()
+        .map(jobs::JobRecord::summary)
+        .collect();
+    debug!(count = records.len(), "listed jobs");
+    Ok(Json(json!({ "jobs": records })))
+}
+
+/// Get the status and progress of a job.
+#[instrument(skip(state))]
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<jobs::JobRecord>, ApiError> {
+    let record = jobs::find_job(&state, &id)?;
+    debug!(status = ?record.status, "job found");
+    Ok(Json(record.summary()))
+}
+
+/// Get the result of a completed job.
+///
+/// The result is what the synchronous map or reduce endpoint would have
+/// returned. Returns `409 JOB_NOT_COMPLETED` for jobs that are still
+/// running or stopped without a result.
+#[instrument(skip(state))]
+pub async fn get_job_result(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let record = jobs::find_job(&state, &id)?;
+    match (record.status, record.result) {
+        (JobStatus::Completed, Some(result)) => Ok(Json(result)),
+        (status, _) => Err(ApiError::job_not_completed(&id, status)),
+    }
+}
+
+/// Cancel a queued or running job, or delete the record of a finished one.
+///
+/// Cancelling responds `202 Accepted` with the job record: the job stops
+/// at its next check, after which its status is `cancelled`. Deleting a
+/// finished job responds `204 No Content`.
+#[instrument(skip(state))]
+pub async fn cancel_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    match jobs::cancel_job(&state, &id).await? {
+        Some(record) => Ok((StatusCode::ACCEPTED, Json(record.summary())).into_response()),
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
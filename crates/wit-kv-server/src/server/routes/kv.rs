@@ -1,15 +1,17 @@
 //! Key-value operation handlers.
 
+use std::time::Duration;
+
 use axum::{
     body::Bytes,
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, HeaderName, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde::Deserialize;
 use tracing::{debug, info, instrument};
 
-use wit_kv::kv::{BinaryExport, KeyList};
+use wit_kv::kv::BinaryExport;
 
 use super::super::{
     content::{AcceptFormat, ContentFormat, FormatResponse, RequestFormat},
@@ -17,16 +19,148 @@ use super::super::{
     state::AppState,
 };
 
+/// Time-to-live of a written value in seconds.
+const TTL: HeaderName = HeaderName::from_static("x-ttl");
+
 /// Query parameters for listing keys.
 #[derive(Debug, Deserialize, Default)]
 pub struct ListQuery {
     pub prefix: Option<String>,
     pub start: Option<String>,
     pub end: Option<String>,
+    /// `next-cursor` of the previous page
+    pub cursor: Option<String>,
     pub limit: Option<usize>,
 }
 
+/// Query parameters for getting a value.
+#[derive(Debug, Deserialize, Default)]
+pub struct GetQuery {
+    /// Comma-separated field paths to return instead of the whole value
+    pub fields: Option<String>,
+}
+
+/// Precondition on a key's current state, from `If-Match`/`If-None-Match`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WritePrecondition {
+    /// No precondition headers
+    None,
+    /// `If-Match: "<revision>"`
+    Revision(u64),
+    /// `If-Match: *`
+    Exists,
+    /// `If-None-Match: *`
+    Absent,
+}
+
+impl WritePrecondition {
+    /// Parse the precondition headers of a write request.
+    ///
+    /// Only a single strong ETag or `*` in `If-Match` and `*` in
+    /// `If-None-Match` are supported; anything else is rejected rather than
+    /// silently ignored.
+    fn from_headers(headers: &HeaderMap) -> Result<Self, ApiError> {
+        let if_match = header_str(headers, header::IF_MATCH)?;
+        let if_none_match = header_str(headers, header::IF_NONE_MATCH)?;
+        match (if_match, if_none_match) {
+            (None, None) => Ok(Self::None),
+            (Some(value), None) if value.trim() == "*" => Ok(Self::Exists),
+            (Some(value), None) => parse_etag(value).map(Self::Revision).ok_or_else(|| {
+                invalid_precondition("If-Match must be * or a single ETag like \"42\"")
+            }),
+            (None, Some("*")) => Ok(Self::Absent),
+            (None, Some(_)) => Err(invalid_precondition(
+                "If-None-Match only supports * on writes",
+            )),
+            (Some(_), Some(_)) => Err(invalid_precondition(
+                "If-Match and If-None-Match cannot be combined",
+            )),
+        }
+    }
+}
+
+/// Format a revision as an ETag header value.
+fn etag(revision: u64) -> String {
+    format!("\"{}\"", revision)
+}
+
+/// Format a revision as a weak ETag, for representations (such as
+/// projections) that do not carry the whole value.
+fn weak_etag(revision: u64) -> String {
+    format!("W/{}", etag(revision))
+}
+
+/// Parse an ETag produced by [`etag`] or [`weak_etag`], ignoring weakness
+/// as the weak comparison of `If-None-Match` does.
+fn parse_etag_weak(value: &str) -> Option<u64> {
+    let value = value.trim();
+    parse_etag(value.strip_prefix("W/").unwrap_or(value))
+}
+
+/// Headers of a value representation: its ETag, weak for projections, and
+/// `Vary: Accept` since the same URL is served in several formats.
+fn representation_headers(revision: u64, projected: bool) -> [(HeaderName, String); 2] {
+    let tag = if projected {
+        weak_etag(revision)
+    } else {
+        etag(revision)
+    };
+    [
+        (header::ETAG, tag),
+        (header::VARY, header::ACCEPT.to_string()),
+    ]
+}
+
+/// Parse a strong ETag produced by [`etag`].
+fn parse_etag(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')?
+        .parse()
+        .ok()
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Result<Option<&str>, ApiError> {
+    headers
+        .get(&name)
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| invalid_precondition(format!("{} is not valid ASCII", name)))
+        })
+        .transpose()
+}
+
+fn invalid_precondition(message: impl Into<String>) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, "INVALID_PRECONDITION", message)
+}
+
+/// Parse the `X-TTL` header of a write request.
+fn ttl_from_headers(headers: &HeaderMap) -> Result<Option<Duration>, ApiError> {
+    headers
+        .get(&TTL)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs)
+                .ok_or_else(|| {
+                    ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "INVALID_TTL",
+                        "X-TTL must be a whole number of seconds",
+                    )
+                })
+        })
+        .transpose()
+}
+
 /// List keys in a keyspace.
+///
+/// A page that stopped at `limit` carries a `next-cursor`; pass it back as
+/// `cursor`, with the same filters, to get the next page.
 #[instrument(skip(state, format), fields(database = %database, keyspace = %keyspace))]
 pub async fn list_keys(
     State(state): State<AppState>,
@@ -38,27 +172,31 @@ pub async fn list_keys(
         prefix = query.prefix.as_deref(),
         start = query.start.as_deref(),
         end = query.end.as_deref(),
+        cursor = query.cursor.as_deref(),
         limit = query.limit,
         "listing keys"
     );
 
     let store = state.get_database(&database)?;
 
-    let keys = store.list(
+    let key_list = store.list(
         &keyspace,
         query.prefix.as_deref(),
         query.start.as_deref(),
         query.end.as_deref(),
+        query.cursor.as_deref(),
         query.limit,
     )?;
 
-    let count = keys.len();
-    let key_list = KeyList::new(keys);
-
-    info!(count, "listed keys");
+    info!(
+        count = key_list.keys.len(),
+        more = key_list.next_cursor.is_some(),
+        "listed keys"
+    );
 
     match format {
         ContentFormat::Wave => Ok(FormatResponse::wave(key_list.to_wave()).into_response()),
+        ContentFormat::Json => Ok(FormatResponse::json(&key_list.to_json()?).into_response()),
         ContentFormat::Binary => {
             let (buffer, memory) = key_list
                 .encode()
@@ -73,28 +211,95 @@ pub async fn list_keys(
 }
 
 /// Get a value from the store.
-#[instrument(skip(state, format), fields(database = %database, keyspace = %keyspace, key = %key))]
+///
+/// The response carries the value's revision as its `ETag`, and `Vary:
+/// Accept` since every format is served from the same URL. A matching
+/// `If-None-Match`, compared weakly, returns 304 Not Modified. With `fields`,
+/// only those fields are returned, as a record synthesized from the keyspace
+/// type (see [`KvStore::get_projected`](wit_kv::kv::KvStore::get_projected)),
+/// with a weak `ETag` since it is not the whole value.
+#[instrument(skip(state, query, format, headers), fields(database = %database, keyspace = %keyspace, key = %key))]
 pub async fn get_value(
     State(state): State<AppState>,
     Path((database, keyspace, key)): Path<(String, String, String)>,
+    Query(query): Query<GetQuery>,
     AcceptFormat(format): AcceptFormat,
+    headers: HeaderMap,
 ) -> Result<Response, ApiError> {
-    debug!("getting value");
+    debug!(fields = query.fields.as_deref(), "getting value");
+
+    let fields = query.fields.as_deref().map(field_paths);
+    let projected = fields.is_some();
 
     let store = state.get_database(&database)?;
+    let if_none_match = header_str(&headers, header::IF_NONE_MATCH)?;
+    let not_modified = |revision: u64| {
+        if_none_match.is_some_and(|value| {
+            value == "*"
+                || value
+                    .split(',')
+                    .any(|tag| parse_etag_weak(tag) == Some(revision))
+        })
+    };
 
     match format {
         ContentFormat::Wave => {
-            let value = store
-                .get(&keyspace, &key)?
-                .ok_or_else(|| ApiError::key_not_found(&database, &keyspace, &key))?;
-            info!("retrieved value");
-            Ok(FormatResponse::wave(value).into_response())
+            let value = match &fields {
+                Some(fields) => store.get_projected_with_revision(&keyspace, &key, fields)?,
+                None => store.get_with_revision(&keyspace, &key)?,
+            };
+            let (value, revision) =
+                value.ok_or_else(|| ApiError::key_not_found(&database, &keyspace, &key))?;
+            if not_modified(revision) {
+                return Ok((
+                    StatusCode::NOT_MODIFIED,
+                    representation_headers(revision, projected),
+                )
+                    .into_response());
+            }
+            info!(revision, "retrieved value");
+            Ok((
+                representation_headers(revision, projected),
+                FormatResponse::wave(value),
+            )
+                .into_response())
+        }
+        ContentFormat::Json => {
+            let value = match &fields {
+                Some(fields) => store.get_projected_json_with_revision(&keyspace, &key, fields)?,
+                None => store.get_json_with_revision(&keyspace, &key)?,
+            };
+            let (value, revision) =
+                value.ok_or_else(|| ApiError::key_not_found(&database, &keyspace, &key))?;
+            if not_modified(revision) {
+                return Ok((
+                    StatusCode::NOT_MODIFIED,
+                    representation_headers(revision, projected),
+                )
+                    .into_response());
+            }
+            info!(revision, "retrieved value (json)");
+            Ok((
+                representation_headers(revision, projected),
+                FormatResponse::json(&value),
+            )
+                .into_response())
         }
         ContentFormat::Binary => {
-            let stored = store
-                .get_raw(&keyspace, &key)?
-                .ok_or_else(|| ApiError::key_not_found(&database, &keyspace, &key))?;
+            let stored = match &fields {
+                Some(fields) => store.get_raw_projected(&keyspace, &key, fields)?,
+                None => store.get_raw(&keyspace, &key)?,
+            };
+            let stored =
+                stored.ok_or_else(|| ApiError::key_not_found(&database, &keyspace, &key))?;
+            let revision = stored.revision;
+            if not_modified(revision) {
+                return Ok((
+                    StatusCode::NOT_MODIFIED,
+                    representation_headers(revision, projected),
+                )
+                    .into_response());
+            }
 
             let export = BinaryExport::from_stored(&stored);
             let (buffer, memory) = export
@@ -105,57 +310,304 @@ pub async fn get_value(
             let mut bytes = buffer;
             bytes.extend(memory);
 
-            info!("retrieved value (binary)");
-            Ok(FormatResponse::binary(bytes).into_response())
+            info!(revision, "retrieved value (binary)");
+            Ok((
+                representation_headers(revision, projected),
+                FormatResponse::binary(bytes),
+            )
+                .into_response())
         }
     }
 }
 
 /// Set a value in the store.
-#[instrument(skip(state, format, body), fields(database = %database, keyspace = %keyspace, key = %key, body_len = body.len()))]
+///
+/// Binary bodies use the `binary-export` encoding returned by a binary GET and
+/// are validated against the keyspace type before they are stored.
+///
+/// `If-Match: "<revision>"` only writes if the key has that revision,
+/// `If-Match: *` only if the key exists and `If-None-Match: *` only if it
+/// does not; otherwise 412 Precondition Failed is returned. The new revision is returned as `ETag`.
+///
+/// `X-TTL: <seconds>` makes the value expire after that many seconds, in
+/// place of the keyspace's default time-to-live.
+#[instrument(skip(state, format, headers, body), fields(database = %database, keyspace = %keyspace, key = %key, body_len = body.len()))]
 pub async fn set_value(
     State(state): State<AppState>,
     Path((database, keyspace, key)): Path<(String, String, String)>,
     RequestFormat(format): RequestFormat,
+    headers: HeaderMap,
     body: Bytes,
-) -> Result<StatusCode, ApiError> {
+) -> Result<Response, ApiError> {
     debug!("setting value");
 
     let store = state.get_database(&database)?;
+    let precondition = WritePrecondition::from_headers(&headers)?;
+    let ttl = ttl_from_headers(&headers)?;
 
+    let mut batch = store.batch();
     match format {
         ContentFormat::Wave => {
             let wave_str = std::str::from_utf8(&body)
                 .map_err(|e| ApiError::invalid_wave_format(format!("Invalid UTF-8: {}", e)))?;
-            store.set(&keyspace, &key, wave_str)?;
+            match precondition {
+                WritePrecondition::None => batch.set(&keyspace, &key, wave_str)?,
+                WritePrecondition::Revision(expected) => {
+                    batch.set_if(&keyspace, &key, wave_str, expected)?
+                }
+                WritePrecondition::Exists => batch.set_if_exists(&keyspace, &key, wave_str)?,
+                WritePrecondition::Absent => batch.set_if_absent(&keyspace, &key, wave_str)?,
+            }
+        }
+        ContentFormat::Json => {
+            let json: serde_json::Value = serde_json::from_slice(&body)
+                .map_err(|e| ApiError::invalid_json(format!("Invalid JSON: {}", e)))?;
+            match precondition {
+                WritePrecondition::None => batch.set_json(&keyspace, &key, &json)?,
+                WritePrecondition::Revision(expected) => {
+                    batch.set_json_if(&keyspace, &key, &json, expected)?
+                }
+                WritePrecondition::Exists => batch.set_json_if_exists(&keyspace, &key, &json)?,
+                WritePrecondition::Absent => batch.set_json_if_absent(&keyspace, &key, &json)?,
+            }
         }
         ContentFormat::Binary => {
-            // Decode the binary export and set via raw API
-            // For now, we require Wave format for setting values
-            // Binary set would require a way to set raw StoredValue
-            return Err(ApiError::new(
-                StatusCode::NOT_IMPLEMENTED,
-                "BINARY_SET_NOT_IMPLEMENTED",
-                "Setting values via binary format is not yet implemented",
-            ));
+            // Same binary-export encoding that binary GET returns
+            let export = BinaryExport::decode_from_bytes(&body)
+                .map_err(|e| ApiError::invalid_binary_format(e.to_string()))?;
+            match precondition {
+                WritePrecondition::None => batch.set_raw(&keyspace, &key, &export)?,
+                WritePrecondition::Revision(expected) => {
+                    batch.set_raw_if(&keyspace, &key, &export, expected)?
+                }
+                WritePrecondition::Exists => batch.set_raw_if_exists(&keyspace, &key, &export)?,
+                WritePrecondition::Absent => batch.set_raw_if_absent(&keyspace, &key, &export)?,
+            }
         }
     }
+    if let Some(ttl) = ttl {
+        batch.set_ttl(&keyspace, &key, Some(ttl))?;
+    }
+    let revision = batch.commit()?;
 
-    info!("value set");
-    Ok(StatusCode::NO_CONTENT)
+    info!(
+        revision,
+        ttl_secs = ttl.map(|ttl| ttl.as_secs()),
+        "value set"
+    );
+    Ok((StatusCode::NO_CONTENT, [(header::ETAG, etag(revision))]).into_response())
 }
 
 /// Delete a value from the store.
-#[instrument(skip(state), fields(database = %database, keyspace = %keyspace, key = %key))]
+///
+/// `If-Match: "<revision>"` only deletes if the key has that revision, and
+/// `If-Match: *` only if it exists; otherwise 412 Precondition Failed is
+/// returned.
+#[instrument(skip(state, headers), fields(database = %database, keyspace = %keyspace, key = %key))]
 pub async fn delete_value(
     State(state): State<AppState>,
     Path((database, keyspace, key)): Path<(String, String, String)>,
+    headers: HeaderMap,
 ) -> Result<StatusCode, ApiError> {
     debug!("deleting value");
 
     let store = state.get_database(&database)?;
-    store.delete(&keyspace, &key)?;
+    match WritePrecondition::from_headers(&headers)? {
+        WritePrecondition::None => store.delete(&keyspace, &key)?,
+        WritePrecondition::Revision(expected) => store.delete_if(&keyspace, &key, expected)?,
+        WritePrecondition::Exists => {
+            let mut batch = store.batch();
+            batch.delete_if_exists(&keyspace, &key)?;
+            batch.commit()?;
+        }
+        WritePrecondition::Absent => {
+            return Err(invalid_precondition(
+                "If-None-Match is not supported on DELETE",
+            ));
+        }
+    }
 
     info!("value deleted");
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Split a comma-separated `fields` parameter into field paths.
+pub(super) fn field_paths(fields: &str) -> Vec<String> {
+    fields
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::server::Config;
+
+    fn state(dir: &std::path::Path) -> AppState {
+        let config = Config::parse(&format!(
+            r#"
+[server]
+bind = "127.0.0.1"
+port = 0
+
+[[databases]]
+name = "default"
+path = "{}"
+"#,
+            dir.join("db").display()
+        ))
+        .unwrap();
+        let state = AppState::from_config(&config).unwrap();
+
+        let wit = dir.join("point.wit");
+        std::fs::write(
+            &wit,
+            "package test:points;\ninterface types { record point { x: s32, y: s32 } }\n",
+        )
+        .unwrap();
+        state
+            .get_database("default")
+            .unwrap()
+            .set_type("points", &wit, Some("point"), false, false)
+            .unwrap();
+        state
+    }
+
+    async fn put(
+        state: &AppState,
+        key: &str,
+        value: &'static str,
+        condition: Option<(HeaderName, &'static str)>,
+    ) -> Result<Response, ApiError> {
+        let mut headers = HeaderMap::new();
+        if let Some((name, value)) = condition {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+        set_value(
+            State(state.clone()),
+            Path(("default".to_string(), "points".to_string(), key.to_string())),
+            RequestFormat(ContentFormat::Wave),
+            headers,
+            Bytes::from_static(value.as_bytes()),
+        )
+        .await
+    }
+
+    fn returned_etag(response: &Response) -> String {
+        response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_conditional_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+
+        let response = put(&state, "p1", "{x: 1, y: 2}", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let etag = returned_etag(&response);
+        let (_, revision) = state
+            .get_database("default")
+            .unwrap()
+            .get_with_revision("points", "p1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(etag, format!("\"{revision}\""));
+
+        let err = put(
+            &state,
+            "p1",
+            "{x: 0, y: 0}",
+            Some((header::IF_NONE_MATCH, "*")),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status, StatusCode::PRECONDITION_FAILED);
+
+        let err = put(
+            &state,
+            "p1",
+            "{x: 0, y: 0}",
+            Some((header::IF_MATCH, "\"999999\"")),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(err.code, "PRECONDITION_FAILED");
+
+        let err = put(&state, "p2", "{x: 0, y: 0}", Some((header::IF_MATCH, "*")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::PRECONDITION_FAILED);
+
+        let response = put(&state, "p1", "{x: 3, y: 4}", Some((header::IF_MATCH, "*")))
+            .await
+            .unwrap();
+        assert_ne!(returned_etag(&response), etag);
+        assert_eq!(
+            state
+                .get_database("default")
+                .unwrap()
+                .get("points", "p1")
+                .unwrap()
+                .as_deref(),
+            Some("{x: 3, y: 4}")
+        );
+    }
+    #[tokio::test]
+    async fn test_get_etags_per_representation() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let revision = state
+            .get_database("default")
+            .unwrap()
+            .set("points", "p1", "{x: 1, y: 2}")
+            .unwrap();
+
+        let get = |fields: Option<&str>, if_none_match: Option<String>| {
+            let mut headers = HeaderMap::new();
+            if let Some(tag) = if_none_match {
+                headers.insert(header::IF_NONE_MATCH, tag.parse().unwrap());
+            }
+            get_value(
+                State(state.clone()),
+                Path((
+                    "default".to_string(),
+                    "points".to_string(),
+                    "p1".to_string(),
+                )),
+                Query(GetQuery {
+                    fields: fields.map(str::to_string),
+                }),
+                AcceptFormat(ContentFormat::Json),
+                headers,
+            )
+        };
+
+        let full = get(None, None).await.unwrap();
+        assert_eq!(returned_etag(&full), format!("\"{revision}\""));
+        assert_eq!(full.headers()[header::VARY], "accept");
+
+        let projected = get(Some("x"), None).await.unwrap();
+        assert_eq!(returned_etag(&projected), format!("W/\"{revision}\""));
+        assert_eq!(projected.headers()[header::VARY], "accept");
+
+        // If-None-Match compares weakly, whichever form it was given
+        for tag in [returned_etag(&full), returned_etag(&projected)] {
+            let response = get(Some("x"), Some(tag.clone())).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            let response = get(None, Some(tag)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        }
+        let response = get(None, Some("W/\"0\"".to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
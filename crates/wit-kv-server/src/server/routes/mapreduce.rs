@@ -1,40 +1,61 @@
 //! Map/reduce operation handlers.
 
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
 use axum::{
     Json,
     extract::{Multipart, Path, State},
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use tracing::{debug, info, instrument, warn};
 
-use wit_kv::kv::KvStore;
-use wit_kv::wasm::TypedRunner;
+use wit_kv::kv::{DEFAULT_MAP_BATCH_SIZE, KvError, KvStore, ModuleKind};
+use wit_kv::load_wit_type_from_string;
+use wit_kv::wasm::{ComponentCache, TypedRunner};
 
-use super::super::{error::ApiError, state::AppState};
+use super::super::{
+    error::ApiError,
+    jobs::{JobProgress, run_job},
+    state::AppState,
+};
 
 /// JSON config for map operation (sent in multipart 'config' field).
+///
+/// With `module` set, the registered mapper of that id runs instead of an
+/// uploaded one, and the types default to those it was registered with.
 #[derive(Debug, Deserialize)]
 pub struct MapConfig {
+    /// Id of a registered mapper to run instead of an uploaded module
+    pub module: Option<String>,
     /// WIT definition text for the module's types
-    pub wit_definition: String,
+    pub wit_definition: Option<String>,
     /// Name of the input type in the WIT definition
-    pub input_type: String,
+    pub input_type: Option<String>,
     /// Name of the output type (defaults to input_type if not specified)
     pub output_type: Option<String>,
+    /// Keyspace to write the transformed values into, under their keys,
+    /// instead of returning them
+    pub output_keyspace: Option<String>,
     /// Optional key filters
     #[serde(default)]
     pub filter: KeyFilter,
 }
 
 /// JSON config for reduce operation (sent in multipart 'config' field).
+///
+/// With `module` set, the registered reducer of that id runs instead of an
+/// uploaded one, and the types default to those it was registered with.
 #[derive(Debug, Deserialize)]
 pub struct ReduceConfig {
+    /// Id of a registered reducer to run instead of an uploaded module
+    pub module: Option<String>,
     /// WIT definition text for the module's types
-    pub wit_definition: String,
+    pub wit_definition: Option<String>,
     /// Name of the input/value type in the WIT definition
-    pub input_type: String,
+    pub input_type: Option<String>,
     /// Name of the state type in the WIT definition
-    pub state_type: String,
+    pub state_type: Option<String>,
     /// Optional key filters
     #[serde(default)]
     pub filter: KeyFilter,
@@ -66,8 +87,11 @@ pub struct MapResult {
     pub filtered: u32,
     /// Errors encountered: list of (key, error message)
     pub errors: Vec<(String, String)>,
-    /// Transformed results: list of (key, wave-encoded value)
+    /// Transformed results: list of (key, wave-encoded value), empty when
+    /// they are written into an output keyspace
     pub results: Vec<(String, String)>,
+    /// Number of transformed values written into the output keyspace
+    pub written: u32,
 }
 
 /// Result of a reduce operation.
@@ -83,12 +107,16 @@ pub struct ReduceResult {
     pub state: String,
 }
 
-/// Extract module bytes and config from multipart request for map operation.
-async fn extract_map_multipart(
+/// Extract module bytes and JSON config from a multipart request.
+///
+/// Expects a `config` field with the operation's JSON config and, unless
+/// the config names a registered module, a `module` field with the WASM
+/// component bytes; other fields are ignored.
+pub(super) async fn extract_module_multipart<C: DeserializeOwned>(
     multipart: &mut Multipart,
-) -> Result<(Vec<u8>, MapConfig), ApiError> {
+) -> Result<(Option<Vec<u8>>, C), ApiError> {
     let mut module_bytes: Option<Vec<u8>> = None;
-    let mut config: Option<MapConfig> = None;
+    let mut config: Option<C> = None;
 
     while let Some(field) = multipart
         .next_field()
@@ -120,53 +148,114 @@ async fn extract_map_multipart(
         }
     }
 
-    let module_bytes = module_bytes.ok_or_else(|| ApiError::missing_field("module"))?;
     let config = config.ok_or_else(|| ApiError::missing_field("config"))?;
 
     Ok((module_bytes, config))
 }
 
-/// Extract module bytes and config from multipart request for reduce operation.
-async fn extract_reduce_multipart(
-    multipart: &mut Multipart,
-) -> Result<(Vec<u8>, ReduceConfig), ApiError> {
-    let mut module_bytes: Option<Vec<u8>> = None;
-    let mut config: Option<ReduceConfig> = None;
-
-    while let Some(field) = multipart
-        .next_field()
-        .await
-        .map_err(|e| ApiError::invalid_multipart(e.to_string()))?
-    {
-        let name = field.name().map(|s| s.to_string());
+/// The module a map or reduce config refers to, and its types.
+struct ModuleSpec<'a> {
+    kind: ModuleKind,
+    id: Option<&'a str>,
+    wit_definition: Option<&'a str>,
+    input_type: Option<&'a str>,
+    output_type: Option<&'a str>,
+}
 
-        match name.as_deref() {
-            Some("module") => {
-                let bytes = field
-                    .bytes()
-                    .await
-                    .map_err(|e| ApiError::invalid_multipart(e.to_string()))?;
-                module_bytes = Some(bytes.to_vec());
-            }
-            Some("config") => {
-                let text = field
-                    .text()
-                    .await
-                    .map_err(|e| ApiError::invalid_multipart(e.to_string()))?;
-                config = Some(serde_json::from_str(&text).map_err(|e| {
-                    ApiError::invalid_multipart(format!("Invalid config JSON: {}", e))
-                })?);
-            }
-            _ => {
-                // Ignore unknown fields
-            }
+impl MapConfig {
+    fn module_spec(&self) -> ModuleSpec<'_> {
+        ModuleSpec {
+            kind: ModuleKind::Mapper,
+            id: self.module.as_deref(),
+            wit_definition: self.wit_definition.as_deref(),
+            input_type: self.input_type.as_deref(),
+            output_type: self.output_type.as_deref(),
         }
     }
+}
 
-    let module_bytes = module_bytes.ok_or_else(|| ApiError::missing_field("module"))?;
-    let config = config.ok_or_else(|| ApiError::missing_field("config"))?;
+impl ReduceConfig {
+    fn module_spec(&self) -> ModuleSpec<'_> {
+        ModuleSpec {
+            kind: ModuleKind::Reducer,
+            id: self.module.as_deref(),
+            wit_definition: self.wit_definition.as_deref(),
+            input_type: self.input_type.as_deref(),
+            output_type: self.state_type.as_deref(),
+        }
+    }
+}
 
-    Ok((module_bytes, config))
+/// Build the runner of a map or reduce operation.
+///
+/// Runs the uploaded `module_bytes`, or the module registered under the
+/// id of `spec`, whose compiled component is kept in the database's module
+/// cache. Types given in `spec` override those of the registration.
+fn build_runner(
+    state: &AppState,
+    database: &str,
+    module_bytes: Option<Vec<u8>>,
+    spec: &ModuleSpec<'_>,
+    cancel: Arc<AtomicBool>,
+) -> Result<TypedRunner, ApiError> {
+    let store = state.get_database(database)?;
+    let (module_bytes, registration) = match (module_bytes, spec.id) {
+        (Some(bytes), None) => (bytes, None),
+        (None, Some(id)) => {
+            let registration = store
+                .get_module(id)?
+                .ok_or_else(|| KvError::ModuleNotFound(id.to_string()))?;
+            if registration.kind != spec.kind {
+                return Err(ApiError::module_kind_mismatch(
+                    id,
+                    spec.kind.as_str(),
+                    registration.kind.as_str(),
+                ));
+            }
+            let bytes = store
+                .get_module_component(id)?
+                .ok_or_else(|| KvError::ModuleNotFound(id.to_string()))?;
+            (bytes, Some(registration))
+        }
+        (Some(_), Some(_)) => {
+            return Err(ApiError::invalid_multipart(
+                "Provide either a 'module' field or a registered module id, not both",
+            ));
+        }
+        (None, None) => return Err(ApiError::missing_field("module")),
+    };
+
+    let wit_definition = spec
+        .wit_definition
+        .or(registration.as_ref().map(|r| r.wit_definition.as_str()))
+        .ok_or_else(|| ApiError::missing_field("wit_definition"))?;
+    let input_type = spec
+        .input_type
+        .or(registration.as_ref().map(|r| r.input_type.as_str()))
+        .ok_or_else(|| ApiError::missing_field("input_type"))?;
+    let output_type = spec
+        .output_type
+        .or(registration.as_ref().map(|r| r.output_type.as_str()));
+    let output_type = match (spec.kind, output_type) {
+        (_, Some(output_type)) => output_type,
+        (ModuleKind::Mapper, None) => input_type,
+        (ModuleKind::Reducer, None) => return Err(ApiError::missing_field("state_type")),
+    };
+
+    let mut builder = TypedRunner::builder()
+        .component_bytes(module_bytes)
+        .wit_text(wit_definition)
+        .input_type(input_type)
+        .output_type(output_type)
+        .limits(state.execution_limits(database))
+        .cancel_flag(cancel);
+    if let Some(registration) = &registration {
+        builder = builder.component_cache(
+            ComponentCache::new(store.module_cache_dir()),
+            &registration.id,
+        );
+    }
+    builder.build().map_err(ApiError::from)
 }
 
 /// Get filtered keys from the store based on the filter options.
@@ -186,8 +275,10 @@ fn get_filtered_keys(
                 filter.prefix.as_deref(),
                 filter.start.as_deref(),
                 filter.end.as_deref(),
+                None,
                 filter.limit,
             )
+            .map(|list| list.keys)
             .map_err(ApiError::from)
     }
 }
@@ -195,8 +286,18 @@ fn get_filtered_keys(
 /// Execute a map operation.
 ///
 /// Expects a multipart/form-data request with:
-/// - `module`: WASM component bytes
+/// - `module`: WASM component bytes, unless `config` names a registered module
 /// - `config`: JSON with MapConfig
+///
+/// The job runs on the blocking thread pool in one of the server's job slots,
+/// and is cancelled if the client disconnects. The module runs within the
+/// execution limits of the database. A per-call limit fails only the key
+/// being processed, a per-job limit the whole operation.
+///
+/// With an `output_keyspace`, whose type must match the module's output
+/// type, transformed values are written into it in batches of
+/// [`DEFAULT_MAP_BATCH_SIZE`]. Batches committed before the operation
+/// fails or is cancelled stay written.
 #[instrument(skip(state, multipart), fields(database = %database, keyspace = %keyspace))]
 pub async fn map_operation(
     State(state): State<AppState>,
@@ -206,47 +307,85 @@ pub async fn map_operation(
     debug!("starting map operation");
 
     // Extract module bytes and config from multipart
-    let (module_bytes, config) = extract_map_multipart(&mut multipart).await?;
+    let (module_bytes, config): (_, MapConfig) = extract_module_multipart(&mut multipart).await?;
 
     debug!(
-        module_size = module_bytes.len(),
-        input_type = %config.input_type,
+        module_size = module_bytes.as_ref().map(Vec::len),
+        module = config.module.as_deref(),
+        input_type = config.input_type.as_deref(),
         output_type = config.output_type.as_deref(),
+        output_keyspace = config.output_keyspace.as_deref(),
         filter.key = config.filter.key.as_deref(),
         filter.prefix = config.filter.prefix.as_deref(),
         filter.limit = config.filter.limit,
         "map config extracted"
     );
+    state.get_database(&database)?;
+
+    let job_state = state.clone();
+    let result = run_job(&state, move |cancel| {
+        run_map(
+            &job_state,
+            &database,
+            &keyspace,
+            module_bytes,
+            &config,
+            cancel,
+            &JobProgress::default(),
+        )
+    })
+    .await?;
+    Ok(Json(result))
+}
 
-    let store = state.get_database(&database)?;
+/// The body of a map operation, run on the blocking thread pool.
+///
+/// Reports its counts on `progress` after every key.
+pub(super) fn run_map(
+    state: &AppState,
+    database: &str,
+    keyspace: &str,
+    module_bytes: Option<Vec<u8>>,
+    config: &MapConfig,
+    cancel: Arc<AtomicBool>,
+    progress: &JobProgress,
+) -> Result<MapResult, ApiError> {
+    let store = state.get_database(database)?;
 
-    // Create TypedRunner from bytes
-    let output_type = config.output_type.as_deref().unwrap_or(&config.input_type);
-    let mut runner = TypedRunner::builder()
-        .component_bytes(module_bytes)
-        .wit_text(&config.wit_definition)
-        .input_type(&config.input_type)
-        .output_type(output_type)
-        .build()
-        .map_err(ApiError::from)?;
+    let mut runner = build_runner(state, database, module_bytes, &config.module_spec(), cancel)?;
 
     // Get keyspace metadata for type version
     let metadata = store
-        .get_type(&keyspace)?
-        .ok_or_else(|| ApiError::keyspace_not_found(&database, &keyspace))?;
+        .get_type(keyspace)?
+        .ok_or_else(|| ApiError::keyspace_not_found(database, keyspace))?;
+
+    // Transformed values are stored as they are, so the output keyspace
+    // must have the module's output type
+    let output_keyspace = config.output_keyspace.as_deref();
+    if let Some(output_keyspace) = output_keyspace {
+        let output = store
+            .get_type(output_keyspace)?
+            .ok_or_else(|| ApiError::keyspace_not_found(database, output_keyspace))?;
+        let (_, _, output_type) =
+            load_wit_type_from_string(&output.wit_definition, Some(&output.type_name))
+                .map_err(|e| ApiError::internal(e.to_string()))?;
+        runner.check_output_type(&output_type)?;
+    }
 
     // Get keys based on filter
-    let keys = get_filtered_keys(store, &keyspace, &config.filter)?;
+    let keys = get_filtered_keys(store, keyspace, &config.filter)?;
 
     // Execute map operation
     let mut processed: u32 = 0;
     let mut transformed: u32 = 0;
     let mut filtered: u32 = 0;
+    let mut written: u32 = 0;
     let mut errors: Vec<(String, String)> = Vec::new();
     let mut results: Vec<(String, String)> = Vec::new();
+    let mut batch = store.batch();
 
     for key in keys {
-        match store.get_raw(&keyspace, &key)? {
+        match store.get_raw(keyspace, &key)? {
             Some(stored) => {
                 // Call filter
                 match runner.call_filter(&stored) {
@@ -254,12 +393,24 @@ pub async fn map_operation(
                         // Call transform
                         match runner.call_transform(&stored, metadata.type_version) {
                             Ok(result) => {
-                                match runner.stored_to_wave_string(&result) {
-                                    Ok(wave_str) => results.push((key.clone(), wave_str)),
-                                    Err(e) => errors.push((key.clone(), format!("encode: {}", e))),
+                                if let Some(output_keyspace) = output_keyspace {
+                                    match batch.set_stored(output_keyspace, &key, result) {
+                                        Ok(()) => transformed += 1,
+                                        Err(e) => {
+                                            errors.push((key.clone(), format!("write: {}", e)))
+                                        }
+                                    }
+                                } else {
+                                    match runner.stored_to_wave_string(&result) {
+                                        Ok(wave_str) => results.push((key.clone(), wave_str)),
+                                        Err(e) => {
+                                            errors.push((key.clone(), format!("encode: {}", e)))
+                                        }
+                                    }
+                                    transformed += 1;
                                 }
-                                transformed += 1;
                             }
+                            Err(e) if e.ends_job() => return Err(e.into()),
                             Err(e) => {
                                 errors.push((key.clone(), format!("transform: {}", e)));
                             }
@@ -268,6 +419,7 @@ pub async fn map_operation(
                     Ok(false) => {
                         filtered += 1;
                     }
+                    Err(e) if e.ends_job() => return Err(e.into()),
                     Err(e) => {
                         errors.push((key.clone(), format!("filter: {}", e)));
                     }
@@ -278,6 +430,18 @@ pub async fn map_operation(
                 errors.push((key.clone(), "not found".to_string()));
             }
         }
+        if batch.len() >= DEFAULT_MAP_BATCH_SIZE {
+            let full = std::mem::replace(&mut batch, store.batch());
+            let count = full.len() as u32;
+            full.commit()?;
+            written += count;
+        }
+        progress.update(processed, transformed, errors.len());
+    }
+    if !batch.is_empty() {
+        let count = batch.len() as u32;
+        batch.commit()?;
+        written += count;
     }
 
     // Log individual errors at warn level
@@ -289,24 +453,31 @@ pub async fn map_operation(
         processed,
         transformed,
         filtered,
+        written,
         errors = errors.len(),
         "map operation completed"
     );
 
-    Ok(Json(MapResult {
+    Ok(MapResult {
         processed,
         transformed,
         filtered,
         errors,
         results,
-    }))
+        written,
+    })
 }
 
 /// Execute a reduce operation.
 ///
 /// Expects a multipart/form-data request with:
-/// - `module`: WASM component bytes
+/// - `module`: WASM component bytes, unless `config` names a registered module
 /// - `config`: JSON with ReduceConfig
+///
+/// The job runs on the blocking thread pool in one of the server's job slots,
+/// and is cancelled if the client disconnects. The module runs within the
+/// execution limits of the database. A per-call limit fails only the key
+/// being processed, a per-job limit the whole operation.
 #[instrument(skip(state, multipart), fields(database = %database, keyspace = %keyspace))]
 pub async fn reduce_operation(
     State(state): State<AppState>,
@@ -316,35 +487,60 @@ pub async fn reduce_operation(
     debug!("starting reduce operation");
 
     // Extract module bytes and config from multipart
-    let (module_bytes, config) = extract_reduce_multipart(&mut multipart).await?;
+    let (module_bytes, config): (_, ReduceConfig) =
+        extract_module_multipart(&mut multipart).await?;
 
     debug!(
-        module_size = module_bytes.len(),
-        input_type = %config.input_type,
-        state_type = %config.state_type,
+        module_size = module_bytes.as_ref().map(Vec::len),
+        module = config.module.as_deref(),
+        input_type = config.input_type.as_deref(),
+        state_type = config.state_type.as_deref(),
         filter.prefix = config.filter.prefix.as_deref(),
         filter.limit = config.filter.limit,
         "reduce config extracted"
     );
+    state.get_database(&database)?;
+
+    let job_state = state.clone();
+    let result = run_job(&state, move |cancel| {
+        run_reduce(
+            &job_state,
+            &database,
+            &keyspace,
+            module_bytes,
+            &config,
+            cancel,
+            &JobProgress::default(),
+        )
+    })
+    .await?;
+    Ok(Json(result))
+}
 
-    let store = state.get_database(&database)?;
+/// The body of a reduce operation, run on the blocking thread pool.
+///
+/// Reports its counts on `progress` after every key.
+pub(super) fn run_reduce(
+    state: &AppState,
+    database: &str,
+    keyspace: &str,
+    module_bytes: Option<Vec<u8>>,
+    config: &ReduceConfig,
+    cancel: Arc<AtomicBool>,
+    progress: &JobProgress,
+) -> Result<ReduceResult, ApiError> {
+    let store = state.get_database(database)?;
 
-    // Create TypedRunner with input_type for values and state_type for state
-    let mut runner = TypedRunner::builder()
-        .component_bytes(module_bytes)
-        .wit_text(&config.wit_definition)
-        .input_type(&config.input_type)
-        .output_type(&config.state_type)
-        .build()
-        .map_err(ApiError::from)?;
+    // The runner takes input_type for values and state_type for state
+    let mut runner = build_runner(state, database, module_bytes, &config.module_spec(), cancel)?;
 
     // Get keyspace metadata for type version
     let metadata = store
-        .get_type(&keyspace)?
-        .ok_or_else(|| ApiError::keyspace_not_found(&database, &keyspace))?;
+        .get_type(keyspace)?
+        .ok_or_else(|| ApiError::keyspace_not_found(database, keyspace))?;
 
     // Get keys based on filter
-    let keys = get_filtered_keys(store, &keyspace, &config.filter)?;
+    let keys = get_filtered_keys(store, keyspace, &config.filter)?;
 
     // Initialize state
     let mut current_state = runner
@@ -355,13 +551,14 @@ pub async fn reduce_operation(
     let mut errors: Vec<(String, String)> = Vec::new();
 
     for key in keys {
-        match store.get_raw(&keyspace, &key)? {
+        match store.get_raw(keyspace, &key)? {
             Some(stored) => {
                 match runner.call_reduce(&current_state, &stored, metadata.type_version) {
                     Ok(new_state) => {
                         current_state = new_state;
                         processed += 1;
                     }
+                    Err(e) if e.ends_job() => return Err(e.into()),
                     Err(e) => {
                         errors.push((key.clone(), format!("reduce: {}", e)));
                     }
@@ -371,6 +568,7 @@ pub async fn reduce_operation(
                 errors.push((key.clone(), "not found".to_string()));
             }
         }
+        progress.update(processed, 0, errors.len());
     }
 
     // Convert final state to WAVE string
@@ -387,10 +585,101 @@ pub async fn reduce_operation(
 
     info!(processed, error_count, "reduce operation completed");
 
-    Ok(Json(ReduceResult {
+    Ok(ReduceResult {
         processed,
         error_count,
         errors,
         state: state_str,
-    }))
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::server::Config;
+
+    const WIT: &str = "package test:mapper;\ninterface types { type counter = u32; }\n";
+
+    /// Keeps non-zero counters and doubles them.
+    const DOUBLER: &str = r#"
+        (component
+            (core module $m
+                (func (export "filter") (param i32) (result i32)
+                    (i32.ne (local.get 0) (i32.const 0)))
+                (func (export "transform") (param i32) (result i32)
+                    (i32.mul (local.get 0) (i32.const 2))))
+            (core instance $i (instantiate $m))
+            (func (export "filter") (param "value" u32) (result bool)
+                (canon lift (core func $i "filter")))
+            (func (export "transform") (param "value" u32) (result u32)
+                (canon lift (core func $i "transform"))))
+    "#;
+
+    fn state(dir: &std::path::Path) -> AppState {
+        let config = Config::parse(&format!(
+            r#"
+[server]
+bind = "127.0.0.1"
+port = 0
+
+[[databases]]
+name = "default"
+path = "{}"
+"#,
+            dir.join("db").display()
+        ))
+        .unwrap();
+        let state = AppState::from_config(&config).unwrap();
+
+        let wit = dir.join("counter.wit");
+        std::fs::write(&wit, WIT).unwrap();
+        let store = state.get_database("default").unwrap();
+        for keyspace in ["counters", "doubled"] {
+            store
+                .set_type(keyspace, &wit, Some("counter"), false, false)
+                .unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn test_map_into_output_keyspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let store = state.get_database("default").unwrap();
+        for (key, value) in [("a", "1"), ("b", "0"), ("c", "21")] {
+            store.set("counters", key, value).unwrap();
+        }
+
+        let config = MapConfig {
+            module: None,
+            wit_definition: Some(WIT.to_string()),
+            input_type: Some("counter".to_string()),
+            output_type: None,
+            output_keyspace: Some("doubled".to_string()),
+            filter: KeyFilter::default(),
+        };
+        let result = run_map(
+            &state,
+            "default",
+            "counters",
+            Some(DOUBLER.as_bytes().to_vec()),
+            &config,
+            Arc::new(AtomicBool::new(false)),
+            &JobProgress::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            (result.processed, result.transformed, result.filtered),
+            (3, 2, 1)
+        );
+        assert_eq!(result.written, 2);
+        assert!(result.results.is_empty());
+        assert!(result.errors.is_empty());
+        assert_eq!(store.get("doubled", "a").unwrap().as_deref(), Some("2"));
+        assert_eq!(store.get("doubled", "b").unwrap(), None);
+        assert_eq!(store.get("doubled", "c").unwrap().as_deref(), Some("42"));
+    }
 }
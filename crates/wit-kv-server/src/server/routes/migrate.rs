@@ -0,0 +1,149 @@
+//! Keyspace migration handlers.
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use axum::{
+    Json,
+    extract::{Multipart, Path, State},
+};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use tempfile::NamedTempFile;
+use tracing::{debug, info, instrument};
+
+use wit_kv::load_wit_type_from_string;
+use wit_kv::wasm::TypedRunner;
+
+use super::super::{error::ApiError, jobs::run_job, state::AppState};
+use super::mapreduce::extract_module_multipart;
+use super::types::{TypeMetadataResponse, TypeVersionResponse};
+
+/// JSON config for a migration (sent in multipart 'config' field).
+#[derive(Debug, Deserialize)]
+pub struct MigrateConfig {
+    /// WIT definition text for the module's types (becomes the keyspace's type definition)
+    pub wit_definition: String,
+    /// Name of the current keyspace type in the WIT definition
+    pub input_type: String,
+    /// Name of the new keyspace type in the WIT definition
+    pub output_type: String,
+}
+
+/// Result of a migration.
+#[derive(Debug, Serialize)]
+pub struct MigrateResult {
+    /// Number of values migrated
+    pub migrated: u32,
+    /// Type version before the migration
+    pub from_version: TypeVersionResponse,
+    /// Type metadata after the migration
+    pub metadata: TypeMetadataResponse,
+}
+
+/// Migrate every value in a keyspace to a new type.
+///
+/// Expects a multipart/form-data request with:
+/// - `module`: WASM component bytes exporting `migrate: func(old: T0) -> T1`
+/// - `config`: JSON with MigrateConfig
+///
+/// The migration is all-or-nothing: if any value fails to migrate, the
+/// keyspace is left unchanged. Like map and reduce, it runs in one of the
+/// server's job slots and is cancelled if the client disconnects.
+#[instrument(skip(state, multipart), fields(database = %database, keyspace = %keyspace))]
+pub async fn migrate_keyspace(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+    mut multipart: Multipart,
+) -> Result<Json<MigrateResult>, ApiError> {
+    debug!("starting migration");
+
+    let (module_bytes, config): (_, MigrateConfig) =
+        extract_module_multipart(&mut multipart).await?;
+    let module_bytes = module_bytes.ok_or_else(|| ApiError::missing_field("module"))?;
+
+    debug!(
+        module_size = module_bytes.len(),
+        input_type = %config.input_type,
+        output_type = %config.output_type,
+        "migrate config extracted"
+    );
+    state.get_database(&database)?;
+
+    let job_state = state.clone();
+    let result = run_job(&state, move |cancel| {
+        run_migrate(
+            &job_state,
+            &database,
+            &keyspace,
+            module_bytes,
+            &config,
+            cancel,
+        )
+    })
+    .await?;
+    Ok(Json(result))
+}
+
+/// The body of a migration, run on the blocking thread pool.
+fn run_migrate(
+    state: &AppState,
+    database: &str,
+    keyspace: &str,
+    module_bytes: Vec<u8>,
+    config: &MigrateConfig,
+    cancel: Arc<AtomicBool>,
+) -> Result<MigrateResult, ApiError> {
+    let store = state.get_database(database)?;
+
+    let previous = store
+        .get_type(keyspace)?
+        .ok_or_else(|| ApiError::keyspace_not_found(database, keyspace))?;
+    let (_, _, keyspace_type) =
+        load_wit_type_from_string(&previous.wit_definition, Some(&previous.type_name))
+            .map_err(|e| ApiError::internal(e.to_string()))?;
+
+    let mut runner = TypedRunner::builder()
+        .component_bytes(module_bytes)
+        .wit_text(&config.wit_definition)
+        .input_type(&config.input_type)
+        .output_type(&config.output_type)
+        // A migration is all or nothing, so only single calls are bounded
+        .limits(state.execution_limits(database).per_call())
+        .cancel_flag(cancel)
+        .build()
+        .map_err(ApiError::from)?;
+    runner.check_input_type(&keyspace_type)?;
+
+    // Write to a temporary file since migrate expects a file path
+    let mut temp_file = NamedTempFile::new()
+        .map_err(|e| ApiError::internal(format!("Failed to create temp file: {}", e)))?;
+
+    temp_file
+        .write_all(config.wit_definition.as_bytes())
+        .map_err(|e| ApiError::internal(format!("Failed to write temp file: {}", e)))?;
+
+    let mut migrated: u32 = 0;
+    let metadata = store.migrate(
+        keyspace,
+        temp_file.path(),
+        Some(&config.output_type),
+        |_, value, type_version| {
+            migrated += 1;
+            runner.call_migrate(value, type_version)
+        },
+    )?;
+
+    info!(
+        migrated,
+        from = %previous.type_version,
+        to = %metadata.type_version,
+        "migration completed"
+    );
+
+    Ok(MigrateResult {
+        migrated,
+        from_version: previous.type_version.into(),
+        metadata: metadata.into(),
+    })
+}
@@ -1,8 +1,18 @@
 //! API routes and handlers.
 
+mod backup;
+mod batch;
+mod indexes;
+mod jobs;
 mod kv;
 mod mapreduce;
+mod migrate;
+mod modules;
+mod openapi;
+mod scan;
+mod transfer;
 mod types;
+mod watch;
 
 use axum::{
     Router,
@@ -28,18 +38,45 @@ pub fn router(state: AppState) -> Router {
         .route("/kv/{keyspace}/{key}", get(kv::get_value))
         .route("/kv/{keyspace}/{key}", put(kv::set_value))
         .route("/kv/{keyspace}/{key}", delete(kv::delete_value))
+        .route("/scan/{keyspace}", get(scan::scan_values))
+        .route("/export/{keyspace}", get(transfer::export_values))
+        .route("/import/{keyspace}", post(transfer::import_values))
+        .route("/batch", post(batch::write_batch))
+        .route("/backup", post(backup::create_backup))
+        .route("/watch/{keyspace}", get(watch::watch_keyspace))
         // Type operations
         .route("/types", get(types::list_types))
         .route("/types/{keyspace}", get(types::get_type))
         .route("/types/{keyspace}", put(types::set_type))
         .route("/types/{keyspace}", delete(types::delete_type))
+        .route("/types/{keyspace}/schema", get(types::get_type_schema))
+        .route("/types/{keyspace}/ttl", put(types::set_default_ttl))
+        .route("/types/{keyspace}/ttl", delete(types::clear_default_ttl))
+        // Secondary indexes
+        .route("/indexes/{keyspace}", get(indexes::list_indexes))
+        .route("/indexes/{keyspace}/{field}", get(indexes::lookup))
+        .route("/indexes/{keyspace}/{field}", put(indexes::create_index))
+        .route("/indexes/{keyspace}/{field}", delete(indexes::drop_index))
         // Map/reduce operations
         .route("/map/{keyspace}", post(mapreduce::map_operation))
-        .route("/reduce/{keyspace}", post(mapreduce::reduce_operation));
+        .route("/reduce/{keyspace}", post(mapreduce::reduce_operation))
+        .route("/jobs", get(jobs::list_jobs))
+        .route("/jobs", post(jobs::submit_job))
+        // Module registry
+        .route("/modules", get(modules::list_modules))
+        .route("/modules/{id}", get(modules::get_module))
+        .route("/modules/{id}", put(modules::register_module))
+        .route("/modules/{id}", delete(modules::delete_module))
+        // Migrations
+        .route("/migrate/{keyspace}", post(migrate::migrate_keyspace));
 
     Router::new()
         .route("/health", get(health))
+        .route("/api/v1/openapi.json", get(openapi::openapi))
         .route("/api/v1/databases", get(list_databases))
+        .route("/api/v1/jobs/{id}", get(jobs::get_job))
+        .route("/api/v1/jobs/{id}", delete(jobs::cancel_job))
+        .route("/api/v1/jobs/{id}/result", get(jobs::get_job_result))
         .nest("/api/v1/db/{database}", db_routes)
         .with_state(state)
 }
@@ -67,6 +104,7 @@ pub async fn list_databases(
 
     match format {
         ContentFormat::Wave => Ok(FormatResponse::wave(db_list.to_wave()).into_response()),
+        ContentFormat::Json => Ok(FormatResponse::json(&db_list.to_json()?).into_response()),
         ContentFormat::Binary => {
             let (buffer, memory) = db_list
                 .encode()
@@ -0,0 +1,217 @@
+//! Module registry handlers.
+
+use axum::{
+    Json,
+    extract::{Multipart, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, instrument, warn};
+
+use wit_kv::kv::{KvError, ModuleKind, ModuleRegistration};
+use wit_kv::wasm::{ComponentCache, TypedRunner};
+
+use super::super::{error::ApiError, jobs::run_job, state::AppState};
+use super::mapreduce::extract_module_multipart;
+
+/// Query parameters for registering a module.
+#[derive(Debug, Deserialize, Default)]
+pub struct RegisterModuleQuery {
+    /// Replace the module if the id is already registered.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// JSON config for registering a module (sent in multipart 'config' field).
+#[derive(Debug, Deserialize)]
+pub struct RegisterModuleConfig {
+    /// Human-readable name (defaults to the id)
+    pub name: Option<String>,
+    /// `mapper` or `reducer`
+    pub kind: String,
+    /// WIT definition text for the module's types
+    pub wit_definition: String,
+    /// Name of the input type in the WIT definition
+    pub input_type: String,
+    /// Name of the output type (mappers, defaults to input_type) or of the
+    /// state type (reducers) in the WIT definition
+    pub output_type: Option<String>,
+}
+
+/// Module registration response (JSON serializable version of ModuleRegistration).
+#[derive(Debug, Serialize)]
+pub struct ModuleResponse {
+    pub id: String,
+    pub name: String,
+    pub kind: &'static str,
+    pub wit_definition: String,
+    pub input_type: String,
+    pub output_type: String,
+    pub created_at: u64,
+}
+
+impl From<ModuleRegistration> for ModuleResponse {
+    fn from(m: ModuleRegistration) -> Self {
+        Self {
+            id: m.id,
+            name: m.name,
+            kind: m.kind.as_str(),
+            wit_definition: m.wit_definition,
+            input_type: m.input_type,
+            output_type: m.output_type,
+            created_at: m.created_at,
+        }
+    }
+}
+
+/// Registered modules of a database.
+#[derive(Debug, Serialize)]
+pub struct ModuleListResponse {
+    pub modules: Vec<ModuleResponse>,
+}
+
+/// List the modules registered in a database.
+#[instrument(skip(state), fields(database = %database))]
+pub async fn list_modules(
+    State(state): State<AppState>,
+    Path(database): Path<String>,
+) -> Result<Json<ModuleListResponse>, ApiError> {
+    debug!("listing modules");
+
+    let store = state.get_database(&database)?;
+    let modules: Vec<ModuleResponse> = store
+        .list_modules()?
+        .into_iter()
+        .map(ModuleResponse::from)
+        .collect();
+
+    info!(count = modules.len(), "listed modules");
+    Ok(Json(ModuleListResponse { modules }))
+}
+
+/// Get the registration of a module.
+#[instrument(skip(state), fields(database = %database, id = %id))]
+pub async fn get_module(
+    State(state): State<AppState>,
+    Path((database, id)): Path<(String, String)>,
+) -> Result<Json<ModuleResponse>, ApiError> {
+    debug!("getting module");
+
+    let store = state.get_database(&database)?;
+    let registration = store
+        .get_module(&id)?
+        .ok_or_else(|| KvError::ModuleNotFound(id.clone()))?;
+    Ok(Json(registration.into()))
+}
+
+/// Register a module under an id.
+///
+/// Expects a multipart/form-data request with:
+/// - `module`: WASM component bytes
+/// - `config`: JSON with RegisterModuleConfig
+///
+/// The module is compiled and instantiated, and must export the functions
+/// of its kind, before it is registered; the compiled component is kept in
+/// the database's module cache. An id that is already registered is
+/// refused with `409 MODULE_EXISTS` unless `force` is set. A replaced
+/// module keeps its compiled component until the new one is registered,
+/// which is then compiled into the cache on its first run.
+#[instrument(skip(state, query, multipart), fields(database = %database, id = %id))]
+pub async fn register_module(
+    State(state): State<AppState>,
+    Path((database, id)): Path<(String, String)>,
+    Query(query): Query<RegisterModuleQuery>,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    debug!("registering module");
+
+    let (module_bytes, config): (_, RegisterModuleConfig) =
+        extract_module_multipart(&mut multipart).await?;
+    let module_bytes = module_bytes.ok_or_else(|| ApiError::missing_field("module"))?;
+    let kind: ModuleKind = config
+        .kind
+        .parse()
+        .map_err(|e: KvError| ApiError::invalid_multipart(e.to_string()))?;
+    let output_type = match (kind, config.output_type) {
+        (_, Some(output_type)) => output_type,
+        (ModuleKind::Mapper, None) => config.input_type.clone(),
+        (ModuleKind::Reducer, None) => return Err(ApiError::missing_field("output_type")),
+    };
+    let registration = ModuleRegistration::new(
+        id.clone(),
+        config.name.unwrap_or_else(|| id.clone()),
+        config.wit_definition,
+        config.input_type,
+        output_type,
+        kind,
+    );
+
+    // Refuse early, before compiling over the cached component of the module
+    ModuleRegistration::validate_id(&id)?;
+    let store = state.get_database(&database)?;
+    if !query.force && store.get_module(&id)?.is_some() {
+        return Err(KvError::ModuleExists(id).into());
+    }
+
+    let job_state = state.clone();
+    let registration = run_job(&state, move |cancel| {
+        let store = job_state.get_database(&database)?;
+        let cache = ComponentCache::new(store.module_cache_dir());
+        // Caching the new component under the id of a registered module would
+        // evict the compiled component of the module before the new one passes
+        let replacing = store.get_module(&registration.id)?.is_some();
+        let mut builder = TypedRunner::builder()
+            .component_bytes(module_bytes.clone())
+            .wit_text(&registration.wit_definition)
+            .input_type(&registration.input_type)
+            .output_type(&registration.output_type)
+            .limits(job_state.execution_limits(&database))
+            .cancel_flag(cancel);
+        if !replacing {
+            builder = builder.component_cache(cache.clone(), &registration.id);
+        }
+        let validated = builder
+            .build()
+            .and_then(|mut runner| runner.check_exports(registration.kind.exports()));
+        if let Err(e) = validated {
+            // Leave no compiled component of a module that is not registered
+            if !replacing {
+                cache.remove(&registration.id)?;
+            }
+            return Err(e.into());
+        }
+
+        store.register_module(&registration, &module_bytes, query.force)?;
+        if replacing && let Err(e) = cache.remove(&registration.id) {
+            warn!(error = %e, "failed to remove compiled components of the replaced module");
+        }
+        Ok(registration)
+    })
+    .await?;
+
+    info!(kind = registration.kind.as_str(), "module registered");
+    Ok((
+        StatusCode::CREATED,
+        Json(ModuleResponse::from(registration)),
+    )
+        .into_response())
+}
+
+/// Delete a registered module, along with its compiled components.
+#[instrument(skip(state), fields(database = %database, id = %id))]
+pub async fn delete_module(
+    State(state): State<AppState>,
+    Path((database, id)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    debug!("deleting module");
+
+    let store = state.get_database(&database)?;
+    store.delete_module(&id)?;
+    if let Err(e) = ComponentCache::new(store.module_cache_dir()).remove(&id) {
+        warn!(error = %e, "failed to remove compiled components");
+    }
+
+    info!("module deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
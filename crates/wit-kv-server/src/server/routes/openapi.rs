@@ -0,0 +1,360 @@
+//! OpenAPI description of the API, generated from the registered types.
+
+use std::collections::HashSet;
+
+use axum::{Json, extract::State};
+use serde_json::{Map, Value, json};
+use tracing::{debug, info, instrument, warn};
+
+use wit_kv::kv::KeyspaceMetadata;
+
+use super::super::{error::ApiError, state::AppState};
+
+/// Describe the API of every database and keyspace as an OpenAPI 3.1 document.
+///
+/// Each keyspace gets its own paths for its values in JSON form, with the
+/// JSON Schema of its type as request and response body, so typed clients
+/// and forms can be generated per keyspace. The document is built on every
+/// request and follows type changes right away.
+#[instrument(skip(state))]
+pub async fn openapi(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    debug!("generating OpenAPI document");
+
+    let mut names = state.database_names();
+    names.sort_unstable();
+
+    let mut paths = Map::new();
+    paths.insert("/databases".to_string(), databases_path());
+
+    let mut schemas = Map::new();
+    schemas.insert("Error".to_string(), error_schema());
+    schemas.insert("KeyList".to_string(), key_list_schema());
+
+    let mut taken = HashSet::new();
+    for database in names {
+        let store = state.get_database(database)?;
+        for metadata in store.list_types()? {
+            // One unusable type should not hide the rest of the API
+            let schema = match metadata.json_schema() {
+                Ok(schema) => schema,
+                Err(e) => {
+                    warn!(database, keyspace = %metadata.name, error = %e, "skipping keyspace without a JSON schema");
+                    continue;
+                }
+            };
+            let name = unique_name(&mut taken, database, &metadata.name);
+            schemas.insert(name.clone(), schema);
+            add_keyspace_paths(&mut paths, database, &metadata, &name);
+        }
+    }
+
+    info!(
+        paths = paths.len(),
+        schemas = schemas.len(),
+        "generated OpenAPI document"
+    );
+    Ok(Json(json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "wit-kv",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Typed key-value store. Values are shown in their JSON form; every value endpoint also accepts and returns WAVE text (application/x-wasm-wave) and canonical ABI binary (application/octet-stream).",
+        },
+        "servers": [{"url": "/api/v1"}],
+        "paths": paths,
+        "components": {"schemas": schemas},
+    })))
+}
+
+/// Add the value endpoints of one keyspace, whose values have schema `name`.
+fn add_keyspace_paths(
+    paths: &mut Map<String, Value>,
+    database: &str,
+    metadata: &KeyspaceMetadata,
+    name: &str,
+) {
+    let value = json!({"$ref": format!("#/components/schemas/{}", name)});
+    let tags = json!([format!("{}/{}", database, metadata.name)]);
+    let prefix = |route: &str| {
+        format!(
+            "/db/{}/{}/{}",
+            encode_segment(database),
+            route,
+            encode_segment(&metadata.name)
+        )
+    };
+    let summary = |action: &str| format!("{} {} ({})", action, metadata.name, metadata.type_name);
+
+    paths.insert(
+        prefix("kv"),
+        json!({
+            "get": {
+                "operationId": format!("listKeys_{}", name),
+                "summary": summary("List keys of"),
+                "tags": tags,
+                "parameters": [
+                    query_param("prefix", "Only keys starting with this prefix", json!({"type": "string"})),
+                    query_param("start", "Only keys >= start", json!({"type": "string"})),
+                    query_param("end", "Only keys < end", json!({"type": "string"})),
+                    query_param("cursor", "next-cursor of the previous page", json!({"type": "string"})),
+                    query_param("limit", "Maximum number of keys", json!({"type": "integer", "minimum": 0})),
+                ],
+                "responses": {
+                    "200": json_response("A page of keys", json!({"$ref": "#/components/schemas/KeyList"})),
+                    "404": error_response("Keyspace not found"),
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        format!("{}/{{key}}", prefix("kv")),
+        json!({
+            "parameters": [{
+                "name": "key",
+                "in": "path",
+                "required": true,
+                "schema": {"type": "string"},
+            }],
+            "get": {
+                "operationId": format!("getValue_{}", name),
+                "summary": summary("Get a value of"),
+                "tags": tags,
+                "parameters": [
+                    query_param("fields", "Comma-separated field paths to return instead of the whole value; the response then only holds those fields", json!({"type": "string"})),
+                    header_param("If-None-Match", "Return 304 if the value still has this ETag"),
+                ],
+                "responses": {
+                    "200": {
+                        "description": "The value",
+                        "headers": {
+                            "ETag": {"description": "Revision of the value, weak (W/\"42\") for a projection", "schema": {"type": "string"}},
+                            "Vary": {"description": "Accept, since every format is served from the same URL", "schema": {"type": "string"}},
+                        },
+                        "content": {"application/json": {"schema": value}},
+                    },
+                    "304": {"description": "Not modified"},
+                    "404": error_response("Key or keyspace not found"),
+                },
+            },
+            "put": {
+                "operationId": format!("setValue_{}", name),
+                "summary": summary("Set a value of"),
+                "tags": tags,
+                "parameters": [
+                    header_param("If-Match", "Only write if the key has this ETag, or exists for *"),
+                    header_param("If-None-Match", "* to only write if the key does not exist"),
+                    header_param("X-TTL", "Expire the value after this many seconds"),
+                ],
+                "requestBody": {
+                    "required": true,
+                    "content": {"application/json": {"schema": value}},
+                },
+                "responses": {
+                    "204": {"description": "Value written", "headers": {"ETag": etag_header()}},
+                    "400": error_response("The value does not match the keyspace type"),
+                    "404": error_response("Keyspace not found"),
+                    "412": error_response("Precondition failed"),
+                },
+            },
+            "delete": {
+                "operationId": format!("deleteValue_{}", name),
+                "summary": summary("Delete a value of"),
+                "tags": tags,
+                "parameters": [header_param("If-Match", "Only delete if the key has this ETag, or exists for *")],
+                "responses": {
+                    "204": {"description": "Value deleted"},
+                    "404": error_response("Keyspace not found"),
+                    "412": error_response("Precondition failed"),
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        prefix("scan"),
+        json!({
+            "get": {
+                "operationId": format!("scanValues_{}", name),
+                "summary": summary("Scan keys and values of"),
+                "tags": tags,
+                "parameters": [
+                    query_param("prefix", "Only keys starting with this prefix", json!({"type": "string"})),
+                    query_param("start", "Only keys >= start", json!({"type": "string"})),
+                    query_param("end", "Only keys < end", json!({"type": "string"})),
+                    query_param("after", "Last key of the previous page", json!({"type": "string"})),
+                    query_param("reverse", "Visit keys from the highest down", json!({"type": "boolean"})),
+                    query_param("limit", "Maximum number of entries (default 1000, at most 10000)", json!({"type": "integer", "minimum": 0, "maximum": 10000})),
+                    query_param("where", "Only values matching this predicate", json!({"type": "string"})),
+                    query_param("fields", "Comma-separated field paths to return instead of whole values", json!({"type": "string"})),
+                ],
+                "responses": {
+                    "200": {
+                        "description": "One entry per line",
+                        "content": {"application/x-ndjson": {"schema": {
+                            "type": "object",
+                            "properties": {"key": {"type": "string"}, "value": value},
+                            "required": ["key", "value"],
+                        }}},
+                    },
+                    "400": error_response("Invalid predicate or fields"),
+                    "404": error_response("Keyspace not found"),
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        format!("{}/schema", prefix("types")),
+        json!({
+            "get": {
+                "operationId": format!("getSchema_{}", name),
+                "summary": summary("JSON Schema of the values of"),
+                "tags": tags,
+                "responses": {
+                    "200": json_response("JSON Schema (2020-12)", json!({"type": "object"})),
+                    "404": error_response("Keyspace not found"),
+                },
+            },
+        }),
+    );
+}
+
+fn databases_path() -> Value {
+    json!({
+        "get": {
+            "operationId": "listDatabases",
+            "summary": "List databases",
+            "responses": {
+                "200": json_response(
+                    "The databases",
+                    json!({
+                        "type": "object",
+                        "properties": {
+                            "databases": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {"name": {"type": "string"}},
+                                    "required": ["name"],
+                                },
+                            },
+                        },
+                        "required": ["databases"],
+                    }),
+                ),
+            },
+        },
+    })
+}
+
+fn error_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "error": {
+                "type": "object",
+                "properties": {
+                    "code": {"type": "string"},
+                    "message": {"type": "string"},
+                    "details": {},
+                },
+                "required": ["code", "message"],
+            },
+        },
+        "required": ["error"],
+    })
+}
+
+fn key_list_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "keys": {"type": "array", "items": {"type": "string"}},
+            "next-cursor": {
+                "anyOf": [{"type": "null"}, {"type": "string"}],
+                "description": "Cursor of the next page, or null on the last page",
+            },
+        },
+        "required": ["keys", "next-cursor"],
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": {"application/json": {"schema": schema}},
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json_response(description, json!({"$ref": "#/components/schemas/Error"}))
+}
+
+fn query_param(name: &str, description: &str, schema: Value) -> Value {
+    json!({"name": name, "in": "query", "description": description, "schema": schema})
+}
+
+fn header_param(name: &str, description: &str) -> Value {
+    json!({"name": name, "in": "header", "description": description, "schema": {"type": "string"}})
+}
+
+fn etag_header() -> Value {
+    json!({"description": "Revision of the value", "schema": {"type": "string"}})
+}
+
+/// Component name for the values of a keyspace, such as `default.users`.
+///
+/// Component names only allow `[A-Za-z0-9._-]`, so other characters are
+/// replaced, with a numeric suffix if that makes two names collide.
+fn unique_name(taken: &mut HashSet<String>, database: &str, keyspace: &str) -> String {
+    let base: String = format!("{}.{}", database, keyspace)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut name = base.clone();
+    let mut suffix = 2;
+    while !taken.insert(name.clone()) {
+        name = format!("{}_{}", base, suffix);
+        suffix += 1;
+    }
+    name
+}
+
+/// Percent-encode a database or keyspace name for use as a path segment.
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_name() {
+        let mut taken = HashSet::new();
+        assert_eq!(unique_name(&mut taken, "default", "users"), "default.users");
+        assert_eq!(unique_name(&mut taken, "default", "a/b"), "default.a_b");
+        assert_eq!(unique_name(&mut taken, "default", "a b"), "default.a_b_2");
+    }
+
+    #[test]
+    fn test_encode_segment() {
+        assert_eq!(encode_segment("users-2.v1"), "users-2.v1");
+        assert_eq!(encode_segment("a/b {c}"), "a%2Fb%20%7Bc%7D");
+    }
+}
@@ -0,0 +1,222 @@
+//! Streaming range scan handlers.
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderValue, header},
+    response::{IntoResponse, Response},
+};
+use futures_util::stream;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{Span, debug, info, instrument, warn};
+
+use wit_kv::kv::{BinaryExport, KvError, KvStore, Scan, ScanOptions, encode_frame};
+
+use super::super::{
+    content::{AcceptFormat, ContentFormat, MIME_NDJSON},
+    error::ApiError,
+    state::AppState,
+};
+use super::kv::field_paths;
+
+/// Entries per page when the request has no `limit`.
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// Largest accepted `limit`.
+const MAX_PAGE_SIZE: usize = 10_000;
+
+/// Encoded entries are buffered up to this size before being sent.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks buffered between the scanning thread and the response body.
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Query parameters for scanning a keyspace.
+#[derive(Debug, Deserialize, Default)]
+pub struct ScanQuery {
+    pub prefix: Option<String>,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    /// Last key of the previous page
+    pub after: Option<String>,
+    #[serde(default)]
+    pub reverse: bool,
+    pub limit: Option<usize>,
+    /// Only return values matching this predicate, e.g. `score > 100`
+    #[serde(rename = "where")]
+    pub filter: Option<String>,
+    /// Comma-separated field paths to return instead of whole values
+    pub fields: Option<String>,
+}
+
+/// Stream one page of `(key, value)` pairs from a keyspace.
+///
+/// WAVE responses have one `("key", value)` tuple per line, and JSON responses
+/// are `application/x-ndjson`, one `{"key": ..., "value": ...}` object per
+/// line. Binary responses
+/// are a sequence of entries, each a little-endian `u32` key length, the UTF-8
+/// key, a `u32` record length and a `binary-export` record.
+///
+/// A page holds at most `limit` entries (default 1000, at most 10000). Pass
+/// the last key as `after` to get the next page; a shorter page is the last.
+/// With `where`, only values matching the predicate are returned (see
+/// [`KvStore::query`]) and `limit` counts matches. With `fields`, only those
+/// fields of each value are returned (see [`KvStore::get_projected`]).
+#[instrument(skip(state, format), fields(database = %database, keyspace = %keyspace))]
+pub async fn scan_values(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+    Query(query): Query<ScanQuery>,
+    AcceptFormat(format): AcceptFormat,
+) -> Result<Response, ApiError> {
+    debug!(
+        prefix = query.prefix.as_deref(),
+        start = query.start.as_deref(),
+        end = query.end.as_deref(),
+        after = query.after.as_deref(),
+        reverse = query.reverse,
+        limit = query.limit,
+        filter = query.filter.as_deref(),
+        fields = query.fields.as_deref(),
+        "scanning keyspace"
+    );
+
+    // Report a missing keyspace, an invalid predicate or invalid fields as an
+    // error response, before the body starts
+    let store = state.get_database(&database)?;
+    if store.get_type(&keyspace)?.is_none() {
+        return Err(ApiError::keyspace_not_found(&database, &keyspace));
+    }
+    let filter = query.filter;
+    let fields = query.fields.as_deref().map(field_paths);
+    open_scan(
+        store,
+        &keyspace,
+        &ScanOptions::default(),
+        filter.as_deref(),
+        fields.as_deref(),
+    )?;
+
+    let options = ScanOptions {
+        prefix: query.prefix,
+        start: query.start,
+        end: query.end,
+        after: query.after,
+        reverse: query.reverse,
+        limit: Some(query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)),
+    };
+
+    // Scanning blocks on disk reads, so it runs off the async executor and
+    // hands encoded chunks to the response body as they fill up
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let Ok(store) = state.get_database(&database) else {
+            return;
+        };
+        let scan = open_scan(
+            store,
+            &keyspace,
+            &options,
+            filter.as_deref(),
+            fields.as_deref(),
+        );
+        match scan.and_then(|scan| stream_scan(scan, format, &tx)) {
+            Ok(count) => info!(count, "scan streamed"),
+            Err(e) => {
+                // Headers are already sent: abort the body so the client sees a truncated response
+                warn!(error = %e, "scan failed");
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+
+    let chunks = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    let content_type = match format {
+        ContentFormat::Json => HeaderValue::from_static(MIME_NDJSON),
+        _ => format.content_type_header(),
+    };
+    Ok((
+        [(header::CONTENT_TYPE, content_type)],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+/// Start a scan, filtered by `filter` and projected onto `fields` if given.
+fn open_scan<'a>(
+    store: &'a KvStore,
+    keyspace: &str,
+    options: &ScanOptions,
+    filter: Option<&str>,
+    fields: Option<&[String]>,
+) -> Result<Scan<'a>, KvError> {
+    let scan = match filter {
+        Some(filter) => store.query(keyspace, filter, options)?,
+        None => store.scan(keyspace, options)?,
+    };
+    match fields {
+        Some(fields) => scan.project(fields),
+        None => Ok(scan),
+    }
+}
+
+/// Encode scanned entries and send them in chunks of about [`CHUNK_SIZE`].
+///
+/// Returns the number of entries sent. Stops early if the client went away.
+fn stream_scan(
+    scan: Scan<'_>,
+    format: ContentFormat,
+    tx: &mpsc::Sender<Result<Vec<u8>, KvError>>,
+) -> Result<usize, KvError> {
+    let entries: Box<dyn Iterator<Item = Result<Vec<u8>, KvError>>> = match format {
+        ContentFormat::Wave => Box::new(
+            scan.wave()
+                .map(|entry| entry.map(|(key, value)| wave_line(&key, &value).into_bytes())),
+        ),
+        ContentFormat::Json => Box::new(
+            scan.json()
+                .map(|entry| entry.map(|(key, value)| json_line(key, value).into_bytes())),
+        ),
+        ContentFormat::Binary => Box::new(scan.map(|entry| {
+            entry.and_then(|(key, stored)| {
+                encode_frame(&key, &BinaryExport::from_stored_owned(stored))
+            })
+        })),
+    };
+
+    let mut count = 0;
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    for entry in entries {
+        let encoded = entry?;
+        chunk.extend(encoded);
+        count += 1;
+
+        if chunk.len() >= CHUNK_SIZE {
+            let full = std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_SIZE));
+            if tx.blocking_send(Ok(full)).is_err() {
+                debug!(count, "client disconnected");
+                return Ok(count);
+            }
+        }
+    }
+
+    if !chunk.is_empty() && tx.blocking_send(Ok(chunk)).is_err() {
+        debug!(count, "client disconnected");
+    }
+    Ok(count)
+}
+
+/// One newline-terminated `("key", value)` WAVE tuple.
+fn wave_line(key: &str, value: &str) -> String {
+    format!("(\"{}\", {})\n", key.escape_default(), value)
+}
+
+/// One newline-terminated `{"key": ..., "value": ...}` JSON object.
+fn json_line(key: String, value: serde_json::Value) -> String {
+    format!("{}\n", serde_json::json!({"key": key, "value": value}))
+}
@@ -0,0 +1,251 @@
+//! Bulk import and export handlers.
+
+use std::io::{self, BufReader, Read, Write};
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{Span, debug, info, instrument, warn};
+
+use wit_kv::kv::{DEFAULT_IMPORT_BATCH_SIZE, ImportError, KvError, TransferFormat};
+
+use super::super::{
+    content::{AcceptFormat, ContentFormat, RequestFormat},
+    error::ApiError,
+    state::AppState,
+};
+
+/// Exported entries are buffered up to this size before being sent.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks buffered between the response or request body and the blocking thread.
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Query parameters for importing into a keyspace.
+#[derive(Debug, Deserialize, Default)]
+pub struct ImportQuery {
+    /// Values committed per batch (default 1000)
+    pub batch_size: Option<usize>,
+}
+
+/// Outcome of an import.
+#[derive(Debug, Serialize)]
+pub struct ImportResult {
+    /// Number of values written
+    pub imported: usize,
+    /// Number of entries skipped because they were invalid
+    pub failed: usize,
+    /// Store revision of the last committed batch, if anything was written
+    pub revision: Option<u64>,
+    /// The first skipped entries
+    pub errors: Vec<ImportErrorInfo>,
+}
+
+/// An entry skipped by an import.
+#[derive(Debug, Serialize)]
+pub struct ImportErrorInfo {
+    /// Line number, or entry number for binary uploads, starting at 1
+    pub entry: usize,
+    /// Key of the entry, if it could be read
+    pub key: Option<String>,
+    /// Why the entry was skipped
+    pub message: String,
+}
+
+impl From<ImportError> for ImportErrorInfo {
+    fn from(error: ImportError) -> Self {
+        Self {
+            entry: error.entry,
+            key: error.key,
+            message: error.message,
+        }
+    }
+}
+
+/// Stream every value of a keyspace.
+///
+/// WAVE responses have one `key<TAB>value` line per entry, and JSON responses
+/// one `{"key": ..., "value": ...}` object per line. Binary responses
+/// use the framing of binary scan responses: a little-endian `u32` key
+/// length, the UTF-8 key, a `u32` record length and a `binary-export` record.
+/// Either can be uploaded again with [`import_values`].
+#[instrument(skip(state, format), fields(database = %database, keyspace = %keyspace))]
+pub async fn export_values(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+    AcceptFormat(format): AcceptFormat,
+) -> Result<Response, ApiError> {
+    debug!(?format, "exporting keyspace");
+
+    // Report a missing keyspace as an error response, before the body starts
+    let store = state.get_database(&database)?;
+    if store.get_type(&keyspace)?.is_none() {
+        return Err(ApiError::keyspace_not_found(&database, &keyspace));
+    }
+
+    // Exporting blocks on disk reads, so it runs off the async executor and
+    // hands encoded chunks to the response body as they fill up
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let Ok(store) = state.get_database(&database) else {
+            return;
+        };
+        let writer = ChunkWriter {
+            tx: tx.clone(),
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+        };
+        match store.export(&keyspace, transfer_format(format), writer) {
+            Ok(count) => info!(count, "export streamed"),
+            Err(KvError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+                debug!("client disconnected");
+            }
+            Err(e) => {
+                // Headers are already sent: abort the body so the client sees a truncated response
+                warn!(error = %e, "export failed");
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+
+    let chunks = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    Ok((
+        [(header::CONTENT_TYPE, format.content_type_header())],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+/// Import `key<TAB>value` lines, `{"key": ..., "value": ...}` lines with
+/// `Content-Type: application/json`, or binary frames with
+/// `Content-Type: application/octet-stream`, into a keyspace.
+///
+/// The body is streamed and committed every `batch_size` values. Invalid
+/// entries are skipped and reported; the response lists the first 100.
+#[instrument(skip(state, format, body), fields(database = %database, keyspace = %keyspace))]
+pub async fn import_values(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+    Query(query): Query<ImportQuery>,
+    RequestFormat(format): RequestFormat,
+    body: Body,
+) -> Result<Json<ImportResult>, ApiError> {
+    let batch_size = query.batch_size.unwrap_or(DEFAULT_IMPORT_BATCH_SIZE);
+    debug!(?format, batch_size, "importing into keyspace");
+    state.get_database(&database)?;
+
+    // Importing blocks on disk writes, so it runs off the async executor
+    // and reads the body as it arrives
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let span = Span::current();
+    let import = tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let reader = BufReader::new(BodyReader {
+            rx,
+            chunk: Bytes::new(),
+        });
+        state
+            .get_database(&database)?
+            .import(&keyspace, transfer_format(format), reader, batch_size)
+            .map_err(ApiError::from)
+    });
+
+    let mut chunks = body.into_data_stream();
+    while let Some(chunk) = chunks.next().await {
+        // The import stopped early if it no longer reads the body
+        if tx.send(chunk).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+
+    let stats = import
+        .await
+        .map_err(|e| ApiError::internal(format!("Import task failed: {}", e)))??;
+
+    info!(
+        imported = stats.imported,
+        failed = stats.failed,
+        "import finished"
+    );
+    Ok(Json(ImportResult {
+        imported: stats.imported,
+        failed: stats.failed,
+        revision: stats.revision,
+        errors: stats.errors.into_iter().map(Into::into).collect(),
+    }))
+}
+
+fn transfer_format(format: ContentFormat) -> TransferFormat {
+    match format {
+        ContentFormat::Wave => TransferFormat::Lines,
+        ContentFormat::Binary => TransferFormat::Frames,
+        ContentFormat::Json => TransferFormat::Json,
+    }
+}
+
+/// Sends written bytes to the response body in chunks of about [`CHUNK_SIZE`].
+///
+/// Writes fail with [`io::ErrorKind::BrokenPipe`] once the client went away.
+struct ChunkWriter {
+    tx: mpsc::Sender<Result<Vec<u8>, KvError>>,
+    chunk: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn send(&mut self) -> io::Result<()> {
+        let full = std::mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_SIZE));
+        self.tx
+            .blocking_send(Ok(full))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.chunk.extend_from_slice(buf);
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        self.send()
+    }
+}
+
+/// Reads the request body chunks forwarded by the handler.
+struct BodyReader {
+    rx: mpsc::Receiver<Result<Bytes, axum::Error>>,
+    chunk: Bytes,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(e)) => return Err(io::Error::other(e)),
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        let (dest, _) = buf.split_at_mut(len);
+        dest.copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
@@ -9,10 +9,11 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 use std::io::Write;
+use std::time::Duration;
 use tempfile::NamedTempFile;
 use tracing::{debug, info, instrument};
 
-use wit_kv::kv::{KeyspaceList, KeyspaceMetadata};
+use wit_kv::kv::{KeyspaceList, KeyspaceMetadata, SemanticVersion};
 
 use super::super::{
     content::{AcceptFormat, ContentFormat, FormatResponse},
@@ -28,6 +29,9 @@ pub struct SetTypeQuery {
     /// Force overwrite if type already exists.
     #[serde(default)]
     pub force: bool,
+    /// Accept breaking changes when overwriting an existing type.
+    #[serde(default)]
+    pub allow_breaking: bool,
 }
 
 /// Query parameters for deleting a type.
@@ -48,6 +52,9 @@ pub struct TypeMetadataResponse {
     pub type_version: TypeVersionResponse,
     pub type_hash: u32,
     pub created_at: u64,
+    /// Default time-to-live of values in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_ttl: Option<u64>,
 }
 
 /// Semantic version response.
@@ -58,6 +65,16 @@ pub struct TypeVersionResponse {
     pub patch: u32,
 }
 
+impl From<SemanticVersion> for TypeVersionResponse {
+    fn from(v: SemanticVersion) -> Self {
+        Self {
+            major: v.major,
+            minor: v.minor,
+            patch: v.patch,
+        }
+    }
+}
+
 impl From<KeyspaceMetadata> for TypeMetadataResponse {
     fn from(m: KeyspaceMetadata) -> Self {
         Self {
@@ -65,13 +82,10 @@ impl From<KeyspaceMetadata> for TypeMetadataResponse {
             qualified_name: m.qualified_name,
             wit_definition: m.wit_definition,
             type_name: m.type_name,
-            type_version: TypeVersionResponse {
-                major: m.type_version.major,
-                minor: m.type_version.minor,
-                patch: m.type_version.patch,
-            },
+            type_version: m.type_version.into(),
             type_hash: m.type_hash,
             created_at: m.created_at,
+            default_ttl: m.default_ttl.map(|ttl| ttl.as_secs()),
         }
     }
 }
@@ -99,6 +113,12 @@ pub async fn list_types(
                 .map_err(|e| ApiError::internal(e.to_string()))?;
             Ok(FormatResponse::wave(wave).into_response())
         }
+        ContentFormat::Json => {
+            let json = keyspace_list
+                .to_json()
+                .map_err(|e| ApiError::internal(e.to_string()))?;
+            Ok(FormatResponse::json(&json).into_response())
+        }
         ContentFormat::Binary => {
             let (buffer, memory) = keyspace_list
                 .encode()
@@ -130,8 +150,29 @@ pub async fn get_type(
     Ok(Json(metadata.into()))
 }
 
+/// Get the JSON Schema of a keyspace's values.
+///
+/// The schema describes the `application/json` form of values, as returned
+/// by GET and accepted by PUT.
+#[instrument(skip(state), fields(database = %database, keyspace = %keyspace))]
+pub async fn get_type_schema(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    debug!("getting type schema");
+
+    let store = state.get_database(&database)?;
+
+    let metadata = store
+        .get_type(&keyspace)?
+        .ok_or_else(|| ApiError::keyspace_not_found(&database, &keyspace))?;
+
+    info!(type_name = %metadata.type_name, "generated type schema");
+    Ok(Json(metadata.json_schema()?))
+}
+
 /// Register a type for a keyspace.
-#[instrument(skip(state, body), fields(database = %database, keyspace = %keyspace, type_name = query.type_name.as_deref(), force = query.force))]
+#[instrument(skip(state, body), fields(database = %database, keyspace = %keyspace, type_name = query.type_name.as_deref(), force = query.force, allow_breaking = query.allow_breaking))]
 pub async fn set_type(
     State(state): State<AppState>,
     Path((database, keyspace)): Path<(String, String)>,
@@ -160,6 +201,7 @@ pub async fn set_type(
         temp_file.path(),
         query.type_name.as_deref(),
         query.force,
+        query.allow_breaking,
     )?;
 
     info!(
@@ -185,3 +227,47 @@ pub async fn delete_type(
     info!("type deleted");
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Set the default time-to-live of a keyspace.
+///
+/// The body is the time-to-live in seconds. Values written afterwards
+/// without an `X-TTL` header expire after that long.
+#[instrument(skip(state, body), fields(database = %database, keyspace = %keyspace))]
+pub async fn set_default_ttl(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Json<TypeMetadataResponse>, ApiError> {
+    debug!("setting default ttl");
+
+    let store = state.get_database(&database)?;
+    let seconds: u64 = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|body| body.trim().parse().ok())
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_TTL",
+                "The body must be a whole number of seconds",
+            )
+        })?;
+    let metadata = store.set_default_ttl(&keyspace, Some(Duration::from_secs(seconds)))?;
+
+    info!(seconds, "default ttl set");
+    Ok(Json(metadata.into()))
+}
+
+/// Clear the default time-to-live of a keyspace.
+#[instrument(skip(state), fields(database = %database, keyspace = %keyspace))]
+pub async fn clear_default_ttl(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+) -> Result<Json<TypeMetadataResponse>, ApiError> {
+    debug!("clearing default ttl");
+
+    let store = state.get_database(&database)?;
+    let metadata = store.set_default_ttl(&keyspace, None)?;
+
+    info!("default ttl cleared");
+    Ok(Json(metadata.into()))
+}
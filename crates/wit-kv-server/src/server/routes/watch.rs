@@ -0,0 +1,311 @@
+//! Change feed handlers.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        Path, Query, State,
+        ws::{
+            CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code,
+            rejection::WebSocketUpgradeRejection,
+        },
+    },
+    http::{HeaderMap, HeaderName, StatusCode},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures_util::stream;
+use serde::Deserialize;
+use tokio::sync::{OwnedSemaphorePermit, mpsc};
+use tracing::{Span, debug, info, instrument, warn};
+
+use wit_kv::kv::{BinaryExport, ChangeEvent, ChangeOp, KvError, Watch};
+
+use super::super::{
+    content::{AcceptFormat, ContentFormat},
+    error::ApiError,
+    state::AppState,
+};
+
+/// Sent by reconnecting `EventSource` clients: the id of the last event received.
+const LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
+
+/// How long the watching thread waits for a change before checking whether
+/// the client went away or the server is shutting down.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest close reason that fits a WebSocket close frame.
+const MAX_CLOSE_REASON: usize = 123;
+
+/// Encoded changes buffered between the watching thread and the response.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Query parameters for watching a keyspace.
+#[derive(Debug, Deserialize, Default)]
+pub struct WatchQuery {
+    /// Sequence number of the first change to send
+    pub from: Option<u64>,
+}
+
+/// A change encoded in the negotiated format.
+struct EncodedChange {
+    seq: u64,
+    op: &'static str,
+    payload: Vec<u8>,
+}
+
+/// Stream the changes committed to a keyspace.
+///
+/// WebSocket upgrade requests get one message per change; every other
+/// request gets a `text/event-stream` of Server-Sent Events, with the
+/// sequence number as event id and `set` or `delete` as event type.
+///
+/// In WAVE form (the default) each change is a record such as
+/// `{seq: 3, revision: 12, key: "a", op: set, value: some({x: 1, y: 2})}`.
+/// With `Accept: application/json`, over SSE or a WebSocket, it is an object
+/// such as `{"seq": 3, "revision": 12, "key": "a", "op": "set", "value": {"x": 1, "y": 2}}`,
+/// with a `null` value for deletes.
+/// With `Accept: application/octet-stream` WebSocket messages are binary: a
+/// little-endian `u64` sequence number, `u64` revision, `u8` operation (0 set,
+/// 1 delete), `u32` key length, the UTF-8 key and, for a set, the
+/// `binary-export` record of the value.
+///
+/// Changes start at `from` if given, else after the `Last-Event-ID` sent by
+/// a reconnecting SSE client, else with the next change committed. Once
+/// `max_watchers` streams are open, further requests get 503 Server Busy.
+#[instrument(skip(state, headers, format, upgrade), fields(database = %database, keyspace = %keyspace))]
+pub async fn watch_keyspace(
+    State(state): State<AppState>,
+    Path((database, keyspace)): Path<(String, String)>,
+    Query(query): Query<WatchQuery>,
+    headers: HeaderMap,
+    AcceptFormat(format): AcceptFormat,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response, ApiError> {
+    let from = match query.from {
+        Some(from) => Some(from),
+        None => last_event_id(&headers)?.map(|id| id.saturating_add(1)),
+    };
+    debug!(from, websocket = upgrade.is_ok(), "watching keyspace");
+
+    if upgrade.is_err() && format == ContentFormat::Binary {
+        return Err(ApiError::new(
+            StatusCode::NOT_ACCEPTABLE,
+            "NOT_ACCEPTABLE",
+            "Binary changes are only sent over a WebSocket",
+        ));
+    }
+
+    // Report a missing keyspace, trimmed changes or a sequence number past
+    // the log as an error response before the stream starts, and pin down
+    // where it starts
+    let store = state.get_database(&database)?;
+    let from = store.watch(&keyspace, from)?.next_seq();
+
+    // Each stream holds a blocking thread, so their number is bounded
+    let slot = state.try_acquire_watch_slot()?;
+    let changes = spawn_watch(state, slot, database, keyspace, from, format);
+    match upgrade {
+        Ok(upgrade) => {
+            Ok(upgrade.on_upgrade(move |socket| send_websocket(socket, changes, format)))
+        }
+        Err(_) => Ok(Sse::new(sse_events(changes))
+            .keep_alive(KeepAlive::default())
+            .into_response()),
+    }
+}
+
+fn last_event_id(headers: &HeaderMap) -> Result<Option<u64>, ApiError> {
+    let Some(value) = headers.get(LAST_EVENT_ID) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|id| id.trim().parse().ok())
+        .map(Some)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_EVENT_ID",
+                "Last-Event-ID must be a sequence number",
+            )
+        })
+}
+
+/// Watch a keyspace on a blocking thread, sending encoded changes until the
+/// receiver is dropped, the server shuts down or reading a change fails.
+/// The slot is released when the thread ends.
+fn spawn_watch(
+    state: AppState,
+    slot: OwnedSemaphorePermit,
+    database: String,
+    keyspace: String,
+    from: u64,
+    format: ContentFormat,
+) -> mpsc::Receiver<Result<EncodedChange, KvError>> {
+    // Waiting for changes blocks, so it runs off the async executor
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _slot = slot;
+        let _entered = span.enter();
+        let Ok(store) = state.get_database(&database) else {
+            return;
+        };
+        let mut watch = match store.watch(&keyspace, Some(from)) {
+            Ok(watch) => watch,
+            Err(e) => {
+                let _ = tx.blocking_send(Err(e));
+                return;
+            }
+        };
+
+        let mut count = 0;
+        while !tx.is_closed() && !state.is_shutting_down() {
+            let Some(event) = watch.next_timeout(POLL_INTERVAL) else {
+                continue;
+            };
+            let encoded = event.and_then(|event| encode_change(&watch, event, format));
+            let failed = encoded.is_err();
+            if let Err(e) = &encoded {
+                warn!(error = %e, "watch failed");
+            }
+            if tx.blocking_send(encoded).is_err() || failed {
+                break;
+            }
+            count += 1;
+        }
+        info!(count, next_seq = watch.next_seq(), "watch ended");
+    });
+    rx
+}
+
+/// Encode a change as a WAVE record, a JSON object or a binary frame.
+fn encode_change(
+    watch: &Watch<'_>,
+    event: ChangeEvent,
+    format: ContentFormat,
+) -> Result<EncodedChange, KvError> {
+    let seq = event.seq;
+    let op = event.op.as_str();
+    let payload = match format {
+        ContentFormat::Wave => {
+            let value = match event.value {
+                Some(value) => format!("some({})", watch.to_wave(value)?),
+                None => "none".to_string(),
+            };
+            format!(
+                "{{seq: {}, revision: {}, key: \"{}\", op: {}, value: {}}}",
+                event.seq,
+                event.revision,
+                event.key.escape_default(),
+                op,
+                value
+            )
+            .into_bytes()
+        }
+        ContentFormat::Json => {
+            let value = event.value.map(|value| watch.to_json(value)).transpose()?;
+            serde_json::json!({
+                "seq": event.seq,
+                "revision": event.revision,
+                "key": event.key,
+                "op": op,
+                "value": value,
+            })
+            .to_string()
+            .into_bytes()
+        }
+        ContentFormat::Binary => binary_frame(event)?,
+    };
+    Ok(EncodedChange { seq, op, payload })
+}
+
+/// One binary change message.
+fn binary_frame(event: ChangeEvent) -> Result<Vec<u8>, KvError> {
+    let key_len = u32::try_from(event.key.len())
+        .map_err(|_| KvError::InvalidFormat("Key too long for a change frame".to_string()))?;
+    let mut frame = Vec::with_capacity(21 + event.key.len());
+    frame.extend(event.seq.to_le_bytes());
+    frame.extend(event.revision.to_le_bytes());
+    frame.push(match event.op {
+        ChangeOp::Set => 0,
+        ChangeOp::Delete => 1,
+    });
+    frame.extend(key_len.to_le_bytes());
+    frame.extend(event.key.as_bytes());
+    if let Some(value) = event.value {
+        let (buffer, memory) = BinaryExport::from_stored_owned(value).encode()?;
+        frame.extend(buffer);
+        frame.extend(memory);
+    }
+    Ok(frame)
+}
+
+/// Server-Sent Events for the changes; a failure ends the stream with an
+/// `error` event.
+fn sse_events(
+    changes: mpsc::Receiver<Result<EncodedChange, KvError>>,
+) -> impl futures_util::Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(changes, |mut changes| async move {
+        let event = match changes.recv().await? {
+            Ok(change) => Event::default()
+                .id(change.seq.to_string())
+                .event(change.op)
+                .data(String::from_utf8_lossy(&change.payload)),
+            Err(e) => Event::default().event("error").data(e.to_string()),
+        };
+        Some((Ok(event), changes))
+    })
+}
+
+/// Send the changes as WebSocket messages until either side closes.
+async fn send_websocket(
+    mut socket: WebSocket,
+    mut changes: mpsc::Receiver<Result<EncodedChange, KvError>>,
+    format: ContentFormat,
+) {
+    loop {
+        tokio::select! {
+            change = changes.recv() => {
+                let message = match change {
+                    Some(Ok(change)) => match format {
+                        ContentFormat::Wave | ContentFormat::Json => {
+                            Message::Text(String::from_utf8_lossy(&change.payload).as_ref().into())
+                        }
+                        ContentFormat::Binary => Message::Binary(change.payload.into()),
+                    },
+                    Some(Err(e)) => Message::Close(Some(CloseFrame {
+                        code: close_code::ERROR,
+                        reason: close_reason(&e.to_string()).into(),
+                    })),
+                    None => Message::Close(None),
+                };
+                let closing = matches!(message, Message::Close(_));
+                if socket.send(message).await.is_err() || closing {
+                    break;
+                }
+            }
+            received = socket.recv() => {
+                // Clients only send control messages; stop once they close
+                if !matches!(received, Some(Ok(_))) || matches!(received, Some(Ok(Message::Close(_)))) {
+                    break;
+                }
+            }
+        }
+    }
+    debug!("websocket closed");
+}
+
+/// `message`, cut at a character boundary to fit a close frame.
+fn close_reason(message: &str) -> &str {
+    let mut end = message.len().min(MAX_CLOSE_REASON);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    message.get(..end).unwrap_or_default()
+}
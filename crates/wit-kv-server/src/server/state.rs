@@ -1,32 +1,63 @@
 //! Application state management.
 
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
 use wit_kv::kv::KvStore;
+use wit_kv::wasm::ExecutionLimits;
 
 use super::config::{Config, DatabaseConfig};
 use super::error::ApiError;
+use super::jobs::{self, JobRegistry};
 
 /// Shared application state.
 #[derive(Clone)]
 pub struct AppState {
     /// Map of database name to KvStore instance.
     databases: Arc<HashMap<String, KvStore>>,
+    /// Map of database name to the directory its backups are written to.
+    backup_dirs: Arc<HashMap<String, PathBuf>>,
+    /// Map of database name to the limits of modules run against it.
+    limits: Arc<HashMap<String, ExecutionLimits>>,
+    /// Slots of the jobs that may run at once.
+    job_slots: Arc<Semaphore>,
+    /// Slots of the change feeds that may be streamed at once.
+    watch_slots: Arc<Semaphore>,
+    /// Background jobs that have not finished yet.
+    jobs: JobRegistry,
+    /// Set when the server starts shutting down, to end long-lived streams.
+    shutting_down: Arc<AtomicBool>,
 }
 
 impl AppState {
     /// Create a new AppState from configuration.
     pub fn from_config(config: &Config) -> Result<Self, StateError> {
         let mut databases = HashMap::new();
+        let mut backup_dirs = HashMap::new();
+        let mut limits = HashMap::new();
 
         for db_config in &config.databases {
             let store = Self::open_or_init_database(db_config)?;
             databases.insert(db_config.name.clone(), store);
+            if let Some(dir) = &db_config.backup_dir {
+                backup_dirs.insert(db_config.name.clone(), PathBuf::from(dir));
+            }
+            limits.insert(db_config.name.clone(), db_config.limits.execution_limits());
         }
 
         Ok(Self {
             databases: Arc::new(databases),
+            backup_dirs: Arc::new(backup_dirs),
+            limits: Arc::new(limits),
+            job_slots: Arc::new(Semaphore::new(config.server.max_concurrent_jobs.max(1))),
+            watch_slots: Arc::new(Semaphore::new(config.server.max_watchers.max(1))),
+            jobs: JobRegistry::default(),
+            shutting_down: Arc::new(AtomicBool::new(false)),
         })
     }
 
@@ -37,27 +68,147 @@ impl AppState {
             .ok_or_else(|| ApiError::database_not_found(name))
     }
 
+    /// Directory backups of a database are written to, if configured.
+    pub fn backup_dir(&self, name: &str) -> Option<&std::path::Path> {
+        self.backup_dirs.get(name).map(PathBuf::as_path)
+    }
+
+    /// Resource limits of modules run against a database.
+    pub fn execution_limits(&self, name: &str) -> ExecutionLimits {
+        self.limits.get(name).copied().unwrap_or_default()
+    }
+
+    /// Take a job slot, held until the permit is dropped.
+    ///
+    /// Fails with `503 SERVER_BUSY` if all slots are taken.
+    pub fn try_acquire_job_slot(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        Arc::clone(&self.job_slots)
+            .try_acquire_owned()
+            .map_err(|_| ApiError::server_busy())
+    }
+
+    /// Wait for a job slot, held until the permit is dropped.
+    pub async fn acquire_job_slot(&self) -> Option<OwnedSemaphorePermit> {
+        Arc::clone(&self.job_slots).acquire_owned().await.ok()
+    }
+
+    /// Take a change feed slot, held until the permit is dropped.
+    ///
+    /// Fails with `503 SERVER_BUSY` if all slots are taken.
+    pub fn try_acquire_watch_slot(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        Arc::clone(&self.watch_slots)
+            .try_acquire_owned()
+            .map_err(|_| ApiError::watchers_busy())
+    }
+
+    /// Background jobs that have not finished yet.
+    pub fn jobs(&self) -> &JobRegistry {
+        &self.jobs
+    }
+
     /// List all database names.
     pub fn database_names(&self) -> Vec<&str> {
         self.databases.keys().map(String::as_str).collect()
     }
 
+    /// End open change feeds and stop background jobs so a graceful
+    /// shutdown does not wait for them.
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        self.jobs.stop_all();
+    }
+
+    /// Whether [`shut_down`](Self::shut_down) was called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Start a background task per database that purges expired values at
+    /// its configured `purge_interval_ms`.
+    ///
+    /// Must be called from within the Tokio runtime. The tasks run until the
+    /// runtime shuts down.
+    pub fn spawn_purge_tasks(&self, config: &Config) {
+        for db_config in &config.databases {
+            let Some(interval) = db_config.purge_interval() else {
+                continue;
+            };
+            info!(
+                database = %db_config.name,
+                interval_ms = db_config.purge_interval_ms,
+                "purging expired values periodically"
+            );
+            let state = self.clone();
+            let name = db_config.name.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    ticker.tick().await;
+                    state.purge_expired(&name).await;
+                }
+            });
+        }
+    }
+
+    /// Purge the expired values of a database, off the async executor.
+    async fn purge_expired(&self, name: &str) {
+        let state = self.clone();
+        let database = name.to_string();
+        let purged = tokio::task::spawn_blocking(move || {
+            state
+                .databases
+                .get(&database)
+                .map(KvStore::purge_expired)
+                .transpose()
+        })
+        .await;
+
+        match purged {
+            Ok(Ok(Some(count))) => debug!(database = %name, count, "expired values purged"),
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => warn!(database = %name, error = %e, "failed to purge expired values"),
+            Err(e) => warn!(database = %name, error = %e, "purge task failed"),
+        }
+    }
+
     fn open_or_init_database(config: &DatabaseConfig) -> Result<KvStore, StateError> {
         let path = std::path::Path::new(&config.path);
 
-        if path.exists() {
+        let store = if path.exists() {
             KvStore::open(path).map_err(|e| StateError::OpenDatabase {
                 name: config.name.clone(),
                 path: config.path.clone(),
                 source: e,
-            })
+            })?
         } else {
             KvStore::init(path).map_err(|e| StateError::InitDatabase {
                 name: config.name.clone(),
                 path: config.path.clone(),
                 source: e,
-            })
+            })?
+        };
+
+        // Jobs of a previous run are not resumed
+        match jobs::interrupt_unfinished_jobs(&store) {
+            Ok(0) => {}
+            Ok(count) => {
+                warn!(database = %config.name, count, "marked unfinished jobs as interrupted")
+            }
+            Err(e) => {
+                warn!(database = %config.name, error = %e.message, "failed to check job records")
+            }
         }
+
+        store
+            .with_upgrade_on_read(config.upgrade_on_read)
+            .with_change_retention(config.change_retention)
+            .with_durability(config.durability())
+            .map_err(|e| StateError::OpenDatabase {
+                name: config.name.clone(),
+                path: config.path.clone(),
+                source: e,
+            })
     }
 }
 
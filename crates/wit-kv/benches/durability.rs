@@ -0,0 +1,96 @@
+//! Write throughput for each durability mode.
+//!
+//! Run with `cargo bench -p wit-kv --bench durability`. Set `WRITES` to change
+//! the number of values written per mode (default 2000).
+
+use std::error::Error;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use wit_kv::{Durability, KvStore};
+
+const TYPES_WIT: &str = r#"package bench:types;
+
+interface types {
+    record event {
+        id: u64,
+        name: string,
+        tags: list<string>,
+    }
+}
+"#;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let writes: usize = match std::env::var("WRITES") {
+        Ok(n) => n.parse()?,
+        Err(_) => 2000,
+    };
+
+    let root = std::env::temp_dir().join(format!("wit-kv-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&root)?;
+    let wit_path = root.join("types.wit");
+    std::fs::write(&wit_path, TYPES_WIT)?;
+
+    println!("{} writes per mode", writes);
+    println!("{:<28} {:>12} {:>14}", "mode", "total", "writes/s");
+
+    let modes = [
+        ("set, sync (default)", Durability::SyncAll, false),
+        (
+            "set, interval(100ms)",
+            Durability::Interval(Duration::from_millis(100)),
+            false,
+        ),
+        ("set, buffer", Durability::Buffer, false),
+        ("batch, sync", Durability::SyncAll, true),
+    ];
+    for (index, (label, durability, batched)) in modes.into_iter().enumerate() {
+        let store_path = root.join(format!("store-{}", index));
+        let elapsed = run(&store_path, &wit_path, durability, batched, writes)?;
+        println!(
+            "{:<28} {:>10.1}ms {:>14.0}",
+            label,
+            elapsed.as_secs_f64() * 1000.0,
+            writes as f64 / elapsed.as_secs_f64()
+        );
+    }
+
+    std::fs::remove_dir_all(&root)?;
+    Ok(())
+}
+
+/// Write `writes` values and return the time taken, including the final flush.
+fn run(
+    store_path: &Path,
+    wit_path: &Path,
+    durability: Durability,
+    batched: bool,
+    writes: usize,
+) -> Result<Duration, Box<dyn Error>> {
+    let store = KvStore::init(store_path)?.with_durability(durability)?;
+    store.set_type("events", wit_path, Some("event"), false, false)?;
+
+    let values: Vec<(String, String)> = (0..writes)
+        .map(|i| {
+            (
+                format!("event-{:08}", i),
+                format!("{{id: {}, name: \"event {}\", tags: [\"a\", \"b\"]}}", i, i),
+            )
+        })
+        .collect();
+
+    let start = Instant::now();
+    if batched {
+        let mut batch = store.batch();
+        for (key, value) in &values {
+            batch.set("events", key, value)?;
+        }
+        batch.commit()?;
+    } else {
+        for (key, value) in &values {
+            store.set("events", key, value)?;
+        }
+    }
+    store.flush()?;
+    Ok(start.elapsed())
+}
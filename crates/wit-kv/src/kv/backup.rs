@@ -0,0 +1,400 @@
+//! Backup archives of a whole store, written by [`KvStore::snapshot_to`] and
+//! read by [`KvStore::restore`].
+//!
+//! An archive is a magic number and archive version followed by records,
+//! each framed with its own checksum:
+//!
+//! ```text
+//! archive: "WITKVBAK" version (u32) record*
+//! record:  tag (u8) length (u32) payload crc32 (u32)
+//! ```
+//!
+//! Integers are little-endian, and the CRC-32 covers the tag, length and
+//! payload. Strings are UTF-8 prefixed with their `u32` length.
+//!
+//! | Tag | Record       | Payload                                                   |
+//! |-----|--------------|-----------------------------------------------------------|
+//! | 1   | header       | store version (u32), revision (u64), created-at (u64, ms) |
+//! | 2   | type         | `keyspace-metadata` of a keyspace's current type          |
+//! | 3   | type version | `keyspace-metadata` of an earlier type version            |
+//! | 4   | index        | keyspace (string), field path (string)                    |
+//! | 5   | value        | keyspace (string), key (string), `stored-value`           |
+//! | 255 | end          | number of records before it (u64)                         |
+//!
+//! The header comes first and the end record last, so a truncated archive
+//! is detected. Types and indexes come before any value. Metadata and values
+//! use the canonical ABI records of `kv.wit`, so values are restored exactly
+//! as stored, with their type version, revision and expiry.
+
+use std::io::{self, Read, Write};
+
+use super::error::KvError;
+use super::types::{KeyspaceMetadata, StoredValue};
+
+/// Magic number at the start of every archive.
+const MAGIC: &[u8; 8] = b"WITKVBAK";
+
+/// Current archive version.
+/// Increment this when changing the framing or a record layout.
+const ARCHIVE_VERSION: u32 = 1;
+
+const TAG_HEADER: u8 = 1;
+const TAG_TYPE: u8 = 2;
+const TAG_TYPE_VERSION: u8 = 3;
+const TAG_INDEX: u8 = 4;
+const TAG_VALUE: u8 = 5;
+const TAG_END: u8 = 255;
+
+/// Summary of a backup archive, returned by [`KvStore::snapshot_to`] and
+/// [`KvStore::restore`].
+///
+/// [`KvStore::snapshot_to`]: super::KvStore::snapshot_to
+/// [`KvStore::restore`]: super::KvStore::restore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// Store revision the archive was taken at.
+    pub revision: u64,
+    /// Unix time in milliseconds at which the archive was taken.
+    pub created_at: u64,
+    /// Number of keyspaces in the archive.
+    pub keyspaces: usize,
+    /// Number of values in the archive.
+    pub values: u64,
+}
+
+/// One record of an archive.
+#[derive(Debug, Clone)]
+pub(super) enum Record {
+    Header {
+        store_version: u32,
+        revision: u64,
+        created_at: u64,
+    },
+    Type(KeyspaceMetadata),
+    TypeVersion(KeyspaceMetadata),
+    Index {
+        keyspace: String,
+        field_path: String,
+    },
+    Value {
+        keyspace: String,
+        key: String,
+        value: StoredValue,
+    },
+    End {
+        records: u64,
+    },
+}
+
+/// Writes records to an archive.
+pub(super) struct ArchiveWriter<W: Write> {
+    out: W,
+    records: u64,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Start an archive, writing the magic number and version.
+    pub(super) fn new(mut out: W) -> Result<Self, KvError> {
+        out.write_all(MAGIC)?;
+        out.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+        Ok(Self { out, records: 0 })
+    }
+
+    pub(super) fn write(&mut self, record: &Record) -> Result<(), KvError> {
+        let mut payload = Vec::new();
+        let tag = match record {
+            Record::Header {
+                store_version,
+                revision,
+                created_at,
+            } => {
+                payload.extend(store_version.to_le_bytes());
+                payload.extend(revision.to_le_bytes());
+                payload.extend(created_at.to_le_bytes());
+                TAG_HEADER
+            }
+            Record::Type(metadata) => {
+                payload = metadata.to_bytes()?;
+                TAG_TYPE
+            }
+            Record::TypeVersion(metadata) => {
+                payload = metadata.to_bytes()?;
+                TAG_TYPE_VERSION
+            }
+            Record::Index {
+                keyspace,
+                field_path,
+            } => {
+                put_str(&mut payload, keyspace)?;
+                put_str(&mut payload, field_path)?;
+                TAG_INDEX
+            }
+            Record::Value {
+                keyspace,
+                key,
+                value,
+            } => {
+                put_str(&mut payload, keyspace)?;
+                put_str(&mut payload, key)?;
+                payload.extend(value.to_bytes()?);
+                TAG_VALUE
+            }
+            Record::End { records } => {
+                payload.extend(records.to_le_bytes());
+                TAG_END
+            }
+        };
+
+        let mut frame = Vec::with_capacity(payload.len() + 9);
+        frame.push(tag);
+        frame.extend(len_u32(payload.len())?.to_le_bytes());
+        frame.extend(payload);
+        let checksum = crc32fast::hash(&frame);
+        self.out.write_all(&frame)?;
+        self.out.write_all(&checksum.to_le_bytes())?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Write the end record and return the underlying writer.
+    pub(super) fn finish(mut self) -> Result<W, KvError> {
+        self.write(&Record::End {
+            records: self.records,
+        })?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Reads and verifies the records of an archive.
+pub(super) struct ArchiveReader<R: Read> {
+    input: R,
+    records: u64,
+    ended: bool,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    /// Open an archive, checking the magic number and version.
+    pub(super) fn new(mut input: R) -> Result<Self, KvError> {
+        let mut magic = [0; 8];
+        read_exact(&mut input, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a wit-kv backup archive"));
+        }
+        let version = u32::from_le_bytes(read_array(&mut input)?);
+        if version != ARCHIVE_VERSION {
+            return Err(invalid(&format!(
+                "unsupported archive version {} (expected {})",
+                version, ARCHIVE_VERSION
+            )));
+        }
+        Ok(Self {
+            input,
+            records: 0,
+            ended: false,
+        })
+    }
+
+    /// The next record before the end record, or `None` after it.
+    ///
+    /// Fails on a checksum mismatch, or if the archive ends before its end
+    /// record or does not hold as many records as the end record says.
+    pub(super) fn next_record(&mut self) -> Result<Option<Record>, KvError> {
+        if self.ended {
+            return Ok(None);
+        }
+
+        let [tag] = read_array::<1>(&mut self.input)?;
+        let len = u32::from_le_bytes(read_array(&mut self.input)?);
+        // Not preallocated: a corrupted length must not allocate gigabytes
+        let mut payload = Vec::new();
+        (&mut self.input)
+            .take(u64::from(len))
+            .read_to_end(&mut payload)?;
+        if payload.len() != usize::try_from(len).unwrap_or(usize::MAX) {
+            return Err(invalid("archive is truncated"));
+        }
+        let checksum = u32::from_le_bytes(read_array(&mut self.input)?);
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&[tag]);
+        hasher.update(&len.to_le_bytes());
+        hasher.update(&payload);
+        if hasher.finalize() != checksum {
+            return Err(invalid(&format!(
+                "checksum mismatch in record {}",
+                self.records + 1
+            )));
+        }
+
+        let mut cursor = payload.as_slice();
+        let record = match tag {
+            TAG_HEADER => Record::Header {
+                store_version: u32::from_le_bytes(take_array(&mut cursor)?),
+                revision: u64::from_le_bytes(take_array(&mut cursor)?),
+                created_at: u64::from_le_bytes(take_array(&mut cursor)?),
+            },
+            TAG_TYPE => Record::Type(KeyspaceMetadata::from_bytes(&payload)?),
+            TAG_TYPE_VERSION => Record::TypeVersion(KeyspaceMetadata::from_bytes(&payload)?),
+            TAG_INDEX => Record::Index {
+                keyspace: take_str(&mut cursor)?,
+                field_path: take_str(&mut cursor)?,
+            },
+            TAG_VALUE => Record::Value {
+                keyspace: take_str(&mut cursor)?,
+                key: take_str(&mut cursor)?,
+                value: StoredValue::from_bytes(cursor)?,
+            },
+            TAG_END => {
+                let records = u64::from_le_bytes(take_array(&mut cursor)?);
+                if records != self.records {
+                    return Err(invalid(&format!(
+                        "archive holds {} records, its end record says {}",
+                        self.records, records
+                    )));
+                }
+                self.ended = true;
+                return Ok(None);
+            }
+            tag => return Err(invalid(&format!("unknown record tag {}", tag))),
+        };
+        self.records += 1;
+        Ok(Some(record))
+    }
+}
+
+fn invalid(reason: &str) -> KvError {
+    KvError::InvalidFormat(format!("Invalid backup archive: {}", reason))
+}
+
+fn len_u32(len: usize) -> Result<u32, KvError> {
+    u32::try_from(len).map_err(|_| invalid(&format!("{} bytes do not fit a record", len)))
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) -> Result<(), KvError> {
+    out.extend(len_u32(s.len())?.to_le_bytes());
+    out.extend(s.as_bytes());
+    Ok(())
+}
+
+/// Read exactly `buf.len()` bytes, reporting a premature end as truncation.
+fn read_exact(input: &mut impl Read, buf: &mut [u8]) -> Result<(), KvError> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid("archive is truncated"),
+        _ => KvError::Io(e),
+    })
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> Result<[u8; N], KvError> {
+    let mut buf = [0; N];
+    read_exact(input, &mut buf)?;
+    Ok(buf)
+}
+
+fn take_array<const N: usize>(cursor: &mut &[u8]) -> Result<[u8; N], KvError> {
+    let (head, rest) = cursor
+        .split_first_chunk::<N>()
+        .ok_or_else(|| invalid("record is too short"))?;
+    *cursor = rest;
+    Ok(*head)
+}
+
+fn take_str(cursor: &mut &[u8]) -> Result<String, KvError> {
+    let len = u32::from_le_bytes(take_array(cursor)?);
+    let len = usize::try_from(len).unwrap_or(usize::MAX);
+    if cursor.len() < len {
+        return Err(invalid("record is too short"));
+    }
+    let (s, rest) = cursor.split_at(len);
+    *cursor = rest;
+    String::from_utf8(s.to_vec()).map_err(|_| invalid("string is not UTF-8"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::kv::SemanticVersion;
+
+    fn records() -> Vec<Record> {
+        let mut value = StoredValue::new(SemanticVersion::INITIAL, vec![1, 0, 0, 0], None);
+        value.revision = 4;
+        value.expires_at = Some(1_000);
+        vec![
+            Record::Header {
+                store_version: 2,
+                revision: 4,
+                created_at: 1_700_000_000_000,
+            },
+            Record::Type(KeyspaceMetadata::new(
+                "points".to_string(),
+                "app:types/types#point".to_string(),
+                "record point { x: s32 }".to_string(),
+                "point".to_string(),
+            )),
+            Record::Index {
+                keyspace: "points".to_string(),
+                field_path: "x".to_string(),
+            },
+            Record::Value {
+                keyspace: "points".to_string(),
+                key: "origin".to_string(),
+                value,
+            },
+        ]
+    }
+
+    fn archive() -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        for record in &records() {
+            writer.write(record).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn read_all(archive: &[u8]) -> Result<Vec<Record>, KvError> {
+        let mut reader = ArchiveReader::new(archive)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let read = read_all(&archive()).unwrap();
+        assert_eq!(read.len(), 4);
+        assert!(matches!(
+            read.first(),
+            Some(Record::Header {
+                store_version: 2,
+                revision: 4,
+                created_at: 1_700_000_000_000,
+            })
+        ));
+        assert!(matches!(read.get(1), Some(Record::Type(m)) if m.name == "points"));
+        assert!(matches!(read.get(2), Some(Record::Index { field_path, .. }) if field_path == "x"));
+        assert!(matches!(
+            read.get(3),
+            Some(Record::Value { key, value, .. })
+                if key == "origin" && value.expires_at == Some(1_000) && value.revision == 4
+        ));
+    }
+
+    #[test]
+    fn test_detects_corruption() {
+        let archive = archive();
+
+        let mut corrupted = archive.clone();
+        if let Some(byte) = corrupted.get_mut(20) {
+            *byte ^= 0xff;
+        }
+        assert!(read_all(&corrupted).is_err());
+
+        let truncated = archive.get(..archive.len() - 5).unwrap();
+        assert!(read_all(truncated).is_err());
+
+        assert!(read_all(b"NOTANARCHIVE").is_err());
+    }
+}
@@ -0,0 +1,566 @@
+//! Atomic multi-key writes across keyspaces.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use fjall::Keyspace;
+use wasm_wave::value::Value;
+use wit_parser::Type;
+
+use crate::logging::{debug, error, trace};
+use crate::{LinearMemory, json_to_wave};
+
+use super::cache::KeyspaceType;
+use super::error::KvError;
+use super::expiry::{expires_at, now_millis};
+use super::format::BinaryExport;
+use super::store::KvStore;
+use super::types::StoredValue;
+
+/// Values committed per batch by a map writing its results into a keyspace,
+/// unless configured otherwise.
+pub const DEFAULT_MAP_BATCH_SIZE: usize = 1000;
+
+/// A set of writes, possibly spanning several keyspaces, that is committed
+/// atomically with a single fsync.
+///
+/// Values are parsed (or, for raw values, lifted) and type-checked against
+/// their keyspace's type when they are added, so a batch that was built without errors only fails to
+/// commit on I/O errors, unmet preconditions or, with
+/// [`KvError::TypeVersionMismatch`], if the type of one of its keyspaces
+/// was changed in the meantime (e.g. by a migration). Writing the same key twice
+/// keeps the last write. Nothing is visible to readers until
+/// [`commit`](Self::commit) is called; dropping the batch discards it.
+///
+/// Conditional writes ([`set_if`](Self::set_if), [`set_if_absent`](Self::set_if_absent),
+/// [`delete_if`](Self::delete_if)) are checked against the store as it is
+/// before the batch, at commit time. If any precondition fails, nothing is
+/// written and [`KvError::RevisionConflict`] is returned, or
+/// [`KvError::ValueMissing`] for the `_if_exists` variants. Expired values
+/// count as absent.
+///
+/// Values get their keyspace's default time-to-live, if it has one, unless
+/// they are given another with [`set_ttl`](Self::set_ttl).
+///
+/// # Example
+///
+/// ```ignore
+/// use wit_kv::KvStore;
+///
+/// let store = KvStore::open(".wit-kv")?;
+/// let mut batch = store.batch();
+/// batch.set("users", "alice", "{name: \"Alice\", age: 30}")?;
+/// batch.set("points", "origin", "{x: 0, y: 0}")?;
+/// batch.delete("users", "bob")?;
+/// let revision = batch.commit()?;
+/// ```
+pub struct WriteBatch<'a> {
+    store: &'a KvStore,
+    keyspaces: HashMap<String, BatchKeyspace>,
+    writes: BTreeMap<(String, String), PendingWrite>,
+}
+
+/// Per-keyspace state resolved on first use within a batch.
+#[derive(Clone)]
+struct BatchKeyspace {
+    ty: Arc<KeyspaceType>,
+    data: Keyspace,
+}
+
+/// A queued write, applied by [`KvStore::commit_writes`].
+pub(super) struct PendingWrite {
+    pub(super) keyspace: String,
+    pub(super) key: String,
+    /// Type of the keyspace when the write was queued.
+    pub(super) ty: Arc<KeyspaceType>,
+    pub(super) data: Keyspace,
+    /// Value to store (revision assigned on commit), or `None` for a delete.
+    pub(super) value: Option<StoredValue>,
+    /// Conditions on the key's state before the batch, all of which must hold.
+    pub(super) preconditions: Vec<Precondition>,
+}
+
+/// Expected state of a key before a conditional write.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Precondition {
+    /// The key exists with exactly this revision.
+    Revision(u64),
+    /// The key does not exist.
+    Absent,
+    /// The key exists, whatever its revision.
+    Exists,
+}
+
+impl Precondition {
+    /// Check the precondition against the key's current revision.
+    pub(super) fn check(self, key: &str, actual: Option<u64>) -> Result<(), KvError> {
+        let expected = match self {
+            Precondition::Revision(revision) => Some(revision),
+            Precondition::Absent => None,
+            Precondition::Exists if actual.is_some() => return Ok(()),
+            Precondition::Exists => {
+                return Err(KvError::ValueMissing {
+                    key: key.to_string(),
+                });
+            }
+        };
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(KvError::RevisionConflict {
+                key: key.to_string(),
+                expected,
+                actual,
+            })
+        }
+    }
+}
+
+impl<'a> WriteBatch<'a> {
+    pub(super) fn new(store: &'a KvStore) -> Self {
+        Self {
+            store,
+            keyspaces: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Queue setting a WAVE-encoded value.
+    pub fn set(&mut self, keyspace: &str, key: &str, wave_value: &str) -> Result<(), KvError> {
+        self.queue_set(keyspace, key, wave_value, None)
+    }
+
+    /// Queue setting a WAVE-encoded value if the key currently has `expected_revision`.
+    pub fn set_if(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        wave_value: &str,
+        expected_revision: u64,
+    ) -> Result<(), KvError> {
+        self.queue_set(
+            keyspace,
+            key,
+            wave_value,
+            Some(Precondition::Revision(expected_revision)),
+        )
+    }
+
+    /// Queue setting a WAVE-encoded value if the key does not exist.
+    pub fn set_if_absent(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        wave_value: &str,
+    ) -> Result<(), KvError> {
+        self.queue_set(keyspace, key, wave_value, Some(Precondition::Absent))
+    }
+
+    /// Queue setting a WAVE-encoded value if the key exists, whatever its revision.
+    pub fn set_if_exists(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        wave_value: &str,
+    ) -> Result<(), KvError> {
+        self.queue_set(keyspace, key, wave_value, Some(Precondition::Exists))
+    }
+
+    /// Queue setting a WAVE-encoded value that expires after `ttl`.
+    pub fn set_with_ttl(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        wave_value: &str,
+        ttl: Duration,
+    ) -> Result<(), KvError> {
+        self.queue_set(keyspace, key, wave_value, None)?;
+        self.set_ttl(keyspace, key, Some(ttl))
+    }
+
+    /// Queue setting a JSON value, in the mapping of [`json_to_wave`].
+    pub fn set_json(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        json_value: &serde_json::Value,
+    ) -> Result<(), KvError> {
+        self.queue_set_json(keyspace, key, json_value, None)
+    }
+
+    /// Queue setting a JSON value if the key currently has `expected_revision`.
+    pub fn set_json_if(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        json_value: &serde_json::Value,
+        expected_revision: u64,
+    ) -> Result<(), KvError> {
+        self.queue_set_json(
+            keyspace,
+            key,
+            json_value,
+            Some(Precondition::Revision(expected_revision)),
+        )
+    }
+
+    /// Queue setting a JSON value if the key does not exist.
+    pub fn set_json_if_absent(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        json_value: &serde_json::Value,
+    ) -> Result<(), KvError> {
+        self.queue_set_json(keyspace, key, json_value, Some(Precondition::Absent))
+    }
+
+    /// Queue setting a JSON value if the key exists, whatever its revision.
+    pub fn set_json_if_exists(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        json_value: &serde_json::Value,
+    ) -> Result<(), KvError> {
+        self.queue_set_json(keyspace, key, json_value, Some(Precondition::Exists))
+    }
+
+    /// Queue setting a canonical ABI encoded value.
+    ///
+    /// The value is validated by lifting it with the keyspace's type, so
+    /// out-of-bounds pointers, invalid discriminants and invalid UTF-8 are
+    /// rejected with [`KvError::InvalidValue`].
+    pub fn set_raw(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+    ) -> Result<(), KvError> {
+        self.queue_set_raw(keyspace, key, value, None)
+    }
+
+    /// Queue setting a value encoded with the keyspace's type, such as the
+    /// output of a component's `transform`.
+    ///
+    /// The value is validated like [`set_raw`](Self::set_raw), without
+    /// copying it, and stored with the keyspace's current type version in
+    /// place of its own.
+    pub fn set_stored(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        value: StoredValue,
+    ) -> Result<(), KvError> {
+        self.queue_encoded(keyspace, key, value.value, value.memory, None)
+    }
+
+    /// Queue setting a canonical ABI encoded value if the key currently has
+    /// `expected_revision`.
+    pub fn set_raw_if(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+        expected_revision: u64,
+    ) -> Result<(), KvError> {
+        self.queue_set_raw(
+            keyspace,
+            key,
+            value,
+            Some(Precondition::Revision(expected_revision)),
+        )
+    }
+
+    /// Queue setting a canonical ABI encoded value if the key does not exist.
+    pub fn set_raw_if_absent(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+    ) -> Result<(), KvError> {
+        self.queue_set_raw(keyspace, key, value, Some(Precondition::Absent))
+    }
+
+    /// Queue setting a canonical ABI encoded value if the key exists,
+    /// whatever its revision.
+    pub fn set_raw_if_exists(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+    ) -> Result<(), KvError> {
+        self.queue_set_raw(keyspace, key, value, Some(Precondition::Exists))
+    }
+
+    /// Queue setting a canonical ABI encoded value that expires after `ttl`.
+    pub fn set_raw_with_ttl(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+        ttl: Duration,
+    ) -> Result<(), KvError> {
+        self.queue_set_raw(keyspace, key, value, None)?;
+        self.set_ttl(keyspace, key, Some(ttl))
+    }
+
+    /// Set the time-to-live of the value queued for a key, counted from now,
+    /// in place of the keyspace's default. `None` makes the value never expire.
+    ///
+    /// Applies to the last value queued for the key, so it must come after
+    /// the set. Returns [`KvError::KeyNotFound`] if no value is queued for
+    /// the key, e.g. because it is queued for deletion.
+    pub fn set_ttl(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), KvError> {
+        let stored = self
+            .writes
+            .get_mut(&(keyspace.to_string(), key.to_string()))
+            .and_then(|write| write.value.as_mut())
+            .ok_or_else(|| KvError::KeyNotFound(key.to_string()))?;
+        stored.expires_at = ttl.map(|ttl| expires_at(now_millis(), ttl));
+        Ok(())
+    }
+
+    /// Queue deleting a key. Deleting a key that does not exist is not an error.
+    pub fn delete(&mut self, keyspace: &str, key: &str) -> Result<(), KvError> {
+        self.queue_delete(keyspace, key, None)
+    }
+
+    /// Queue deleting a key if it currently has `expected_revision`.
+    pub fn delete_if(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        expected_revision: u64,
+    ) -> Result<(), KvError> {
+        self.queue_delete(
+            keyspace,
+            key,
+            Some(Precondition::Revision(expected_revision)),
+        )
+    }
+
+    /// Queue deleting a key if it exists, whatever its revision.
+    pub fn delete_if_exists(&mut self, keyspace: &str, key: &str) -> Result<(), KvError> {
+        self.queue_delete(keyspace, key, Some(Precondition::Exists))
+    }
+
+    /// Number of distinct keys written by this batch.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Returns `true` if nothing has been queued.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Atomically apply every queued write and persist it with a single fsync.
+    ///
+    /// Returns the store revision assigned to the written values.
+    pub fn commit(self) -> Result<u64, KvError> {
+        trace!(count = self.writes.len(), "committing batch");
+        let revision = self
+            .store
+            .commit_writes(self.writes.into_values().collect())?;
+
+        debug!(revision = revision, "batch committed");
+        Ok(revision)
+    }
+
+    fn queue_set(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        wave_value: &str,
+        precondition: Option<Precondition>,
+    ) -> Result<(), KvError> {
+        trace!(keyspace = keyspace, key = key, "batch set");
+
+        let target = self.keyspace(keyspace)?.clone();
+        let ty = &target.ty;
+
+        // Parse the WAVE value
+        let value: Value = wasm_wave::from_str(&ty.wave_type, wave_value).map_err(|e| {
+            error!(keyspace = keyspace, key = key, error = %e, "failed to parse WAVE value");
+            KvError::WaveParse(e.to_string())
+        })?;
+        self.queue_value(keyspace, key, target, &value, precondition)
+    }
+
+    fn queue_set_json(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        json_value: &serde_json::Value,
+        precondition: Option<Precondition>,
+    ) -> Result<(), KvError> {
+        trace!(keyspace = keyspace, key = key, "batch set json");
+
+        let target = self.keyspace(keyspace)?.clone();
+        let value = json_to_wave(json_value, &target.ty.wave_type).map_err(|e| {
+            error!(keyspace = keyspace, key = key, error = %e, "failed to convert JSON value");
+            KvError::from(e)
+        })?;
+        self.queue_value(keyspace, key, target, &value, precondition)
+    }
+
+    /// Lower a parsed value to canonical ABI and queue it.
+    fn queue_value(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        target: BatchKeyspace,
+        value: &Value,
+        precondition: Option<Precondition>,
+    ) -> Result<(), KvError> {
+        let ty = &target.ty;
+
+        // Lower to canonical ABI
+        let mut memory = LinearMemory::new();
+        let encoded =
+            ty.abi()
+                .lower_with_memory(value, &Type::Id(ty.type_id), &ty.wave_type, &mut memory)?;
+        trace!(
+            buffer_size = encoded.len(),
+            memory_size = memory.len(),
+            "value encoded to canonical ABI"
+        );
+
+        let stored = StoredValue::new(
+            ty.metadata.type_version,
+            encoded,
+            if memory.is_empty() {
+                None
+            } else {
+                Some(memory.into_bytes())
+            },
+        );
+        self.queue(keyspace, key, target, Some(stored), precondition);
+        Ok(())
+    }
+
+    fn queue_set_raw(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+        precondition: Option<Precondition>,
+    ) -> Result<(), KvError> {
+        self.queue_encoded(
+            keyspace,
+            key,
+            value.buffer.clone(),
+            value.memory.clone(),
+            precondition,
+        )
+    }
+
+    /// Validate a canonical ABI encoded value against the keyspace's type and queue it.
+    fn queue_encoded(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        buffer: Vec<u8>,
+        memory: Option<Vec<u8>>,
+        precondition: Option<Precondition>,
+    ) -> Result<(), KvError> {
+        trace!(
+            keyspace = keyspace,
+            key = key,
+            buffer_size = buffer.len(),
+            "batch set raw"
+        );
+
+        let target = self.keyspace(keyspace)?.clone();
+        let ty = &target.ty;
+
+        // Lifting checks every pointer, discriminant, bool, char and string
+        let invalid = |reason: String| KvError::InvalidValue {
+            keyspace: keyspace.to_string(),
+            reason,
+        };
+        let memory = LinearMemory::from_option(memory);
+        let (_, size) = ty
+            .abi()
+            .lift_to_val(&buffer, &Type::Id(ty.type_id), None, &memory)
+            .map_err(|e| invalid(e.to_string()))?;
+        if size != buffer.len() {
+            return Err(invalid(format!(
+                "expected a {} byte buffer, got {} bytes",
+                size,
+                buffer.len()
+            )));
+        }
+
+        let stored = StoredValue::new(
+            ty.metadata.type_version,
+            buffer,
+            if memory.is_empty() {
+                None
+            } else {
+                Some(memory.into_bytes())
+            },
+        );
+        self.queue(keyspace, key, target, Some(stored), precondition);
+        Ok(())
+    }
+
+    fn queue_delete(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        precondition: Option<Precondition>,
+    ) -> Result<(), KvError> {
+        trace!(keyspace = keyspace, key = key, "batch delete");
+
+        let target = self.keyspace(keyspace)?.clone();
+        self.queue(keyspace, key, target, None, precondition);
+        Ok(())
+    }
+
+    /// Queue a write, replacing any earlier write to the same key but keeping
+    /// its preconditions. Values get the keyspace's default time-to-live.
+    fn queue(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        target: BatchKeyspace,
+        mut value: Option<StoredValue>,
+        precondition: Option<Precondition>,
+    ) {
+        if let (Some(stored), Some(ttl)) = (&mut value, target.ty.metadata.default_ttl) {
+            stored.expires_at = Some(expires_at(now_millis(), ttl));
+        }
+
+        let write = self
+            .writes
+            .entry((keyspace.to_string(), key.to_string()))
+            .or_insert_with(|| PendingWrite {
+                keyspace: keyspace.to_string(),
+                key: key.to_string(),
+                ty: target.ty,
+                data: target.data,
+                value: None,
+                preconditions: Vec::new(),
+            });
+        write.value = value;
+        write.preconditions.extend(precondition);
+    }
+
+    fn keyspace(&mut self, keyspace: &str) -> Result<&BatchKeyspace, KvError> {
+        if !self.keyspaces.contains_key(keyspace) {
+            let ty = self.store.keyspace_type(keyspace)?;
+            let data = self.store.data_keyspace(keyspace)?;
+            self.keyspaces
+                .insert(keyspace.to_string(), BatchKeyspace { ty, data });
+        }
+        self.keyspaces
+            .get(keyspace)
+            .ok_or_else(|| KvError::KeyspaceNotFound(keyspace.to_string()))
+    }
+}
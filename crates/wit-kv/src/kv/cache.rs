@@ -0,0 +1,211 @@
+//! Cache of parsed keyspace types.
+
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use wasm_wave::value::{Type as WaveType, Value};
+use wit_kv_abi::{val_to_wave, wave_to_json};
+use wit_parser::{Resolve, SizeAlign, Type, TypeId};
+
+use crate::{CanonicalAbi, LinearMemory};
+
+use super::error::KvError;
+use super::types::{KeyspaceMetadata, StoredValue};
+
+/// A registered keyspace type, parsed and ready to encode and decode values.
+pub(super) struct KeyspaceType {
+    pub(super) metadata: KeyspaceMetadata,
+    pub(super) resolve: Resolve,
+    pub(super) type_id: TypeId,
+    pub(super) wave_type: WaveType,
+    /// Canonical ABI sizes for `resolve`, computed once.
+    sizes: SizeAlign,
+}
+
+impl KeyspaceType {
+    /// Parse the WIT definition recorded in `metadata`.
+    pub(super) fn parse(metadata: KeyspaceMetadata) -> Result<Self, KvError> {
+        let (resolve, type_id, wave_type) =
+            crate::load_wit_type_from_string(&metadata.wit_definition, Some(&metadata.type_name))
+                .map_err(|e| KvError::WaveParse(e.to_string()))?;
+        let mut sizes = SizeAlign::default();
+        sizes.fill(&resolve);
+
+        Ok(Self {
+            metadata,
+            resolve,
+            type_id,
+            wave_type,
+            sizes,
+        })
+    }
+
+    /// Canonical ABI encoder/decoder for this type, without recomputing sizes.
+    pub(super) fn abi(&self) -> CanonicalAbi<'_> {
+        CanonicalAbi::with_sizes(&self.resolve, &self.sizes)
+    }
+
+    /// Decode a value already at this type version.
+    pub(super) fn to_value(&self, stored: &StoredValue) -> Result<Value, KvError> {
+        let memory = LinearMemory::from_optional(stored.memory.as_ref());
+        let (val, _) =
+            self.abi()
+                .lift_to_val(&stored.value, &Type::Id(self.type_id), None, &memory)?;
+        val_to_wave(&val, &self.wave_type).map_err(|e| KvError::WaveParse(e.to_string()))
+    }
+
+    /// Decode a value already at this type version as JSON.
+    pub(super) fn to_json(&self, stored: &StoredValue) -> Result<serde_json::Value, KvError> {
+        Ok(wave_to_json(&self.to_value(stored)?, &self.wave_type)?)
+    }
+}
+
+/// Parsed keyspace types keyed by the metadata key they were loaded from,
+/// shared by every thread using the store.
+///
+/// Entries are only added if no invalidation happened while they were being
+/// loaded, so a reader racing with [`invalidate`](Self::invalidate) can never
+/// put back a type that was just replaced.
+#[derive(Default)]
+pub(super) struct TypeCache {
+    inner: RwLock<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    types: HashMap<String, Arc<KeyspaceType>>,
+    /// Bumped on every invalidation.
+    generation: u64,
+}
+
+impl TypeCache {
+    /// Get the type stored under `key`, loading and caching it on a miss.
+    ///
+    /// `load` returns `None` if there is no such type; misses are not cached.
+    pub(super) fn get_or_load(
+        &self,
+        key: &str,
+        load: impl FnOnce() -> Result<Option<KeyspaceMetadata>, KvError>,
+    ) -> Result<Option<Arc<KeyspaceType>>, KvError> {
+        let generation = {
+            let state = self.inner.read().unwrap_or_else(PoisonError::into_inner);
+            if let Some(ty) = state.types.get(key) {
+                return Ok(Some(Arc::clone(ty)));
+            }
+            state.generation
+        };
+
+        let Some(metadata) = load()? else {
+            return Ok(None);
+        };
+        let ty = Arc::new(KeyspaceType::parse(metadata)?);
+
+        let mut state = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+        if state.generation == generation {
+            state.types.insert(key.to_string(), Arc::clone(&ty));
+        }
+        Ok(Some(ty))
+    }
+
+    /// Drop every cached type whose key matches.
+    ///
+    /// Call after the metadata has been written, so later loads see it.
+    pub(super) fn invalidate(&self, matches: impl Fn(&str) -> bool) {
+        let mut state = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+        state.types.retain(|key, _| !matches(key));
+        state.generation += 1;
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::panic)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> KeyspaceMetadata {
+        KeyspaceMetadata::new(
+            "points".to_string(),
+            "test:types/types#point".to_string(),
+            "package test:types;\ninterface types { record point { x: u32, y: u32 } }".to_string(),
+            "point".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_get_or_load_caches_hits() {
+        let cache = TypeCache::default();
+        let first = cache
+            .get_or_load("types/points", || Ok(Some(metadata())))
+            .unwrap()
+            .unwrap();
+        let second = cache
+            .get_or_load("types/points", || panic!("should be cached"))
+            .unwrap()
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_misses_are_not_cached() {
+        let cache = TypeCache::default();
+        assert!(
+            cache
+                .get_or_load("types/points", || Ok(None))
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            cache
+                .get_or_load("types/points", || Ok(Some(metadata())))
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_invalidate_reloads() {
+        let cache = TypeCache::default();
+        cache
+            .get_or_load("types/points", || Ok(Some(metadata())))
+            .unwrap();
+        cache
+            .get_or_load("history/points@0.1.0", || Ok(Some(metadata())))
+            .unwrap();
+        cache
+            .get_or_load("types/other", || Ok(Some(metadata())))
+            .unwrap();
+
+        cache.invalidate(|key| key == "types/points" || key.starts_with("history/points@"));
+
+        let mut loads = 0;
+        for key in ["types/points", "history/points@0.1.0", "types/other"] {
+            cache
+                .get_or_load(key, || {
+                    loads += 1;
+                    Ok(Some(metadata()))
+                })
+                .unwrap();
+        }
+        assert_eq!(loads, 2);
+    }
+
+    #[test]
+    fn test_load_racing_invalidate_is_not_cached() {
+        let cache = TypeCache::default();
+        cache
+            .get_or_load("types/points", || {
+                cache.invalidate(|key| key == "types/points");
+                Ok(Some(metadata()))
+            })
+            .unwrap();
+
+        let mut reloaded = false;
+        cache
+            .get_or_load("types/points", || {
+                reloaded = true;
+                Ok(Some(metadata()))
+            })
+            .unwrap();
+        assert!(reloaded);
+    }
+}
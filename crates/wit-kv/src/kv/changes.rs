@@ -0,0 +1,342 @@
+//! Change log of value writes, read by [`KvStore::watch`].
+//!
+//! Every committed set and delete is appended to the `changes_{keyspace}`
+//! fjall keyspace under the next sequence number of that keyspace:
+//!
+//! ```text
+//! key:   seq (u64, big-endian) primary-key
+//! value: revision (u64, little-endian) [stored-value]
+//! ```
+//!
+//! A set carries the stored envelope as written; a delete carries none.
+//! Sequence numbers start at 1 and increase by one per change, so the next
+//! one is found from the last entry, and the log is trimmed to its most
+//! recent changes by removing entries from the start. The store remembers
+//! where each log starts, so trimming only visits the entries it removes
+//! rather than the tombstones left by earlier trims.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use fjall::{Keyspace, OwnedWriteBatch};
+
+use crate::logging::trace;
+
+use super::error::KvError;
+use super::store::KvStore;
+use super::types::StoredValue;
+
+/// Changes kept per keyspace unless configured otherwise.
+pub(super) const DEFAULT_CHANGE_RETENTION: usize = 10_000;
+
+/// Entries read from the log at a time by a [`Watch`].
+const READ_AHEAD: usize = 256;
+
+/// Kind of write recorded in the change log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    /// The key was set to a new value.
+    Set,
+    /// The key was deleted, or its value expired and was purged.
+    Delete,
+}
+
+impl ChangeOp {
+    /// Lowercase name of the operation (`set` or `delete`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeOp::Set => "set",
+            ChangeOp::Delete => "delete",
+        }
+    }
+}
+
+/// A committed write to a keyspace, yielded by a [`Watch`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// Position of the change in the keyspace's change log, starting at 1.
+    pub seq: u64,
+    /// Store revision the write was committed under.
+    pub revision: u64,
+    /// Key that was written.
+    pub key: String,
+    /// Whether the key was set or deleted.
+    pub op: ChangeOp,
+    /// New value for [`ChangeOp::Set`], at the keyspace's current type version.
+    pub value: Option<StoredValue>,
+}
+
+/// The change log of one keyspace, appended to while committing a batch.
+pub(super) struct ChangeLog {
+    log: Keyspace,
+    next_seq: u64,
+    /// Sequence number of the oldest change kept, `next_seq` if none are.
+    first_seq: u64,
+}
+
+impl ChangeLog {
+    /// Open a change log, starting at `known_first` if the caller remembers
+    /// where it starts, else at its first entry.
+    pub(super) fn open(log: Keyspace, known_first: Option<u64>) -> Result<Self, KvError> {
+        let next_seq = last_seq(&log)? + 1;
+        let first_seq = match known_first {
+            Some(seq) if seq <= next_seq => seq,
+            _ => first_seq(&log)?.unwrap_or(next_seq),
+        };
+        Ok(Self {
+            log,
+            next_seq,
+            first_seq,
+        })
+    }
+
+    /// Sequence number of the oldest change kept once the batch is committed.
+    pub(super) fn first_seq(&self) -> u64 {
+        self.first_seq
+    }
+
+    /// Queue a change under the next sequence number.
+    pub(super) fn append(
+        &mut self,
+        batch: &mut OwnedWriteBatch,
+        key: &str,
+        revision: u64,
+        value: Option<&StoredValue>,
+    ) -> Result<(), KvError> {
+        let mut entry = revision.to_le_bytes().to_vec();
+        if let Some(value) = value {
+            entry.extend(value.to_bytes()?);
+        }
+        batch.insert(&self.log, entry_key(self.next_seq, key), entry);
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// Queue the removal of all but the `retention` most recent changes.
+    pub(super) fn trim(
+        &mut self,
+        batch: &mut OwnedWriteBatch,
+        retention: usize,
+    ) -> Result<(), KvError> {
+        let retention = u64::try_from(retention).unwrap_or(u64::MAX);
+        let oldest_kept = self.next_seq.saturating_sub(retention);
+        if oldest_kept <= self.first_seq {
+            return Ok(());
+        }
+        for guard in self
+            .log
+            .range(entry_key(self.first_seq, "")..entry_key(oldest_kept, ""))
+        {
+            batch.remove(&self.log, guard.key()?);
+        }
+        self.first_seq = oldest_kept;
+        Ok(())
+    }
+}
+
+/// Lazy, blocking iterator over the changes of a keyspace, returned by
+/// [`KvStore::watch`].
+///
+/// Changes are yielded in sequence order: first those already in the log,
+/// then new ones as they are committed. [`next`](Iterator::next) blocks
+/// until a change is available and never returns `None`; use
+/// [`next_timeout`](Self::next_timeout) to wait for a limited time instead.
+///
+/// Values written with an older type version are brought to the current one
+/// like [`KvStore::get_raw`] does.
+pub struct Watch<'a> {
+    store: &'a KvStore,
+    keyspace: String,
+    log: Keyspace,
+    next_seq: u64,
+    pending: VecDeque<Result<ChangeEvent, KvError>>,
+}
+
+impl<'a> Watch<'a> {
+    pub(super) fn new(store: &'a KvStore, keyspace: &str, log: Keyspace, next_seq: u64) -> Self {
+        Self {
+            store,
+            keyspace: keyspace.to_string(),
+            log,
+            next_seq,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Sequence number of the next change to be read from the log.
+    ///
+    /// Pass it to [`KvStore::watch`] to resume watching later.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Next change, waiting at most `timeout` for one to be committed.
+    ///
+    /// Returns `None` if no change arrived in time.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Result<ChangeEvent, KvError>> {
+        self.next_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Render the value of a change as WAVE text.
+    pub fn to_wave(&self, value: StoredValue) -> Result<String, KvError> {
+        let ty = self.store.keyspace_type(&self.keyspace)?;
+        let current = self.store.upgrade_if_needed(&self.keyspace, &ty, value)?;
+        KvStore::value_to_wave(current, &ty)
+    }
+
+    /// Render the value of a change as JSON.
+    pub fn to_json(&self, value: StoredValue) -> Result<serde_json::Value, KvError> {
+        let ty = self.store.keyspace_type(&self.keyspace)?;
+        let current = self.store.upgrade_if_needed(&self.keyspace, &ty, value)?;
+        ty.to_json(&current)
+    }
+
+    /// Next change, waiting until `deadline` (forever if `None`).
+    fn next_until(&mut self, deadline: Option<Instant>) -> Option<Result<ChangeEvent, KvError>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+
+            // Read the revision first, so a commit racing with the read
+            // below ends the wait right away
+            let seen = self.store.committed_revision();
+            if let Err(e) = self.read_ahead() {
+                return Some(Err(e));
+            }
+            if self.pending.is_empty() && !self.store.wait_for_commit(seen, deadline) {
+                return None;
+            }
+        }
+    }
+
+    /// Queue the next changes in the log, if any.
+    fn read_ahead(&mut self) -> Result<(), KvError> {
+        for guard in self
+            .log
+            .range(entry_key(self.next_seq, "")..)
+            .take(READ_AHEAD)
+        {
+            let (entry_key, entry) = guard.into_inner()?;
+            let mut event = parse_entry(&entry_key, &entry)?;
+            self.next_seq = event.seq + 1;
+
+            let ty = self.store.keyspace_type(&self.keyspace);
+            let event = ty.and_then(|ty| {
+                event.value = event
+                    .value
+                    .map(|value| self.store.upgrade_if_needed(&self.keyspace, &ty, value))
+                    .transpose()?;
+                Ok(event)
+            });
+            self.pending.push_back(event);
+        }
+        if !self.pending.is_empty() {
+            trace!(
+                keyspace = %self.keyspace,
+                count = self.pending.len(),
+                next_seq = self.next_seq,
+                "changes read"
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Watch<'_> {
+    type Item = Result<ChangeEvent, KvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_until(None)
+    }
+}
+
+/// Sequence number of the last change in `log`, or 0 if it is empty.
+pub(super) fn last_seq(log: &Keyspace) -> Result<u64, KvError> {
+    match log.last_key_value() {
+        Some(guard) => Ok(entry_seq(&guard.key()?)?),
+        None => Ok(0),
+    }
+}
+
+/// Sequence number of the oldest change kept in `log`, if any.
+pub(super) fn first_seq(log: &Keyspace) -> Result<Option<u64>, KvError> {
+    log.first_key_value()
+        .map(|guard| entry_seq(&guard.key()?))
+        .transpose()
+}
+
+/// Key of the change log entry for a change of `key` with sequence number `seq`.
+fn entry_key(seq: u64, key: &str) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(8 + key.len());
+    entry.extend(seq.to_be_bytes());
+    entry.extend(key.as_bytes());
+    entry
+}
+
+fn entry_seq(entry_key: &[u8]) -> Result<u64, KvError> {
+    entry_key
+        .first_chunk::<8>()
+        .map(|seq| u64::from_be_bytes(*seq))
+        .ok_or_else(|| KvError::InvalidFormat("Invalid change log entry".to_string()))
+}
+
+/// Decode a change log entry.
+fn parse_entry(entry_key: &[u8], entry: &[u8]) -> Result<ChangeEvent, KvError> {
+    let invalid = || KvError::InvalidFormat("Invalid change log entry".to_string());
+    let (seq, key) = entry_key.split_first_chunk::<8>().ok_or_else(invalid)?;
+    let (revision, value) = entry.split_first_chunk::<8>().ok_or_else(invalid)?;
+
+    let value = if value.is_empty() {
+        None
+    } else {
+        Some(StoredValue::from_bytes(value)?)
+    };
+    Ok(ChangeEvent {
+        seq: u64::from_be_bytes(*seq),
+        revision: u64::from_le_bytes(*revision),
+        key: String::from_utf8_lossy(key).into_owned(),
+        op: if value.is_some() {
+            ChangeOp::Set
+        } else {
+            ChangeOp::Delete
+        },
+        value,
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::kv::SemanticVersion;
+
+    #[test]
+    fn test_entry_roundtrip() {
+        let mut value = StoredValue::new(SemanticVersion::INITIAL, vec![1, 2, 3, 4], None);
+        value.revision = 7;
+
+        let mut set = 7u64.to_le_bytes().to_vec();
+        set.extend(value.to_bytes().unwrap());
+        let event = parse_entry(&entry_key(3, "k"), &set).unwrap();
+        assert_eq!(event.seq, 3);
+        assert_eq!(event.revision, 7);
+        assert_eq!(event.key, "k");
+        assert_eq!(event.op, ChangeOp::Set);
+        assert_eq!(event.value, Some(value));
+
+        let event = parse_entry(&entry_key(4, "k"), &8u64.to_le_bytes()).unwrap();
+        assert_eq!(event.op, ChangeOp::Delete);
+        assert_eq!(event.value, None);
+
+        assert!(parse_entry(&[0; 4], &[]).is_err());
+    }
+
+    #[test]
+    fn test_entries_sort_by_seq() {
+        let mut entries = [entry_key(256, "a"), entry_key(2, "z"), entry_key(10, "")];
+        entries.sort();
+        let seqs: Vec<u64> = entries.iter().map(|e| entry_seq(e).unwrap()).collect();
+        assert_eq!(seqs, vec![2, 10, 256]);
+    }
+}
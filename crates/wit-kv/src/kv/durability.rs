@@ -0,0 +1,85 @@
+//! Write durability settings.
+
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use fjall::PersistMode;
+
+use crate::logging::{trace, warn};
+
+use super::error::KvError;
+
+/// When value writes are fsynced to disk.
+///
+/// Writes are always atomic and immediately visible to readers; durability
+/// only controls how much recently written data a crash can lose. Type
+/// registration and migrations are always synced, whatever the setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// fsync after every write or batch (the default).
+    #[default]
+    SyncAll,
+    /// fsync from a background thread at the given interval.
+    ///
+    /// A crash can lose writes made since the last sync.
+    Interval(Duration),
+    /// Hand writes to the operating system without fsync.
+    ///
+    /// Writes survive a process crash, but not an OS crash or power loss
+    /// until [`KvStore::flush`](super::KvStore::flush) is called or the store
+    /// is dropped.
+    Buffer,
+}
+
+impl fmt::Display for Durability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Durability::SyncAll => write!(f, "sync"),
+            Durability::Interval(interval) => write!(f, "interval({}ms)", interval.as_millis()),
+            Durability::Buffer => write!(f, "buffer"),
+        }
+    }
+}
+
+/// Background thread that fsyncs the database at a fixed interval.
+///
+/// The thread is stopped and joined when this is dropped.
+pub(super) struct IntervalSync {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl IntervalSync {
+    pub(super) fn start(db: fjall::Database, interval: Duration) -> Result<Self, KvError> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = std::thread::Builder::new()
+            .name("wit-kv-sync".to_string())
+            .spawn(move || {
+                // Any message or a dropped sender stops the thread
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    // fjall logs the underlying I/O error
+                    if db.persist(PersistMode::SyncAll).is_err() {
+                        warn!("interval sync failed, retrying at next interval");
+                    } else {
+                        trace!("interval sync");
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for IntervalSync {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -2,7 +2,7 @@
 
 use thiserror::Error;
 
-use crate::CanonicalAbiError;
+use crate::{CanonicalAbiError, JsonConvertError};
 
 use super::version::SemanticVersion;
 
@@ -18,6 +18,9 @@ pub enum KvError {
     #[error("Keyspace not found: {0}")]
     KeyspaceNotFound(String),
 
+    #[error("Invalid keyspace name '{0}': names cannot be empty or contain '@' or '/'")]
+    InvalidKeyspaceName(String),
+
     #[error("Keyspace already exists: {0}")]
     KeyspaceExists(String),
 
@@ -30,6 +33,77 @@ pub enum KvError {
         current: SemanticVersion,
     },
 
+    #[error("Breaking type change for keyspace {keyspace}: {changes}")]
+    IncompatibleTypeChange { keyspace: String, changes: String },
+
+    #[error("Cannot read value written with type version {from} as {to}: {reason}")]
+    ProjectionFailed {
+        from: SemanticVersion,
+        to: SemanticVersion,
+        reason: String,
+    },
+
+    #[error("Precondition failed for key {key}: expected an existing value, found no value")]
+    ValueMissing { key: String },
+
+    #[error("Migration failed at key {key}: {reason}")]
+    MigrationFailed { key: String, reason: String },
+
+    #[error(
+        "Revision conflict for key {key}: expected {}, found {}",
+        describe_revision(*.expected),
+        describe_revision(*.actual)
+    )]
+    RevisionConflict {
+        key: String,
+        /// Expected revision, or `None` if the key was expected to be absent
+        expected: Option<u64>,
+        /// Current revision, or `None` if the key does not exist
+        actual: Option<u64>,
+    },
+
+    #[error("Invalid value for keyspace {keyspace}: {reason}")]
+    InvalidValue { keyspace: String, reason: String },
+
+    #[error("No index on {field} in keyspace {keyspace}")]
+    IndexNotFound { keyspace: String, field: String },
+
+    #[error("Index on {field} already exists in keyspace {keyspace}")]
+    IndexExists { keyspace: String, field: String },
+
+    #[error("Cannot index field {field}: {reason}")]
+    InvalidIndex { field: String, reason: String },
+
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+
+    #[error("Invalid projection: {0}")]
+    InvalidProjection(String),
+
+    #[error("Changes of keyspace {keyspace} before sequence number {oldest} are no longer kept")]
+    ChangesTrimmed { keyspace: String, oldest: u64 },
+
+    #[error(
+        "Sequence number {from} is ahead of the changes of keyspace {keyspace}: the next change is {next}"
+    )]
+    ChangesAhead {
+        keyspace: String,
+        from: u64,
+        next: u64,
+    },
+
+    #[error("Module not found: {0}")]
+    ModuleNotFound(String),
+
+    #[error("Module already exists: {0}")]
+    ModuleExists(String),
+
+    #[error("Invalid module id '{0}': use 1 to 128 ASCII letters, digits, '-' and '_'")]
+    InvalidModuleId(String),
+
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+
     #[error("Invalid format: {0}")]
     InvalidFormat(String),
 
@@ -42,9 +116,25 @@ pub enum KvError {
     #[error("WAVE parsing error: {0}")]
     WaveParse(String),
 
+    #[error("Invalid JSON value: {0}")]
+    InvalidJson(String),
+
     #[error("Type not found in WIT: {0}")]
     TypeNotFound(String),
 
     #[error("Database not initialized at {0}")]
     NotInitialized(String),
 }
+
+impl From<JsonConvertError> for KvError {
+    fn from(e: JsonConvertError) -> Self {
+        KvError::InvalidJson(e.to_string())
+    }
+}
+
+fn describe_revision(revision: Option<u64>) -> String {
+    match revision {
+        Some(revision) => format!("revision {}", revision),
+        None => "no value".to_string(),
+    }
+}
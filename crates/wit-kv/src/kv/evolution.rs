@@ -0,0 +1,661 @@
+//! Schema evolution: structural comparison of keyspace types.
+//!
+//! When a keyspace type is re-registered, the old and new WAVE types are
+//! compared structurally to decide how far the keyspace's `type_version`
+//! has to move. Compatibility is judged from the reader's side: can a value
+//! written with the old type still be read as the new one?
+//!
+//! Values written under an older compatible version are read by lifting
+//! them with the type they were written with and projecting the result onto
+//! the current type (see [`project_value`]).
+
+use std::fmt;
+
+use wasm_wave::value::{Type as WaveType, Value};
+use wasm_wave::wasm::{DisplayType, WasmType, WasmTypeKind, WasmValue};
+
+/// How significant a change between two versions of a type is.
+///
+/// Variants are ordered by severity, so the overall kind of a diff is the
+/// maximum of its individual changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeKind {
+    /// The types are structurally identical (e.g. only comments, docs or
+    /// the package changed). Stored values keep the exact same layout.
+    Patch,
+    /// Values written with the old type can still be read with the new one:
+    /// added optional fields, new variant/enum cases, added or removed flags,
+    /// removed record fields, or reordered fields and cases.
+    Minor,
+    /// Values written with the old type cannot be read with the new one:
+    /// changed primitives, added required fields, removed variant/enum cases,
+    /// or otherwise changed shapes.
+    Major,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeKind::Patch => "patch",
+            ChangeKind::Minor => "minor",
+            ChangeKind::Major => "major",
+        })
+    }
+}
+
+/// Result of structurally comparing two versions of a keyspace type.
+///
+/// # Example
+///
+/// ```ignore
+/// use wit_kv::{ChangeKind, TypeDiff};
+///
+/// let diff = TypeDiff::between(&old_type, &new_type);
+/// if diff.kind() == ChangeKind::Major {
+///     for change in diff.changes() {
+///         println!("breaking: {}", change);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeDiff {
+    kind: ChangeKind,
+    changes: Vec<TypeChange>,
+}
+
+/// A single difference found while comparing two types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeChange {
+    /// How significant this change is on its own.
+    pub kind: ChangeKind,
+    /// Dotted path to the changed member (empty for the top-level type).
+    pub path: String,
+    /// What changed, e.g. "optional field added".
+    pub description: String,
+}
+
+impl fmt::Display for TypeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let location = if self.path.is_empty() {
+            "<root>"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {}", location, self.description)
+    }
+}
+
+impl TypeDiff {
+    /// Compare the type values were written with (`old`) to the type they
+    /// will be read with (`new`).
+    pub fn between(old: &WaveType, new: &WaveType) -> Self {
+        let mut diff = Self {
+            kind: ChangeKind::Patch,
+            changes: Vec::new(),
+        };
+        diff.compare("", old, new);
+        diff
+    }
+
+    /// The overall kind of the change (the most severe individual change).
+    pub fn kind(&self) -> ChangeKind {
+        self.kind
+    }
+
+    /// The individual changes, in the order they were found.
+    pub fn changes(&self) -> &[TypeChange] {
+        &self.changes
+    }
+
+    /// Whether values written with the old type can no longer be read.
+    pub fn is_breaking(&self) -> bool {
+        self.kind == ChangeKind::Major
+    }
+
+    /// The changes that make this diff breaking.
+    pub fn breaking_changes(&self) -> impl Iterator<Item = &TypeChange> {
+        self.changes.iter().filter(|c| c.kind == ChangeKind::Major)
+    }
+
+    fn record(&mut self, kind: ChangeKind, path: &str, what: impl fmt::Display) {
+        self.kind = self.kind.max(kind);
+        self.changes.push(TypeChange {
+            kind,
+            path: path.to_string(),
+            description: what.to_string(),
+        });
+    }
+
+    fn compare(&mut self, path: &str, old: &WaveType, new: &WaveType) {
+        if old.kind() != new.kind() {
+            self.record(
+                ChangeKind::Major,
+                path,
+                format_args!("changed from {} to {}", DisplayType(old), DisplayType(new)),
+            );
+            return;
+        }
+
+        match old.kind() {
+            WasmTypeKind::List => {
+                if let (Some(old_elem), Some(new_elem)) =
+                    (old.list_element_type(), new.list_element_type())
+                {
+                    self.compare(&format!("{}[]", path), &old_elem, &new_elem);
+                }
+            }
+            // The length is not exposed through `WasmType`, so fall back to
+            // structural equality.
+            WasmTypeKind::FixedSizeList if old != new => {
+                self.record(
+                    ChangeKind::Major,
+                    path,
+                    format_args!("changed from {} to {}", DisplayType(old), DisplayType(new)),
+                );
+            }
+            WasmTypeKind::Record => self.compare_record(path, old, new),
+            WasmTypeKind::Tuple => {
+                let old_elems: Vec<_> = old.tuple_element_types().collect();
+                let new_elems: Vec<_> = new.tuple_element_types().collect();
+                if old_elems.len() != new_elems.len() {
+                    self.record(
+                        ChangeKind::Major,
+                        path,
+                        format_args!(
+                            "tuple arity changed from {} to {}",
+                            old_elems.len(),
+                            new_elems.len()
+                        ),
+                    );
+                    return;
+                }
+                for (i, (o, n)) in old_elems.iter().zip(&new_elems).enumerate() {
+                    self.compare(&child(path, &i.to_string()), o, n);
+                }
+            }
+            WasmTypeKind::Variant => self.compare_variant(path, old, new),
+            WasmTypeKind::Enum => {
+                let old_cases: Vec<_> = old.enum_cases().collect();
+                let new_cases: Vec<_> = new.enum_cases().collect();
+                self.compare_names(path, "case", &old_cases, &new_cases, ChangeKind::Major);
+            }
+            WasmTypeKind::Flags => {
+                let old_flags: Vec<_> = old.flags_names().collect();
+                let new_flags: Vec<_> = new.flags_names().collect();
+                self.compare_names(path, "flag", &old_flags, &new_flags, ChangeKind::Minor);
+            }
+            WasmTypeKind::Option => {
+                if let (Some(o), Some(n)) = (old.option_some_type(), new.option_some_type()) {
+                    self.compare(&format!("{}?", path), &o, &n);
+                }
+            }
+            WasmTypeKind::Result => {
+                let (Some((old_ok, old_err)), Some((new_ok, new_err))) =
+                    (old.result_types(), new.result_types())
+                else {
+                    return;
+                };
+                self.compare_payload(&child(path, "ok"), old_ok.as_ref(), new_ok.as_ref());
+                self.compare_payload(&child(path, "err"), old_err.as_ref(), new_err.as_ref());
+            }
+            // Primitives of the same kind are identical.
+            _ => {}
+        }
+    }
+
+    fn compare_record(&mut self, path: &str, old: &WaveType, new: &WaveType) {
+        let old_fields: Vec<_> = old.record_fields().collect();
+        let new_fields: Vec<_> = new.record_fields().collect();
+
+        for (name, old_ty) in &old_fields {
+            let field_path = child(path, name);
+            match new_fields.iter().find(|(n, _)| n == name) {
+                Some((_, new_ty)) => self.compare(&field_path, old_ty, new_ty),
+                None => self.record(ChangeKind::Minor, &field_path, "field removed"),
+            }
+        }
+
+        for (name, new_ty) in &new_fields {
+            if old_fields.iter().any(|(n, _)| n == name) {
+                continue;
+            }
+            let field_path = child(path, name);
+            if new_ty.kind() == WasmTypeKind::Option {
+                self.record(ChangeKind::Minor, &field_path, "optional field added");
+            } else {
+                self.record(ChangeKind::Major, &field_path, "required field added");
+            }
+        }
+
+        let old_names: Vec<&str> = old_fields.iter().map(|(n, _)| n.as_ref()).collect();
+        let new_names: Vec<&str> = new_fields.iter().map(|(n, _)| n.as_ref()).collect();
+        if is_reordered(&old_names, &new_names) {
+            self.record(ChangeKind::Minor, path, "fields reordered");
+        }
+    }
+
+    fn compare_variant(&mut self, path: &str, old: &WaveType, new: &WaveType) {
+        let old_cases: Vec<_> = old.variant_cases().collect();
+        let new_cases: Vec<_> = new.variant_cases().collect();
+
+        for (name, old_payload) in &old_cases {
+            let case_path = child(path, name);
+            match new_cases.iter().find(|(n, _)| n == name) {
+                Some((_, new_payload)) => {
+                    self.compare_payload(&case_path, old_payload.as_ref(), new_payload.as_ref())
+                }
+                None => self.record(ChangeKind::Major, &case_path, "case removed"),
+            }
+        }
+
+        for (name, _) in &new_cases {
+            if !old_cases.iter().any(|(n, _)| n == name) {
+                self.record(ChangeKind::Minor, &child(path, name), "case added");
+            }
+        }
+
+        let old_names: Vec<&str> = old_cases.iter().map(|(n, _)| n.as_ref()).collect();
+        let new_names: Vec<&str> = new_cases.iter().map(|(n, _)| n.as_ref()).collect();
+        if is_reordered(&old_names, &new_names) {
+            self.record(ChangeKind::Minor, path, "cases reordered");
+        }
+    }
+
+    fn compare_payload(&mut self, path: &str, old: Option<&WaveType>, new: Option<&WaveType>) {
+        match (old, new) {
+            (Some(o), Some(n)) => self.compare(path, o, n),
+            (None, None) => {}
+            (Some(_), None) => self.record(ChangeKind::Major, path, "payload removed"),
+            (None, Some(_)) => self.record(ChangeKind::Major, path, "payload added"),
+        }
+    }
+
+    /// Compare name-only members (enum cases, flags). Added names are always
+    /// compatible; `removed` is the kind recorded for names that disappeared.
+    fn compare_names<S: AsRef<str>>(
+        &mut self,
+        path: &str,
+        what: &str,
+        old: &[S],
+        new: &[S],
+        removed: ChangeKind,
+    ) {
+        let old: Vec<&str> = old.iter().map(AsRef::as_ref).collect();
+        let new: Vec<&str> = new.iter().map(AsRef::as_ref).collect();
+
+        for name in &old {
+            if !new.contains(name) {
+                self.record(
+                    removed,
+                    &child(path, name),
+                    format_args!("{} removed", what),
+                );
+            }
+        }
+        for name in &new {
+            if !old.contains(name) {
+                self.record(
+                    ChangeKind::Minor,
+                    &child(path, name),
+                    format_args!("{} added", what),
+                );
+            }
+        }
+        if is_reordered(&old, &new) {
+            self.record(ChangeKind::Minor, path, format_args!("{}s reordered", what));
+        }
+    }
+}
+
+impl fmt::Display for TypeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return write!(f, "{} (no structural changes)", self.kind);
+        }
+        write!(f, "{} (", self.kind)?;
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", change)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Project a value written with type `old` onto type `new`.
+///
+/// New optional record fields default to `none`, removed fields and flags
+/// are dropped, and variant/enum cases are matched by name. Fails with a
+/// description if the value cannot be represented in the new type (e.g. it
+/// uses a case that no longer exists, or a required field was added).
+pub(crate) fn project_value(
+    value: &Value,
+    old: &WaveType,
+    new: &WaveType,
+) -> Result<Value, String> {
+    project_at("", value, old, new)
+}
+
+fn project_at(path: &str, value: &Value, old: &WaveType, new: &WaveType) -> Result<Value, String> {
+    if old == new {
+        return Ok(value.clone());
+    }
+    let location = if path.is_empty() { "<root>" } else { path };
+    if old.kind() != new.kind() || value.kind() != old.kind() {
+        return Err(format!(
+            "{}: cannot convert {} to {}",
+            location,
+            DisplayType(old),
+            DisplayType(new)
+        ));
+    }
+    let make_err = |e: wasm_wave::wasm::WasmValueError| format!("{}: {}", location, e);
+
+    match new.kind() {
+        WasmTypeKind::List => {
+            let (Some(old_elem), Some(new_elem)) =
+                (old.list_element_type(), new.list_element_type())
+            else {
+                return Err(format!("{}: invalid list type", location));
+            };
+            let elem_path = format!("{}[]", path);
+            let items = value
+                .unwrap_list()
+                .map(|v| project_at(&elem_path, &v, &old_elem, &new_elem))
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::make_list(new, items).map_err(make_err)
+        }
+        WasmTypeKind::Record => {
+            let old_fields: Vec<_> = old.record_fields().collect();
+            let values: Vec<_> = value.unwrap_record().collect();
+            let mut fields = Vec::new();
+            for (name, new_ty) in new.record_fields() {
+                let field_path = child(path, &name);
+                let old_ty = old_fields.iter().find(|(n, _)| *n == name);
+                let old_val = values.iter().find(|(n, _)| *n == name);
+                let projected = match (old_ty, old_val) {
+                    (Some((_, old_ty)), Some((_, old_val))) => {
+                        project_at(&field_path, old_val, old_ty, &new_ty)?
+                    }
+                    _ if new_ty.kind() == WasmTypeKind::Option => {
+                        Value::make_option(&new_ty, None).map_err(make_err)?
+                    }
+                    _ => return Err(format!("{}: missing required field", field_path)),
+                };
+                fields.push((name.into_owned(), projected));
+            }
+            Value::make_record(new, fields.iter().map(|(n, v)| (n.as_str(), v.clone())))
+                .map_err(make_err)
+        }
+        WasmTypeKind::Tuple => {
+            let old_elems: Vec<_> = old.tuple_element_types().collect();
+            let new_elems: Vec<_> = new.tuple_element_types().collect();
+            if old_elems.len() != new_elems.len() {
+                return Err(format!("{}: tuple arity changed", location));
+            }
+            let items = value
+                .unwrap_tuple()
+                .zip(old_elems.iter().zip(&new_elems))
+                .enumerate()
+                .map(|(i, (v, (o, n)))| project_at(&child(path, &i.to_string()), &v, o, n))
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::make_tuple(new, items).map_err(make_err)
+        }
+        WasmTypeKind::Variant => {
+            let (case, payload) = value.unwrap_variant();
+            let case_path = child(path, &case);
+            let old_payload = old
+                .variant_cases()
+                .find(|(n, _)| *n == case)
+                .and_then(|(_, ty)| ty);
+            let Some((_, new_payload)) = new.variant_cases().find(|(n, _)| *n == case) else {
+                return Err(format!("{}: case no longer exists", case_path));
+            };
+            let projected = match (payload, old_payload, new_payload) {
+                (Some(v), Some(o), Some(n)) => Some(project_at(&case_path, &v, &o, &n)?),
+                (None, None, None) => None,
+                _ => return Err(format!("{}: payload changed", case_path)),
+            };
+            Value::make_variant(new, &case, projected).map_err(make_err)
+        }
+        WasmTypeKind::Enum => {
+            let case = value.unwrap_enum();
+            if !new.enum_cases().any(|n| n == case) {
+                return Err(format!("{}: case no longer exists", child(path, &case)));
+            }
+            Value::make_enum(new, &case).map_err(make_err)
+        }
+        WasmTypeKind::Flags => {
+            let known: Vec<_> = new.flags_names().collect();
+            let set: Vec<_> = value.unwrap_flags().filter(|f| known.contains(f)).collect();
+            Value::make_flags(new, set.iter().map(|f| f.as_ref())).map_err(make_err)
+        }
+        WasmTypeKind::Option => {
+            let (Some(old_some), Some(new_some)) = (old.option_some_type(), new.option_some_type())
+            else {
+                return Err(format!("{}: invalid option type", location));
+            };
+            let projected = value
+                .unwrap_option()
+                .map(|v| project_at(&format!("{}?", path), &v, &old_some, &new_some))
+                .transpose()?;
+            Value::make_option(new, projected).map_err(make_err)
+        }
+        WasmTypeKind::Result => {
+            let (Some((old_ok, old_err)), Some((new_ok, new_err))) =
+                (old.result_types(), new.result_types())
+            else {
+                return Err(format!("{}: invalid result type", location));
+            };
+            let project_payload = |name: &str,
+                                   v: Option<std::borrow::Cow<'_, Value>>,
+                                   o: Option<WaveType>,
+                                   n: Option<WaveType>| {
+                let payload_path = child(path, name);
+                match (v, o, n) {
+                    (Some(v), Some(o), Some(n)) => project_at(&payload_path, &v, &o, &n).map(Some),
+                    (None, None, None) => Ok(None),
+                    _ => Err(format!("{}: payload changed", payload_path)),
+                }
+            };
+            let projected = match value.unwrap_result() {
+                Ok(v) => Ok(project_payload("ok", v, old_ok, new_ok)?),
+                Err(v) => Err(project_payload("err", v, old_err, new_err)?),
+            };
+            Value::make_result(new, projected).map_err(make_err)
+        }
+        // Primitives of the same kind project to themselves; anything else
+        // (e.g. fixed-size lists of a different length) was caught above.
+        WasmTypeKind::Bool
+        | WasmTypeKind::S8
+        | WasmTypeKind::S16
+        | WasmTypeKind::S32
+        | WasmTypeKind::S64
+        | WasmTypeKind::U8
+        | WasmTypeKind::U16
+        | WasmTypeKind::U32
+        | WasmTypeKind::U64
+        | WasmTypeKind::F32
+        | WasmTypeKind::F64
+        | WasmTypeKind::Char
+        | WasmTypeKind::String => Ok(value.clone()),
+        _ => Err(format!(
+            "{}: cannot convert {} to {}",
+            location,
+            DisplayType(old),
+            DisplayType(new)
+        )),
+    }
+}
+
+fn child(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/// Whether the names present in both lists appear in a different order.
+fn is_reordered(old: &[&str], new: &[&str]) -> bool {
+    let old_common = old.iter().filter(|n| new.contains(n));
+    let new_common = new.iter().filter(|n| old.contains(n));
+    !old_common.eq(new_common)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::load_wit_type_from_string;
+
+    fn wave_type(body: &str) -> WaveType {
+        let wit = format!(
+            "package test:evolution;\ninterface types {{\n{}\n}}\n",
+            body
+        );
+        load_wit_type_from_string(&wit, Some("subject")).unwrap().2
+    }
+
+    fn diff(old: &str, new: &str) -> TypeDiff {
+        TypeDiff::between(&wave_type(old), &wave_type(new))
+    }
+
+    #[test]
+    fn test_identical_is_patch() {
+        let d = diff(
+            "record subject { x: u32, y: u32 }",
+            "/// A point.\nrecord subject { x: u32, y: u32 }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Patch);
+        assert!(d.changes().is_empty());
+    }
+
+    #[test]
+    fn test_record_changes() {
+        let d = diff(
+            "record subject { x: u32 }",
+            "record subject { x: u32, label: option<string> }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Minor);
+
+        let d = diff(
+            "record subject { x: u32, y: u32 }",
+            "record subject { x: u32 }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Minor);
+
+        let d = diff(
+            "record subject { x: u32, y: u32 }",
+            "record subject { y: u32, x: u32 }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Minor);
+
+        let d = diff(
+            "record subject { x: u32 }",
+            "record subject { x: u32, y: u32 }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Major);
+        assert_eq!(d.breaking_changes().count(), 1);
+
+        let d = diff("record subject { x: u32 }", "record subject { x: s64 }");
+        assert_eq!(d.kind(), ChangeKind::Major);
+        assert_eq!(
+            d.changes().first().unwrap().to_string(),
+            "x: changed from u32 to s64"
+        );
+    }
+
+    #[test]
+    fn test_nested_changes() {
+        let d = diff(
+            "record inner { a: u8 }\nrecord subject { items: list<inner> }",
+            "record inner { a: u16 }\nrecord subject { items: list<inner> }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Major);
+        assert_eq!(d.changes().first().unwrap().path, "items[].a");
+    }
+
+    #[test]
+    fn test_variant_and_enum_changes() {
+        let d = diff(
+            "variant subject { a, b(u32) }",
+            "variant subject { a, b(u32), c(string) }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Minor);
+
+        let d = diff("variant subject { a, b(u32) }", "variant subject { a }");
+        assert_eq!(d.kind(), ChangeKind::Major);
+
+        let d = diff(
+            "variant subject { a, b(u32) }",
+            "variant subject { a, b(string) }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Major);
+
+        let d = diff(
+            "enum subject { red, green }",
+            "enum subject { red, green, blue }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Minor);
+
+        let d = diff("enum subject { red, green }", "enum subject { red }");
+        assert_eq!(d.kind(), ChangeKind::Major);
+    }
+
+    fn project(old: &str, new: &str, value: &str) -> Result<String, String> {
+        let (old_ty, new_ty) = (wave_type(old), wave_type(new));
+        let value: Value = wasm_wave::from_str(&old_ty, value).unwrap();
+        project_value(&value, &old_ty, &new_ty).map(|v| wasm_wave::to_string(&v).unwrap())
+    }
+
+    #[test]
+    fn test_project_record() {
+        let projected = project(
+            "record subject { x: u32, y: u32 }",
+            "record subject { label: option<string>, x: u32 }",
+            "{x: 1, y: 2}",
+        );
+        // WAVE omits `none` option fields
+        assert_eq!(projected.unwrap(), "{x: 1}");
+
+        let projected = project(
+            "record subject { x: u32 }",
+            "record subject { x: u32, y: u32 }",
+            "{x: 1}",
+        );
+        assert!(projected.unwrap_err().contains("y: missing required field"));
+    }
+
+    #[test]
+    fn test_project_variant() {
+        let old = "variant subject { a, b(u32), c(string) }";
+        let new = "variant subject { b(u32), a, d }";
+        assert_eq!(project(old, new, "b(7)").unwrap(), "b(7)");
+        assert_eq!(project(old, new, "a").unwrap(), "a");
+        assert!(project(old, new, "c(\"x\")").is_err());
+    }
+
+    #[test]
+    fn test_project_flags() {
+        let projected = project(
+            "flags subject { read, write, exec }",
+            "flags subject { read, exec, admin }",
+            "{read, write}",
+        );
+        assert_eq!(projected.unwrap(), "{read}");
+    }
+
+    #[test]
+    fn test_flags_changes() {
+        let d = diff(
+            "flags subject { read, write }",
+            "flags subject { read, write, exec }",
+        );
+        assert_eq!(d.kind(), ChangeKind::Minor);
+
+        let d = diff("flags subject { read, write }", "flags subject { read }");
+        assert_eq!(d.kind(), ChangeKind::Minor);
+    }
+}
@@ -0,0 +1,92 @@
+//! Time-to-live bookkeeping.
+//!
+//! A value with a time-to-live carries its expiry in its stored envelope,
+//! which is what reads check. So that expired values can be purged without
+//! reading every value, each expiring write also adds an empty entry to the
+//! `expiry_{keyspace}` fjall keyspace:
+//!
+//! ```text
+//! expires-at (u64, big-endian) primary-key
+//! ```
+//!
+//! Entries sort by expiry, so every entry that is due is found with a single
+//! range scan from the start. Entries are only added on write, never
+//! updated: an entry whose key has since been rewritten or deleted is stale,
+//! and is dropped when it comes due.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fjall::Keyspace;
+
+/// Current Unix time in milliseconds.
+pub(super) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
+/// Unix time in milliseconds at which a value written at `now` with `ttl` expires.
+pub(super) fn expires_at(now: u64, ttl: Duration) -> u64 {
+    now.saturating_add(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Key of the expiry entry for `key` expiring at `expires_at`.
+pub(super) fn entry_key(expires_at: u64, key: &str) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(8 + key.len());
+    entry.extend(expires_at.to_be_bytes());
+    entry.extend(key.as_bytes());
+    entry
+}
+
+/// Split an expiry entry key into its expiry and primary key.
+pub(super) fn parse_entry(entry: &[u8]) -> Option<(u64, String)> {
+    let (expires_at, key) = entry.split_first_chunk::<8>()?;
+    Some((
+        u64::from_be_bytes(*expires_at),
+        String::from_utf8_lossy(key).into_owned(),
+    ))
+}
+
+/// Entries of `expiry` for values expired at `now`, soonest first.
+pub(super) fn due_entries(expiry: &Keyspace, now: u64) -> fjall::Iter {
+    match now.checked_add(1) {
+        Some(end) => expiry.range(..entry_key(end, "")),
+        None => expiry.iter(),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_sort_by_expiry() {
+        let entries = [
+            entry_key(1_000, "b"),
+            entry_key(256, "z"),
+            entry_key(1_000, "a"),
+            entry_key(u64::MAX, ""),
+        ];
+        let mut sorted = entries.to_vec();
+        sorted.sort();
+        let parsed: Vec<(u64, String)> = sorted.iter().map(|e| parse_entry(e).unwrap()).collect();
+        assert_eq!(
+            parsed,
+            vec![
+                (256, "z".to_string()),
+                (1_000, "a".to_string()),
+                (1_000, "b".to_string()),
+                (u64::MAX, String::new()),
+            ]
+        );
+        assert!(parse_entry(&[1, 2, 3]).is_none());
+    }
+
+    #[test]
+    fn test_expires_at_saturates() {
+        assert_eq!(expires_at(1_000, Duration::from_secs(2)), 3_000);
+        assert_eq!(expires_at(u64::MAX - 1, Duration::from_secs(1)), u64::MAX);
+    }
+}
@@ -5,15 +5,16 @@
 
 use std::borrow::Cow;
 use std::sync::LazyLock;
+use std::time::Duration;
 
 use wasm_wave::value::{Type as WaveType, Value, resolve_wit_type};
 use wasm_wave::wasm::{WasmType, WasmValue};
-use wit_parser::{Resolve, Type, TypeId};
+use wit_parser::{Field, Int, Resolve, SizeAlign, Type, TypeDefKind, TypeId};
 
-use crate::{CanonicalAbi, LinearMemory, find_type_by_name};
+use crate::{CanonicalAbi, LinearMemory, find_type_by_name, wave_to_json};
 
 use super::error::KvError;
-use super::types::{KeyspaceMetadata, StoredValue};
+use super::types::{KeyspaceMetadata, ModuleKind, ModuleRegistration, StoredValue};
 use super::version::SemanticVersion;
 
 /// Helper to find a type by name and return an error if not found.
@@ -29,6 +30,8 @@ fn require_wave_type(resolve: &Resolve, type_id: TypeId) -> Result<WaveType, KvE
 /// Lazily loaded KV WIT types.
 struct KvTypes {
     resolve: Resolve,
+    /// Canonical ABI sizes for `resolve`, shared by every encode and decode.
+    sizes: SizeAlign,
     stored_value_id: TypeId,
     keyspace_metadata_id: TypeId,
     binary_export_id: TypeId,
@@ -36,6 +39,7 @@ struct KvTypes {
     keyspace_list_id: TypeId,
     _database_info_id: TypeId,
     database_list_id: TypeId,
+    module_registration_id: TypeId,
     stored_value_wave_type: WaveType,
     keyspace_metadata_wave_type: WaveType,
     binary_export_wave_type: WaveType,
@@ -43,6 +47,22 @@ struct KvTypes {
     keyspace_list_wave_type: WaveType,
     database_info_wave_type: WaveType,
     database_list_wave_type: WaveType,
+    module_registration_wave_type: WaveType,
+    stored_value_flat_size: usize,
+    /// Flat size of a format version 1 stored-value (without `revision`).
+    stored_value_v1_flat_size: usize,
+    /// Flat size of a format version 2 stored-value (without `expires-at`).
+    stored_value_v2_flat_size: usize,
+    /// Offset of the `expires-at` discriminant in a stored-value, and of its
+    /// payload relative to the discriminant.
+    expires_at_offset: usize,
+    expires_at_payload_offset: usize,
+    keyspace_metadata_flat_size: usize,
+    /// Flat size of a keyspace-metadata written before `default-ttl`.
+    keyspace_metadata_legacy_flat_size: usize,
+    /// Offsets of the length of every string in a keyspace-metadata.
+    keyspace_metadata_string_lengths: Vec<usize>,
+    module_registration_flat_size: usize,
 }
 
 static KV_TYPES: LazyLock<KvTypes> = LazyLock::new(|| {
@@ -64,6 +84,7 @@ fn load_kv_types() -> Result<KvTypes, KvError> {
     let keyspace_list_id = require_type(&resolve, "keyspace-list")?;
     let database_info_id = require_type(&resolve, "database-info")?;
     let database_list_id = require_type(&resolve, "database-list")?;
+    let module_registration_id = require_type(&resolve, "module-registration")?;
 
     // Resolve wave types
     let stored_value_wave_type = require_wave_type(&resolve, stored_value_id)?;
@@ -73,9 +94,35 @@ fn load_kv_types() -> Result<KvTypes, KvError> {
     let keyspace_list_wave_type = require_wave_type(&resolve, keyspace_list_id)?;
     let database_info_wave_type = require_wave_type(&resolve, database_info_id)?;
     let database_list_wave_type = require_wave_type(&resolve, database_list_id)?;
+    let module_registration_wave_type = require_wave_type(&resolve, module_registration_id)?;
+
+    // Flat sizes for single-record encodings (flat buffer followed by memory)
+    let mut sizes = SizeAlign::default();
+    sizes.fill(&resolve);
+    let stored_value_flat_size = sizes.size(&Type::Id(stored_value_id)).size_wasm32();
+    let stored_value = record_fields(&resolve, stored_value_id, "stored-value")?;
+    let stored_value_v1_flat_size = prefix_flat_size(&sizes, stored_value, 4);
+    let stored_value_v2_flat_size = prefix_flat_size(&sizes, stored_value, 5);
+    let expires_at_offset = field_offset(&sizes, stored_value, "expires-at")?;
+    let expires_at_payload_offset = sizes
+        .payload_offset(Int::U8, [Some(&Type::U64)])
+        .size_wasm32();
+
+    let keyspace_metadata_flat_size = sizes.size(&Type::Id(keyspace_metadata_id)).size_wasm32();
+    let keyspace_metadata = record_fields(&resolve, keyspace_metadata_id, "keyspace-metadata")?;
+    let keyspace_metadata_legacy_flat_size = prefix_flat_size(&sizes, keyspace_metadata, 7);
+    let keyspace_metadata_string_lengths = sizes
+        .field_offsets(keyspace_metadata.iter().map(|f| &f.ty))
+        .into_iter()
+        .filter(|(_, ty)| **ty == Type::String)
+        // A string is a (pointer, length) pair of u32s
+        .map(|(offset, _)| offset.size_wasm32() + 4)
+        .collect();
+    let module_registration_flat_size = sizes.size(&Type::Id(module_registration_id)).size_wasm32();
 
     Ok(KvTypes {
         resolve,
+        sizes,
         stored_value_id,
         keyspace_metadata_id,
         binary_export_id,
@@ -83,6 +130,7 @@ fn load_kv_types() -> Result<KvTypes, KvError> {
         keyspace_list_id,
         _database_info_id: database_info_id,
         database_list_id,
+        module_registration_id,
         stored_value_wave_type,
         keyspace_metadata_wave_type,
         binary_export_wave_type,
@@ -90,9 +138,74 @@ fn load_kv_types() -> Result<KvTypes, KvError> {
         keyspace_list_wave_type,
         database_info_wave_type,
         database_list_wave_type,
+        module_registration_wave_type,
+        stored_value_flat_size,
+        stored_value_v1_flat_size,
+        stored_value_v2_flat_size,
+        expires_at_offset,
+        expires_at_payload_offset,
+        keyspace_metadata_flat_size,
+        keyspace_metadata_legacy_flat_size,
+        keyspace_metadata_string_lengths,
+        module_registration_flat_size,
     })
 }
 
+/// Fields of a record type in kv.wit.
+fn record_fields<'a>(
+    resolve: &'a Resolve,
+    type_id: TypeId,
+    name: &str,
+) -> Result<&'a [Field], KvError> {
+    match resolve.types.get(type_id).map(|t| &t.kind) {
+        Some(TypeDefKind::Record(record)) => Ok(&record.fields),
+        _ => Err(KvError::TypeNotFound(format!("{} record", name))),
+    }
+}
+
+/// Flat size of a record made of the first `count` of `fields`, i.e. the
+/// record as it was before later fields were appended.
+fn prefix_flat_size(sizes: &SizeAlign, fields: &[Field], count: usize) -> usize {
+    sizes
+        .record(fields.iter().take(count).map(|f| &f.ty))
+        .size
+        .size_wasm32()
+}
+
+/// Offset of a named field within a record's flat layout.
+fn field_offset(sizes: &SizeAlign, fields: &[Field], name: &str) -> Result<usize, KvError> {
+    sizes
+        .field_offsets(fields.iter().map(|f| &f.ty))
+        .into_iter()
+        .zip(fields)
+        .find(|(_, field)| field.name == name)
+        .map(|((offset, _), _)| offset.size_wasm32())
+        .ok_or_else(|| KvError::TypeNotFound(format!("{} field", name)))
+}
+
+/// Join a flat buffer and its linear memory into one self-contained record.
+fn join_record(mut buffer: Vec<u8>, memory: Vec<u8>) -> Vec<u8> {
+    buffer.extend(memory);
+    buffer
+}
+
+/// Split a self-contained record into its flat buffer and linear memory.
+fn split_record<'a>(
+    data: &'a [u8],
+    flat_size: usize,
+    what: &str,
+) -> Result<(&'a [u8], &'a [u8]), KvError> {
+    if data.len() < flat_size {
+        return Err(KvError::InvalidFormat(format!(
+            "{} data too small: {} bytes, need at least {}",
+            what,
+            data.len(),
+            flat_size
+        )));
+    }
+    Ok(data.split_at(flat_size))
+}
+
 /// Helper to get a record field type by name
 fn get_field_type(wave_type: &WaveType, field_name: &str) -> Option<WaveType> {
     wave_type
@@ -132,6 +245,18 @@ fn make_semantic_version(
     .map_err(|e| KvError::WaveParse(e.to_string()))
 }
 
+/// Helper to create an `option<u64>` WAVE value for a record field
+fn make_optional_u64(
+    parent_type: &WaveType,
+    field_name: &str,
+    value: Option<u64>,
+) -> Result<Value, KvError> {
+    let option_type = get_field_type(parent_type, field_name)
+        .ok_or_else(|| KvError::InvalidFormat(format!("Missing {} field type", field_name)))?;
+    Value::make_option(&option_type, value.map(Value::make_u64))
+        .map_err(|e| KvError::WaveParse(e.to_string()))
+}
+
 /// Helper to extract a SemanticVersion from a WAVE record value
 fn extract_semantic_version(value: &Value) -> Result<SemanticVersion, KvError> {
     let fields: RecordFields<'_> = value.unwrap_record().collect();
@@ -147,7 +272,7 @@ impl StoredValue {
     /// Encode the StoredValue to binary using canonical ABI.
     pub fn encode(&self) -> Result<(Vec<u8>, Vec<u8>), KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
 
         // Build WAVE value for stored-value record
         let wave_value = self.to_wave_value(&kv.stored_value_wave_type)?;
@@ -164,13 +289,29 @@ impl StoredValue {
     }
 
     /// Decode a StoredValue from binary using canonical ABI.
+    ///
+    /// Buffers of older format versions are accepted: version 1 (without
+    /// `revision`) decodes with revision 0, and versions 1 and 2 (without
+    /// `expires-at`) decode without expiry.
     pub fn decode(buffer: &[u8], memory: &[u8]) -> Result<Self, KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
         let mem = LinearMemory::from_slice(memory);
 
+        // Older layouts are a prefix of the current one: zero-fill the rest
+        let is_older = buffer
+            .first()
+            .is_some_and(|&version| version < Self::CURRENT_VERSION);
+        let buffer: Cow<'_, [u8]> = if is_older && buffer.len() < kv.stored_value_flat_size {
+            let mut padded = buffer.to_vec();
+            padded.resize(kv.stored_value_flat_size, 0);
+            Cow::Owned(padded)
+        } else {
+            Cow::Borrowed(buffer)
+        };
+
         let (value, _) = abi.lift_with_memory(
-            buffer,
+            &buffer,
             &Type::Id(kv.stored_value_id),
             &kv.stored_value_wave_type,
             &mem,
@@ -179,6 +320,42 @@ impl StoredValue {
         Self::from_wave_value(&value)
     }
 
+    /// Encode the StoredValue as a single record: the flat buffer followed by linear memory.
+    ///
+    /// This is the on-disk representation used by [`KvStore`](super::KvStore).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KvError> {
+        let (buffer, memory) = self.encode()?;
+        Ok(join_record(buffer, memory))
+    }
+
+    /// Decode a StoredValue from a single record produced by [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(data: &[u8]) -> Result<Self, KvError> {
+        // The first field is the format version, which determines the flat size
+        let flat_size = match data.first() {
+            Some(1) => KV_TYPES.stored_value_v1_flat_size,
+            Some(2) => KV_TYPES.stored_value_v2_flat_size,
+            _ => KV_TYPES.stored_value_flat_size,
+        };
+        let (buffer, memory) = split_record(data, flat_size, "Stored value")?;
+        Self::decode(buffer, memory)
+    }
+
+    /// Read the expiry of a record produced by [`to_bytes`](Self::to_bytes)
+    /// without decoding the rest of it.
+    pub(super) fn peek_expires_at(data: &[u8]) -> Option<u64> {
+        let kv = &*KV_TYPES;
+        // Expiry was added in format version 3
+        if data.first().is_none_or(|&version| version < 3) {
+            return None;
+        }
+        if data.get(kv.expires_at_offset) != Some(&1) {
+            return None;
+        }
+        let start = kv.expires_at_offset + kv.expires_at_payload_offset;
+        let bytes = data.get(start..start + 8)?;
+        bytes.try_into().ok().map(u64::from_le_bytes)
+    }
+
     fn to_wave_value(&self, wave_type: &WaveType) -> Result<Value, KvError> {
         // Get field types from the record type
         let value_field_type = get_field_type(wave_type, "value")
@@ -216,6 +393,8 @@ impl StoredValue {
                 .map_err(|e| KvError::WaveParse(e.to_string()))?,
         };
 
+        let expires_at_val = make_optional_u64(wave_type, "expires-at", self.expires_at)?;
+
         // Build the record
         Value::make_record(
             wave_type,
@@ -224,6 +403,8 @@ impl StoredValue {
                 ("type-version", type_version_val),
                 ("value", value_val),
                 ("memory", memory_val),
+                ("revision", Value::make_u64(self.revision)),
+                ("expires-at", expires_at_val),
             ],
         )
         .map_err(|e| KvError::WaveParse(e.to_string()))
@@ -233,6 +414,7 @@ impl StoredValue {
         let fields: RecordFields<'_> = value.unwrap_record().collect();
 
         let version = get_field(&fields, "version")?.unwrap_u8();
+        let revision = get_field(&fields, "revision")?.unwrap_u64();
         let type_version = extract_semantic_version(get_field(&fields, "type-version")?)?;
         let value_bytes: Vec<u8> = get_field(&fields, "value")?
             .unwrap_list()
@@ -241,12 +423,17 @@ impl StoredValue {
         let memory = get_field(&fields, "memory")?
             .unwrap_option()
             .map(|inner| inner.unwrap_list().map(|e| e.unwrap_u8()).collect());
+        let expires_at = get_field(&fields, "expires-at")?
+            .unwrap_option()
+            .map(|inner| inner.unwrap_u64());
 
         Ok(StoredValue {
             version,
             type_version,
             value: value_bytes,
             memory,
+            revision,
+            expires_at,
         })
     }
 }
@@ -255,7 +442,7 @@ impl KeyspaceMetadata {
     /// Encode the KeyspaceMetadata to binary using canonical ABI.
     pub fn encode(&self) -> Result<(Vec<u8>, Vec<u8>), KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
 
         let wave_value = self.to_wave_value(&kv.keyspace_metadata_wave_type)?;
 
@@ -271,13 +458,25 @@ impl KeyspaceMetadata {
     }
 
     /// Decode a KeyspaceMetadata from binary using canonical ABI.
+    ///
+    /// Buffers written before `default-ttl` was added are accepted and decode
+    /// without a default TTL.
     pub fn decode(buffer: &[u8], memory: &[u8]) -> Result<Self, KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
         let mem = LinearMemory::from_slice(memory);
 
+        // Older layouts are a prefix of the current one: zero-fill the rest
+        let buffer: Cow<'_, [u8]> = if buffer.len() < kv.keyspace_metadata_flat_size {
+            let mut padded = buffer.to_vec();
+            padded.resize(kv.keyspace_metadata_flat_size, 0);
+            Cow::Owned(padded)
+        } else {
+            Cow::Borrowed(buffer)
+        };
+
         let (value, _) = abi.lift_with_memory(
-            buffer,
+            &buffer,
             &Type::Id(kv.keyspace_metadata_id),
             &kv.keyspace_metadata_wave_type,
             &mem,
@@ -286,9 +485,47 @@ impl KeyspaceMetadata {
         Self::from_wave_value(&value)
     }
 
+    /// Encode the KeyspaceMetadata as a single record: the flat buffer followed by linear memory.
+    ///
+    /// This is the on-disk representation used by [`KvStore`](super::KvStore).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KvError> {
+        let (buffer, memory) = self.encode()?;
+        Ok(join_record(buffer, memory))
+    }
+
+    /// Decode a KeyspaceMetadata from a single record produced by [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(data: &[u8]) -> Result<Self, KvError> {
+        let (buffer, memory) = split_record(data, Self::flat_size(data), "Keyspace metadata")?;
+        Self::decode(buffer, memory)
+    }
+
+    /// Flat size of a record produced by [`to_bytes`](Self::to_bytes).
+    ///
+    /// Metadata has no format version, so records written before
+    /// `default-ttl` was added are told apart by their length: the memory
+    /// holds nothing but the strings, back to back.
+    fn flat_size(data: &[u8]) -> usize {
+        let kv = &*KV_TYPES;
+        let strings_len = kv
+            .keyspace_metadata_string_lengths
+            .iter()
+            .filter_map(|&offset| data.get(offset..offset + 4))
+            .filter_map(|bytes| bytes.try_into().ok().map(u32::from_le_bytes))
+            .fold(0usize, |total, len| total.saturating_add(len as usize));
+        if data.len().checked_sub(strings_len) == Some(kv.keyspace_metadata_legacy_flat_size) {
+            kv.keyspace_metadata_legacy_flat_size
+        } else {
+            kv.keyspace_metadata_flat_size
+        }
+    }
+
     fn to_wave_value(&self, wave_type: &WaveType) -> Result<Value, KvError> {
         // Build the semantic-version record
         let type_version_val = make_semantic_version(&self.type_version, wave_type)?;
+        let default_ttl_millis = self
+            .default_ttl
+            .map(|ttl| u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX));
+        let default_ttl_val = make_optional_u64(wave_type, "default-ttl", default_ttl_millis)?;
 
         Value::make_record(
             wave_type,
@@ -309,6 +546,7 @@ impl KeyspaceMetadata {
                 ("type-version", type_version_val),
                 ("type-hash", Value::make_u32(self.type_hash)),
                 ("created-at", Value::make_u64(self.created_at)),
+                ("default-ttl", default_ttl_val),
             ],
         )
         .map_err(|e| KvError::WaveParse(e.to_string()))
@@ -328,6 +566,9 @@ impl KeyspaceMetadata {
         let type_version = extract_semantic_version(get_field(&fields, "type-version")?)?;
         let type_hash = get_field(&fields, "type-hash")?.unwrap_u32();
         let created_at = get_field(&fields, "created-at")?.unwrap_u64();
+        let default_ttl = get_field(&fields, "default-ttl")?
+            .unwrap_option()
+            .map(|inner| Duration::from_millis(inner.unwrap_u64()));
 
         Ok(KeyspaceMetadata {
             name,
@@ -337,6 +578,118 @@ impl KeyspaceMetadata {
             type_version,
             type_hash,
             created_at,
+            default_ttl,
+        })
+    }
+}
+
+impl ModuleRegistration {
+    /// Encode the ModuleRegistration to binary using canonical ABI.
+    pub fn encode(&self) -> Result<(Vec<u8>, Vec<u8>), KvError> {
+        let kv = &*KV_TYPES;
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
+
+        let wave_value = self.to_wave_value(&kv.module_registration_wave_type)?;
+
+        let mut memory = LinearMemory::new();
+        let buffer = abi.lower_with_memory(
+            &wave_value,
+            &Type::Id(kv.module_registration_id),
+            &kv.module_registration_wave_type,
+            &mut memory,
+        )?;
+
+        Ok((buffer, memory.into_bytes()))
+    }
+
+    /// Decode a ModuleRegistration from binary using canonical ABI.
+    pub fn decode(buffer: &[u8], memory: &[u8]) -> Result<Self, KvError> {
+        let kv = &*KV_TYPES;
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
+        let mem = LinearMemory::from_slice(memory);
+
+        let (value, _) = abi.lift_with_memory(
+            buffer,
+            &Type::Id(kv.module_registration_id),
+            &kv.module_registration_wave_type,
+            &mem,
+        )?;
+
+        Self::from_wave_value(&value)
+    }
+
+    /// Encode the ModuleRegistration as a single record: the flat buffer followed by linear memory.
+    ///
+    /// This is the on-disk representation used by [`KvStore`](super::KvStore).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KvError> {
+        let (buffer, memory) = self.encode()?;
+        Ok(join_record(buffer, memory))
+    }
+
+    /// Decode a ModuleRegistration from a single record produced by [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(data: &[u8]) -> Result<Self, KvError> {
+        let flat_size = KV_TYPES.module_registration_flat_size;
+        let (buffer, memory) = split_record(data, flat_size, "Module registration")?;
+        Self::decode(buffer, memory)
+    }
+
+    fn to_wave_value(&self, wave_type: &WaveType) -> Result<Value, KvError> {
+        let kind_type = get_field_type(wave_type, "kind")
+            .ok_or_else(|| KvError::InvalidFormat("Missing kind field type".to_string()))?;
+        let kind_val = Value::make_enum(&kind_type, self.kind.as_str())
+            .map_err(|e| KvError::WaveParse(e.to_string()))?;
+
+        Value::make_record(
+            wave_type,
+            vec![
+                ("id", Value::make_string(Cow::Borrowed(&self.id))),
+                ("name", Value::make_string(Cow::Borrowed(&self.name))),
+                (
+                    "wit-definition",
+                    Value::make_string(Cow::Borrowed(&self.wit_definition)),
+                ),
+                (
+                    "input-type",
+                    Value::make_string(Cow::Borrowed(&self.input_type)),
+                ),
+                (
+                    "output-type",
+                    Value::make_string(Cow::Borrowed(&self.output_type)),
+                ),
+                ("kind", kind_val),
+                ("created-at", Value::make_u64(self.created_at)),
+            ],
+        )
+        .map_err(|e| KvError::WaveParse(e.to_string()))
+    }
+
+    fn from_wave_value(value: &Value) -> Result<Self, KvError> {
+        let fields: RecordFields<'_> = value.unwrap_record().collect();
+
+        let id = get_field(&fields, "id")?.unwrap_string().to_string();
+        let name = get_field(&fields, "name")?.unwrap_string().to_string();
+        let wit_definition = get_field(&fields, "wit-definition")?
+            .unwrap_string()
+            .to_string();
+        let input_type = get_field(&fields, "input-type")?
+            .unwrap_string()
+            .to_string();
+        let output_type = get_field(&fields, "output-type")?
+            .unwrap_string()
+            .to_string();
+        let kind = get_field(&fields, "kind")?
+            .unwrap_enum()
+            .parse::<ModuleKind>()?;
+        let created_at = get_field(&fields, "created-at")?.unwrap_u64();
+
+        Ok(ModuleRegistration {
+            id,
+            name,
+            wit_definition,
+            input_type,
+            output_type,
+            kind,
+            created_at,
         })
     }
 }
@@ -378,7 +731,7 @@ impl BinaryExport {
     /// Encode the BinaryExport to binary using canonical ABI.
     pub fn encode(&self) -> Result<(Vec<u8>, Vec<u8>), KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
 
         let wave_value = self.to_wave_value(&kv.binary_export_wave_type)?;
 
@@ -396,21 +749,14 @@ impl BinaryExport {
     /// Decode a BinaryExport from a single byte slice.
     /// The first FLAT_SIZE bytes are the flat buffer, the rest is linear memory.
     pub fn decode_from_bytes(data: &[u8]) -> Result<Self, KvError> {
-        if data.len() < Self::FLAT_SIZE {
-            return Err(KvError::InvalidFormat(format!(
-                "Binary export data too small: {} bytes, need at least {}",
-                data.len(),
-                Self::FLAT_SIZE
-            )));
-        }
-        let (buffer, memory) = data.split_at(Self::FLAT_SIZE);
+        let (buffer, memory) = split_record(data, Self::FLAT_SIZE, "Binary export")?;
         Self::decode(buffer, memory)
     }
 
     /// Decode a BinaryExport from separate buffer and memory slices.
     pub fn decode(buffer: &[u8], memory: &[u8]) -> Result<Self, KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
         let mem = LinearMemory::from_slice(memory);
 
         let (value, _) = abi.lift_with_memory(
@@ -483,18 +829,24 @@ impl BinaryExport {
 pub struct KeyList {
     /// The keys in the keyspace
     pub keys: Vec<String>,
+    /// Opaque cursor to pass to [`KvStore::list`](super::KvStore::list) for
+    /// the next page, or `None` if this is the last page
+    pub next_cursor: Option<String>,
 }
 
 impl KeyList {
-    /// Create a new KeyList from a vector of strings.
+    /// Create a new KeyList from a vector of strings, without a next page.
     pub fn new(keys: Vec<String>) -> Self {
-        Self { keys }
+        Self {
+            keys,
+            next_cursor: None,
+        }
     }
 
     /// Encode the KeyList to binary using canonical ABI.
     pub fn encode(&self) -> Result<(Vec<u8>, Vec<u8>), KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
 
         let wave_value = self.to_wave_value(&kv.key_list_wave_type)?;
 
@@ -520,10 +872,22 @@ impl KeyList {
             }
             write!(out, "\"{}\"", key.escape_default()).ok();
         }
-        out.push_str("]}");
+        out.push_str("], next-cursor: ");
+        match &self.next_cursor {
+            Some(cursor) => write!(out, "some(\"{}\")", cursor.escape_default()).ok(),
+            None => write!(out, "none").ok(),
+        };
+        out.push('}');
         out
     }
 
+    /// Convert to JSON, in the mapping of [`wave_to_json`].
+    pub fn to_json(&self) -> Result<serde_json::Value, KvError> {
+        let kv = &*KV_TYPES;
+        let value = self.to_wave_value(&kv.key_list_wave_type)?;
+        Ok(wave_to_json(&value, &kv.key_list_wave_type)?)
+    }
+
     fn to_wave_value(&self, wave_type: &WaveType) -> Result<Value, KvError> {
         let keys_field_type = get_field_type(wave_type, "keys")
             .ok_or_else(|| KvError::InvalidFormat("Missing keys field type".to_string()))?;
@@ -536,8 +900,21 @@ impl KeyList {
         )
         .map_err(|e| KvError::WaveParse(e.to_string()))?;
 
-        Value::make_record(wave_type, vec![("keys", keys_val)])
-            .map_err(|e| KvError::WaveParse(e.to_string()))
+        let next_cursor_type = get_field_type(wave_type, "next-cursor")
+            .ok_or_else(|| KvError::InvalidFormat("Missing next-cursor field type".to_string()))?;
+        let next_cursor_val = Value::make_option(
+            &next_cursor_type,
+            self.next_cursor
+                .as_ref()
+                .map(|c| Value::make_string(Cow::Borrowed(c))),
+        )
+        .map_err(|e| KvError::WaveParse(e.to_string()))?;
+
+        Value::make_record(
+            wave_type,
+            vec![("keys", keys_val), ("next-cursor", next_cursor_val)],
+        )
+        .map_err(|e| KvError::WaveParse(e.to_string()))
     }
 }
 
@@ -558,7 +935,7 @@ impl KeyspaceList {
     /// Encode the KeyspaceList to binary using canonical ABI.
     pub fn encode(&self) -> Result<(Vec<u8>, Vec<u8>), KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
 
         let wave_value = self.to_wave_value(&kv.keyspace_list_wave_type)?;
 
@@ -600,6 +977,13 @@ impl KeyspaceList {
         Ok(out)
     }
 
+    /// Convert to JSON, in the mapping of [`wave_to_json`].
+    pub fn to_json(&self) -> Result<serde_json::Value, KvError> {
+        let kv = &*KV_TYPES;
+        let value = self.to_wave_value(&kv.keyspace_list_wave_type)?;
+        Ok(wave_to_json(&value, &kv.keyspace_list_wave_type)?)
+    }
+
     fn to_wave_value(&self, wave_type: &WaveType) -> Result<Value, KvError> {
         let keyspaces_field_type = get_field_type(wave_type, "keyspaces")
             .ok_or_else(|| KvError::InvalidFormat("Missing keyspaces field type".to_string()))?;
@@ -664,7 +1048,7 @@ impl DatabaseList {
     /// Encode the DatabaseList to binary using canonical ABI.
     pub fn encode(&self) -> Result<(Vec<u8>, Vec<u8>), KvError> {
         let kv = &*KV_TYPES;
-        let abi = CanonicalAbi::new(&kv.resolve);
+        let abi = CanonicalAbi::with_sizes(&kv.resolve, &kv.sizes);
 
         let wave_value = self.to_wave_value(&kv.database_list_wave_type)?;
 
@@ -694,6 +1078,13 @@ impl DatabaseList {
         out
     }
 
+    /// Convert to JSON, in the mapping of [`wave_to_json`].
+    pub fn to_json(&self) -> Result<serde_json::Value, KvError> {
+        let kv = &*KV_TYPES;
+        let value = self.to_wave_value(&kv.database_list_wave_type)?;
+        Ok(wave_to_json(&value, &kv.database_list_wave_type)?)
+    }
+
     fn to_wave_value(&self, wave_type: &WaveType) -> Result<Value, KvError> {
         let databases_field_type = get_field_type(wave_type, "databases")
             .ok_or_else(|| KvError::InvalidFormat("Missing databases field type".to_string()))?;
@@ -746,6 +1137,124 @@ mod tests {
         assert_eq!(original.memory, decoded.memory);
     }
 
+    #[test]
+    fn test_stored_value_bytes_roundtrip() {
+        for memory in [Some(vec![5, 6, 7]), None] {
+            let original =
+                StoredValue::new(SemanticVersion::new(0, 1, 0), vec![1, 2, 3, 4], memory);
+            let bytes = original.to_bytes().unwrap();
+            assert_eq!(StoredValue::from_bytes(&bytes).unwrap(), original);
+        }
+
+        assert!(StoredValue::from_bytes(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_stored_value_revision_roundtrip() {
+        let mut original = StoredValue::new(SemanticVersion::new(0, 1, 0), vec![1, 2], None);
+        original.revision = u64::MAX - 1;
+        let bytes = original.to_bytes().unwrap();
+        assert_eq!(StoredValue::from_bytes(&bytes).unwrap(), original);
+    }
+
+    #[test]
+    fn test_stored_value_v1_decode() {
+        // A version 1 record is the current flat layout cut before `revision`
+        let mut original =
+            StoredValue::new(SemanticVersion::new(0, 2, 0), vec![9, 8, 7], Some(vec![6]));
+        original.version = 1;
+        let (buffer, memory) = original.encode().unwrap();
+        let mut legacy = buffer
+            .get(..KV_TYPES.stored_value_v1_flat_size)
+            .unwrap()
+            .to_vec();
+        legacy.extend(&memory);
+
+        let decoded = StoredValue::from_bytes(&legacy).unwrap();
+        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.revision, 0);
+        assert_eq!(decoded.type_version, original.type_version);
+        assert_eq!(decoded.value, original.value);
+        assert_eq!(decoded.memory, original.memory);
+    }
+
+    #[test]
+    fn test_stored_value_expiry() {
+        let mut original =
+            StoredValue::new(SemanticVersion::new(0, 1, 0), vec![1, 2], Some(vec![3]));
+        let bytes = original.to_bytes().unwrap();
+        assert_eq!(StoredValue::peek_expires_at(&bytes), None);
+
+        original.expires_at = Some(1_700_000_000_000);
+        let bytes = original.to_bytes().unwrap();
+        assert_eq!(StoredValue::from_bytes(&bytes).unwrap(), original);
+        assert_eq!(StoredValue::peek_expires_at(&bytes), original.expires_at);
+        assert!(original.is_expired_at(1_700_000_000_000));
+        assert!(!original.is_expired_at(1_699_999_999_999));
+    }
+
+    #[test]
+    fn test_stored_value_v2_decode() {
+        // A version 2 record is the current flat layout cut before `expires-at`
+        let mut original =
+            StoredValue::new(SemanticVersion::new(0, 2, 0), vec![9, 8, 7], Some(vec![6]));
+        original.version = 2;
+        original.revision = 7;
+        let (buffer, memory) = original.encode().unwrap();
+        let mut legacy = buffer
+            .get(..KV_TYPES.stored_value_v2_flat_size)
+            .unwrap()
+            .to_vec();
+        legacy.extend(&memory);
+
+        assert_eq!(StoredValue::from_bytes(&legacy).unwrap(), original);
+        assert_eq!(StoredValue::peek_expires_at(&legacy), None);
+    }
+
+    #[test]
+    fn test_keyspace_metadata_legacy_decode() {
+        let mut original = KeyspaceMetadata::new(
+            "task".to_string(),
+            "test:types/types#task".to_string(),
+            "record task { name: string }".to_string(),
+            "task".to_string(),
+        );
+
+        // Written before `default-ttl`: the flat layout cut before it
+        let (buffer, memory) = original.encode().unwrap();
+        let mut legacy = buffer
+            .get(..KV_TYPES.keyspace_metadata_legacy_flat_size)
+            .unwrap()
+            .to_vec();
+        legacy.extend(&memory);
+        let decoded = KeyspaceMetadata::from_bytes(&legacy).unwrap();
+        assert_eq!(decoded.wit_definition, original.wit_definition);
+        assert_eq!(decoded.created_at, original.created_at);
+        assert_eq!(decoded.default_ttl, None);
+
+        original.default_ttl = Some(Duration::from_secs(90));
+        let decoded = KeyspaceMetadata::from_bytes(&original.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.wit_definition, original.wit_definition);
+        assert_eq!(decoded.default_ttl, original.default_ttl);
+    }
+
+    #[test]
+    fn test_keyspace_metadata_bytes_roundtrip() {
+        let original = KeyspaceMetadata::new(
+            "task".to_string(),
+            "test:types/types#task".to_string(),
+            "record task { name: string }".to_string(),
+            "task".to_string(),
+        );
+
+        let bytes = original.to_bytes().unwrap();
+        let decoded = KeyspaceMetadata::from_bytes(&bytes).unwrap();
+
+        assert_eq!(original.wit_definition, decoded.wit_definition);
+        assert_eq!(original.type_version, decoded.type_version);
+        assert_eq!(original.created_at, decoded.created_at);
+    }
+
     #[test]
     fn test_keyspace_metadata_roundtrip() {
         let original = KeyspaceMetadata::new(
@@ -766,6 +1275,24 @@ mod tests {
         assert_eq!(original.type_hash, decoded.type_hash);
     }
 
+    #[test]
+    fn test_module_registration_bytes_roundtrip() {
+        for kind in [ModuleKind::Mapper, ModuleKind::Reducer] {
+            let original = ModuleRegistration::new(
+                "sum-ages".to_string(),
+                "Sum ages".to_string(),
+                "record person { age: u32 }".to_string(),
+                "person".to_string(),
+                "u64".to_string(),
+                kind,
+            );
+            let bytes = original.to_bytes().unwrap();
+            assert_eq!(ModuleRegistration::from_bytes(&bytes).unwrap(), original);
+        }
+
+        assert!(ModuleRegistration::from_bytes(&[1, 2, 3]).is_err());
+    }
+
     #[test]
     fn test_binary_export_roundtrip() {
         let original = BinaryExport {
@@ -802,4 +1329,29 @@ mod tests {
         assert_eq!(export.buffer, stored.value);
         assert_eq!(export.memory, stored.memory);
     }
+
+    #[test]
+    fn test_key_list_wave_parses() {
+        let kv = &*KV_TYPES;
+        for next_cursor in [None, Some("6b6579".to_string())] {
+            let list = KeyList {
+                keys: vec!["a".to_string(), "b \"quoted\"".to_string()],
+                next_cursor,
+            };
+            let parsed: Value =
+                wasm_wave::from_str(&kv.key_list_wave_type, &list.to_wave()).unwrap();
+            let expected = list.to_wave_value(&kv.key_list_wave_type).unwrap();
+            assert_eq!(parsed, expected);
+        }
+    }
+
+    #[test]
+    fn test_key_list_encode() {
+        let list = KeyList {
+            keys: vec!["a".to_string()],
+            next_cursor: Some("61".to_string()),
+        };
+        let (buffer, _) = list.encode().unwrap();
+        assert_eq!(buffer.len(), 20);
+    }
 }
@@ -0,0 +1,434 @@
+//! Secondary indexes on record fields.
+//!
+//! An index maps the value of one (possibly nested) record field to the
+//! primary keys holding it. All indexes of a keyspace share one
+//! `index_{keyspace}` fjall keyspace, with one empty entry per indexed value:
+//!
+//! ```text
+//! field-path 0x00 encoded-field-value primary-key
+//! ```
+//!
+//! Field values are encoded so that byte order matches value order (see
+//! [`encode_field`]) and every encoding is self-delimiting, so the primary
+//! key is whatever follows it and all keys with a given field value are
+//! found with a single prefix scan.
+
+use std::collections::BTreeSet;
+
+use fjall::{Keyspace, OwnedWriteBatch};
+use wasm_wave::value::{Type as WaveType, Value};
+use wasm_wave::wasm::{WasmType, WasmTypeKind, WasmValue};
+
+use super::error::KvError;
+
+/// Separates the field path from the encoded field value in entry keys.
+const PATH_TERMINATOR: u8 = 0x00;
+
+/// A secondary index on one field of a keyspace's record type.
+#[derive(Debug, Clone)]
+pub(super) struct FieldIndex {
+    field_path: String,
+    segments: Vec<String>,
+    /// Type of the indexed field, without an enclosing `option`.
+    field_type: WaveType,
+}
+
+impl FieldIndex {
+    /// Resolve a dotted field path like `address.city` against a keyspace type.
+    ///
+    /// Every segment but the last must name a `record` field (optionally
+    /// wrapped in an `option`), and the last one a scalar, `string`, `char`
+    /// or `enum` field (optionally wrapped in an `option`; `none` values are
+    /// not indexed).
+    pub(super) fn new(field_path: &str, ty: &WaveType) -> Result<Self, String> {
+        let segments: Vec<String> = field_path.split('.').map(str::to_string).collect();
+        if segments.iter().any(String::is_empty) {
+            return Err("field path must be dot-separated field names".to_string());
+        }
+
+        let mut current = ty.clone();
+        for (depth, segment) in segments.iter().enumerate() {
+            let parent = unwrap_option_type(&current);
+            if parent.kind() != WasmTypeKind::Record {
+                let at = segments.get(..depth).unwrap_or_default().join(".");
+                return Err(if at.is_empty() {
+                    format!("the keyspace type is a {}, not a record", parent.kind())
+                } else {
+                    format!("'{}' is a {}, not a record", at, parent.kind())
+                });
+            }
+            current = parent
+                .record_fields()
+                .find(|(name, _)| name == segment)
+                .map(|(_, field_type)| field_type)
+                .ok_or_else(|| format!("no field named '{}'", segment))?;
+        }
+
+        let field_type = unwrap_option_type(&current);
+        if !is_indexable(field_type.kind()) {
+            return Err(format!(
+                "{} fields cannot be indexed; index a scalar, string, char or enum field",
+                field_type.kind()
+            ));
+        }
+
+        Ok(Self {
+            field_path: field_path.to_string(),
+            segments,
+            field_type,
+        })
+    }
+
+    /// Type of the indexed field, for parsing lookup values.
+    pub(super) fn field_type(&self) -> &WaveType {
+        &self.field_type
+    }
+
+    /// Prefix shared by the entries of every key whose field equals `field`.
+    pub(super) fn value_prefix(&self, field: &Value) -> Vec<u8> {
+        let mut prefix = entry_prefix(&self.field_path);
+        encode_field(field, &mut prefix);
+        prefix
+    }
+
+    /// Entry key for `value` stored under `key`, or `None` if the field is
+    /// absent (a `none` along the path).
+    pub(super) fn entry(&self, value: &Value, key: &str) -> Option<Vec<u8>> {
+        let field = self.extract(value)?;
+        let mut entry = self.value_prefix(&field);
+        entry.extend_from_slice(key.as_bytes());
+        Some(entry)
+    }
+
+    /// Primary key of an entry of this index.
+    pub(super) fn primary_key(&self, entry: &[u8], value_prefix: &[u8]) -> String {
+        String::from_utf8_lossy(entry.get(value_prefix.len()..).unwrap_or_default()).into_owned()
+    }
+
+    /// Walk the field path down `value`.
+    fn extract(&self, value: &Value) -> Option<Value> {
+        let mut current = value.clone();
+        for segment in &self.segments {
+            let record = unwrap_option_value(current)?;
+            if record.kind() != WasmTypeKind::Record {
+                return None;
+            }
+            current = record
+                .unwrap_record()
+                .find(|(name, _)| name == segment)
+                .map(|(_, field)| field.into_owned())?;
+        }
+        unwrap_option_value(current)
+    }
+}
+
+/// The indexes of one keyspace, ready to be updated alongside value writes.
+pub(super) struct KeyspaceIndexes {
+    pub(super) data: Keyspace,
+    pub(super) indexes: Vec<FieldIndex>,
+}
+
+impl KeyspaceIndexes {
+    /// Entries of every index for `value` stored under `key`.
+    pub(super) fn entries<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a Value,
+    ) -> impl Iterator<Item = Vec<u8>> + 'a {
+        self.indexes
+            .iter()
+            .filter_map(move |index| index.entry(value, key))
+    }
+
+    /// Queue the entry changes for `key` going from `old` to `new`.
+    pub(super) fn update(
+        &self,
+        batch: &mut OwnedWriteBatch,
+        key: &str,
+        old: Option<&Value>,
+        new: Option<&Value>,
+    ) {
+        for index in &self.indexes {
+            let old = old.and_then(|value| index.entry(value, key));
+            let new = new.and_then(|value| index.entry(value, key));
+            if old == new {
+                continue;
+            }
+            if let Some(old) = old {
+                batch.remove(&self.data, old);
+            }
+            if let Some(new) = new {
+                batch.insert(&self.data, new, []);
+            }
+        }
+    }
+
+    /// Queue replacing every entry of these indexes with `entries`.
+    ///
+    /// Entries that stay are left alone rather than removed and re-inserted,
+    /// since a batch must not write the same key twice.
+    pub(super) fn replace(
+        &self,
+        batch: &mut OwnedWriteBatch,
+        entries: &BTreeSet<Vec<u8>>,
+    ) -> Result<(), KvError> {
+        for index in &self.indexes {
+            for guard in self.data.prefix(entry_prefix(&index.field_path)) {
+                let entry = guard.key()?;
+                if !entries.contains(entry.as_ref()) {
+                    batch.remove(&self.data, entry);
+                }
+            }
+        }
+        for entry in entries {
+            batch.insert(&self.data, entry.as_slice(), []);
+        }
+        Ok(())
+    }
+}
+
+/// Queue removing every entry of the index on `field_path`.
+pub(super) fn clear_entries(
+    data: &Keyspace,
+    batch: &mut OwnedWriteBatch,
+    field_path: &str,
+) -> Result<(), KvError> {
+    for guard in data.prefix(entry_prefix(field_path)) {
+        batch.remove(data, guard.key()?);
+    }
+    Ok(())
+}
+
+/// Prefix shared by every entry of the index on `field_path`.
+fn entry_prefix(field_path: &str) -> Vec<u8> {
+    let mut prefix = field_path.as_bytes().to_vec();
+    prefix.push(PATH_TERMINATOR);
+    prefix
+}
+
+/// Append the order-preserving encoding of a field value.
+///
+/// - `bool`: one byte, `0` or `1`
+/// - integers: big-endian, with the sign bit flipped for signed types
+/// - floats: big-endian IEEE 754 bits, with the sign bit flipped for positive
+///   numbers and all bits flipped for negative ones (`-0` is encoded as `0`)
+/// - `char`: its code point as a big-endian `u32`
+/// - `string` and `enum` (by case name): the UTF-8 bytes with `0x00` escaped
+///   as `0x00 0xff`, terminated by `0x00 0x01`
+pub(super) fn encode_field(value: &Value, out: &mut Vec<u8>) {
+    match value.kind() {
+        WasmTypeKind::Bool => out.push(u8::from(value.unwrap_bool())),
+        WasmTypeKind::U8 => out.push(value.unwrap_u8()),
+        WasmTypeKind::U16 => out.extend(value.unwrap_u16().to_be_bytes()),
+        WasmTypeKind::U32 => out.extend(value.unwrap_u32().to_be_bytes()),
+        WasmTypeKind::U64 => out.extend(value.unwrap_u64().to_be_bytes()),
+        WasmTypeKind::S8 => out.push(value.unwrap_s8() as u8 ^ 0x80),
+        WasmTypeKind::S16 => out.extend((value.unwrap_s16() as u16 ^ (1 << 15)).to_be_bytes()),
+        WasmTypeKind::S32 => out.extend((value.unwrap_s32() as u32 ^ (1 << 31)).to_be_bytes()),
+        WasmTypeKind::S64 => out.extend((value.unwrap_s64() as u64 ^ (1 << 63)).to_be_bytes()),
+        WasmTypeKind::F32 => {
+            let bits = (value.unwrap_f32() + 0.0).to_bits();
+            let ordered = if bits >> 31 == 1 {
+                !bits
+            } else {
+                bits | (1 << 31)
+            };
+            out.extend(ordered.to_be_bytes());
+        }
+        WasmTypeKind::F64 => {
+            let bits = (value.unwrap_f64() + 0.0).to_bits();
+            let ordered = if bits >> 63 == 1 {
+                !bits
+            } else {
+                bits | (1 << 63)
+            };
+            out.extend(ordered.to_be_bytes());
+        }
+        WasmTypeKind::Char => out.extend(u32::from(value.unwrap_char()).to_be_bytes()),
+        WasmTypeKind::String => encode_str(&value.unwrap_string(), out),
+        WasmTypeKind::Enum => encode_str(&value.unwrap_enum(), out),
+        // FieldIndex::new only accepts the kinds above
+        _ => {}
+    }
+}
+
+fn encode_str(s: &str, out: &mut Vec<u8>) {
+    for &byte in s.as_bytes() {
+        out.push(byte);
+        if byte == 0x00 {
+            out.push(0xff);
+        }
+    }
+    out.extend([0x00, 0x01]);
+}
+
+fn is_indexable(kind: WasmTypeKind) -> bool {
+    matches!(
+        kind,
+        WasmTypeKind::Bool
+            | WasmTypeKind::S8
+            | WasmTypeKind::S16
+            | WasmTypeKind::S32
+            | WasmTypeKind::S64
+            | WasmTypeKind::U8
+            | WasmTypeKind::U16
+            | WasmTypeKind::U32
+            | WasmTypeKind::U64
+            | WasmTypeKind::F32
+            | WasmTypeKind::F64
+            | WasmTypeKind::Char
+            | WasmTypeKind::String
+            | WasmTypeKind::Enum
+    )
+}
+
+pub(super) fn unwrap_option_type(ty: &WaveType) -> WaveType {
+    match ty.option_some_type() {
+        Some(some) if ty.kind() == WasmTypeKind::Option => some,
+        _ => ty.clone(),
+    }
+}
+
+pub(super) fn unwrap_option_value(value: Value) -> Option<Value> {
+    if value.kind() == WasmTypeKind::Option {
+        value.unwrap_option().map(|some| some.into_owned())
+    } else {
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::load_wit_type_from_string;
+
+    const WIT: &str = "package test:index;
+interface types {
+    enum tier { gold, silver }
+    record address { city: string, zip: option<u32> }
+    record user {
+        email: string,
+        age: s32,
+        score: f64,
+        tier: tier,
+        address: option<address>,
+        tags: list<string>,
+    }
+}
+";
+
+    fn user_type() -> WaveType {
+        load_wit_type_from_string(WIT, Some("user")).unwrap().2
+    }
+
+    fn user(email: &str, address: &str) -> Value {
+        let wave = format!(
+            "{{email: \"{}\", age: -3, score: 1.5, tier: gold, address: {}, tags: []}}",
+            email, address
+        );
+        wasm_wave::from_str(&user_type(), &wave).unwrap()
+    }
+
+    fn encoded(ty: &str, wave: &str) -> Vec<u8> {
+        let ty = load_wit_type_from_string(
+            &format!("package test:e;\ninterface t {{ type subject = {}; }}", ty),
+            Some("subject"),
+        )
+        .unwrap()
+        .2;
+        let mut out = Vec::new();
+        encode_field(&wasm_wave::from_str(&ty, wave).unwrap(), &mut out);
+        out
+    }
+
+    #[test]
+    fn test_resolve_field_paths() {
+        let ty = user_type();
+        for path in [
+            "email",
+            "age",
+            "score",
+            "tier",
+            "address.city",
+            "address.zip",
+        ] {
+            assert!(FieldIndex::new(path, &ty).is_ok(), "{}", path);
+        }
+        assert!(
+            FieldIndex::new("missing", &ty)
+                .unwrap_err()
+                .contains("no field")
+        );
+        assert!(
+            FieldIndex::new("tags", &ty)
+                .unwrap_err()
+                .contains("cannot be indexed")
+        );
+        assert!(
+            FieldIndex::new("address", &ty)
+                .unwrap_err()
+                .contains("cannot be indexed")
+        );
+        assert!(
+            FieldIndex::new("email.x", &ty)
+                .unwrap_err()
+                .contains("not a record")
+        );
+        assert!(FieldIndex::new("address..city", &ty).is_err());
+    }
+
+    #[test]
+    fn test_entries() {
+        let index = FieldIndex::new("address.city", &user_type()).unwrap();
+        let with_city = user("a@x", "some({city: \"Paris\", zip: none})");
+        let entry = index.entry(&with_city, "alice").unwrap();
+        let city = wasm_wave::from_str(index.field_type(), "\"Paris\"").unwrap();
+        let prefix = index.value_prefix(&city);
+        assert!(entry.starts_with(&prefix));
+        assert_eq!(index.primary_key(&entry, &prefix), "alice");
+
+        assert!(index.entry(&user("a@x", "none"), "alice").is_none());
+        let zip = FieldIndex::new("address.zip", &user_type()).unwrap();
+        assert!(zip.entry(&with_city, "alice").is_none());
+    }
+
+    #[test]
+    fn test_encoding_preserves_order() {
+        let cases: [(&str, &[&str]); 6] = [
+            ("s32", &["-2147483648", "-1", "0", "1", "2147483647"]),
+            ("u64", &["0", "255", "256", "18446744073709551615"]),
+            ("f64", &["-inf", "-2.5", "-0.5", "0", "0.5", "3", "inf"]),
+            (
+                "string",
+                &[
+                    "\"\"",
+                    "\"a\"",
+                    "\"a\\u{0}\"",
+                    "\"a\\u{0}b\"",
+                    "\"ab\"",
+                    "\"b\"",
+                ],
+            ),
+            ("char", &["'a'", "'b'", "'é'"]),
+            ("bool", &["false", "true"]),
+        ];
+        for (ty, values) in cases {
+            let encoded: Vec<_> = values.iter().map(|v| encoded(ty, v)).collect();
+            for pair in encoded.windows(2) {
+                assert!(pair.first() < pair.last(), "{}: {:?}", ty, values);
+            }
+        }
+        assert_eq!(encoded("f64", "-0"), encoded("f64", "0"));
+    }
+
+    #[test]
+    fn test_string_encoding_is_prefix_free() {
+        // "a" must not be a prefix of "ab", or lookups for "a" would find "ab"
+        let a = encoded("string", "\"a\"");
+        let ab = encoded("string", "\"ab\"");
+        let a_nul = encoded("string", "\"a\\u{0}\"");
+        assert!(!ab.starts_with(&a));
+        assert!(!a_nul.starts_with(&a));
+    }
+}
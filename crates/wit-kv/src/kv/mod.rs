@@ -4,14 +4,35 @@
 //! is associated with a WIT type. Values are stored using the canonical ABI
 //! binary format.
 
+mod backup;
+mod batch;
+mod cache;
+mod changes;
+mod durability;
 mod error;
+mod evolution;
+mod expiry;
 mod format;
+mod index;
+mod projection;
+mod query;
+mod scan;
 mod store;
+mod transfer;
 mod types;
 mod version;
 
+pub use backup::BackupInfo;
+pub use batch::{DEFAULT_MAP_BATCH_SIZE, WriteBatch};
+pub use changes::{ChangeEvent, ChangeOp, Watch};
+pub use durability::Durability;
 pub use error::KvError;
+pub use evolution::{ChangeKind, TypeChange, TypeDiff};
 pub use format::{BinaryExport, DatabaseInfo, DatabaseList, KeyList, KeyspaceList};
+pub use scan::{Scan, ScanOptions};
 pub use store::KvStore;
-pub use types::{KeyspaceMetadata, StoredValue};
+pub use transfer::{
+    DEFAULT_IMPORT_BATCH_SIZE, ImportError, ImportStats, TransferFormat, encode_frame,
+};
+pub use types::{KeyspaceMetadata, ModuleKind, ModuleRegistration, StoredValue};
 pub use version::{ParseVersionError, SemanticVersion};
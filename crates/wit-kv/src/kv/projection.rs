@@ -0,0 +1,494 @@
+//! Projections of selected record fields.
+//!
+//! A projection is built from a set of field paths such as `name` and
+//! `address.city`, and synthesizes a record type holding only those fields,
+//! nested like the original:
+//!
+//! ```text
+//! record user { name: string, avatar: list<u8>, address: option<address> }
+//! record address { street: string, city: string }
+//!
+//! // fields: name, address.city
+//! record user-projection { name: string, address: option<user-projection-address> }
+//! record user-projection-address { city: string }
+//! ```
+//!
+//! Selected fields keep their declaration order and type. Projected values
+//! are lifted straight from the stored canonical ABI buffer: only the
+//! selected fields are read, so strings and lists in other fields are never
+//! decoded from linear memory.
+
+use std::collections::BTreeMap;
+
+use wasm_wave::value::{Type as WaveType, Value};
+use wasm_wave::wasm::{WasmType, WasmValue};
+use wit_parser::{
+    Docs, Field, Int, Record, Resolve, SizeAlign, Stability, Type, TypeDef, TypeDefKind, TypeOwner,
+};
+
+use crate::{CanonicalAbi, CanonicalAbiError, LinearMemory, resolve_wit_type, wave_to_json};
+
+use super::cache::KeyspaceType;
+use super::error::KvError;
+use super::types::StoredValue;
+
+/// A record type synthesized from selected fields of a keyspace type.
+pub(super) struct Projection {
+    /// The keyspace type's resolve, plus the synthesized records.
+    resolve: Resolve,
+    sizes: SizeAlign,
+    /// The synthesized root record.
+    type_id: wit_parser::TypeId,
+    wave_type: WaveType,
+    fields: Vec<ProjectedField>,
+}
+
+/// Where a selected field is read from in the source record.
+struct ProjectedField {
+    /// Offset from the start of the enclosing source record.
+    offset: usize,
+    kind: FieldKind,
+}
+
+enum FieldKind {
+    /// The whole field, of this type.
+    Whole(Type),
+    /// Selected fields of a nested record.
+    Record(Vec<ProjectedField>),
+    /// Selected fields of an optional nested record, whose payload starts
+    /// `payload_offset` bytes after the discriminant.
+    OptionRecord {
+        payload_offset: usize,
+        fields: Vec<ProjectedField>,
+    },
+}
+
+/// Field paths merged into a tree.
+#[derive(Default)]
+struct Selection {
+    /// Selected fields; `None` selects the whole field.
+    fields: BTreeMap<String, Option<Selection>>,
+}
+
+impl Selection {
+    fn insert(&mut self, segments: &[&str]) {
+        let Some((first, rest)) = segments.split_first() else {
+            return;
+        };
+        if rest.is_empty() {
+            self.fields.insert(first.to_string(), None);
+        } else if let Some(sub) = self
+            .fields
+            .entry(first.to_string())
+            .or_insert_with(|| Some(Selection::default()))
+        {
+            sub.insert(rest);
+        }
+    }
+}
+
+impl Projection {
+    /// Synthesize the projection of `fields` from the keyspace type `ty`.
+    ///
+    /// Every segment of a path but the last must name a `record` field,
+    /// optionally wrapped in an `option`; the last one can have any type.
+    /// Selecting a field also selects everything below it, so `address` and
+    /// `address.city` together select all of `address`.
+    pub(super) fn new(ty: &KeyspaceType, fields: &[impl AsRef<str>]) -> Result<Self, KvError> {
+        let invalid = |reason: String| KvError::InvalidProjection(reason);
+        if fields.is_empty() {
+            return Err(invalid("no fields selected".to_string()));
+        }
+
+        let mut selection = Selection::default();
+        for path in fields {
+            let path = path.as_ref();
+            let segments: Vec<&str> = path.split('.').collect();
+            if segments.iter().any(|segment| segment.is_empty()) {
+                return Err(invalid(format!(
+                    "'{}' is not a path of dot-separated field names",
+                    path
+                )));
+            }
+            selection.insert(&segments);
+        }
+
+        let mut sizes = SizeAlign::default();
+        sizes.fill(&ty.resolve);
+        let mut builder = Builder {
+            resolve: ty.resolve.clone(),
+            sizes,
+        };
+        let name = format!("{}-projection", ty.metadata.type_name);
+        let (type_id, fields) = builder
+            .record(&Type::Id(ty.type_id), &selection, &name, "")
+            .map_err(invalid)?;
+
+        let Builder { resolve, .. } = builder;
+        let mut sizes = SizeAlign::default();
+        sizes.fill(&resolve);
+        let wave_type =
+            resolve_wit_type(&resolve, type_id).map_err(|e| KvError::WaveParse(e.to_string()))?;
+
+        Ok(Self {
+            resolve,
+            sizes,
+            type_id,
+            wave_type,
+            fields,
+        })
+    }
+
+    /// Lift the selected fields of a value stored with the projected type's
+    /// source version.
+    pub(super) fn lift(&self, stored: &StoredValue) -> Result<Value, KvError> {
+        let memory = LinearMemory::from_optional(stored.memory.as_ref());
+        self.lift_record(&self.fields, &self.wave_type, &stored.value, 0, &memory)
+    }
+
+    /// Lift the selected fields of a value as JSON. See [`lift`](Self::lift).
+    pub(super) fn lift_json(&self, stored: &StoredValue) -> Result<serde_json::Value, KvError> {
+        Ok(wave_to_json(&self.lift(stored)?, &self.wave_type)?)
+    }
+
+    /// Re-encode a projected value with the synthesized type, keeping the
+    /// source value's type version, revision and expiry.
+    pub(super) fn encode(
+        &self,
+        value: &Value,
+        source: &StoredValue,
+    ) -> Result<StoredValue, KvError> {
+        let abi = CanonicalAbi::with_sizes(&self.resolve, &self.sizes);
+        let encoded = abi.encode(value, &Type::Id(self.type_id), &self.wave_type)?;
+        Ok(StoredValue {
+            revision: source.revision,
+            expires_at: source.expires_at,
+            ..StoredValue::new(source.type_version, encoded.buffer, encoded.memory)
+        })
+    }
+
+    fn lift_record(
+        &self,
+        fields: &[ProjectedField],
+        wave_type: &WaveType,
+        buffer: &[u8],
+        base: usize,
+        memory: &LinearMemory,
+    ) -> Result<Value, KvError> {
+        let abi = CanonicalAbi::with_sizes(&self.resolve, &self.sizes);
+        let mut values = Vec::with_capacity(fields.len());
+        for (field, (name, field_type)) in fields.iter().zip(wave_type.record_fields()) {
+            let at = base + field.offset;
+            let value = match &field.kind {
+                // Field offsets are aligned for the field, so lifting from a
+                // slice starting at the field yields the same layout
+                FieldKind::Whole(ty) => {
+                    let rest = buffer.get(at..).ok_or(CanonicalAbiError::BufferTooSmall {
+                        needed: at,
+                        available: buffer.len(),
+                    })?;
+                    abi.lift_with_memory(rest, ty, &field_type, memory)?.0
+                }
+                FieldKind::Record(fields) => {
+                    self.lift_record(fields, &field_type, buffer, at, memory)?
+                }
+                FieldKind::OptionRecord {
+                    payload_offset,
+                    fields,
+                } => {
+                    let some = match buffer.get(at) {
+                        Some(0) => None,
+                        Some(1) => {
+                            let some_type = field_type.option_some_type().ok_or_else(|| {
+                                KvError::WaveParse(format!("{} is not an option", name))
+                            })?;
+                            Some(self.lift_record(
+                                fields,
+                                &some_type,
+                                buffer,
+                                at + payload_offset,
+                                memory,
+                            )?)
+                        }
+                        Some(&discriminant) => {
+                            return Err(CanonicalAbiError::InvalidDiscriminant {
+                                discriminant: u32::from(discriminant),
+                                num_cases: 2,
+                            }
+                            .into());
+                        }
+                        None => {
+                            return Err(CanonicalAbiError::BufferTooSmall {
+                                needed: at + 1,
+                                available: buffer.len(),
+                            }
+                            .into());
+                        }
+                    };
+                    Value::make_option(&field_type, some)
+                        .map_err(|e| KvError::WaveParse(e.to_string()))?
+                }
+            };
+            values.push((name, value));
+        }
+
+        Value::make_record(
+            wave_type,
+            values
+                .iter()
+                .map(|(name, value)| (name.as_ref(), value.clone())),
+        )
+        .map_err(|e| KvError::WaveParse(e.to_string()))
+    }
+}
+
+/// Synthesizes projected records into a copy of the source resolve.
+struct Builder {
+    resolve: Resolve,
+    /// Sizes of the source types, for field offsets.
+    sizes: SizeAlign,
+}
+
+impl Builder {
+    /// Synthesize the record of `selection` from the record type `ty`.
+    ///
+    /// Returns the synthesized type and where its fields come from.
+    fn record(
+        &mut self,
+        ty: &Type,
+        selection: &Selection,
+        name: &str,
+        path: &str,
+    ) -> Result<(wit_parser::TypeId, Vec<ProjectedField>), String> {
+        let Some(TypeDefKind::Record(source)) = self.kind(ty) else {
+            return Err(if path.is_empty() {
+                "the keyspace type is not a record".to_string()
+            } else {
+                format!("'{}' is not a record", path)
+            });
+        };
+        let source = source.clone();
+        if let Some(missing) = selection
+            .fields
+            .keys()
+            .find(|selected| !source.fields.iter().any(|field| field.name == **selected))
+        {
+            return Err(format!("no field named '{}'", join(path, missing)));
+        }
+
+        let offsets: Vec<usize> = self
+            .sizes
+            .field_offsets(source.fields.iter().map(|field| &field.ty))
+            .into_iter()
+            .map(|(offset, _)| offset.size_wasm32())
+            .collect();
+
+        let mut fields = Vec::new();
+        let mut projected = Vec::new();
+        for (field, offset) in source.fields.iter().zip(offsets) {
+            let Some(selected) = selection.fields.get(&field.name) else {
+                continue;
+            };
+            let (ty, kind) = match selected {
+                None => (field.ty, FieldKind::Whole(field.ty)),
+                Some(sub) => self.nested(&field.ty, sub, name, &join(path, &field.name))?,
+            };
+            fields.push(Field {
+                name: field.name.clone(),
+                ty,
+                docs: Docs::default(),
+            });
+            projected.push(ProjectedField { offset, kind });
+        }
+
+        let id = self.alloc(
+            Some(name.to_string()),
+            TypeDefKind::Record(Record { fields }),
+        );
+        Ok((id, projected))
+    }
+
+    /// Synthesize the projection of a nested record field, which may be an
+    /// `option` of a record.
+    fn nested(
+        &mut self,
+        ty: &Type,
+        selection: &Selection,
+        parent: &str,
+        path: &str,
+    ) -> Result<(Type, FieldKind), String> {
+        let name = format!("{}-{}", parent, path.rsplit('.').next().unwrap_or(path));
+        match self.kind(ty) {
+            Some(TypeDefKind::Option(some)) => {
+                let some = *some;
+                let payload_offset = self
+                    .sizes
+                    .payload_offset(Int::U8, [Some(&some)])
+                    .size_wasm32();
+                let (record, fields) = self.record(&some, selection, &name, path)?;
+                let id = self.alloc(None, TypeDefKind::Option(Type::Id(record)));
+                Ok((
+                    Type::Id(id),
+                    FieldKind::OptionRecord {
+                        payload_offset,
+                        fields,
+                    },
+                ))
+            }
+            _ => {
+                let (record, fields) = self.record(ty, selection, &name, path)?;
+                Ok((Type::Id(record), FieldKind::Record(fields)))
+            }
+        }
+    }
+
+    /// Kind of the type `ty` names, following aliases.
+    fn kind(&self, ty: &Type) -> Option<&TypeDefKind> {
+        let mut ty = *ty;
+        loop {
+            let Type::Id(id) = ty else {
+                return None;
+            };
+            match &self.resolve.types.get(id)?.kind {
+                TypeDefKind::Type(inner) => ty = *inner,
+                kind => return Some(kind),
+            }
+        }
+    }
+
+    fn alloc(&mut self, name: Option<String>, kind: TypeDefKind) -> wit_parser::TypeId {
+        self.resolve.types.alloc(TypeDef {
+            name,
+            kind,
+            owner: TypeOwner::None,
+            docs: Docs::default(),
+            stability: Stability::Unknown,
+        })
+    }
+}
+
+fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_string()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::kv::types::KeyspaceMetadata;
+    use crate::kv::version::SemanticVersion;
+
+    const WIT: &str = "package test:projection;
+interface types {
+    record address { street: string, city: string, zip: option<u32> }
+    type home = address;
+    record user {
+        flag: u8,
+        id: u64,
+        name: string,
+        avatar: list<u8>,
+        address: option<address>,
+        home: home,
+        tags: list<string>,
+    }
+}
+";
+
+    fn user_type() -> KeyspaceType {
+        KeyspaceType::parse(KeyspaceMetadata::new(
+            "users".to_string(),
+            "test:projection/types#user".to_string(),
+            WIT.to_string(),
+            "user".to_string(),
+        ))
+        .unwrap()
+    }
+
+    fn stored(ty: &KeyspaceType, address: &str) -> StoredValue {
+        let wave = format!(
+            "{{flag: 7, id: 18446744073709551615, name: \"alice\", avatar: [1, 2, 3], \
+             address: {}, home: {{street: \"Main\", city: \"Oslo\", zip: some(1)}}, \
+             tags: [\"a\"]}}",
+            address
+        );
+        let value: Value = wasm_wave::from_str(&ty.wave_type, &wave).unwrap();
+        let encoded = ty
+            .abi()
+            .encode(&value, &Type::Id(ty.type_id), &ty.wave_type)
+            .unwrap();
+        StoredValue::new(SemanticVersion::INITIAL, encoded.buffer, encoded.memory)
+    }
+
+    fn projected(fields: &[&str], address: &str) -> String {
+        let ty = user_type();
+        let projection = Projection::new(&ty, fields).unwrap();
+        wasm_wave::to_string(&projection.lift(&stored(&ty, address)).unwrap()).unwrap()
+    }
+
+    const PARIS: &str = "some({street: \"Rue\", city: \"Paris\", zip: none})";
+
+    #[test]
+    fn test_project_fields() {
+        // Declaration order, not selection order
+        assert_eq!(
+            projected(&["name", "id"], PARIS),
+            "{id: 18446744073709551615, name: \"alice\"}"
+        );
+        assert_eq!(
+            projected(&["tags", "address.city", "home.zip"], PARIS),
+            "{address: some({city: \"Paris\"}), home: {zip: some(1)}, tags: [\"a\"]}"
+        );
+        // WAVE leaves out `none` fields
+        assert_eq!(projected(&["address.city"], "none"), "{:}");
+        // Selecting a record selects all of it
+        assert_eq!(
+            projected(&["address.city", "address"], PARIS),
+            "{address: some({street: \"Rue\", city: \"Paris\"})}"
+        );
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let ty = user_type();
+        let projection = Projection::new(&ty, &["flag", "name", "address.zip"]).unwrap();
+        let source = StoredValue {
+            revision: 42,
+            ..stored(&ty, PARIS)
+        };
+        let value = projection.lift(&source).unwrap();
+        let encoded = projection.encode(&value, &source).unwrap();
+        assert_eq!(encoded.revision, 42);
+
+        let abi = CanonicalAbi::with_sizes(&projection.resolve, &projection.sizes);
+        let memory = LinearMemory::from_optional(encoded.memory.as_ref());
+        let (decoded, _) = abi
+            .lift_with_memory(
+                &encoded.value,
+                &Type::Id(projection.type_id),
+                &projection.wave_type,
+                &memory,
+            )
+            .unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_invalid_projections() {
+        let ty = user_type();
+        let error = |fields: &[&str]| match Projection::new(&ty, fields) {
+            Err(KvError::InvalidProjection(reason)) => reason,
+            Err(e) => format!("unexpected error: {}", e),
+            Ok(_) => "projection built".to_string(),
+        };
+        assert!(error(&[]).contains("no fields"));
+        assert!(error(&["missing"]).contains("no field named 'missing'"));
+        assert!(error(&["address.country"]).contains("no field named 'address.country'"));
+        assert!(error(&["name.first"]).contains("'name' is not a record"));
+        assert!(error(&["tags.x"]).contains("'tags' is not a record"));
+        assert!(error(&["address..city"]).contains("dot-separated"));
+    }
+}
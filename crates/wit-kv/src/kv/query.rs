@@ -0,0 +1,663 @@
+//! Declarative predicates over keyspace values.
+//!
+//! A predicate is a boolean expression over the fields of a value, parsed
+//! and type-checked against the keyspace type before anything is read:
+//!
+//! ```text
+//! score > 100 and not (tier is bronze or tags contains "banned")
+//! ```
+//!
+//! | Test                        | Field types                           | Matches when               |
+//! |-----------------------------|---------------------------------------|----------------------------|
+//! | `path == v`, `path != v`    | any                                   | the field (not) equals `v` |
+//! | `path < v`, `<=`, `>`, `>=` | integers, floats, `char`, `string`    | the field compares to `v`  |
+//! | `path is case`              | `variant`, `enum`, `result`, `option` | the field is in that case  |
+//! | `path has flag`             | `flags`                               | the flag is set            |
+//! | `path contains v`           | `list`                                | an element equals `v`      |
+//!
+//! Values are WAVE literals of the field's type. Tests combine with `and`,
+//! `or`, `not` and parentheses, with `and` binding tighter than `or`.
+//!
+//! Paths are dot-separated record field names, or `.` for the whole value.
+//! Records along the path may be `option`s, and so may the field itself:
+//! `is` tests an `option` field for `some` or `none`, and every other test
+//! looks inside it. A test that reaches a `none` is false.
+
+use std::cmp::Ordering;
+
+use wasm_wave::value::{Type as WaveType, Value};
+use wasm_wave::wasm::{WasmType, WasmTypeKind, WasmValue};
+
+use super::error::KvError;
+use super::index::{unwrap_option_type, unwrap_option_value};
+
+/// A parsed, type-checked predicate over the values of a keyspace.
+#[derive(Debug, Clone)]
+pub(super) struct Predicate {
+    expr: Expr,
+}
+
+impl Predicate {
+    /// Parse `text` and check it against the keyspace type `ty`.
+    ///
+    /// Returns [`KvError::InvalidQuery`] for syntax errors, unknown fields
+    /// or cases, tests that do not apply to a field's type, and values that
+    /// are not valid WAVE for it.
+    pub(super) fn parse(text: &str, ty: &WaveType) -> Result<Self, KvError> {
+        let mut parser = Parser { text, pos: 0, ty };
+        let expr = parser.or().and_then(|expr| {
+            if parser.at_end() {
+                Ok(expr)
+            } else {
+                Err(parser.expected("'and', 'or' or end of query"))
+            }
+        });
+        Ok(Self {
+            expr: expr.map_err(KvError::InvalidQuery)?,
+        })
+    }
+
+    /// Returns `true` if `value` satisfies the predicate.
+    pub(super) fn matches(&self, value: &Value) -> bool {
+        self.expr.eval(value)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Test { path: Vec<String>, test: Test },
+}
+
+#[derive(Debug, Clone)]
+enum Test {
+    Compare(Comparison, Value),
+    Case(String),
+    Flag(String),
+    Contains(Value),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    /// Operators, longest first so `<=` is not read as `<`.
+    const OPERATORS: [(&'static str, Self); 6] = [
+        ("==", Self::Eq),
+        ("!=", Self::Ne),
+        ("<=", Self::Le),
+        (">=", Self::Ge),
+        ("<", Self::Lt),
+        (">", Self::Gt),
+    ];
+
+    fn is_ordering(self) -> bool {
+        !matches!(self, Self::Eq | Self::Ne)
+    }
+
+    fn holds(self, ordering: Option<Ordering>) -> bool {
+        match self {
+            Self::Eq => ordering == Some(Ordering::Equal),
+            Self::Ne => ordering != Some(Ordering::Equal),
+            Self::Lt => ordering == Some(Ordering::Less),
+            Self::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Self::Gt => ordering == Some(Ordering::Greater),
+            Self::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, value: &Value) -> bool {
+        match self {
+            Self::And(a, b) => a.eval(value) && b.eval(value),
+            Self::Or(a, b) => a.eval(value) || b.eval(value),
+            Self::Not(expr) => !expr.eval(value),
+            Self::Test { path, test } => field(value, path).is_some_and(|field| test.eval(field)),
+        }
+    }
+}
+
+impl Test {
+    fn eval(&self, field: Value) -> bool {
+        if let Self::Case(case) = self {
+            return case_name(&field).is_some_and(|name| name == *case);
+        }
+        let Some(field) = unwrap_option_value(field) else {
+            return false;
+        };
+        match self {
+            Self::Compare(comparison, operand) => comparison.holds(compare(&field, operand)),
+            Self::Flag(flag) => {
+                field.kind() == WasmTypeKind::Flags && field.unwrap_flags().any(|set| set == *flag)
+            }
+            Self::Contains(item) => {
+                field.kind() == WasmTypeKind::List
+                    && field.unwrap_list().any(|element| element.as_ref() == item)
+            }
+            Self::Case(_) => false,
+        }
+    }
+}
+
+/// Walk `path` down `value`, or `None` if a record along it is `none`.
+fn field(value: &Value, path: &[String]) -> Option<Value> {
+    let mut current = value.clone();
+    for segment in path {
+        let record = unwrap_option_value(current)?;
+        if record.kind() != WasmTypeKind::Record {
+            return None;
+        }
+        current = record
+            .unwrap_record()
+            .find(|(name, _)| name == segment)
+            .map(|(_, field)| field.into_owned())?;
+    }
+    Some(current)
+}
+
+/// Name of the case a variant-like value is in.
+fn case_name(value: &Value) -> Option<String> {
+    let name = match value.kind() {
+        WasmTypeKind::Variant => value.unwrap_variant().0.into_owned(),
+        WasmTypeKind::Enum => value.unwrap_enum().into_owned(),
+        WasmTypeKind::Option if value.unwrap_option().is_some() => "some".to_string(),
+        WasmTypeKind::Option => "none".to_string(),
+        WasmTypeKind::Result if value.unwrap_result().is_ok() => "ok".to_string(),
+        WasmTypeKind::Result => "err".to_string(),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Order two values of the same type.
+///
+/// Values that cannot be ordered are only ever `Equal` or unordered.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    if a.kind() != b.kind() {
+        return None;
+    }
+    match a.kind() {
+        WasmTypeKind::S8 => Some(a.unwrap_s8().cmp(&b.unwrap_s8())),
+        WasmTypeKind::S16 => Some(a.unwrap_s16().cmp(&b.unwrap_s16())),
+        WasmTypeKind::S32 => Some(a.unwrap_s32().cmp(&b.unwrap_s32())),
+        WasmTypeKind::S64 => Some(a.unwrap_s64().cmp(&b.unwrap_s64())),
+        WasmTypeKind::U8 => Some(a.unwrap_u8().cmp(&b.unwrap_u8())),
+        WasmTypeKind::U16 => Some(a.unwrap_u16().cmp(&b.unwrap_u16())),
+        WasmTypeKind::U32 => Some(a.unwrap_u32().cmp(&b.unwrap_u32())),
+        WasmTypeKind::U64 => Some(a.unwrap_u64().cmp(&b.unwrap_u64())),
+        WasmTypeKind::F32 => a.unwrap_f32().partial_cmp(&b.unwrap_f32()),
+        WasmTypeKind::F64 => a.unwrap_f64().partial_cmp(&b.unwrap_f64()),
+        WasmTypeKind::Char => Some(a.unwrap_char().cmp(&b.unwrap_char())),
+        WasmTypeKind::String => Some(a.unwrap_string().cmp(&b.unwrap_string())),
+        _ => (a == b).then_some(Ordering::Equal),
+    }
+}
+
+fn is_ordered(kind: WasmTypeKind) -> bool {
+    matches!(
+        kind,
+        WasmTypeKind::S8
+            | WasmTypeKind::S16
+            | WasmTypeKind::S32
+            | WasmTypeKind::S64
+            | WasmTypeKind::U8
+            | WasmTypeKind::U16
+            | WasmTypeKind::U32
+            | WasmTypeKind::U64
+            | WasmTypeKind::F32
+            | WasmTypeKind::F64
+            | WasmTypeKind::Char
+            | WasmTypeKind::String
+    )
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '%')
+}
+
+/// Recursive descent parser; errors are messages for [`KvError::InvalidQuery`].
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    ty: &'a WaveType,
+}
+
+impl<'a> Parser<'a> {
+    /// `or := and ("or" and)*`
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    /// `and := unary ("and" unary)*`
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    /// `unary := "not" unary | "(" or ")" | test`
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.symbol("(") {
+            let expr = self.or()?;
+            if !self.symbol(")") {
+                return Err(self.expected("')'"));
+            }
+            return Ok(expr);
+        }
+        self.test()
+    }
+
+    /// `test := path (comparison value | "is" case | "has" flag | "contains" value)`
+    fn test(&mut self) -> Result<Expr, String> {
+        let path = self.word();
+        if path.is_empty() {
+            return Err(self.expected("a field path"));
+        }
+        let (segments, field_type) = resolve_path(path, self.ty)?;
+
+        let test = if self.keyword("is") {
+            let case = self.word();
+            let cases = cases(&field_type).ok_or_else(|| {
+                format!(
+                    "'{}' has type {}; 'is' needs a variant, enum, result or option",
+                    path,
+                    field_type.kind()
+                )
+            })?;
+            if !cases.iter().any(|name| name == case) {
+                return Err(format!(
+                    "'{}' has no case '{}' (expected one of: {})",
+                    path,
+                    case,
+                    cases.join(", ")
+                ));
+            }
+            Test::Case(case.to_string())
+        } else if self.keyword("has") {
+            let field_type = unwrap_option_type(&field_type);
+            if field_type.kind() != WasmTypeKind::Flags {
+                return Err(format!(
+                    "'{}' has type {}; 'has' needs flags",
+                    path,
+                    field_type.kind()
+                ));
+            }
+            let flag = self.word();
+            if !field_type.flags_names().any(|name| name == flag) {
+                return Err(format!("'{}' has no flag '{}'", path, flag));
+            }
+            Test::Flag(flag.to_string())
+        } else if self.keyword("contains") {
+            let element_type = unwrap_option_type(&field_type)
+                .list_element_type()
+                .ok_or_else(|| {
+                    format!(
+                        "'{}' has type {}; 'contains' needs a list",
+                        path,
+                        field_type.kind()
+                    )
+                })?;
+            Test::Contains(self.value(path, &element_type)?)
+        } else if let Some(comparison) = self.comparison() {
+            let field_type = unwrap_option_type(&field_type);
+            if comparison.is_ordering() && !is_ordered(field_type.kind()) {
+                return Err(format!(
+                    "'{}' has type {}, which has no order; use == or !=",
+                    path,
+                    field_type.kind()
+                ));
+            }
+            Test::Compare(comparison, self.value(path, &field_type)?)
+        } else {
+            return Err(self.expected("a comparison, 'is', 'has' or 'contains'"));
+        };
+
+        Ok(Expr::Test {
+            path: segments,
+            test,
+        })
+    }
+
+    fn comparison(&mut self) -> Option<Comparison> {
+        Comparison::OPERATORS
+            .into_iter()
+            .find(|(operator, _)| self.symbol(operator))
+            .map(|(_, comparison)| comparison)
+    }
+
+    /// Parse a WAVE literal of `ty`.
+    fn value(&mut self, path: &str, ty: &WaveType) -> Result<Value, String> {
+        let literal = self.literal();
+        if literal.is_empty() {
+            return Err(self.expected("a value"));
+        }
+        wasm_wave::from_str(ty, literal)
+            .map_err(|e| format!("invalid value {} for '{}': {}", literal, path, e))
+    }
+
+    /// Take the next WAVE literal: a quoted string or char, a bracketed
+    /// group, or a bare word such as a number or case name.
+    fn literal(&mut self) -> &'a str {
+        let rest = self.rest();
+        let mut end = rest.len();
+        let mut depth = 0usize;
+        let mut quote = None;
+        let mut escaped = false;
+        for (i, c) in rest.char_indices() {
+            if let Some(open) = quote {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == open {
+                    quote = None;
+                    if depth == 0 {
+                        end = i + c.len_utf8();
+                        break;
+                    }
+                }
+                continue;
+            }
+            match c {
+                '"' | '\'' => quote = Some(c),
+                '{' | '[' | '(' => depth += 1,
+                '}' | ']' | ')' if depth == 0 => {
+                    end = i;
+                    break;
+                }
+                '}' | ']' | ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = i + 1;
+                        break;
+                    }
+                }
+                c if c.is_whitespace() && depth == 0 => {
+                    end = i;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        self.take(end)
+    }
+
+    /// Take the next run of field path or keyword characters.
+    fn word(&mut self) -> &'a str {
+        let rest = self.rest();
+        let end = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+        self.take(end)
+    }
+
+    /// Take `keyword` if it is the next whole word.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let rest = self.rest();
+        let end = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+        if rest.get(..end) == Some(keyword) {
+            self.take(end);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take `symbol` if the input continues with it.
+    fn symbol(&mut self, symbol: &str) -> bool {
+        if self.rest().starts_with(symbol) {
+            self.take(symbol.len());
+            true
+        } else {
+            false
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.rest().is_empty()
+    }
+
+    /// Unparsed input after skipping whitespace.
+    fn rest(&mut self) -> &'a str {
+        let rest = self.text.get(self.pos..).unwrap_or_default();
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        trimmed
+    }
+
+    fn take(&mut self, len: usize) -> &'a str {
+        let rest = self.rest();
+        let taken = rest.get(..len).unwrap_or(rest);
+        self.pos += taken.len();
+        taken
+    }
+
+    fn expected(&mut self, what: &str) -> String {
+        let rest = self.rest();
+        if rest.is_empty() {
+            format!("expected {} at end of query", what)
+        } else {
+            let found: String = rest.chars().take(20).collect();
+            format!(
+                "expected {} at position {}, found '{}'",
+                what, self.pos, found
+            )
+        }
+    }
+}
+
+/// Resolve a field path to its segments and the type of the field.
+fn resolve_path(path: &str, ty: &WaveType) -> Result<(Vec<String>, WaveType), String> {
+    if path == "." {
+        return Ok((Vec::new(), ty.clone()));
+    }
+    let segments: Vec<String> = path.split('.').map(str::to_string).collect();
+    if segments.iter().any(String::is_empty) {
+        return Err(format!(
+            "'{}' is not a path of dot-separated field names",
+            path
+        ));
+    }
+
+    let mut current = ty.clone();
+    for (depth, segment) in segments.iter().enumerate() {
+        let parent = unwrap_option_type(&current);
+        if parent.kind() != WasmTypeKind::Record {
+            let at = segments.get(..depth).unwrap_or_default().join(".");
+            return Err(if at.is_empty() {
+                format!("the keyspace type is {}, not a record", parent.kind())
+            } else {
+                format!("'{}' has type {}, not a record", at, parent.kind())
+            });
+        }
+        current = parent
+            .record_fields()
+            .find(|(name, _)| name == segment)
+            .map(|(_, field_type)| field_type)
+            .ok_or_else(|| format!("no field named '{}' in '{}'", segment, path))?;
+    }
+    Ok((segments, current))
+}
+
+/// Case names `is` accepts for a field of type `ty`.
+fn cases(ty: &WaveType) -> Option<Vec<String>> {
+    let names = match ty.kind() {
+        WasmTypeKind::Variant => ty
+            .variant_cases()
+            .map(|(name, _)| name.into_owned())
+            .collect(),
+        WasmTypeKind::Enum => ty.enum_cases().map(|name| name.into_owned()).collect(),
+        WasmTypeKind::Option => vec!["some".to_string(), "none".to_string()],
+        WasmTypeKind::Result => vec!["ok".to_string(), "err".to_string()],
+        _ => return None,
+    };
+    Some(names)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::load_wit_type_from_string;
+
+    const WIT: &str = "package test:query;
+interface types {
+    enum tier { gold, silver, bronze }
+    flags permissions { read, write, admin }
+    variant status { active, banned(string) }
+    record address { city: string, zip: option<u32> }
+    record user {
+        name: string,
+        score: s32,
+        ratio: f64,
+        tier: tier,
+        status: status,
+        permissions: permissions,
+        nickname: option<string>,
+        address: option<address>,
+        tags: list<string>,
+    }
+}
+";
+
+    fn user_type() -> WaveType {
+        load_wit_type_from_string(WIT, Some("user")).unwrap().2
+    }
+
+    fn alice() -> Value {
+        wasm_wave::from_str(
+            &user_type(),
+            "{name: \"alice\", score: 120, ratio: 0.5, tier: gold, status: active, \
+             permissions: {read, write}, nickname: some(\"al\"), \
+             address: some({city: \"Paris\", zip: none}), tags: [\"new\", \"beta tester\"]}",
+        )
+        .unwrap()
+    }
+
+    fn bob() -> Value {
+        wasm_wave::from_str(
+            &user_type(),
+            "{name: \"bob\", score: -5, ratio: nan, tier: bronze, status: banned(\"spam\"), \
+             permissions: {}, nickname: none, address: none, tags: []}",
+        )
+        .unwrap()
+    }
+
+    fn matching(query: &str) -> Vec<String> {
+        let predicate = Predicate::parse(query, &user_type()).unwrap();
+        [("alice", alice()), ("bob", bob())]
+            .into_iter()
+            .filter(|(_, value)| predicate.matches(value))
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    fn parse_error(query: &str) -> String {
+        match Predicate::parse(query, &user_type()) {
+            Err(KvError::InvalidQuery(message)) => message,
+            Err(e) => format!("unexpected error: {}", e),
+            Ok(_) => format!("'{}' parsed", query),
+        }
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(matching("score > 100"), ["alice"]);
+        assert_eq!(matching("score<=-5"), ["bob"]);
+        assert_eq!(matching("score != 120"), ["bob"]);
+        assert_eq!(matching("name >= \"b\""), ["bob"]);
+        assert_eq!(matching("tier == bronze"), ["bob"]);
+        assert_eq!(matching("address.city == \"Paris\""), ["alice"]);
+        assert_eq!(matching("nickname == \"al\""), ["alice"]);
+        assert_eq!(matching("permissions == {write, read}"), ["alice"]);
+        // NaN is unordered and unequal to everything, itself included
+        assert_eq!(matching("ratio < 1"), ["alice"]);
+        assert_eq!(matching("ratio == nan"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_cases_flags_and_lists() {
+        assert_eq!(matching("status is banned"), ["bob"]);
+        assert_eq!(matching("tier is gold"), ["alice"]);
+        assert_eq!(matching("nickname is none"), ["bob"]);
+        assert_eq!(matching("address is some"), ["alice"]);
+        assert_eq!(matching("permissions has write"), ["alice"]);
+        assert_eq!(matching("tags contains \"beta tester\""), ["alice"]);
+        assert_eq!(matching("address.zip is none"), ["alice"]);
+    }
+
+    #[test]
+    fn test_boolean_operators() {
+        assert_eq!(matching("score > 0 or tier is bronze"), ["alice", "bob"]);
+        assert_eq!(
+            matching("score > 0 and tier is bronze"),
+            Vec::<String>::new()
+        );
+        assert_eq!(matching("not status is active"), ["bob"]);
+        // `and` binds tighter than `or`
+        assert_eq!(
+            matching("tier is bronze or tier is gold and score < 0"),
+            ["bob"]
+        );
+        assert_eq!(
+            matching("(tier is bronze or tier is gold) and not (score < 0)"),
+            ["alice"]
+        );
+        // A test that reaches a `none` is false, so its negation is true
+        assert_eq!(matching("not address.city == \"Paris\""), ["bob"]);
+    }
+
+    #[test]
+    fn test_whole_value_path() {
+        let ty = load_wit_type_from_string(
+            "package test:q;\ninterface t { type score = u32; }",
+            Some("score"),
+        )
+        .unwrap()
+        .2;
+        let predicate = Predicate::parse(". >= 10", &ty).unwrap();
+        assert!(predicate.matches(&wasm_wave::from_str(&ty, "10").unwrap()));
+        assert!(!predicate.matches(&wasm_wave::from_str(&ty, "9").unwrap()));
+    }
+
+    #[test]
+    fn test_type_errors() {
+        assert!(parse_error("missing == 1").contains("no field named 'missing'"));
+        assert!(parse_error("name.first == 1").contains("not a record"));
+        assert!(parse_error("tier is platinum").contains("no case 'platinum'"));
+        assert!(parse_error("score is some").contains("'is' needs"));
+        assert!(parse_error("permissions has delete").contains("no flag 'delete'"));
+        assert!(parse_error("tags has read").contains("'has' needs flags"));
+        assert!(parse_error("name contains \"a\"").contains("'contains' needs a list"));
+        assert!(parse_error("tier < gold").contains("has no order"));
+        assert!(parse_error("score > \"high\"").contains("invalid value"));
+        assert!(parse_error("score > 300000000000").contains("invalid value"));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert!(parse_error("").contains("expected a field path at end of query"));
+        assert!(parse_error("score").contains("expected a comparison"));
+        assert!(parse_error("score >").contains("expected a value"));
+        assert!(parse_error("(score > 1").contains("expected ')'"));
+        assert!(parse_error("score > 1 score < 2").contains("position 10"));
+        assert!(parse_error("score > 1 and").contains("expected a field path"));
+    }
+}
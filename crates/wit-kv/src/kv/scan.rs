@@ -0,0 +1,451 @@
+//! Range scans over the values of a keyspace.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use fjall::Keyspace;
+
+use crate::logging::{debug, trace};
+
+use super::cache::KeyspaceType;
+use super::error::KvError;
+use super::expiry::now_millis;
+use super::projection::Projection;
+use super::query::Predicate;
+use super::store::KvStore;
+use super::types::{KeyspaceMetadata, StoredValue};
+
+/// Which keys a [`KvStore::scan`] visits, and in which order.
+///
+/// All bounds combine: a scan with both `prefix` and `start` only visits keys
+/// that start with the prefix and are >= start.
+///
+/// # Example
+///
+/// ```ignore
+/// use wit_kv::kv::ScanOptions;
+///
+/// // The 100 highest keys starting with "user/", from the highest down
+/// let options = ScanOptions {
+///     prefix: Some("user/".to_string()),
+///     reverse: true,
+///     limit: Some(100),
+///     ..ScanOptions::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Only visit keys starting with this prefix.
+    pub prefix: Option<String>,
+    /// Only visit keys >= start (inclusive).
+    pub start: Option<String>,
+    /// Only visit keys < end (exclusive).
+    pub end: Option<String>,
+    /// Resume after this key: only visit keys > after, or < after when
+    /// scanning in reverse. Pass the last key of the previous page.
+    pub after: Option<String>,
+    /// Visit keys from the highest down.
+    pub reverse: bool,
+    /// Maximum number of entries to return.
+    pub limit: Option<usize>,
+}
+
+impl ScanOptions {
+    /// Key range in storage order covered by these options.
+    fn bounds(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        let mut lower = Bound::Unbounded;
+        let mut upper = Bound::Unbounded;
+
+        if let Some(prefix) = &self.prefix {
+            lower = tighter_lower(lower, Bound::Included(prefix.as_bytes().to_vec()));
+            upper = tighter_upper(upper, prefix_upper_bound(prefix.as_bytes()));
+        }
+        if let Some(start) = &self.start {
+            lower = tighter_lower(lower, Bound::Included(start.as_bytes().to_vec()));
+        }
+        if let Some(end) = &self.end {
+            upper = tighter_upper(upper, Bound::Excluded(end.as_bytes().to_vec()));
+        }
+        if let Some(after) = &self.after {
+            let after = Bound::Excluded(after.as_bytes().to_vec());
+            if self.reverse {
+                upper = tighter_upper(upper, after);
+            } else {
+                lower = tighter_lower(lower, after);
+            }
+        }
+
+        (lower, upper)
+    }
+}
+
+/// Lazy iterator over the `(key, value)` pairs of a keyspace, returned by
+/// [`KvStore::scan`].
+///
+/// Entries are read and decoded one at a time as the iterator advances, and
+/// values written with an older type version are brought to the current one
+/// like [`KvStore::get_raw`] does. The scan reads from a consistent snapshot
+/// taken when it was created, so concurrent writes are not observed. Values
+/// expired when the scan was created are skipped.
+///
+/// Scans returned by [`KvStore::query`] skip values that do not match the
+/// query, and count only matching values towards the limit.
+///
+/// Use [`wave`](Self::wave) to get the values as WAVE text instead, and
+/// [`project`](Self::project) to only get some of their fields.
+pub struct Scan<'a> {
+    store: &'a KvStore,
+    keyspace: String,
+    ty: Arc<KeyspaceType>,
+    data: Keyspace,
+    entries: Box<dyn Iterator<Item = fjall::Guard> + Send>,
+    remaining: Option<usize>,
+    /// Unix time in milliseconds at which the scan was created.
+    now: u64,
+    filter: Option<Predicate>,
+    projection: Option<Projection>,
+}
+
+impl<'a> Scan<'a> {
+    pub(super) fn new(
+        store: &'a KvStore,
+        keyspace: &str,
+        ty: Arc<KeyspaceType>,
+        data: Keyspace,
+        options: &ScanOptions,
+    ) -> Self {
+        let entries = key_range(&data, options);
+        debug!(
+            keyspace = keyspace,
+            reverse = options.reverse,
+            limit = options.limit,
+            "scan started"
+        );
+        Self {
+            store,
+            keyspace: keyspace.to_string(),
+            ty,
+            data,
+            entries,
+            remaining: options.limit,
+            now: now_millis(),
+            filter: None,
+            projection: None,
+        }
+    }
+
+    /// Only yield values matching `predicate`.
+    pub(super) fn with_filter(mut self, predicate: Predicate) -> Self {
+        self.filter = Some(predicate);
+        self
+    }
+
+    /// Type metadata of the scanned keyspace.
+    pub fn metadata(&self) -> &KeyspaceMetadata {
+        &self.ty.metadata
+    }
+
+    /// Only yield the given fields of each value, as a record synthesized
+    /// from the keyspace's record type.
+    ///
+    /// Fields are dot-separated paths such as `name` or `address.city`; see
+    /// [`KvStore::get_projected`] for how the record is built. Only the
+    /// selected fields are decoded. Stored envelopes yielded by a projected
+    /// scan are encoded with the synthesized type.
+    ///
+    /// Returns [`KvError::InvalidProjection`] if a path does not name a field.
+    pub fn project(mut self, fields: &[impl AsRef<str>]) -> Result<Self, KvError> {
+        self.projection = Some(Projection::new(&self.ty, fields)?);
+        Ok(self)
+    }
+
+    /// Yield the values as WAVE text instead of stored envelopes.
+    pub fn wave(mut self) -> impl Iterator<Item = Result<(String, String), KvError>> + 'a {
+        std::iter::from_fn(move || {
+            let entry = self.next_current()?;
+            Some(entry.and_then(|(key, stored)| {
+                let value = match &self.projection {
+                    Some(projection) => projection.lift(&stored)?,
+                    None => self.ty.to_value(&stored)?,
+                };
+                let wave =
+                    wasm_wave::to_string(&value).map_err(|e| KvError::WaveParse(e.to_string()))?;
+                Ok((key, wave))
+            }))
+        })
+    }
+
+    /// Yield the values as JSON instead of stored envelopes.
+    pub fn json(
+        mut self,
+    ) -> impl Iterator<Item = Result<(String, serde_json::Value), KvError>> + 'a {
+        std::iter::from_fn(move || {
+            let entry = self.next_current()?;
+            Some(entry.and_then(|(key, stored)| {
+                let json = match &self.projection {
+                    Some(projection) => projection.lift_json(&stored)?,
+                    None => self.ty.to_json(&stored)?,
+                };
+                Ok((key, json))
+            }))
+        })
+    }
+}
+
+impl Iterator for Scan<'_> {
+    type Item = Result<(String, StoredValue), KvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.next_current()?;
+        Some(entry.and_then(|(key, stored)| match &self.projection {
+            Some(projection) => {
+                let value = projection.lift(&stored)?;
+                Ok((key, projection.encode(&value, &stored)?))
+            }
+            None => Ok((key, stored)),
+        }))
+    }
+}
+
+impl Scan<'_> {
+    /// Next matching entry within the limit, at the current type version.
+    fn next_current(&mut self) -> Option<Result<(String, StoredValue), KvError>> {
+        while self.remaining != Some(0) {
+            let guard = self.entries.next()?;
+            let entry = match self.decode(guard) {
+                Ok(None) => continue,
+                Ok(Some(entry)) => match self.matches(&entry.1) {
+                    Ok(true) => Ok(entry),
+                    Ok(false) => continue,
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            if let Some(remaining) = &mut self.remaining {
+                *remaining -= 1;
+            }
+            return Some(entry);
+        }
+        None
+    }
+
+    /// Decode an entry, or `None` if its value is expired.
+    fn decode(&self, guard: fjall::Guard) -> Result<Option<(String, StoredValue)>, KvError> {
+        let (key_bytes, record) = guard.into_inner()?;
+        if StoredValue::peek_expires_at(&record).is_some_and(|at| at <= self.now) {
+            return Ok(None);
+        }
+        let key = String::from_utf8_lossy(&key_bytes).into_owned();
+        let stored = StoredValue::from_bytes(&record)?;
+        let current =
+            self.store
+                .current_value(&self.data, &self.keyspace, &key, &self.ty, stored)?;
+        Ok(Some((key, current)))
+    }
+
+    fn matches(&self, stored: &StoredValue) -> Result<bool, KvError> {
+        match &self.filter {
+            Some(predicate) => Ok(predicate.matches(&self.ty.to_value(stored)?)),
+            None => Ok(true),
+        }
+    }
+}
+
+/// Iterate over the entries of `data` selected by `options`, in scan order.
+///
+/// `limit` is not applied.
+pub(super) fn key_range(
+    data: &Keyspace,
+    options: &ScanOptions,
+) -> Box<dyn Iterator<Item = fjall::Guard> + Send> {
+    let (lower, upper) = options.bounds();
+    if is_empty_range(&lower, &upper) {
+        trace!("scan range is empty");
+        Box::new(std::iter::empty())
+    } else if options.reverse {
+        Box::new(data.range((lower, upper)).rev())
+    } else {
+        Box::new(data.range((lower, upper)))
+    }
+}
+
+/// Encode the key a page ended at as an opaque cursor for the next page.
+///
+/// Cursors are the hex-encoded key, so they can be passed around in URLs
+/// and shells without escaping.
+pub(super) fn encode_cursor(key: &str) -> String {
+    use std::fmt::Write;
+    key.bytes()
+        .fold(String::with_capacity(key.len() * 2), |mut out, b| {
+            write!(out, "{:02x}", b).ok();
+            out
+        })
+}
+
+/// Decode a cursor produced by [`encode_cursor`] back into the key it ends at.
+pub(super) fn decode_cursor(cursor: &str) -> Result<String, KvError> {
+    let invalid = || KvError::InvalidCursor(cursor.to_string());
+    if !cursor.len().is_multiple_of(2) || !cursor.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| {
+            cursor
+                .get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect::<Result<Vec<u8>, KvError>>()?;
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Smallest key range that is strictly greater than every key with `prefix`,
+/// or unbounded if there is none (the prefix is empty or all `0xff`).
+fn prefix_upper_bound(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < u8::MAX {
+            upper.push(last + 1);
+            return Bound::Excluded(upper);
+        }
+    }
+    Bound::Unbounded
+}
+
+/// The more restrictive of two lower bounds.
+fn tighter_lower(a: Bound<Vec<u8>>, b: Bound<Vec<u8>>) -> Bound<Vec<u8>> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            if x > y || (x == y && matches!(a, Bound::Excluded(_))) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+/// The more restrictive of two upper bounds.
+fn tighter_upper(a: Bound<Vec<u8>>, b: Bound<Vec<u8>>) -> Bound<Vec<u8>> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            if x < y || (x == y && matches!(a, Bound::Excluded(_))) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+/// Returns `true` if no key can satisfy both bounds.
+fn is_empty_range(lower: &Bound<Vec<u8>>, upper: &Bound<Vec<u8>>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(l), Bound::Included(u)) => l > u,
+        (Bound::Included(l) | Bound::Excluded(l), Bound::Excluded(u))
+        | (Bound::Excluded(l), Bound::Included(u)) => l >= u,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(s: &str) -> Vec<u8> {
+        s.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(prefix_upper_bound(b"ab"), Bound::Excluded(bytes("ac")));
+        assert_eq!(
+            prefix_upper_bound(&[b'a', 0xff]),
+            Bound::Excluded(bytes("b"))
+        );
+        assert_eq!(prefix_upper_bound(&[0xff, 0xff]), Bound::Unbounded);
+        assert_eq!(prefix_upper_bound(b""), Bound::Unbounded);
+    }
+
+    #[test]
+    fn test_bounds_combine() {
+        let options = ScanOptions {
+            prefix: Some("user/".to_string()),
+            start: Some("user/b".to_string()),
+            end: Some("zzz".to_string()),
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            options.bounds(),
+            (
+                Bound::Included(bytes("user/b")),
+                Bound::Excluded(bytes("user0"))
+            )
+        );
+    }
+
+    #[test]
+    fn test_bounds_after_follows_direction() {
+        let forward = ScanOptions {
+            start: Some("b".to_string()),
+            after: Some("b".to_string()),
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            forward.bounds(),
+            (Bound::Excluded(bytes("b")), Bound::Unbounded)
+        );
+
+        let reverse = ScanOptions {
+            end: Some("m".to_string()),
+            after: Some("k".to_string()),
+            reverse: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            reverse.bounds(),
+            (Bound::Unbounded, Bound::Excluded(bytes("k")))
+        );
+    }
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        for key in ["", "a", "user/42", "ключ", "x\ty.memory"] {
+            let cursor = encode_cursor(key);
+            assert!(cursor.bytes().all(|b| b.is_ascii_hexdigit()));
+            assert_eq!(decode_cursor(&cursor).ok().as_deref(), Some(key));
+        }
+    }
+
+    #[test]
+    fn test_invalid_cursor() {
+        for cursor in ["abc", "zz", "ff", "+1"] {
+            assert!(matches!(
+                decode_cursor(cursor),
+                Err(KvError::InvalidCursor(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_empty_range() {
+        assert!(is_empty_range(
+            &Bound::Excluded(bytes("b")),
+            &Bound::Excluded(bytes("b"))
+        ));
+        assert!(is_empty_range(
+            &Bound::Included(bytes("c")),
+            &Bound::Excluded(bytes("b"))
+        ));
+        assert!(!is_empty_range(
+            &Bound::Included(bytes("b")),
+            &Bound::Excluded(bytes("c"))
+        ));
+        assert!(!is_empty_range(&Bound::Unbounded, &Bound::Unbounded));
+    }
+}
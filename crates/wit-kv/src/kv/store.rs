@@ -1,30 +1,95 @@
 //! KV Store implementation using fjall.
 
-use std::path::Path;
-
-use fjall::{Keyspace, KeyspaceCreateOptions, PersistMode};
-use wasm_wave::value::Value;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use fjall::{Keyspace, KeyspaceCreateOptions, OwnedWriteBatch, PersistMode, Readable};
+use wasm_wave::value::{Type as WaveType, Value};
 use wit_parser::{Resolve, Type, TypeId};
 
 use crate::logging::{debug, error, info, trace, warn};
-use crate::{CanonicalAbi, LinearMemory, find_first_named_type, find_type_by_name};
+use crate::{LinearMemory, find_first_named_type, find_type_by_name, resolve_wit_type};
 use wit_kv_abi::val_to_wave;
 
+use super::backup::{ArchiveReader, ArchiveWriter, BackupInfo, Record};
+use super::batch::{PendingWrite, WriteBatch};
+use super::cache::{KeyspaceType, TypeCache};
+use super::changes::{self, ChangeLog, DEFAULT_CHANGE_RETENTION, Watch};
+use super::durability::{Durability, IntervalSync};
 use super::error::KvError;
-use super::types::{KeyspaceMetadata, StoredValue};
+use super::evolution::{TypeDiff, project_value};
+use super::expiry::{self, due_entries, now_millis, parse_entry};
+use super::format::{BinaryExport, KeyList};
+use super::index::{FieldIndex, KeyspaceIndexes, clear_entries};
+use super::projection::Projection;
+use super::query::Predicate;
+use super::scan::{Scan, ScanOptions, decode_cursor, encode_cursor, key_range};
+use super::transfer::{self, ImportStats, TransferFormat};
+use super::types::{KeyspaceMetadata, ModuleRegistration, StoredValue};
+use super::version::SemanticVersion;
+
+/// Keyspace of type definitions and store configuration.
+const META_KEYSPACE: &str = "_meta";
 
 /// Key prefixes for the metadata keyspace.
 const META_TYPES_PREFIX: &str = "types/";
 const META_QUALIFIED_PREFIX: &str = "qualified/";
+const META_HISTORY_PREFIX: &str = "history/";
+const META_INDEXES_PREFIX: &str = "indexes/";
 const META_CONFIG_KEY: &str = "config";
+/// Progress of an interrupted store format upgrade: the keyspace being
+/// upgraded and the last key merged, separated by a NUL byte.
+const META_UPGRADE_KEY: &str = "upgrade";
+/// Last committed store revision (u64, little-endian).
+const META_REVISION_KEY: &str = "revision";
 
 /// Data keyspace prefix.
 const DATA_PREFIX: &str = "data_";
 
-/// Current store version (1).
+/// Index keyspace prefix.
+const INDEX_PREFIX: &str = "index_";
+
+/// Expiry keyspace prefix.
+const EXPIRY_PREFIX: &str = "expiry_";
+
+/// Change log keyspace prefix.
+const CHANGES_PREFIX: &str = "changes_";
+
+/// Keyspace of background job records.
+const JOBS_KEYSPACE: &str = "_jobs";
+
+/// Keyspace of registered modules.
+const MODULES_KEYSPACE: &str = "_modules";
+/// Module registration key prefix within the modules keyspace.
+const MODULE_REGISTRATION_PREFIX: &str = "registration/";
+/// Module component bytes key prefix within the modules keyspace.
+const MODULE_COMPONENT_PREFIX: &str = "component/";
+
+/// Directory within the store holding compiled module components.
+const MODULE_CACHE_DIR: &str = "module-cache";
+
+/// Current store version (2).
 /// Increment this when changing the on-disk layout or metadata format.
-/// The store will reject opening databases with a different version.
-const STORE_VERSION: u32 = 1;
+/// Older versions are upgraded in place on open; newer ones are rejected.
+///
+/// - 1: envelopes split into a `key` buffer entry and a `key.memory` entry
+/// - 2: envelopes stored as a single record (flat buffer followed by memory)
+const STORE_VERSION: u32 = 2;
+
+/// Values restored per batch by [`KvStore::restore`].
+const RESTORE_BATCH_SIZE: usize = 10_000;
+
+/// Suffix of the separate memory entries used by store version 1.
+const V1_MEMORY_SUFFIX: &str = ".memory";
+
+/// Records merged per batch when upgrading a version 1 store.
+const UPGRADE_BATCH_SIZE: usize = 10_000;
 
 /// A typed key-value store backed by fjall.
 ///
@@ -42,7 +107,7 @@ const STORE_VERSION: u32 = 1;
 ///
 /// // Register a type for a keyspace
 /// // Given a types.wit file: record point { x: s32, y: s32 }
-/// store.set_type("points", "types.wit", Some("point"), false)?;
+/// store.set_type("points", "types.wit", Some("point"), false, false)?;
 ///
 /// // Store values using WAVE text format
 /// store.set("points", "origin", "{x: 0, y: 0}")?;
@@ -53,11 +118,12 @@ const STORE_VERSION: u32 = 1;
 ///     println!("Origin: {}", value); // "{x: 0, y: 0}"
 /// }
 ///
-/// // List keys
-/// let keys = store.list("points", None, None, None, Some(10))?;
-/// for key in keys {
+/// // List keys, 10 at a time
+/// let page = store.list("points", None, None, None, None, Some(10))?;
+/// for key in &page.keys {
 ///     println!("Key: {}", key);
 /// }
+/// let next = store.list("points", None, None, None, page.next_cursor.as_deref(), Some(10))?;
 ///
 /// // Delete a value
 /// store.delete("points", "p1")?;
@@ -69,13 +135,70 @@ const STORE_VERSION: u32 = 1;
 /// stored in that keyspace. The type is registered using [`set_type`](Self::set_type)
 /// and values are validated against this schema.
 ///
+/// # Schema Evolution
+///
+/// Every registered version of a keyspace type is kept in the metadata
+/// keyspace. Values written under an older, compatible version are lifted
+/// with the type they were written with and projected onto the current type
+/// on read. Use [`with_upgrade_on_read`](Self::with_upgrade_on_read) to also
+/// write the upgraded value back.
+///
+/// # Indexes
+///
+/// Record fields can be indexed with [`create_index`](Self::create_index) to
+/// find keys by field value with [`lookup_by_index`](Self::lookup_by_index).
+/// Indexes are updated in the same atomic batch as the values they index.
+///
+/// # Expiration
+///
+/// Values can be given a time-to-live with [`set_with_ttl`](Self::set_with_ttl),
+/// or get their keyspace's default one (see
+/// [`set_default_ttl`](Self::set_default_ttl)). Expired values are hidden
+/// from reads right away, and removed, along with their index entries, by
+/// [`purge_expired`](Self::purge_expired).
+///
+/// # Watching
+///
+/// Every committed set and delete is recorded in a per-keyspace change log
+/// under a sequence number. [`watch`](Self::watch) reads the log from a
+/// given sequence number and then waits for new changes. Only the most
+/// recent changes are kept; see [`with_change_retention`](Self::with_change_retention).
+///
+/// # Backups
+///
+/// [`snapshot_to`](Self::snapshot_to) writes a consistent, checksummed
+/// archive of every keyspace type and value while the store stays in use,
+/// and [`restore`](Self::restore) creates a new store from one.
+///
 /// # Persistence
 ///
-/// The store is backed by fjall, an LSM-tree based storage engine. All write
-/// operations are durably persisted before returning.
+/// The store is backed by fjall, an LSM-tree based storage engine. By default
+/// all write operations are durably persisted before returning; see
+/// [`with_durability`](Self::with_durability) to trade durability for write
+/// throughput.
 pub struct KvStore {
     db: fjall::Database,
+    path: PathBuf,
     meta: Keyspace,
+    /// Write upgraded values back when reading values with an older type version.
+    upgrade_on_read: bool,
+    /// Last committed revision. Holding the lock serializes value writes,
+    /// including those that must observe the current value (conditional
+    /// writes, upgrade write-back).
+    write_lock: Mutex<u64>,
+    /// Notified whenever a new revision is committed.
+    committed: Condvar,
+    /// Changes kept in the change log of each keyspace.
+    change_retention: usize,
+    /// Where the change log of each keyspace starts, once known. Only used
+    /// while holding `write_lock`.
+    change_log_starts: Mutex<HashMap<String, u64>>,
+    /// When value writes are fsynced.
+    durability: Durability,
+    /// Background syncer for [`Durability::Interval`].
+    interval_sync: Option<IntervalSync>,
+    /// Parsed types of keyspaces and their recorded versions.
+    types: TypeCache,
 }
 
 impl KvStore {
@@ -100,7 +223,7 @@ impl KvStore {
         }
 
         let db = fjall::Database::builder(path).open()?;
-        let meta = db.keyspace("_meta", KeyspaceCreateOptions::default)?;
+        let meta = db.keyspace(META_KEYSPACE, KeyspaceCreateOptions::default)?;
 
         // Verify store version
         if let Some(config) = meta.get(META_CONFIG_KEY)? {
@@ -110,7 +233,9 @@ impl KvStore {
                     .try_into()
                     .map_err(|_| KvError::InvalidFormat("Invalid config format".to_string()))?,
             );
-            if version != STORE_VERSION {
+            if version == 1 {
+                Self::upgrade_from_v1(&db, &meta, UPGRADE_BATCH_SIZE)?;
+            } else if version != STORE_VERSION {
                 error!(
                     stored_version = version,
                     expected_version = STORE_VERSION,
@@ -128,7 +253,7 @@ impl KvStore {
         }
 
         info!(path = %path.display(), "KV store opened");
-        Ok(Self { db, meta })
+        Self::from_parts(path, db, meta)
     }
 
     /// Initialize a new KV store at the given path.
@@ -147,25 +272,218 @@ impl KvStore {
         debug!(path = %path.display(), "initializing KV store");
 
         let db = fjall::Database::builder(path).open()?;
-        let meta = db.keyspace("_meta", KeyspaceCreateOptions::default)?;
+        let meta = db.keyspace(META_KEYSPACE, KeyspaceCreateOptions::default)?;
 
         // Write store version
         meta.insert(META_CONFIG_KEY, STORE_VERSION.to_le_bytes())?;
         db.persist(PersistMode::SyncAll)?;
 
         info!(path = %path.display(), version = STORE_VERSION, "KV store initialized");
-        Ok(Self { db, meta })
+        Self::from_parts(path, db, meta)
+    }
+
+    /// Upgrade a version 1 store in place: merge every `key` / `key.memory`
+    /// pair into a single record and bump the stored version.
+    ///
+    /// Keyspaces are upgraded one at a time, `batch_size` records per batch.
+    /// Each batch also records the last key it merged, so an interrupted
+    /// upgrade resumes after it on the next open. The version is only bumped
+    /// once every keyspace is upgraded.
+    fn upgrade_from_v1(
+        db: &fjall::Database,
+        meta: &Keyspace,
+        batch_size: usize,
+    ) -> Result<(), KvError> {
+        info!(from = 1, to = STORE_VERSION, "upgrading store format");
+
+        let mut names: Vec<String> = db
+            .list_keyspace_names()
+            .iter()
+            .filter(|name| name.starts_with(DATA_PREFIX))
+            .map(|name| name.to_string())
+            .collect();
+        names.sort();
+        names.insert(0, META_KEYSPACE.to_string());
+
+        // Skip the keyspaces an interrupted upgrade got through
+        let mut resume = match meta.get(META_UPGRADE_KEY)? {
+            Some(progress) => Some(parse_upgrade_progress(&progress)?),
+            None => None,
+        };
+        let resume_at = resume
+            .as_ref()
+            .and_then(|(name, _)| names.iter().position(|n| n == name));
+        match resume_at {
+            Some(done) => {
+                names.drain(..done);
+                debug!(keyspaces = names.len(), "resuming store format upgrade");
+            }
+            None => resume = None,
+        }
+
+        for name in &names {
+            let ks = db.keyspace(name, KeyspaceCreateOptions::default)?;
+            let start = match resume.take() {
+                Some((_, key)) => Bound::Excluded(key),
+                None => Bound::Unbounded,
+            };
+
+            let snapshot = db.snapshot();
+            let mut batch = db.batch();
+            let mut pending = 0usize;
+            for kv in snapshot.range(&ks, (start, Bound::Unbounded)) {
+                let (memory_key, memory) = kv.into_inner()?;
+                let Some(base_key) = memory_key.strip_suffix(V1_MEMORY_SUFFIX.as_bytes()) else {
+                    continue;
+                };
+                // A `.memory` key without a base entry is a regular user key
+                let Some(buffer) = snapshot.get(&ks, base_key)? else {
+                    continue;
+                };
+
+                let mut record = buffer.to_vec();
+                record.extend_from_slice(&memory);
+                batch.insert(&ks, base_key, record);
+                batch.remove(&ks, memory_key.to_vec());
+                pending += 1;
+
+                if pending >= batch_size {
+                    trace!(keyspace = %name, records = pending, "merging split records");
+                    batch.insert(meta, META_UPGRADE_KEY, upgrade_progress(name, &memory_key));
+                    std::mem::replace(&mut batch, db.batch()).commit()?;
+                    pending = 0;
+                }
+            }
+            trace!(keyspace = %name, records = pending, "merging split records");
+            batch.commit()?;
+            debug!(keyspace = %name, "keyspace upgraded");
+        }
+
+        let mut batch = db.batch();
+        batch.remove(meta, META_UPGRADE_KEY);
+        batch.insert(meta, META_CONFIG_KEY, STORE_VERSION.to_le_bytes());
+        batch.commit()?;
+        db.persist(PersistMode::SyncAll)?;
+
+        info!(version = STORE_VERSION, "store format upgraded");
+        Ok(())
+    }
+
+    fn from_parts(path: &Path, db: fjall::Database, meta: Keyspace) -> Result<Self, KvError> {
+        let revision = match meta.get(META_REVISION_KEY)? {
+            Some(bytes) => u64::from_le_bytes(
+                bytes
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| KvError::InvalidFormat("Invalid revision format".to_string()))?,
+            ),
+            None => 0,
+        };
+        trace!(revision = revision, "loaded store revision");
+
+        Ok(Self {
+            db,
+            path: path.to_path_buf(),
+            meta,
+            upgrade_on_read: false,
+            write_lock: Mutex::new(revision),
+            committed: Condvar::new(),
+            change_retention: DEFAULT_CHANGE_RETENTION,
+            change_log_starts: Mutex::default(),
+            durability: Durability::SyncAll,
+            interval_sync: None,
+            types: TypeCache::default(),
+        })
+    }
+
+    /// Choose when value writes are fsynced (default [`Durability::SyncAll`]).
+    ///
+    /// With [`Durability::Interval`] a background thread syncs the store
+    /// until it is dropped. Pending writes are always synced when the store
+    /// is dropped or [`flush`](Self::flush) is called.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use std::time::Duration;
+    /// use wit_kv::{Durability, KvStore};
+    ///
+    /// let store = KvStore::open(".wit-kv")?
+    ///     .with_durability(Durability::Interval(Duration::from_millis(100)))?;
+    /// ```
+    pub fn with_durability(mut self, durability: Durability) -> Result<Self, KvError> {
+        // Replacing the syncer stops the previous thread
+        self.interval_sync = match durability {
+            Durability::Interval(interval) => Some(IntervalSync::start(self.db.clone(), interval)?),
+            Durability::SyncAll | Durability::Buffer => None,
+        };
+        self.durability = durability;
+        debug!(durability = %durability, "durability configured");
+        Ok(self)
+    }
+
+    /// The configured durability.
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Fsync every write made so far, whatever the configured durability.
+    pub fn flush(&self) -> Result<(), KvError> {
+        self.db.persist(PersistMode::SyncAll)?;
+        trace!("store flushed");
+        Ok(())
+    }
+
+    /// Write values back in the current type version when they are read
+    /// with an older one (disabled by default).
+    ///
+    /// Without this, values written under an older compatible version are
+    /// projected onto the current type on every read but stay unchanged on disk.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wit_kv::KvStore;
+    ///
+    /// let store = KvStore::open(".wit-kv")?.with_upgrade_on_read(true);
+    /// ```
+    pub fn with_upgrade_on_read(mut self, enabled: bool) -> Self {
+        self.upgrade_on_read = enabled;
+        self
+    }
+
+    /// Number of changes kept in the change log of each keyspace (default 10000).
+    ///
+    /// Older changes are dropped as new ones are committed, so watchers that
+    /// fall further behind cannot resume. At least one change is always kept.
+    pub fn with_change_retention(mut self, changes: usize) -> Self {
+        self.change_retention = changes.max(1);
+        self
     }
 
     /// Register a type for a keyspace.
     ///
+    /// Re-registering an existing keyspace requires `force`. The new type is
+    /// then compared against the registered one and `type_version` is bumped
+    /// according to the kind of change (see [`TypeDiff`]). Breaking changes
+    /// are refused with [`KvError::IncompatibleTypeChange`] unless
+    /// `allow_breaking` is set. Re-registering an unchanged definition is a
+    /// no-op that returns the existing metadata.
+    ///
+    /// Keyspace names cannot be empty or contain `@` or `/` (see
+    /// [`KeyspaceMetadata::validate_name`]).
+    ///
     /// # Example
     ///
     /// ```ignore
     /// use wit_kv::KvStore;
     ///
     /// let store = KvStore::init(".wit-kv")?;
-    /// store.set_type("points", "types.wit", Some("point"), false)?;
+    /// store.set_type("points", "types.wit", Some("point"), false, false)?;
+    ///
+    /// // Later: add an optional field to `point` and re-register
+    /// let metadata = store.set_type("points", "types.wit", Some("point"), true, false)?;
+    /// println!("now at {}", metadata.type_version); // "0.1.1"
     /// ```
     pub fn set_type(
         &self,
@@ -173,6 +491,7 @@ impl KvStore {
         wit_path: impl AsRef<Path>,
         type_name: Option<&str>,
         force: bool,
+        allow_breaking: bool,
     ) -> Result<KeyspaceMetadata, KvError> {
         let wit_path = wit_path.as_ref();
         debug!(
@@ -180,65 +499,83 @@ impl KvStore {
             wit_path = %wit_path.display(),
             type_name = type_name,
             force = force,
+            allow_breaking = allow_breaking,
             "registering type for keyspace"
         );
+        KeyspaceMetadata::validate_name(keyspace)?;
 
         // Check if keyspace already exists
-        let key = format!("{}{}", META_TYPES_PREFIX, keyspace);
-        if !force && self.meta.get(&key)?.is_some() {
+        let existing = self.cached_type(&format!("{}{}", META_TYPES_PREFIX, keyspace))?;
+        let mut stale_indexes = None;
+        if !force && existing.is_some() {
             warn!(keyspace = keyspace, "keyspace already exists");
             return Err(KvError::KeyspaceExists(keyspace.to_string()));
         }
 
-        // Parse the WIT file
-        trace!(wit_path = %wit_path.display(), "parsing WIT file");
-        let mut resolve = Resolve::new();
-        resolve.push_path(wit_path)?;
-
-        // Find the type
-        let type_id = match type_name {
-            Some(tn) => find_type_by_name(&resolve, tn).ok_or_else(|| {
-                error!(type_name = tn, "type not found in WIT");
-                KvError::TypeNotFound(tn.to_string())
-            })?,
-            None => find_first_named_type(&resolve).ok_or_else(|| {
-                error!("no named type found in WIT");
-                KvError::TypeNotFound("No named type found".to_string())
-            })?,
-        };
-
-        let type_def = resolve
-            .types
-            .get(type_id)
-            .ok_or_else(|| KvError::TypeNotFound(format!("Type {:?} not found", type_id)))?;
-        let actual_type_name = type_def.name.clone().unwrap_or_default();
+        let (resolve, type_id, mut metadata) =
+            self.load_type_definition(keyspace, wit_path, type_name)?;
 
-        // Build qualified name from package info
-        let qualified_name = self.build_qualified_name(&resolve, type_id, &actual_type_name)?;
-        trace!(
-            type_name = %actual_type_name,
-            qualified_name = %qualified_name,
-            "resolved type"
-        );
+        if let Some(existing) = &existing {
+            let previous = &existing.metadata;
+            if previous.wit_definition == metadata.wit_definition
+                && previous.type_name == metadata.type_name
+            {
+                debug!(keyspace = keyspace, "type definition unchanged");
+                return Ok(previous.clone());
+            }
 
-        // Read WIT file content
-        let wit_definition = std::fs::read_to_string(wit_path)?;
+            let new_type = resolve_wit_type(&resolve, type_id)
+                .map_err(|e| KvError::WaveParse(e.to_string()))?;
+            let diff = TypeDiff::between(&existing.wave_type, &new_type);
+
+            if diff.is_breaking() && !allow_breaking {
+                warn!(keyspace = keyspace, diff = %diff, "refusing breaking type change");
+                let changes: Vec<String> = diff.breaking_changes().map(|c| c.to_string()).collect();
+                return Err(KvError::IncompatibleTypeChange {
+                    keyspace: keyspace.to_string(),
+                    changes: changes.join("; "),
+                });
+            }
 
-        // Create metadata
-        let metadata = KeyspaceMetadata::new(
-            keyspace.to_string(),
-            qualified_name.clone(),
-            wit_definition,
-            actual_type_name,
-        );
+            // Indexed fields must still exist. After a breaking change no
+            // existing value can be read, so neither can be found by index.
+            let indexes = self.load_indexes(keyspace, &new_type)?;
+            if diff.is_breaking() {
+                stale_indexes = indexes;
+            }
 
-        // Encode and store
-        let (buffer, memory) = metadata.encode()?;
-        self.store_with_memory(&self.meta, &key, &buffer, &memory)?;
+            metadata.type_version = previous.type_version.bump(diff.kind());
+            metadata.created_at = previous.created_at;
+            metadata.default_ttl = previous.default_ttl;
+            debug!(
+                keyspace = keyspace,
+                diff = %diff,
+                from = %previous.type_version,
+                to = %metadata.type_version,
+                "type changed"
+            );
+        }
 
-        // Store reverse lookup
-        let qualified_key = format!("{}{}", META_QUALIFIED_PREFIX, qualified_name);
-        self.meta.insert(&qualified_key, keyspace.as_bytes())?;
+        let mut batch = self.db.batch();
+        self.write_type_metadata(
+            &mut batch,
+            keyspace,
+            existing.as_ref().map(|ty| &ty.metadata),
+            &metadata,
+        )?;
+        if let Some(indexes) = &stale_indexes {
+            indexes.replace(&mut batch, &BTreeSet::new())?;
+        }
+        {
+            // Serialized with value writes, which check the type they were
+            // encoded with under the same lock
+            let _guard = self
+                .write_lock
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            batch.commit()?;
+            self.invalidate_type(keyspace);
+        }
 
         // Create data keyspace for this keyspace
         let data_keyspace_name = format!("{}{}", DATA_PREFIX, keyspace);
@@ -251,16 +588,183 @@ impl KvStore {
         info!(
             keyspace = keyspace,
             qualified_name = %metadata.qualified_name,
-            type_version = %format!("{}.{}.{}", metadata.type_version.major, metadata.type_version.minor, metadata.type_version.patch),
+            type_version = %metadata.type_version,
             "type registered"
         );
         Ok(metadata)
     }
 
+    /// Migrate every value in a keyspace to a new type.
+    ///
+    /// `migrate` is called once per key with the current value (already
+    /// brought to the keyspace's current type version) and the version the
+    /// keyspace will have afterwards, and must return the value encoded with
+    /// the new type. All values are migrated before anything is written; the
+    /// new values, type definition and bumped `type_version` are then
+    /// committed in a single atomic batch. If `migrate` fails for any key,
+    /// nothing is changed and [`KvError::MigrationFailed`] is returned.
+    ///
+    /// Indexes of the keyspace are rebuilt from the migrated values in the
+    /// same batch; every indexed field must exist in the new type.
+    ///
+    /// Writes to the keyspace are blocked while the migration runs.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wit_kv::{KvStore, TypedRunner};
+    ///
+    /// let store = KvStore::open(".wit-kv")?;
+    /// let mut runner = TypedRunner::new("migrate.wasm", "migrate.wit", "user-v1", Some("user-v2"))?;
+    /// let metadata = store.migrate("users", "migrate.wit", Some("user-v2"), |_key, value, version| {
+    ///     runner.call_migrate(value, version)
+    /// })?;
+    /// ```
+    pub fn migrate<F, E>(
+        &self,
+        keyspace: &str,
+        wit_path: impl AsRef<Path>,
+        type_name: Option<&str>,
+        mut migrate: F,
+    ) -> Result<KeyspaceMetadata, KvError>
+    where
+        F: FnMut(&str, &StoredValue, SemanticVersion) -> Result<StoredValue, E>,
+        E: std::fmt::Display,
+    {
+        let wit_path = wit_path.as_ref();
+        debug!(
+            keyspace = keyspace,
+            wit_path = %wit_path.display(),
+            type_name = type_name,
+            "migrating keyspace"
+        );
+
+        // The type is read and replaced under the lock, so no other type
+        // change can land in between
+        let mut revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.invalidate_type(keyspace);
+        let current = self.keyspace_type(keyspace)?;
+        let previous = &current.metadata;
+
+        let (resolve, type_id, mut metadata) =
+            self.load_type_definition(keyspace, wit_path, type_name)?;
+
+        let new_type =
+            resolve_wit_type(&resolve, type_id).map_err(|e| KvError::WaveParse(e.to_string()))?;
+        let diff = TypeDiff::between(&current.wave_type, &new_type);
+        let indexes = self.load_indexes(keyspace, &new_type)?;
+        metadata.type_version = previous.type_version.bump(diff.kind());
+        metadata.created_at = previous.created_at;
+        metadata.default_ttl = previous.default_ttl;
+        let target = KeyspaceType::parse(metadata.clone())?;
+        debug!(
+            keyspace = keyspace,
+            diff = %diff,
+            from = %previous.type_version,
+            to = %metadata.type_version,
+            "migration target resolved"
+        );
+
+        let keyspace_name = format!("{}{}", DATA_PREFIX, keyspace);
+        let ks = self
+            .db
+            .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?;
+        let next_revision = *revision + 1;
+
+        // Every value must be migrated, so a key that cannot be read fails it
+        let keys: Vec<String> = ks
+            .iter()
+            .map(|kv| kv.key())
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .map(|k| String::from_utf8_lossy(k).into_owned())
+            .collect();
+
+        let mut batch = self.db.batch();
+        let mut log = self.open_change_log(keyspace)?;
+        let mut index_entries = BTreeSet::new();
+        for key in &keys {
+            let Some(stored) = self.load_stored_value(&ks, key)? else {
+                continue;
+            };
+            let value = self.upgrade_if_needed(keyspace, &current, stored)?;
+
+            let migrated = migrate(key, &value, metadata.type_version).map_err(|e| {
+                error!(keyspace = keyspace, key = %key, error = %e, "migration failed");
+                KvError::MigrationFailed {
+                    key: key.clone(),
+                    reason: e.to_string(),
+                }
+            })?;
+
+            let mut migrated =
+                StoredValue::new(metadata.type_version, migrated.value, migrated.memory);
+            migrated.expires_at = value.expires_at;
+
+            // Make sure the migrated value decodes as the new type
+            let value = target
+                .to_value(&migrated)
+                .map_err(|e| KvError::MigrationFailed {
+                    key: key.clone(),
+                    reason: format!("result does not match {}: {}", metadata.type_name, e),
+                })?;
+            if let Some(indexes) = &indexes {
+                index_entries.extend(indexes.entries(key, &value));
+            }
+
+            migrated.revision = next_revision;
+            log.append(&mut batch, key, next_revision, Some(&migrated))?;
+            batch.insert(&ks, key.as_str(), migrated.to_bytes()?);
+        }
+        log.trim(&mut batch, self.change_retention)?;
+
+        if let Some(indexes) = &indexes {
+            indexes.replace(&mut batch, &index_entries)?;
+        }
+        self.write_type_metadata(&mut batch, keyspace, Some(previous), &metadata)?;
+        batch.insert(&self.meta, META_REVISION_KEY, next_revision.to_le_bytes());
+        batch.commit()?;
+        self.remember_change_log(keyspace, &log);
+        self.invalidate_type(keyspace);
+        *revision = next_revision;
+        self.committed.notify_all();
+        self.db.persist(PersistMode::SyncAll)?;
+
+        info!(
+            keyspace = keyspace,
+            count = keys.len(),
+            from = %previous.type_version,
+            to = %metadata.type_version,
+            "keyspace migrated"
+        );
+        Ok(metadata)
+    }
+
     /// Get the type metadata for a keyspace.
     pub fn get_type(&self, keyspace: &str) -> Result<Option<KeyspaceMetadata>, KvError> {
         let key = format!("{}{}", META_TYPES_PREFIX, keyspace);
-        self.load_metadata(&key)
+        Ok(self.cached_type(&key)?.map(|ty| ty.metadata.clone()))
+    }
+
+    /// Get every registered version of a keyspace type, oldest first.
+    pub fn type_history(&self, keyspace: &str) -> Result<Vec<KeyspaceMetadata>, KvError> {
+        let prefix = format!("{}{}@", META_HISTORY_PREFIX, keyspace);
+        let mut versions = Vec::new();
+
+        for kv in self.meta.prefix(&prefix) {
+            let key_bytes = kv.key()?;
+            let key_str = String::from_utf8_lossy(&key_bytes);
+
+            if let Some(metadata) = self.load_metadata(&key_str)? {
+                versions.push(metadata);
+            }
+        }
+
+        versions.sort_by_key(|m| m.type_version);
+        Ok(versions)
     }
 
     /// Delete a keyspace type (and optionally its data).
@@ -271,18 +775,51 @@ impl KvStore {
             "deleting type"
         );
         let key = format!("{}{}", META_TYPES_PREFIX, keyspace);
+        let _revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        // The type, its history and its indexes go in one batch, so a
+        // concurrent write never sees indexes without a type
+        let mut batch = self.db.batch();
 
         // Get metadata to find qualified name
         if let Some(metadata) = self.load_metadata(&key)? {
             // Delete qualified name lookup
             let qualified_key = format!("{}{}", META_QUALIFIED_PREFIX, metadata.qualified_name);
-            self.meta.remove(&qualified_key)?;
+            batch.remove(&self.meta, qualified_key);
         }
 
         // Delete metadata
-        let memory_key = format!("{}.memory", key);
-        self.meta.remove(&key)?;
-        self.meta.remove(&memory_key)?;
+        batch.remove(&self.meta, key);
+
+        // Delete type history
+        let history_prefix = format!("{}{}@", META_HISTORY_PREFIX, keyspace);
+        for kv in self.meta.prefix(&history_prefix) {
+            batch.remove(&self.meta, kv.key()?);
+        }
+
+        // Delete indexes, which cannot be used without a type
+        let index_prefix = index_key(keyspace, "");
+        let index_keys: Vec<_> = self
+            .meta
+            .prefix(&index_prefix)
+            .map(|kv| kv.key())
+            .collect::<Result<_, _>>()?;
+        if !index_keys.is_empty() {
+            let index_keyspace = self.index_keyspace(keyspace)?;
+            trace!(index_count = index_keys.len(), "deleting indexes");
+            for kv in index_keyspace.iter() {
+                batch.remove(&index_keyspace, kv.key()?);
+            }
+            for k in index_keys {
+                batch.remove(&self.meta, k);
+            }
+        }
+
+        batch.commit()?;
+        self.invalidate_type(keyspace);
 
         // Delete data keyspace if requested
         if delete_data {
@@ -301,6 +838,19 @@ impl KvStore {
                     data_keyspace.remove(&k)?;
                 }
             }
+
+            let expiry_keyspace_name = format!("{}{}", EXPIRY_PREFIX, keyspace);
+            if self.db.keyspace_exists(&expiry_keyspace_name) {
+                let expiry_keyspace = self.expiry_keyspace(keyspace)?;
+                let entries: Vec<Vec<u8>> = expiry_keyspace
+                    .iter()
+                    .filter_map(|kv| kv.key().ok().map(|k| k.to_vec()))
+                    .collect();
+                trace!(entry_count = entries.len(), "deleting expiry entries");
+                for k in entries {
+                    expiry_keyspace.remove(&k)?;
+                }
+            }
         }
 
         self.db.persist(PersistMode::SyncAll)?;
@@ -322,11 +872,6 @@ impl KvStore {
             };
             let key_str = String::from_utf8_lossy(&key_bytes);
 
-            // Skip memory keys
-            if key_str.ends_with(".memory") {
-                continue;
-            }
-
             if let Some(metadata) = self.load_metadata(&key_str)? {
                 types.push(metadata);
             }
@@ -336,7 +881,9 @@ impl KvStore {
     }
 
     /// Set a value in a keyspace.
-    pub fn set(&self, keyspace: &str, key: &str, wave_value: &str) -> Result<(), KvError> {
+    ///
+    /// Returns the revision assigned to the value.
+    pub fn set(&self, keyspace: &str, key: &str, wave_value: &str) -> Result<u64, KvError> {
         debug!(
             keyspace = keyspace,
             key = key,
@@ -344,163 +891,408 @@ impl KvStore {
             "setting value"
         );
 
-        let metadata = self
-            .get_type(keyspace)?
-            .ok_or_else(|| KvError::KeyspaceNotFound(keyspace.to_string()))?;
-
-        // Parse WIT type from stored definition
-        let (resolve, type_id, wave_type) = self.parse_stored_type(&metadata)?;
-        trace!(type_name = %metadata.type_name, "parsed WIT type for encoding");
-
-        // Parse the WAVE value
-        let value: Value = wasm_wave::from_str(&wave_type, wave_value).map_err(|e| {
-            error!(keyspace = keyspace, key = key, error = %e, "failed to parse WAVE value");
-            KvError::WaveParse(e.to_string())
-        })?;
+        let mut batch = self.batch();
+        batch.set(keyspace, key, wave_value)?;
+        let revision = batch.commit()?;
 
-        // Lower to canonical ABI
-        let abi = CanonicalAbi::new(&resolve);
-        let mut memory = LinearMemory::new();
-        let encoded = abi.lower_with_memory(&value, &Type::Id(type_id), &wave_type, &mut memory)?;
-        trace!(
-            buffer_size = encoded.len(),
-            memory_size = memory.len(),
-            "value encoded to canonical ABI"
+        debug!(
+            keyspace = keyspace,
+            key = key,
+            revision = revision,
+            "value set"
         );
+        Ok(revision)
+    }
 
-        // Create StoredValue
-        let stored = StoredValue::new(
-            metadata.type_version,
-            encoded,
-            if memory.is_empty() {
-                None
-            } else {
-                Some(memory.into_bytes())
-            },
+    /// Set a value that expires after `ttl`, in place of the keyspace's
+    /// default time-to-live.
+    ///
+    /// The value is hidden from reads once it expires, and removed by
+    /// [`purge_expired`](Self::purge_expired). Returns the revision assigned
+    /// to the value.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use std::time::Duration;
+    /// use wit_kv::KvStore;
+    ///
+    /// let store = KvStore::open(".wit-kv")?;
+    /// store.set_with_ttl("sessions", "abc123", "{user: \"alice\"}", Duration::from_secs(3600))?;
+    /// ```
+    pub fn set_with_ttl(
+        &self,
+        keyspace: &str,
+        key: &str,
+        wave_value: &str,
+        ttl: Duration,
+    ) -> Result<u64, KvError> {
+        debug!(
+            keyspace = keyspace,
+            key = key,
+            ttl_ms = ttl.as_millis(),
+            "setting value with ttl"
         );
 
-        // Encode and store
-        let (buffer, mem) = stored.encode()?;
-
-        let keyspace_name = format!("{}{}", DATA_PREFIX, keyspace);
-        let ks = self
-            .db
-            .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?;
+        let mut batch = self.batch();
+        batch.set_with_ttl(keyspace, key, wave_value, ttl)?;
+        batch.commit()
+    }
 
-        self.store_with_memory(&ks, key, &buffer, &mem)?;
-        self.db.persist(PersistMode::SyncAll)?;
+    /// Set a value only if the key currently has `expected_revision`.
+    ///
+    /// Returns the new revision, or [`KvError::RevisionConflict`] if the key
+    /// was changed, deleted, or never existed.
+    pub fn set_if(
+        &self,
+        keyspace: &str,
+        key: &str,
+        wave_value: &str,
+        expected_revision: u64,
+    ) -> Result<u64, KvError> {
+        debug!(
+            keyspace = keyspace,
+            key = key,
+            expected_revision = expected_revision,
+            "setting value conditionally"
+        );
 
-        debug!(keyspace = keyspace, key = key, "value set");
-        Ok(())
+        let mut batch = self.batch();
+        batch.set_if(keyspace, key, wave_value, expected_revision)?;
+        batch.commit()
     }
 
-    /// Get a value from a keyspace as WAVE text.
-    pub fn get(&self, keyspace: &str, key: &str) -> Result<Option<String>, KvError> {
-        debug!(keyspace = keyspace, key = key, "getting value");
+    /// Set a value only if the key does not exist.
+    ///
+    /// Returns the new revision, or [`KvError::RevisionConflict`] if the key
+    /// already exists.
+    pub fn set_if_absent(
+        &self,
+        keyspace: &str,
+        key: &str,
+        wave_value: &str,
+    ) -> Result<u64, KvError> {
+        debug!(keyspace = keyspace, key = key, "setting value if absent");
 
-        let metadata = self
-            .get_type(keyspace)?
-            .ok_or_else(|| KvError::KeyspaceNotFound(keyspace.to_string()))?;
+        let mut batch = self.batch();
+        batch.set_if_absent(keyspace, key, wave_value)?;
+        batch.commit()
+    }
+
+    /// Set a canonical ABI encoded value, e.g. one produced by a Wasm component.
+    ///
+    /// The value is validated by lifting it with the keyspace's type and
+    /// rejected with [`KvError::InvalidValue`] if it is malformed. Returns the
+    /// revision assigned to the value.
+    pub fn set_raw(&self, keyspace: &str, key: &str, value: &BinaryExport) -> Result<u64, KvError> {
+        debug!(
+            keyspace = keyspace,
+            key = key,
+            buffer_size = value.buffer.len(),
+            "setting raw value"
+        );
+
+        let mut batch = self.batch();
+        batch.set_raw(keyspace, key, value)?;
+        batch.commit()
+    }
+
+    /// Set a canonical ABI encoded value that expires after `ttl`.
+    /// See [`set_raw`](Self::set_raw) and [`set_with_ttl`](Self::set_with_ttl).
+    pub fn set_raw_with_ttl(
+        &self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+        ttl: Duration,
+    ) -> Result<u64, KvError> {
+        let mut batch = self.batch();
+        batch.set_raw_with_ttl(keyspace, key, value, ttl)?;
+        batch.commit()
+    }
+
+    /// Set a canonical ABI encoded value only if the key currently has
+    /// `expected_revision`. See [`set_raw`](Self::set_raw) and [`set_if`](Self::set_if).
+    pub fn set_raw_if(
+        &self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+        expected_revision: u64,
+    ) -> Result<u64, KvError> {
+        let mut batch = self.batch();
+        batch.set_raw_if(keyspace, key, value, expected_revision)?;
+        batch.commit()
+    }
+
+    /// Set a canonical ABI encoded value only if the key does not exist.
+    /// See [`set_raw`](Self::set_raw) and [`set_if_absent`](Self::set_if_absent).
+    pub fn set_raw_if_absent(
+        &self,
+        keyspace: &str,
+        key: &str,
+        value: &BinaryExport,
+    ) -> Result<u64, KvError> {
+        let mut batch = self.batch();
+        batch.set_raw_if_absent(keyspace, key, value)?;
+        batch.commit()
+    }
+
+    /// Set a value given as JSON, in the mapping of [`json_to_wave`](crate::json_to_wave).
+    ///
+    /// Returns the revision assigned to the value.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wit_kv::KvStore;
+    ///
+    /// let store = KvStore::open(".wit-kv")?;
+    /// store.set_json("users", "alice", &serde_json::json!({"name": "Alice", "age": 30}))?;
+    /// ```
+    pub fn set_json(
+        &self,
+        keyspace: &str,
+        key: &str,
+        json_value: &serde_json::Value,
+    ) -> Result<u64, KvError> {
+        debug!(keyspace = keyspace, key = key, "setting JSON value");
+
+        let mut batch = self.batch();
+        batch.set_json(keyspace, key, json_value)?;
+        batch.commit()
+    }
+
+    /// Get a value from a keyspace as WAVE text.
+    pub fn get(&self, keyspace: &str, key: &str) -> Result<Option<String>, KvError> {
+        Ok(self
+            .get_with_revision(keyspace, key)?
+            .map(|(wave_str, _)| wave_str))
+    }
+
+    /// Get a value from a keyspace as WAVE text, along with its revision.
+    ///
+    /// The revision can be passed to [`set_if`](Self::set_if) or
+    /// [`delete_if`](Self::delete_if) to only write if nobody changed the
+    /// value in the meantime.
+    pub fn get_with_revision(
+        &self,
+        keyspace: &str,
+        key: &str,
+    ) -> Result<Option<(String, u64)>, KvError> {
+        debug!(keyspace = keyspace, key = key, "getting value");
+
+        let ty = self.keyspace_type(keyspace)?;
 
         let keyspace_name = format!("{}{}", DATA_PREFIX, keyspace);
         let ks = self
             .db
             .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?;
 
-        // Load stored value
-        let Some(stored) = self.load_stored_value(&ks, key)? else {
+        // Load stored value, upgrading it if it was written with an older type
+        let Some(stored) = self.load_current_value(&ks, keyspace, key, &ty)? else {
             trace!(keyspace = keyspace, key = key, "key not found");
             return Ok(None);
         };
 
-        // Check type version compatibility
-        if !metadata.type_version.can_read_from(&stored.type_version) {
-            warn!(
-                keyspace = keyspace,
-                key = key,
-                stored_version = %format!("{}.{}.{}", stored.type_version.major, stored.type_version.minor, stored.type_version.patch),
-                current_version = %format!("{}.{}.{}", metadata.type_version.major, metadata.type_version.minor, metadata.type_version.patch),
-                "type version mismatch"
-            );
-            return Err(KvError::TypeVersionMismatch {
-                stored: stored.type_version,
-                current: metadata.type_version,
-            });
-        }
-
-        // Parse WIT type
-        let (resolve, type_id, wave_type) = self.parse_stored_type(&metadata)?;
-
-        // Lift from canonical ABI to Val, then convert to wasm_wave::Value for text display
-        let abi = CanonicalAbi::new(&resolve);
-        let memory = LinearMemory::from_option(stored.memory);
-
-        let (val, _) = abi.lift_to_val(&stored.value, &Type::Id(type_id), None, &memory)?;
-        let value = val_to_wave(&val, &wave_type).map_err(|e| KvError::WaveParse(e.to_string()))?;
-
-        // Convert to WAVE text
-        let wave_str =
-            wasm_wave::to_string(&value).map_err(|e| KvError::WaveParse(e.to_string()))?;
+        let revision = stored.revision;
+        let wave_str = Self::value_to_wave(stored, &ty)?;
 
         debug!(keyspace = keyspace, key = key, "value retrieved");
-        Ok(Some(wave_str))
+        Ok(Some((wave_str, revision)))
     }
 
     /// Get raw stored value (for --binary/--raw output).
+    ///
+    /// Values written with an older type version are returned re-encoded in
+    /// the current version, like [`get`](Self::get).
     pub fn get_raw(&self, keyspace: &str, key: &str) -> Result<Option<StoredValue>, KvError> {
-        let _ = self
-            .get_type(keyspace)?
-            .ok_or_else(|| KvError::KeyspaceNotFound(keyspace.to_string()))?;
+        let ty = self.keyspace_type(keyspace)?;
 
         let keyspace_name = format!("{}{}", DATA_PREFIX, keyspace);
         let ks = self
             .db
             .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?;
 
-        self.load_stored_value(&ks, key)
+        self.load_current_value(&ks, keyspace, key, &ty)
+    }
+
+    /// Get selected fields of a value as WAVE text.
+    ///
+    /// `fields` are dot-separated paths into the keyspace's record type,
+    /// such as `name` or `address.city`; records along a path may be
+    /// `option`s. The result is a value of a record type synthesized from the
+    /// keyspace type, holding only the selected fields in declaration order
+    /// and nested like the original, e.g. `{name: "Alice", address:
+    /// some({city: "Paris"})}`. Only the selected fields are decoded, so
+    /// large strings and lists in other fields are never read.
+    ///
+    /// Returns [`KvError::InvalidProjection`] if a path does not name a field.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wit_kv::KvStore;
+    ///
+    /// let store = KvStore::open(".wit-kv")?;
+    /// let summary = store.get_projected("users", "alice", &["name", "address.city"])?;
+    /// ```
+    pub fn get_projected(
+        &self,
+        keyspace: &str,
+        key: &str,
+        fields: &[impl AsRef<str>],
+    ) -> Result<Option<String>, KvError> {
+        Ok(self
+            .get_projected_with_revision(keyspace, key, fields)?
+            .map(|(wave_str, _)| wave_str))
+    }
+
+    /// Get selected fields of a value as WAVE text, along with the value's
+    /// revision. See [`get_projected`](Self::get_projected).
+    pub fn get_projected_with_revision(
+        &self,
+        keyspace: &str,
+        key: &str,
+        fields: &[impl AsRef<str>],
+    ) -> Result<Option<(String, u64)>, KvError> {
+        let Some((projection, stored)) = self.load_projected(keyspace, key, fields)? else {
+            return Ok(None);
+        };
+        let value = projection.lift(&stored)?;
+        let wave_str =
+            wasm_wave::to_string(&value).map_err(|e| KvError::WaveParse(e.to_string()))?;
+        Ok(Some((wave_str, stored.revision)))
+    }
+
+    /// Get selected fields of a value, encoded with the synthesized record
+    /// type. See [`get_projected`](Self::get_projected).
+    ///
+    /// The returned envelope keeps the value's type version and revision.
+    pub fn get_raw_projected(
+        &self,
+        keyspace: &str,
+        key: &str,
+        fields: &[impl AsRef<str>],
+    ) -> Result<Option<StoredValue>, KvError> {
+        let Some((projection, stored)) = self.load_projected(keyspace, key, fields)? else {
+            return Ok(None);
+        };
+        let value = projection.lift(&stored)?;
+        Ok(Some(projection.encode(&value, &stored)?))
+    }
+
+    /// Get a value from a keyspace as JSON, in the mapping of [`wave_to_json`](crate::wave_to_json).
+    pub fn get_json(
+        &self,
+        keyspace: &str,
+        key: &str,
+    ) -> Result<Option<serde_json::Value>, KvError> {
+        Ok(self
+            .get_json_with_revision(keyspace, key)?
+            .map(|(json, _)| json))
+    }
+
+    /// Get a value from a keyspace as JSON, along with its revision.
+    pub fn get_json_with_revision(
+        &self,
+        keyspace: &str,
+        key: &str,
+    ) -> Result<Option<(serde_json::Value, u64)>, KvError> {
+        debug!(keyspace = keyspace, key = key, "getting JSON value");
+
+        let ty = self.keyspace_type(keyspace)?;
+        let data = self.data_keyspace(keyspace)?;
+        let Some(stored) = self.load_current_value(&data, keyspace, key, &ty)? else {
+            trace!(keyspace = keyspace, key = key, "key not found");
+            return Ok(None);
+        };
+        Ok(Some((ty.to_json(&stored)?, stored.revision)))
+    }
+
+    /// Get selected fields of a value as JSON, along with the value's
+    /// revision. See [`get_projected`](Self::get_projected).
+    pub fn get_projected_json_with_revision(
+        &self,
+        keyspace: &str,
+        key: &str,
+        fields: &[impl AsRef<str>],
+    ) -> Result<Option<(serde_json::Value, u64)>, KvError> {
+        let Some((projection, stored)) = self.load_projected(keyspace, key, fields)? else {
+            return Ok(None);
+        };
+        Ok(Some((projection.lift_json(&stored)?, stored.revision)))
     }
 
     /// Delete a value from a keyspace.
     pub fn delete(&self, keyspace: &str, key: &str) -> Result<(), KvError> {
         debug!(keyspace = keyspace, key = key, "deleting value");
 
-        let _ = self
-            .get_type(keyspace)?
-            .ok_or_else(|| KvError::KeyspaceNotFound(keyspace.to_string()))?;
+        let mut batch = self.batch();
+        batch.delete(keyspace, key)?;
+        batch.commit()?;
 
-        let keyspace_name = format!("{}{}", DATA_PREFIX, keyspace);
-        let ks = self
-            .db
-            .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?;
+        debug!(keyspace = keyspace, key = key, "value deleted");
+        Ok(())
+    }
 
-        let memory_key = format!("{}.memory", key);
-        ks.remove(key)?;
-        ks.remove(&memory_key)?;
+    /// Delete a value only if the key currently has `expected_revision`.
+    ///
+    /// Returns [`KvError::RevisionConflict`] if the key was changed, deleted,
+    /// or never existed.
+    pub fn delete_if(
+        &self,
+        keyspace: &str,
+        key: &str,
+        expected_revision: u64,
+    ) -> Result<(), KvError> {
+        debug!(
+            keyspace = keyspace,
+            key = key,
+            expected_revision = expected_revision,
+            "deleting value conditionally"
+        );
 
-        self.db.persist(PersistMode::SyncAll)?;
-        debug!(keyspace = keyspace, key = key, "value deleted");
+        let mut batch = self.batch();
+        batch.delete_if(keyspace, key, expected_revision)?;
+        batch.commit()?;
         Ok(())
     }
 
-    /// List keys in a keyspace with optional filtering.
+    /// Start a batch of writes that is committed atomically with a single fsync.
+    ///
+    /// See [`WriteBatch`] for details.
+    pub fn batch(&self) -> WriteBatch<'_> {
+        WriteBatch::new(self)
+    }
+
+    /// List keys in a keyspace with optional filtering, one page at a time.
     ///
     /// - `prefix`: Only return keys starting with this prefix
     /// - `start`: Only return keys >= start (inclusive)
     /// - `end`: Only return keys < end (exclusive)
+    /// - `cursor`: Continue after the page that returned this `next_cursor`
     /// - `limit`: Maximum number of keys to return
+    ///
+    /// If more keys match after `limit` keys, the returned list carries a
+    /// `next_cursor`. Pass it back with the same filters to get the next
+    /// page; the filters still apply, so pages never leave the prefix or range.
+    /// Returns [`KvError::InvalidCursor`] for a cursor this store did not produce.
     pub fn list(
         &self,
         keyspace: &str,
         prefix: Option<&str>,
         start: Option<&str>,
         end: Option<&str>,
+        cursor: Option<&str>,
         limit: Option<usize>,
-    ) -> Result<Vec<String>, KvError> {
+    ) -> Result<KeyList, KvError> {
         debug!(
             keyspace = keyspace,
             prefix = prefix,
             start = start,
             end = end,
+            cursor = cursor,
             limit = limit,
             "listing keys"
         );
@@ -508,141 +1300,1561 @@ impl KvStore {
         let _ = self
             .get_type(keyspace)?
             .ok_or_else(|| KvError::KeyspaceNotFound(keyspace.to_string()))?;
-
-        let keyspace_name = format!("{}{}", DATA_PREFIX, keyspace);
-        let ks = self
-            .db
-            .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?;
-
-        let mut keys = Vec::new();
-
-        // Use range or prefix based on what's provided
-        let iter: Box<dyn Iterator<Item = _>> = match (start, end) {
-            (Some(s), Some(e)) => Box::new(ks.range(s..e)),
-            (Some(s), None) => Box::new(ks.range(s..)),
-            (None, Some(e)) => Box::new(ks.range(..e)),
-            (None, None) => match prefix {
-                Some(p) => Box::new(ks.prefix(p)),
-                None => Box::new(ks.prefix("")),
-            },
+        let data = self.data_keyspace(keyspace)?;
+
+        let options = ScanOptions {
+            prefix: prefix.map(str::to_string),
+            start: start.map(str::to_string),
+            end: end.map(str::to_string),
+            after: cursor.map(decode_cursor).transpose()?,
+            ..ScanOptions::default()
         };
 
-        for kv in iter {
-            let Ok(key_bytes) = kv.key() else {
-                continue;
-            };
-            let key_str = String::from_utf8_lossy(&key_bytes);
-
-            // Skip memory keys
-            if key_str.ends_with(".memory") {
-                continue;
+        // Every entry is a whole value (no separate memory entries since store
+        // version 2), so keys can be listed without decoding values; only
+        // their expiry is read
+        let now = now_millis();
+        let mut entries = key_range(&data, &options).filter_map(|guard| match guard.into_inner() {
+            Ok((key_bytes, record)) => {
+                let expired = StoredValue::peek_expires_at(&record).is_some_and(|at| at <= now);
+                (!expired).then_some(Ok(key_bytes))
             }
+            Err(e) => Some(Err(e)),
+        });
+        let mut keys = Vec::new();
+        if limit != Some(0) {
+            for key_bytes in entries.by_ref() {
+                let key_bytes = key_bytes?;
+                keys.push(String::from_utf8_lossy(&key_bytes).into_owned());
 
-            // Apply prefix filter if we're using range (range doesn't filter by prefix)
-            if let Some(p) = prefix
-                && !key_str.starts_with(p)
-            {
-                continue;
+                if limit.is_some_and(|l| keys.len() >= l) {
+                    break;
+                }
             }
+        }
 
-            keys.push(key_str.into_owned());
-
-            if let Some(l) = limit
-                && keys.len() >= l
-            {
-                break;
+        // Only hand out a cursor if the page is full and something follows
+        let next_cursor = match keys.last() {
+            Some(last) if limit.is_some_and(|l| keys.len() >= l) && entries.next().is_some() => {
+                Some(encode_cursor(last))
             }
-        }
+            _ => None,
+        };
 
-        debug!(keyspace = keyspace, count = keys.len(), "listed keys");
-        Ok(keys)
+        debug!(
+            keyspace = keyspace,
+            count = keys.len(),
+            more = next_cursor.is_some(),
+            "listed keys"
+        );
+        Ok(KeyList { keys, next_cursor })
     }
 
-    // Helper methods
-
-    fn build_qualified_name(
-        &self,
-        resolve: &Resolve,
-        type_id: TypeId,
-        type_name: &str,
-    ) -> Result<String, KvError> {
-        let type_def = resolve
-            .types
-            .get(type_id)
-            .ok_or_else(|| KvError::TypeNotFound(format!("Type id {:?} not found", type_id)))?;
+    /// Iterate over the `(key, value)` pairs of a keyspace in key order.
+    ///
+    /// Unlike [`list`](Self::list) followed by one [`get_raw`](Self::get_raw)
+    /// per key, values are read in the same pass as the keys and decoded
+    /// lazily as the returned [`Scan`] advances. See [`ScanOptions`] for
+    /// ranges, reverse order and resuming from a cursor.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wit_kv::kv::{KvStore, ScanOptions};
+    ///
+    /// let store = KvStore::open(".wit-kv")?;
+    /// let options = ScanOptions { limit: Some(100), ..ScanOptions::default() };
+    /// let mut last = None;
+    /// for entry in store.scan("points", &options)?.wave() {
+    ///     let (key, value) = entry?;
+    ///     println!("{}: {}", key, value);
+    ///     last = Some(key);
+    /// }
+    ///
+    /// // Next page
+    /// let options = ScanOptions { after: last, ..options };
+    /// ```
+    pub fn scan(&self, keyspace: &str, options: &ScanOptions) -> Result<Scan<'_>, KvError> {
+        debug!(
+            keyspace = keyspace,
+            prefix = options.prefix.as_deref(),
+            start = options.start.as_deref(),
+            end = options.end.as_deref(),
+            after = options.after.as_deref(),
+            "scanning keyspace"
+        );
 
-        // Try to find the interface and package
-        match type_def.owner {
-            wit_parser::TypeOwner::Interface(iface_id) => {
-                let Some(iface) = resolve.interfaces.get(iface_id) else {
-                    return Ok(type_name.to_string());
-                };
-                if let Some(pkg_id) = iface.package
-                    && let Some(pkg) = resolve.packages.get(pkg_id)
-                {
-                    let iface_name = iface.name.as_deref().unwrap_or("types");
-                    return Ok(format!(
-                        "{}:{}/{}#{}",
-                        pkg.name.namespace, pkg.name.name, iface_name, type_name
-                    ));
-                }
-            }
-            wit_parser::TypeOwner::World(world_id) => {
-                let Some(world) = resolve.worlds.get(world_id) else {
-                    return Ok(type_name.to_string());
-                };
-                if let Some(pkg_id) = world.package
-                    && let Some(pkg) = resolve.packages.get(pkg_id)
-                {
-                    return Ok(format!(
-                        "{}:{}#{}",
-                        pkg.name.namespace, pkg.name.name, type_name
-                    ));
-                }
-            }
-            wit_parser::TypeOwner::None => {}
-        }
+        let ty = self.keyspace_type(keyspace)?;
+        let data = self.data_keyspace(keyspace)?;
 
-        // Fallback to just the type name
-        Ok(type_name.to_string())
+        Ok(Scan::new(self, keyspace, ty, data, options))
     }
 
-    fn parse_stored_type(
+    /// Iterate over the `(key, value)` pairs of a keyspace whose values match
+    /// a predicate, such as `score > 100 and tier is gold`.
+    ///
+    /// A predicate combines field tests with `and`, `or`, `not` and
+    /// parentheses. Fields are dot-separated paths into the record type (or
+    /// `.` for the whole value), and values are WAVE literals of the field's
+    /// type:
+    ///
+    /// - `path == value`, `!=`, and for numbers, `char` and `string` fields
+    ///   also `<`, `<=`, `>`, `>=`
+    /// - `path is case` for `variant`, `enum`, `result` (`ok`, `err`) and
+    ///   `option` (`some`, `none`) fields
+    /// - `path has flag` for `flags` fields
+    /// - `path contains value` for `list` fields
+    ///
+    /// Other tests look inside `option` fields, and a test that reaches a
+    /// `none` is false.
+    ///
+    /// The predicate is parsed and checked against the keyspace type before
+    /// anything is read, then evaluated on every value in the range selected
+    /// by `options`; `options.limit` counts matching values only.
+    ///
+    /// Returns [`KvError::InvalidQuery`] if the predicate is malformed or does
+    /// not fit the keyspace type.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wit_kv::kv::{KvStore, ScanOptions};
+    ///
+    /// let store = KvStore::open(".wit-kv")?;
+    /// let query = "address.city == \"Paris\" and not status is banned";
+    /// for entry in store.query("users", query, &ScanOptions::default())?.wave() {
+    ///     let (key, value) = entry?;
+    ///     println!("{}: {}", key, value);
+    /// }
+    /// ```
+    pub fn query(
         &self,
-        metadata: &KeyspaceMetadata,
-    ) -> Result<(Resolve, TypeId, wasm_wave::value::Type), KvError> {
-        crate::load_wit_type_from_string(&metadata.wit_definition, Some(&metadata.type_name))
-            .map_err(|e| KvError::WaveParse(e.to_string()))
+        keyspace: &str,
+        predicate: &str,
+        options: &ScanOptions,
+    ) -> Result<Scan<'_>, KvError> {
+        debug!(
+            keyspace = keyspace,
+            predicate = predicate,
+            prefix = options.prefix.as_deref(),
+            start = options.start.as_deref(),
+            end = options.end.as_deref(),
+            after = options.after.as_deref(),
+            "querying keyspace"
+        );
+
+        let ty = self.keyspace_type(keyspace)?;
+        let predicate = Predicate::parse(predicate, &ty.wave_type)?;
+        let data = self.data_keyspace(keyspace)?;
+
+        Ok(Scan::new(self, keyspace, ty, data, options).with_filter(predicate))
     }
 
-    fn store_with_memory(
-        &self,
-        keyspace: &Keyspace,
-        key: &str,
-        buffer: &[u8],
-        memory: &[u8],
-    ) -> Result<(), KvError> {
-        keyspace.insert(key, buffer)?;
-        if !memory.is_empty() {
-            let memory_key = format!("{}.memory", key);
-            keyspace.insert(&memory_key, memory)?;
+    /// Create a secondary index on a field of a keyspace's record type.
+    ///
+    /// `field_path` names a scalar, `string`, `char` or `enum` field, such as
+    /// `email`, or a field of a nested record, such as `address.city`.
+    /// Fields and records along the path may be `option`s; values where the
+    /// field is `none` are not indexed. Existing values are indexed before
+    /// this returns, and from then on every write updates the index in the
+    /// same atomic batch.
+    ///
+    /// Returns [`KvError::InvalidIndex`] if the path does not name an
+    /// indexable field, and [`KvError::IndexExists`] if it is already indexed.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wit_kv::KvStore;
+    ///
+    /// let store = KvStore::open(".wit-kv")?;
+    /// store.create_index("users", "email")?;
+    /// let keys = store.lookup_by_index("users", "email", "\"alice@example.com\"")?;
+    /// ```
+    pub fn create_index(&self, keyspace: &str, field_path: &str) -> Result<(), KvError> {
+        debug!(
+            keyspace = keyspace,
+            field_path = field_path,
+            "creating index"
+        );
+
+        let ty = self.keyspace_type(keyspace)?;
+        let index = FieldIndex::new(field_path, &ty.wave_type)
+            .map_err(|reason| invalid_index(field_path, reason))?;
+        let definition_key = index_key(keyspace, field_path);
+
+        // Hold the write lock so no value is written between indexing the
+        // existing values and registering the index
+        let _revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.meta.get(&definition_key)?.is_some() {
+            return Err(KvError::IndexExists {
+                keyspace: keyspace.to_string(),
+                field: field_path.to_string(),
+            });
+        }
+
+        let indexes = KeyspaceIndexes {
+            data: self.index_keyspace(keyspace)?,
+            indexes: vec![index],
+        };
+        let mut entries = BTreeSet::new();
+        for guard in self.data_keyspace(keyspace)?.iter() {
+            let (key_bytes, record) = guard.into_inner()?;
+            let key = String::from_utf8_lossy(&key_bytes);
+            let stored = StoredValue::from_bytes(&record)?;
+            if let Some(value) = self.indexed_value(keyspace, &ty, stored) {
+                entries.extend(indexes.entries(&key, &value));
+            }
+        }
+
+        let mut batch = self.db.batch();
+        indexes.replace(&mut batch, &entries)?;
+        batch.insert(&self.meta, definition_key, field_path.as_bytes());
+        batch.commit()?;
+        self.db.persist(PersistMode::SyncAll)?;
+
+        info!(
+            keyspace = keyspace,
+            field_path = field_path,
+            entries = entries.len(),
+            "index created"
+        );
+        Ok(())
+    }
+
+    /// Drop a secondary index and all of its entries.
+    pub fn drop_index(&self, keyspace: &str, field_path: &str) -> Result<(), KvError> {
+        debug!(
+            keyspace = keyspace,
+            field_path = field_path,
+            "dropping index"
+        );
+
+        let definition_key = index_key(keyspace, field_path);
+        let _revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.meta.get(&definition_key)?.is_none() {
+            return Err(KvError::IndexNotFound {
+                keyspace: keyspace.to_string(),
+                field: field_path.to_string(),
+            });
+        }
+
+        let mut batch = self.db.batch();
+        clear_entries(&self.index_keyspace(keyspace)?, &mut batch, field_path)?;
+        batch.remove(&self.meta, definition_key);
+        batch.commit()?;
+        self.db.persist(PersistMode::SyncAll)?;
+
+        info!(
+            keyspace = keyspace,
+            field_path = field_path,
+            "index dropped"
+        );
+        Ok(())
+    }
+
+    /// List the field paths indexed in a keyspace.
+    pub fn list_indexes(&self, keyspace: &str) -> Result<Vec<String>, KvError> {
+        let _ = self.keyspace_type(keyspace)?;
+
+        let prefix = index_key(keyspace, "");
+        let mut fields = Vec::new();
+        for kv in self.meta.prefix(&prefix) {
+            let key_bytes = kv.key()?;
+            let field = key_bytes.get(prefix.len()..).unwrap_or_default();
+            fields.push(String::from_utf8_lossy(field).into_owned());
+        }
+        Ok(fields)
+    }
+
+    /// Find the keys whose indexed field equals `wave_value`, in key order.
+    ///
+    /// The value is WAVE text of the field's type (without an enclosing
+    /// `option`), e.g. `"alice@example.com"` for a `string` field.
+    pub fn lookup_by_index(
+        &self,
+        keyspace: &str,
+        field_path: &str,
+        wave_value: &str,
+    ) -> Result<Vec<String>, KvError> {
+        debug!(
+            keyspace = keyspace,
+            field_path = field_path,
+            "looking up by index"
+        );
+
+        let ty = self.keyspace_type(keyspace)?;
+        if self.meta.get(index_key(keyspace, field_path))?.is_none() {
+            return Err(KvError::IndexNotFound {
+                keyspace: keyspace.to_string(),
+                field: field_path.to_string(),
+            });
+        }
+        let index = FieldIndex::new(field_path, &ty.wave_type)
+            .map_err(|reason| invalid_index(field_path, reason))?;
+
+        let field: Value = wasm_wave::from_str(index.field_type(), wave_value)
+            .map_err(|e| KvError::WaveParse(e.to_string()))?;
+        let prefix = index.value_prefix(&field);
+
+        // Index entries of expired values stay until they are purged
+        let data = self.data_keyspace(keyspace)?;
+        let now = now_millis();
+        let mut keys = Vec::new();
+        for kv in self.index_keyspace(keyspace)?.prefix(&prefix) {
+            let key = index.primary_key(&kv.key()?, &prefix);
+            let expired = data.get(&key)?.is_some_and(|record| {
+                StoredValue::peek_expires_at(&record).is_some_and(|at| at <= now)
+            });
+            if !expired {
+                keys.push(key);
+            }
+        }
+
+        debug!(
+            keyspace = keyspace,
+            field_path = field_path,
+            count = keys.len(),
+            "index lookup done"
+        );
+        Ok(keys)
+    }
+
+    /// Set or clear the default time-to-live of a keyspace.
+    ///
+    /// Values written afterwards without an explicit time-to-live expire
+    /// `ttl` after they are written. Values already stored keep their expiry.
+    /// The default is kept when the keyspace type is replaced or migrated.
+    pub fn set_default_ttl(
+        &self,
+        keyspace: &str,
+        ttl: Option<Duration>,
+    ) -> Result<KeyspaceMetadata, KvError> {
+        debug!(
+            keyspace = keyspace,
+            ttl_ms = ttl.map(|ttl| ttl.as_millis()),
+            "setting default ttl"
+        );
+
+        // The record is rewritten from the type as it is under the lock, so a
+        // concurrent type change or deletion is never undone
+        let _revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.invalidate_type(keyspace);
+        let current = self.keyspace_type(keyspace)?;
+        let mut metadata = current.metadata.clone();
+        metadata.default_ttl = ttl;
+
+        let mut batch = self.db.batch();
+        self.write_type_metadata(&mut batch, keyspace, Some(&current.metadata), &metadata)?;
+        batch.commit()?;
+        self.invalidate_type(keyspace);
+        self.db.persist(PersistMode::SyncAll)?;
+
+        info!(
+            keyspace = keyspace,
+            ttl_ms = ttl.map(|ttl| ttl.as_millis()),
+            "default ttl set"
+        );
+        Ok(metadata)
+    }
+
+    /// Remove every expired value, and its index entries, from the store.
+    ///
+    /// Expired values are already hidden from reads; purging reclaims their
+    /// space. Purging finds expired values without reading the others, so it
+    /// is cheap to call periodically. Returns the number of values removed.
+    pub fn purge_expired(&self) -> Result<usize, KvError> {
+        let now = now_millis();
+        let mut purged = 0;
+        for metadata in self.list_types()? {
+            purged += self.purge_keyspace(&metadata.name, now)?;
+        }
+
+        if purged > 0 {
+            info!(count = purged, "expired values purged");
+        } else {
+            trace!("no expired values");
+        }
+        Ok(purged)
+    }
+
+    /// Watch the changes committed to a keyspace.
+    ///
+    /// The watch starts at change `from_seq` if given, replaying the changes
+    /// from there that are still in the change log, or with the next change
+    /// otherwise. Pass [`Watch::next_seq`] of an earlier watch, or the last
+    /// sequence number seen plus one, to resume without missing changes.
+    ///
+    /// Returns [`KvError::ChangesTrimmed`] if changes from `from_seq` on are
+    /// no longer kept, and [`KvError::ChangesAhead`] if `from_seq` is past
+    /// the next change, since changes before it would be skipped silently.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use std::time::Duration;
+    ///
+    /// let mut watch = store.watch("points", None)?;
+    /// while let Some(event) = watch.next_timeout(Duration::from_secs(1)) {
+    ///     let event = event?;
+    ///     println!("{} {} {}", event.seq, event.op.as_str(), event.key);
+    /// }
+    /// ```
+    pub fn watch(&self, keyspace: &str, from_seq: Option<u64>) -> Result<Watch<'_>, KvError> {
+        self.keyspace_type(keyspace)?;
+        let log = self.changes_keyspace(keyspace)?;
+
+        let next_seq = match from_seq {
+            Some(from_seq) => {
+                // Sequence numbers start at 1: a log starting later was trimmed
+                if let Some(oldest) = changes::first_seq(&log)?
+                    && oldest > 1
+                    && from_seq < oldest
+                {
+                    return Err(KvError::ChangesTrimmed {
+                        keyspace: keyspace.to_string(),
+                        oldest,
+                    });
+                }
+                let next = changes::last_seq(&log)? + 1;
+                if from_seq > next {
+                    return Err(KvError::ChangesAhead {
+                        keyspace: keyspace.to_string(),
+                        from: from_seq,
+                        next,
+                    });
+                }
+                from_seq.max(1)
+            }
+            None => changes::last_seq(&log)? + 1,
+        };
+
+        debug!(keyspace = keyspace, next_seq = next_seq, "watch started");
+        Ok(Watch::new(self, keyspace, log, next_seq))
+    }
+
+    /// Write a backup archive of the whole store to `path`.
+    ///
+    /// The archive holds every keyspace type with all its recorded versions,
+    /// the index definitions and every value as stored, read from a
+    /// consistent snapshot: writes committed while the backup runs are not
+    /// included, and are not blocked. The archive is written next to `path`
+    /// and renamed into place once complete, replacing any existing file.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let info = store.snapshot_to("backups/store.wkvb")?;
+    /// println!("{} values at revision {}", info.values, info.revision);
+    /// ```
+    pub fn snapshot_to(&self, path: impl AsRef<Path>) -> Result<BackupInfo, KvError> {
+        let path = path.as_ref();
+        debug!(path = %path.display(), "writing backup");
+
+        let partial = partial_path(path);
+        let result = File::create(&partial)
+            .map_err(KvError::from)
+            .and_then(|file| self.write_archive(file))
+            .and_then(|info| {
+                std::fs::rename(&partial, path)?;
+                Ok(info)
+            });
+        if result.is_err() {
+            let _ = std::fs::remove_file(&partial);
+        }
+        let info = result?;
+
+        info!(
+            path = %path.display(),
+            revision = info.revision,
+            keyspaces = info.keyspaces,
+            values = info.values,
+            "backup written"
+        );
+        Ok(info)
+    }
+
+    /// Create a new store at `path` from a backup archive written by
+    /// [`snapshot_to`](Self::snapshot_to).
+    ///
+    /// Every record is checked against its checksum, and a truncated archive
+    /// is rejected. Index entries are rebuilt from the restored values, and
+    /// change logs start empty. Fails if `path` already exists; if restoring
+    /// fails, the partially restored store is removed.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let (store, info) = KvStore::restore("backups/store.wkvb", ".wit-kv-restored")?;
+    /// ```
+    pub fn restore(
+        archive: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+    ) -> Result<(Self, BackupInfo), KvError> {
+        let (archive, path) = (archive.as_ref(), path.as_ref());
+        debug!(archive = %archive.display(), path = %path.display(), "restoring backup");
+
+        if path.exists() {
+            return Err(KvError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Cannot restore into existing path {}", path.display()),
+            )));
+        }
+        let reader = ArchiveReader::new(BufReader::new(File::open(archive)?))?;
+
+        let store = Self::init(path)?;
+        let info = match store.restore_records(reader) {
+            Ok(info) => info,
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "restore failed");
+                drop(store);
+                let _ = std::fs::remove_dir_all(path);
+                return Err(e);
+            }
+        };
+
+        info!(
+            path = %path.display(),
+            revision = info.revision,
+            keyspaces = info.keyspaces,
+            values = info.values,
+            "backup restored"
+        );
+        Ok((store, info))
+    }
+
+    /// Import `key<TAB>wave-value` lines or binary frames into a keyspace
+    /// (see [`TransferFormat`]).
+    ///
+    /// Every value is validated against the keyspace type; invalid entries
+    /// are skipped and reported in [`ImportStats::errors`]. Values are
+    /// committed in batches of `batch_size`, each atomically, so an I/O error
+    /// or broken framing stops the import with the earlier batches written.
+    /// Imported values get the keyspace's default time-to-live.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use std::io::BufReader;
+    /// use wit_kv::kv::{DEFAULT_IMPORT_BATCH_SIZE, TransferFormat};
+    ///
+    /// let file = BufReader::new(File::open("users.tsv")?);
+    /// let stats = store.import("users", TransferFormat::Lines, file, DEFAULT_IMPORT_BATCH_SIZE)?;
+    /// for error in &stats.errors {
+    ///     eprintln!("line {}: {}", error.entry, error.message);
+    /// }
+    /// ```
+    pub fn import(
+        &self,
+        keyspace: &str,
+        format: TransferFormat,
+        reader: impl BufRead,
+        batch_size: usize,
+    ) -> Result<ImportStats, KvError> {
+        debug!(keyspace = keyspace, format = ?format, batch_size = batch_size, "importing");
+        transfer::import(self, keyspace, format, reader, batch_size)
+    }
+
+    /// Export every live value of a keyspace as `key<TAB>wave-value` lines or
+    /// binary frames (see [`TransferFormat`]), in key order.
+    ///
+    /// Values are read from a consistent snapshot and brought to the current
+    /// type version; expiry times are not exported. Returns the number of
+    /// values written.
+    pub fn export(
+        &self,
+        keyspace: &str,
+        format: TransferFormat,
+        writer: impl Write,
+    ) -> Result<usize, KvError> {
+        debug!(keyspace = keyspace, format = ?format, "exporting");
+        transfer::export(self, keyspace, format, writer)
+    }
+
+    /// Store the record of a background job under `id`, replacing any earlier one.
+    ///
+    /// Job records are opaque to the store: they are kept in the `_jobs`
+    /// keyspace, apart from the typed keyspaces, and are not part of backups.
+    /// Every write is persisted before returning, so records survive a crash.
+    pub fn put_job_record(&self, id: &str, record: &[u8]) -> Result<(), KvError> {
+        trace!(id = id, size = record.len(), "writing job record");
+        self.jobs_keyspace()?.insert(id, record)?;
+        self.db.persist(PersistMode::SyncAll)?;
+        Ok(())
+    }
+
+    /// The record of a background job, if there is one.
+    pub fn get_job_record(&self, id: &str) -> Result<Option<Vec<u8>>, KvError> {
+        Ok(self.jobs_keyspace()?.get(id)?.map(|record| record.to_vec()))
+    }
+
+    /// Every job record as `(id, record)` pairs, in id order.
+    pub fn job_records(&self) -> Result<Vec<(String, Vec<u8>)>, KvError> {
+        self.jobs_keyspace()?
+            .iter()
+            .map(|kv| {
+                let (id, record) = kv.into_inner()?;
+                Ok((String::from_utf8_lossy(&id).into_owned(), record.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Remove the record of a background job.
+    pub fn delete_job_record(&self, id: &str) -> Result<(), KvError> {
+        trace!(id = id, "deleting job record");
+        self.jobs_keyspace()?.remove(id)?;
+        self.db.persist(PersistMode::SyncAll)?;
+        Ok(())
+    }
+
+    /// Register a WebAssembly component, so map and reduce operations can
+    /// run it by id.
+    ///
+    /// The registration and the component bytes are kept in the `_modules`
+    /// keyspace and written in one batch. Registering an id that is already
+    /// taken fails with [`KvError::ModuleExists`] unless `replace` is set.
+    /// The component is not validated here.
+    pub fn register_module(
+        &self,
+        registration: &ModuleRegistration,
+        component: &[u8],
+        replace: bool,
+    ) -> Result<(), KvError> {
+        ModuleRegistration::validate_id(&registration.id)?;
+        debug!(
+            id = %registration.id,
+            kind = registration.kind.as_str(),
+            size = component.len(),
+            "registering module"
+        );
+
+        let modules = self.modules_keyspace()?;
+        let registration_key = format!("{}{}", MODULE_REGISTRATION_PREFIX, registration.id);
+        // Held so two registrations of the same id cannot both see it free
+        let _revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !replace && modules.contains_key(&registration_key)? {
+            return Err(KvError::ModuleExists(registration.id.clone()));
+        }
+
+        let mut batch = self.db.batch();
+        batch.insert(&modules, registration_key, registration.to_bytes()?);
+        batch.insert(
+            &modules,
+            format!("{}{}", MODULE_COMPONENT_PREFIX, registration.id),
+            component,
+        );
+        batch.commit()?;
+        self.db.persist(PersistMode::SyncAll)?;
+
+        info!(id = %registration.id, "module registered");
+        Ok(())
+    }
+
+    /// The registration of a module, if there is one.
+    pub fn get_module(&self, id: &str) -> Result<Option<ModuleRegistration>, KvError> {
+        self.modules_keyspace()?
+            .get(format!("{}{}", MODULE_REGISTRATION_PREFIX, id))?
+            .map(|bytes| ModuleRegistration::from_bytes(&bytes))
+            .transpose()
+    }
+
+    /// The component bytes of a registered module, if there is one.
+    pub fn get_module_component(&self, id: &str) -> Result<Option<Vec<u8>>, KvError> {
+        Ok(self
+            .modules_keyspace()?
+            .get(format!("{}{}", MODULE_COMPONENT_PREFIX, id))?
+            .map(|bytes| bytes.to_vec()))
+    }
+
+    /// Every module registration, in id order.
+    pub fn list_modules(&self) -> Result<Vec<ModuleRegistration>, KvError> {
+        self.modules_keyspace()?
+            .prefix(MODULE_REGISTRATION_PREFIX)
+            .map(|kv| ModuleRegistration::from_bytes(&kv.value()?))
+            .collect()
+    }
+
+    /// Remove a registered module and its component bytes.
+    ///
+    /// Compiled components of the module in the
+    /// [`module_cache_dir`](Self::module_cache_dir) are not removed.
+    pub fn delete_module(&self, id: &str) -> Result<(), KvError> {
+        debug!(id = id, "deleting module");
+        let modules = self.modules_keyspace()?;
+        let registration_key = format!("{}{}", MODULE_REGISTRATION_PREFIX, id);
+        if !modules.contains_key(&registration_key)? {
+            return Err(KvError::ModuleNotFound(id.to_string()));
+        }
+
+        let mut batch = self.db.batch();
+        batch.remove(&modules, registration_key);
+        batch.remove(&modules, format!("{}{}", MODULE_COMPONENT_PREFIX, id));
+        batch.commit()?;
+        self.db.persist(PersistMode::SyncAll)?;
+
+        info!(id = id, "module deleted");
+        Ok(())
+    }
+
+    /// Directory within the store for compiled module components.
+    ///
+    /// The store does not create it; it is meant for a `ComponentCache`
+    /// shared by everything that runs the store's modules.
+    pub fn module_cache_dir(&self) -> PathBuf {
+        self.path.join(MODULE_CACHE_DIR)
+    }
+
+    // Helper methods
+
+    fn build_qualified_name(
+        &self,
+        resolve: &Resolve,
+        type_id: TypeId,
+        type_name: &str,
+    ) -> Result<String, KvError> {
+        let type_def = resolve
+            .types
+            .get(type_id)
+            .ok_or_else(|| KvError::TypeNotFound(format!("Type id {:?} not found", type_id)))?;
+
+        // Try to find the interface and package
+        match type_def.owner {
+            wit_parser::TypeOwner::Interface(iface_id) => {
+                let Some(iface) = resolve.interfaces.get(iface_id) else {
+                    return Ok(type_name.to_string());
+                };
+                if let Some(pkg_id) = iface.package
+                    && let Some(pkg) = resolve.packages.get(pkg_id)
+                {
+                    let iface_name = iface.name.as_deref().unwrap_or("types");
+                    return Ok(format!(
+                        "{}:{}/{}#{}",
+                        pkg.name.namespace, pkg.name.name, iface_name, type_name
+                    ));
+                }
+            }
+            wit_parser::TypeOwner::World(world_id) => {
+                let Some(world) = resolve.worlds.get(world_id) else {
+                    return Ok(type_name.to_string());
+                };
+                if let Some(pkg_id) = world.package
+                    && let Some(pkg) = resolve.packages.get(pkg_id)
+                {
+                    return Ok(format!(
+                        "{}:{}#{}",
+                        pkg.name.namespace, pkg.name.name, type_name
+                    ));
+                }
+            }
+            wit_parser::TypeOwner::None => {}
+        }
+
+        // Fallback to just the type name
+        Ok(type_name.to_string())
+    }
+
+    /// Parse a WIT file and build (unversioned) metadata for `keyspace`.
+    fn load_type_definition(
+        &self,
+        keyspace: &str,
+        wit_path: &Path,
+        type_name: Option<&str>,
+    ) -> Result<(Resolve, TypeId, KeyspaceMetadata), KvError> {
+        // Parse the WIT file
+        trace!(wit_path = %wit_path.display(), "parsing WIT file");
+        let mut resolve = Resolve::new();
+        resolve.push_path(wit_path)?;
+
+        // Find the type
+        let type_id = match type_name {
+            Some(tn) => find_type_by_name(&resolve, tn).ok_or_else(|| {
+                error!(type_name = tn, "type not found in WIT");
+                KvError::TypeNotFound(tn.to_string())
+            })?,
+            None => find_first_named_type(&resolve).ok_or_else(|| {
+                error!("no named type found in WIT");
+                KvError::TypeNotFound("No named type found".to_string())
+            })?,
+        };
+
+        let type_def = resolve
+            .types
+            .get(type_id)
+            .ok_or_else(|| KvError::TypeNotFound(format!("Type {:?} not found", type_id)))?;
+        let actual_type_name = type_def.name.clone().unwrap_or_default();
+
+        // Build qualified name from package info
+        let qualified_name = self.build_qualified_name(&resolve, type_id, &actual_type_name)?;
+        trace!(
+            type_name = %actual_type_name,
+            qualified_name = %qualified_name,
+            "resolved type"
+        );
+
+        // Read WIT file content
+        let wit_definition = std::fs::read_to_string(wit_path)?;
+
+        let metadata = KeyspaceMetadata::new(
+            keyspace.to_string(),
+            qualified_name,
+            wit_definition,
+            actual_type_name,
+        );
+        Ok((resolve, type_id, metadata))
+    }
+
+    /// Queue the metadata writes that register `metadata` in place of `previous`.
+    fn write_type_metadata(
+        &self,
+        batch: &mut OwnedWriteBatch,
+        keyspace: &str,
+        previous: Option<&KeyspaceMetadata>,
+        metadata: &KeyspaceMetadata,
+    ) -> Result<(), KvError> {
+        if let Some(previous) = previous {
+            // Drop the reverse lookup if the qualified name moved
+            if previous.qualified_name != metadata.qualified_name {
+                let old_qualified_key =
+                    format!("{}{}", META_QUALIFIED_PREFIX, previous.qualified_name);
+                batch.remove(&self.meta, old_qualified_key);
+            }
+
+            // Keyspaces registered before history was kept have no entry yet
+            let previous_key = history_key(keyspace, &previous.type_version);
+            if self.meta.get(&previous_key)?.is_none() {
+                batch.insert(&self.meta, previous_key, previous.to_bytes()?);
+            }
+        }
+
+        // Store metadata, keeping a copy per version for reading older values
+        let key = format!("{}{}", META_TYPES_PREFIX, keyspace);
+        let record = metadata.to_bytes()?;
+        batch.insert(&self.meta, key, record.clone());
+        batch.insert(
+            &self.meta,
+            history_key(keyspace, &metadata.type_version),
+            record,
+        );
+
+        // Store reverse lookup
+        let qualified_key = format!("{}{}", META_QUALIFIED_PREFIX, metadata.qualified_name);
+        batch.insert(&self.meta, qualified_key, keyspace.as_bytes());
+        Ok(())
+    }
+
+    /// Write every type, index definition and value to `file` as an archive,
+    /// reading from a single snapshot.
+    fn write_archive(&self, file: File) -> Result<BackupInfo, KvError> {
+        let snapshot = self.db.snapshot();
+        let revision = match snapshot.get(&self.meta, META_REVISION_KEY)? {
+            Some(bytes) => u64::from_le_bytes(
+                bytes
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| KvError::InvalidFormat("Invalid revision format".to_string()))?,
+            ),
+            None => 0,
+        };
+        let mut info = BackupInfo {
+            revision,
+            created_at: now_millis(),
+            keyspaces: 0,
+            values: 0,
+        };
+
+        let mut writer = ArchiveWriter::new(BufWriter::new(file))?;
+        writer.write(&Record::Header {
+            store_version: STORE_VERSION,
+            revision,
+            created_at: info.created_at,
+        })?;
+
+        let mut keyspaces = Vec::new();
+        for guard in snapshot.prefix(&self.meta, META_TYPES_PREFIX) {
+            let metadata = KeyspaceMetadata::from_bytes(&guard.into_inner()?.1)?;
+            keyspaces.push(metadata.name.clone());
+            writer.write(&Record::Type(metadata))?;
+        }
+        for guard in snapshot.prefix(&self.meta, META_HISTORY_PREFIX) {
+            let metadata = KeyspaceMetadata::from_bytes(&guard.into_inner()?.1)?;
+            writer.write(&Record::TypeVersion(metadata))?;
+        }
+        for keyspace in &keyspaces {
+            for guard in snapshot.prefix(&self.meta, index_key(keyspace, "")) {
+                let field_path = String::from_utf8_lossy(&guard.into_inner()?.1).into_owned();
+                writer.write(&Record::Index {
+                    keyspace: keyspace.clone(),
+                    field_path,
+                })?;
+            }
+        }
+
+        for keyspace in &keyspaces {
+            if !self
+                .db
+                .keyspace_exists(&format!("{}{}", DATA_PREFIX, keyspace))
+            {
+                continue;
+            }
+            let data = self.data_keyspace(keyspace)?;
+            for guard in snapshot.iter(&data) {
+                let (key, record) = guard.into_inner()?;
+                writer.write(&Record::Value {
+                    keyspace: keyspace.clone(),
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                    value: StoredValue::from_bytes(&record)?,
+                })?;
+                info.values += 1;
+            }
+        }
+        info.keyspaces = keyspaces.len();
+
+        let file = writer
+            .finish()?
+            .into_inner()
+            .map_err(|e| KvError::Io(e.into_error()))?;
+        file.sync_all()?;
+        Ok(info)
+    }
+
+    /// Write the records of an archive into this new store, then rebuild
+    /// its indexes.
+    fn restore_records(
+        &self,
+        mut reader: ArchiveReader<impl std::io::Read>,
+    ) -> Result<BackupInfo, KvError> {
+        let invalid =
+            |reason: &str| KvError::InvalidFormat(format!("Invalid backup archive: {}", reason));
+        let Some(Record::Header {
+            revision,
+            created_at,
+            ..
+        }) = reader.next_record()?
+        else {
+            return Err(invalid("missing header"));
+        };
+        let mut info = BackupInfo {
+            revision,
+            created_at,
+            keyspaces: 0,
+            values: 0,
+        };
+
+        let mut keyspaces: HashMap<String, Keyspace> = HashMap::new();
+        let mut indexes = Vec::new();
+        let mut batch = self.db.batch();
+        let mut pending = 0;
+        while let Some(record) = reader.next_record()? {
+            match record {
+                Record::Header { .. } | Record::End { .. } => {
+                    return Err(invalid("unexpected header or end record"));
+                }
+                Record::Type(metadata) => {
+                    let qualified_key =
+                        format!("{}{}", META_QUALIFIED_PREFIX, metadata.qualified_name);
+                    batch.insert(&self.meta, qualified_key, metadata.name.as_bytes());
+                    let key = format!("{}{}", META_TYPES_PREFIX, metadata.name);
+                    batch.insert(&self.meta, key, metadata.to_bytes()?);
+                    keyspaces.insert(metadata.name.clone(), self.data_keyspace(&metadata.name)?);
+                }
+                Record::TypeVersion(metadata) => {
+                    let key = history_key(&metadata.name, &metadata.type_version);
+                    batch.insert(&self.meta, key, metadata.to_bytes()?);
+                }
+                Record::Index {
+                    keyspace,
+                    field_path,
+                } => indexes.push((keyspace, field_path)),
+                Record::Value {
+                    keyspace,
+                    key,
+                    value,
+                } => {
+                    let data = keyspaces.get(&keyspace).ok_or_else(|| {
+                        invalid(&format!("value for unknown keyspace {}", keyspace))
+                    })?;
+                    if let Some(expires_at) = value.expires_at {
+                        let entry = expiry::entry_key(expires_at, &key);
+                        batch.insert(&self.expiry_keyspace(&keyspace)?, entry, []);
+                    }
+                    batch.insert(data, key.as_str(), value.to_bytes()?);
+                    info.values += 1;
+                    pending += 1;
+                }
+            }
+
+            if pending >= RESTORE_BATCH_SIZE {
+                std::mem::replace(&mut batch, self.db.batch()).commit()?;
+                pending = 0;
+            }
+        }
+        info.keyspaces = keyspaces.len();
+
+        batch.insert(&self.meta, META_REVISION_KEY, revision.to_le_bytes());
+        batch.commit()?;
+        *self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = revision;
+
+        for (keyspace, field_path) in indexes {
+            self.create_index(&keyspace, &field_path)?;
         }
-        Ok(())
+        self.db.persist(PersistMode::SyncAll)?;
+        Ok(info)
     }
 
-    fn load_metadata(&self, key: &str) -> Result<Option<KeyspaceMetadata>, KvError> {
-        let Some(buffer) = self.meta.get(key)? else {
+    /// Open the data keyspace backing `keyspace`.
+    pub(super) fn data_keyspace(&self, keyspace: &str) -> Result<Keyspace, KvError> {
+        let keyspace_name = format!("{}{}", DATA_PREFIX, keyspace);
+        Ok(self
+            .db
+            .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?)
+    }
+
+    /// Open the keyspace holding the entries of every index on `keyspace`.
+    fn index_keyspace(&self, keyspace: &str) -> Result<Keyspace, KvError> {
+        let keyspace_name = format!("{}{}", INDEX_PREFIX, keyspace);
+        Ok(self
+            .db
+            .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?)
+    }
+
+    /// Open the keyspace listing the expiring values of `keyspace`.
+    fn expiry_keyspace(&self, keyspace: &str) -> Result<Keyspace, KvError> {
+        let keyspace_name = format!("{}{}", EXPIRY_PREFIX, keyspace);
+        Ok(self
+            .db
+            .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?)
+    }
+
+    /// Open the change log of `keyspace`.
+    fn changes_keyspace(&self, keyspace: &str) -> Result<Keyspace, KvError> {
+        let keyspace_name = format!("{}{}", CHANGES_PREFIX, keyspace);
+        Ok(self
+            .db
+            .keyspace(&keyspace_name, KeyspaceCreateOptions::default)?)
+    }
+
+    /// Open the keyspace holding job records.
+    fn jobs_keyspace(&self) -> Result<Keyspace, KvError> {
+        Ok(self
+            .db
+            .keyspace(JOBS_KEYSPACE, KeyspaceCreateOptions::default)?)
+    }
+
+    /// Open the keyspace holding registered modules.
+    fn modules_keyspace(&self) -> Result<Keyspace, KvError> {
+        Ok(self
+            .db
+            .keyspace(MODULES_KEYSPACE, KeyspaceCreateOptions::default)?)
+    }
+
+    /// Remove the values of `keyspace` expired at `now`, along with their
+    /// index entries, in one atomic batch under a new store revision.
+    fn purge_keyspace(&self, keyspace: &str, now: u64) -> Result<usize, KvError> {
+        // Keyspaces that never had an expiring value have no expiry keyspace
+        if !self
+            .db
+            .keyspace_exists(&format!("{}{}", EXPIRY_PREFIX, keyspace))
+        {
+            return Ok(0);
+        }
+        let expiry = self.expiry_keyspace(keyspace)?;
+        let data = self.data_keyspace(keyspace)?;
+        let ty = self.keyspace_type(keyspace)?;
+
+        let mut revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let indexes = self.load_indexes(keyspace, &ty.wave_type)?;
+
+        let mut batch = self.db.batch();
+        let mut log = self.open_change_log(keyspace)?;
+        let next_revision = *revision + 1;
+        let mut purged = 0;
+        let mut stale = 0;
+        for guard in due_entries(&expiry, now) {
+            let entry = guard.key()?;
+            batch.remove(&expiry, entry.clone());
+
+            // Skip keys deleted or rewritten since the entry was added
+            let Some((expires_at, key)) = parse_entry(&entry) else {
+                continue;
+            };
+            let Some(stored) = self.load_stored_value(&data, &key)? else {
+                stale += 1;
+                continue;
+            };
+            if stored.expires_at != Some(expires_at) {
+                stale += 1;
+                continue;
+            }
+
+            if let Some(indexes) = &indexes {
+                let old = self.indexed_value(keyspace, &ty, stored);
+                indexes.update(&mut batch, &key, old.as_ref(), None);
+            }
+            batch.remove(&data, key.as_str());
+            log.append(&mut batch, &key, next_revision, None)?;
+            purged += 1;
+        }
+
+        if purged == 0 && stale == 0 {
+            return Ok(0);
+        }
+        if purged > 0 {
+            log.trim(&mut batch, self.change_retention)?;
+            batch.insert(&self.meta, META_REVISION_KEY, next_revision.to_le_bytes());
+        }
+        batch.commit()?;
+        if purged > 0 {
+            self.remember_change_log(keyspace, &log);
+            *revision = next_revision;
+            self.committed.notify_all();
+        }
+        self.sync_writes()?;
+
+        debug!(
+            keyspace = keyspace,
+            purged = purged,
+            stale = stale,
+            "keyspace purged"
+        );
+        Ok(purged)
+    }
+
+    /// Load the indexes of a keyspace, resolving their fields against
+    /// `wave_type`, or `None` if it has none.
+    fn load_indexes(
+        &self,
+        keyspace: &str,
+        wave_type: &WaveType,
+    ) -> Result<Option<KeyspaceIndexes>, KvError> {
+        let prefix = index_key(keyspace, "");
+        let mut indexes = Vec::new();
+        for kv in self.meta.prefix(&prefix) {
+            let key_bytes = kv.key()?;
+            let field_path =
+                String::from_utf8_lossy(key_bytes.get(prefix.len()..).unwrap_or_default());
+            let index = FieldIndex::new(&field_path, wave_type)
+                .map_err(|reason| invalid_index(&field_path, reason))?;
+            indexes.push(index);
+        }
+
+        if indexes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(KeyspaceIndexes {
+            data: self.index_keyspace(keyspace)?,
+            indexes,
+        }))
+    }
+
+    /// Decode a stored value for indexing, or `None` if it cannot be read as
+    /// the keyspace's current type (such values are not indexed).
+    fn indexed_value(
+        &self,
+        keyspace: &str,
+        ty: &KeyspaceType,
+        stored: StoredValue,
+    ) -> Option<Value> {
+        self.upgrade_if_needed(keyspace, ty, stored)
+            .and_then(|current| ty.to_value(&current))
+            .map_err(|e| {
+                warn!(keyspace = keyspace, error = %e, "unreadable value not indexed");
+                e
+            })
+            .ok()
+    }
+
+    /// Check preconditions, then atomically apply value writes under a new
+    /// store revision and persist them according to the configured durability.
+    ///
+    /// All single-key and batched value writes go through here. Returns the
+    /// revision assigned to the written values, or the current revision if
+    /// there was nothing to write.
+    pub(super) fn commit_writes(&self, writes: Vec<PendingWrite>) -> Result<u64, KvError> {
+        let mut revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if writes.is_empty() {
+            return Ok(*revision);
+        }
+
+        // Loaded under the write lock, so an index is either created with
+        // these writes already indexed or updated by them. Values were
+        // encoded with the type of their keyspace when they were queued,
+        // which a migration since then may have replaced.
+        let mut indexes: HashMap<&str, Option<KeyspaceIndexes>> = HashMap::new();
+        for write in &writes {
+            if !indexes.contains_key(write.keyspace.as_str()) {
+                let current = self.keyspace_type(&write.keyspace)?;
+                let queued = write.ty.metadata.type_version;
+                if current.metadata.type_version != queued {
+                    warn!(
+                        keyspace = %write.keyspace,
+                        queued = %queued,
+                        current = %current.metadata.type_version,
+                        "keyspace type changed since the batch was built"
+                    );
+                    return Err(KvError::TypeVersionMismatch {
+                        stored: queued,
+                        current: current.metadata.type_version,
+                    });
+                }
+                let loaded = self.load_indexes(&write.keyspace, &write.ty.wave_type)?;
+                indexes.insert(&write.keyspace, loaded);
+            }
+        }
+
+        let next_revision = *revision + 1;
+        let now = now_millis();
+        let mut batch = self.db.batch();
+        for write in &writes {
+            let keyspace_indexes = indexes
+                .get(write.keyspace.as_str())
+                .and_then(Option::as_ref);
+            if write.preconditions.is_empty() && keyspace_indexes.is_none() {
+                continue;
+            }
+
+            // An expired value counts as absent, but is still indexed until purged
+            let previous = self.load_stored_value(&write.data, &write.key)?;
+            let actual = previous
+                .as_ref()
+                .filter(|stored| !stored.is_expired_at(now))
+                .map(|stored| stored.revision);
+            for precondition in &write.preconditions {
+                precondition.check(&write.key, actual)?;
+            }
+
+            if let Some(keyspace_indexes) = keyspace_indexes {
+                let old = previous
+                    .and_then(|stored| self.indexed_value(&write.keyspace, &write.ty, stored));
+                let new = write
+                    .value
+                    .as_ref()
+                    .map(|stored| write.ty.to_value(stored))
+                    .transpose()?;
+                keyspace_indexes.update(&mut batch, &write.key, old.as_ref(), new.as_ref());
+            }
+        }
+
+        let mut logs: HashMap<String, ChangeLog> = HashMap::new();
+        for write in writes {
+            let log = match logs.entry(write.keyspace.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.open_change_log(&write.keyspace)?),
+            };
+            match write.value {
+                Some(mut stored) => {
+                    if let Some(expires_at) = stored.expires_at {
+                        let entry = expiry::entry_key(expires_at, &write.key);
+                        batch.insert(&self.expiry_keyspace(&write.keyspace)?, entry, []);
+                    }
+                    stored.revision = next_revision;
+                    log.append(&mut batch, &write.key, next_revision, Some(&stored))?;
+                    batch.insert(&write.data, write.key.as_str(), stored.to_bytes()?);
+                }
+                None => {
+                    log.append(&mut batch, &write.key, next_revision, None)?;
+                    batch.remove(&write.data, write.key.as_str());
+                }
+            }
+        }
+        for log in logs.values_mut() {
+            log.trim(&mut batch, self.change_retention)?;
+        }
+        batch.insert(&self.meta, META_REVISION_KEY, next_revision.to_le_bytes());
+        batch.commit()?;
+        for (keyspace, log) in &logs {
+            self.remember_change_log(keyspace, log);
+        }
+        *revision = next_revision;
+        self.committed.notify_all();
+
+        self.sync_writes()?;
+        Ok(next_revision)
+    }
+
+    /// Open the change log of `keyspace` for appending, starting where the
+    /// last commit left it. Call while holding `write_lock`.
+    fn open_change_log(&self, keyspace: &str) -> Result<ChangeLog, KvError> {
+        let known_first = self
+            .change_log_starts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(keyspace)
+            .copied();
+        ChangeLog::open(self.changes_keyspace(keyspace)?, known_first)
+    }
+
+    /// Remember where the change log of `keyspace` starts after a commit.
+    fn remember_change_log(&self, keyspace: &str, log: &ChangeLog) {
+        self.change_log_starts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(keyspace.to_string(), log.first_seq());
+    }
+
+    /// Last committed store revision.
+    pub(super) fn committed_revision(&self) -> u64 {
+        *self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Block until a revision after `seen` is committed, or until `deadline`
+    /// (forever if `None`). Returns `false` if the deadline passed first.
+    pub(super) fn wait_for_commit(&self, seen: u64, deadline: Option<Instant>) -> bool {
+        let mut revision = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        while *revision == seen {
+            revision = match deadline {
+                Some(deadline) => {
+                    let Some(timeout) = deadline.checked_duration_since(Instant::now()) else {
+                        return false;
+                    };
+                    self.committed
+                        .wait_timeout(revision, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .committed
+                    .wait(revision)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+        true
+    }
+
+    /// Persist value writes if the durability requires it on every write.
+    fn sync_writes(&self) -> Result<(), KvError> {
+        match self.durability {
+            Durability::SyncAll => self.flush(),
+            Durability::Interval(_) | Durability::Buffer => Ok(()),
+        }
+    }
+
+    /// The parsed current type of a keyspace.
+    pub(super) fn keyspace_type(&self, keyspace: &str) -> Result<Arc<KeyspaceType>, KvError> {
+        self.cached_type(&format!("{}{}", META_TYPES_PREFIX, keyspace))?
+            .ok_or_else(|| KvError::KeyspaceNotFound(keyspace.to_string()))
+    }
+
+    /// The parsed type stored under a metadata key, from the cache if possible.
+    fn cached_type(&self, key: &str) -> Result<Option<Arc<KeyspaceType>>, KvError> {
+        self.types.get_or_load(key, || self.load_metadata(key))
+    }
+
+    /// Drop the cached current and recorded types of a keyspace.
+    fn invalidate_type(&self, keyspace: &str) {
+        let type_key = format!("{}{}", META_TYPES_PREFIX, keyspace);
+        let history_prefix = format!("{}{}@", META_HISTORY_PREFIX, keyspace);
+        self.types
+            .invalidate(|key| key == type_key || key.starts_with(&history_prefix));
+        trace!(keyspace = keyspace, "cached types invalidated");
+    }
+
+    /// Render a value already at the keyspace's current type version as WAVE text.
+    pub(super) fn value_to_wave(stored: StoredValue, ty: &KeyspaceType) -> Result<String, KvError> {
+        let value = ty.to_value(&stored)?;
+        wasm_wave::to_string(&value).map_err(|e| KvError::WaveParse(e.to_string()))
+    }
+
+    /// Build a projection of `fields` and load the value to project.
+    fn load_projected(
+        &self,
+        keyspace: &str,
+        key: &str,
+        fields: &[impl AsRef<str>],
+    ) -> Result<Option<(Projection, StoredValue)>, KvError> {
+        debug!(keyspace = keyspace, key = key, "getting projected value");
+
+        let ty = self.keyspace_type(keyspace)?;
+        let projection = Projection::new(&ty, fields)?;
+        let data = self.data_keyspace(keyspace)?;
+
+        Ok(self
+            .load_current_value(&data, keyspace, key, &ty)?
+            .map(|stored| (projection, stored)))
+    }
+
+    /// Load a stored value and bring it to the keyspace's current type version,
+    /// writing the upgraded value back if enabled. Expired values are not returned.
+    fn load_current_value(
+        &self,
+        ks: &Keyspace,
+        keyspace: &str,
+        key: &str,
+        ty: &KeyspaceType,
+    ) -> Result<Option<StoredValue>, KvError> {
+        let Some(stored) = self.load_stored_value(ks, key)? else {
             return Ok(None);
         };
+        if stored.is_expired_at(now_millis()) {
+            trace!(keyspace = keyspace, key = key, "value expired");
+            return Ok(None);
+        }
+        self.current_value(ks, keyspace, key, ty, stored).map(Some)
+    }
 
-        let memory_key = format!("{}.memory", key);
-        let memory = self
-            .meta
-            .get(&memory_key)?
-            .map(|v| v.to_vec())
-            .unwrap_or_default();
+    /// Bring a value read from `ks` to the keyspace's current type version,
+    /// writing the upgraded value back if enabled.
+    pub(super) fn current_value(
+        &self,
+        ks: &Keyspace,
+        keyspace: &str,
+        key: &str,
+        ty: &KeyspaceType,
+        stored: StoredValue,
+    ) -> Result<StoredValue, KvError> {
+        if stored.type_version == ty.metadata.type_version {
+            return Ok(stored);
+        }
+
+        let upgraded = self.upgrade_if_needed(keyspace, ty, stored.clone())?;
+
+        if self.upgrade_on_read {
+            let _guard = self
+                .write_lock
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            // Only write back if nobody replaced the value in the meantime
+            if self.load_stored_value(ks, key)?.as_ref() == Some(&stored) {
+                ks.insert(key, upgraded.to_bytes()?)?;
+                self.sync_writes()?;
+                debug!(
+                    keyspace = keyspace,
+                    key = key,
+                    "upgraded value written back"
+                );
+            }
+        }
+
+        Ok(upgraded)
+    }
+
+    /// Bring a stored value to the keyspace's current type version.
+    ///
+    /// Values written with an older compatible version are lifted with the
+    /// type they were written with and projected onto the current type.
+    pub(super) fn upgrade_if_needed(
+        &self,
+        keyspace: &str,
+        ty: &KeyspaceType,
+        stored: StoredValue,
+    ) -> Result<StoredValue, KvError> {
+        let metadata = &ty.metadata;
+        if stored.type_version == metadata.type_version {
+            return Ok(stored);
+        }
+
+        let mismatch = || KvError::TypeVersionMismatch {
+            stored: stored.type_version,
+            current: metadata.type_version,
+        };
+
+        // Check type version compatibility
+        if !metadata.type_version.can_read_from(&stored.type_version) {
+            warn!(
+                keyspace = keyspace,
+                stored_version = %stored.type_version,
+                current_version = %metadata.type_version,
+                "type version mismatch"
+            );
+            return Err(mismatch());
+        }
+
+        let Some(written_with) = self.cached_type(&history_key(keyspace, &stored.type_version))?
+        else {
+            warn!(
+                keyspace = keyspace,
+                stored_version = %stored.type_version,
+                "no type definition recorded for stored version"
+            );
+            return Err(mismatch());
+        };
+
+        let upgraded = self.upgrade_value(&stored, &written_with, ty)?;
+        trace!(
+            keyspace = keyspace,
+            from = %stored.type_version,
+            to = %metadata.type_version,
+            "value upgraded"
+        );
+        Ok(upgraded)
+    }
+
+    /// Re-encode a value written with `from` as a value of type `to`.
+    fn upgrade_value(
+        &self,
+        stored: &StoredValue,
+        from: &KeyspaceType,
+        to: &KeyspaceType,
+    ) -> Result<StoredValue, KvError> {
+        let memory = LinearMemory::from_option(stored.memory.clone());
+        let (val, _) =
+            from.abi()
+                .lift_to_val(&stored.value, &Type::Id(from.type_id), None, &memory)?;
+        let old_value =
+            val_to_wave(&val, &from.wave_type).map_err(|e| KvError::WaveParse(e.to_string()))?;
+
+        let value =
+            project_value(&old_value, &from.wave_type, &to.wave_type).map_err(|reason| {
+                KvError::ProjectionFailed {
+                    from: from.metadata.type_version,
+                    to: to.metadata.type_version,
+                    reason,
+                }
+            })?;
+
+        let mut memory = LinearMemory::new();
+        let encoded = to.abi().lower_with_memory(
+            &value,
+            &Type::Id(to.type_id),
+            &to.wave_type,
+            &mut memory,
+        )?;
+        let mut upgraded = StoredValue::new(
+            to.metadata.type_version,
+            encoded,
+            if memory.is_empty() {
+                None
+            } else {
+                Some(memory.into_bytes())
+            },
+        );
+        // An upgrade changes the encoding, not the value: keep its revision and expiry
+        upgraded.revision = stored.revision;
+        upgraded.expires_at = stored.expires_at;
+        Ok(upgraded)
+    }
 
-        Ok(Some(KeyspaceMetadata::decode(&buffer, &memory)?))
+    fn load_metadata(&self, key: &str) -> Result<Option<KeyspaceMetadata>, KvError> {
+        let Some(record) = self.meta.get(key)? else {
+            return Ok(None);
+        };
+        Ok(Some(KeyspaceMetadata::from_bytes(&record)?))
     }
 
     fn load_stored_value(
@@ -650,16 +2862,542 @@ impl KvStore {
         keyspace: &Keyspace,
         key: &str,
     ) -> Result<Option<StoredValue>, KvError> {
-        let Some(buffer) = keyspace.get(key)? else {
+        let Some(record) = keyspace.get(key)? else {
             return Ok(None);
         };
+        Ok(Some(StoredValue::from_bytes(&record)?))
+    }
+}
+
+impl Drop for KvStore {
+    fn drop(&mut self) {
+        if self.durability != Durability::SyncAll && self.flush().is_err() {
+            warn!("failed to sync pending writes on close");
+        }
+    }
+}
+
+/// Where an archive is written before being renamed to `path`.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    PathBuf::from(partial)
+}
+
+/// Metadata key under which a specific version of a keyspace type is kept.
+fn history_key(keyspace: &str, version: &SemanticVersion) -> String {
+    format!("{}{}@{}", META_HISTORY_PREFIX, keyspace, version)
+}
+
+/// Metadata key registering the index on `field_path`.
+fn index_key(keyspace: &str, field_path: &str) -> String {
+    format!("{}{}@{}", META_INDEXES_PREFIX, keyspace, field_path)
+}
+
+/// Encode the progress of a store format upgrade (see [`META_UPGRADE_KEY`]).
+fn upgrade_progress(keyspace: &str, key: &[u8]) -> Vec<u8> {
+    let mut progress = keyspace.as_bytes().to_vec();
+    progress.push(0);
+    progress.extend_from_slice(key);
+    progress
+}
+
+fn parse_upgrade_progress(progress: &[u8]) -> Result<(String, Vec<u8>), KvError> {
+    let invalid = || KvError::InvalidFormat("Invalid upgrade progress".to_string());
+    let separator = progress.iter().position(|&b| b == 0).ok_or_else(invalid)?;
+    let (keyspace, key) = progress.split_at(separator);
+    let keyspace = std::str::from_utf8(keyspace).map_err(|_| invalid())?;
+    Ok((
+        keyspace.to_string(),
+        key.get(1..).unwrap_or_default().to_vec(),
+    ))
+}
+
+fn invalid_index(field_path: &str, reason: String) -> KvError {
+    KvError::InvalidIndex {
+        field: field_path.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const POINT_V1: &str =
+        "package test:points;\ninterface types { record point { x: s32, y: s32 } }\n";
+    const POINT_V2: &str = "package test:points;\ninterface types { record point { x: s32, y: s32, label: option<string> } }\n";
+
+    /// A new store in a temporary directory, with `wit` written to `point.wit`.
+    fn store(wit: &str) -> (TempDir, KvStore, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KvStore::init(dir.path().join("store")).unwrap();
+        let wit_path = write_wit(&dir, "point.wit", wit);
+        (dir, store, wit_path)
+    }
+
+    fn write_wit(dir: &TempDir, name: &str, wit: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, wit).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_keyspace_names_do_not_share_history() {
+        let (dir, store, v1) = store(POINT_V1);
+        let v2 = write_wit(&dir, "point-v2.wit", POINT_V2);
+
+        assert!(matches!(
+            store.set_type("a@x", &v1, Some("point"), false, false),
+            Err(KvError::InvalidKeyspaceName(_))
+        ));
+        assert!(matches!(
+            store.set_type("a/x", &v1, Some("point"), false, false),
+            Err(KvError::InvalidKeyspaceName(_))
+        ));
+
+        // `a` is a prefix of `ab`, and both are evolved
+        for keyspace in ["a", "ab"] {
+            store
+                .set_type(keyspace, &v1, Some("point"), false, false)
+                .unwrap();
+            store
+                .set_type(keyspace, &v2, Some("point"), true, false)
+                .unwrap();
+        }
+        assert_eq!(store.type_history("a").unwrap().len(), 2);
+
+        store.delete_type("a", false).unwrap();
+        assert!(store.type_history("a").unwrap().is_empty());
+        let history = store.type_history("ab").unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history.iter().all(|metadata| metadata.name == "ab"));
+    }
+
+    /// Rewrite the store at `path` in the version 1 layout, with every
+    /// value and type record split into a buffer entry and a `.memory` entry.
+    fn downgrade_to_v1(path: &Path) {
+        let db = fjall::Database::builder(path).open().unwrap();
+        for name in [META_KEYSPACE, "data_points"] {
+            let ks = db.keyspace(name, KeyspaceCreateOptions::default).unwrap();
+            let records: Vec<_> = ks.iter().map(|kv| kv.into_inner().unwrap()).collect();
+            for (key, record) in records {
+                let key = String::from_utf8(key.to_vec()).unwrap();
+                let (buffer, memory) = if name == META_KEYSPACE {
+                    if !key.starts_with(META_TYPES_PREFIX) && !key.starts_with(META_HISTORY_PREFIX)
+                    {
+                        continue;
+                    }
+                    KeyspaceMetadata::from_bytes(&record)
+                        .unwrap()
+                        .encode()
+                        .unwrap()
+                } else {
+                    StoredValue::from_bytes(&record).unwrap().encode().unwrap()
+                };
+                ks.insert(&key, buffer).unwrap();
+                if !memory.is_empty() {
+                    ks.insert(format!("{}{}", key, V1_MEMORY_SUFFIX), memory)
+                        .unwrap();
+                }
+            }
+            // A user key that merely looks like a memory entry
+            if name != META_KEYSPACE {
+                ks.insert("orphan.memory", b"kept").unwrap();
+            }
+        }
+        let meta = db
+            .keyspace(META_KEYSPACE, KeyspaceCreateOptions::default)
+            .unwrap();
+        meta.insert(META_CONFIG_KEY, 1u32.to_le_bytes()).unwrap();
+        db.persist(PersistMode::SyncAll).unwrap();
+    }
+
+    const LABELED: &str = "package test:points;\ninterface types { record point { x: s32, y: s32, label: string } }\n";
+
+    fn v1_store(dir: &TempDir) -> PathBuf {
+        let path = dir.path().join("v1");
+        let wit = write_wit(dir, "labeled.wit", LABELED);
+        let store = KvStore::init(&path).unwrap();
+        store
+            .set_type("points", &wit, Some("point"), false, false)
+            .unwrap();
+        for i in 0..5 {
+            let value = format!("{{x: {i}, y: {i}, label: \"point {i}\"}}");
+            store.set("points", &format!("p{i}"), &value).unwrap();
+        }
+        drop(store);
+        downgrade_to_v1(&path);
+        path
+    }
+
+    fn assert_upgraded(path: &Path) {
+        let store = KvStore::open(path).unwrap();
+        assert_eq!(
+            store.get("points", "p3").unwrap().as_deref(),
+            Some("{x: 3, y: 3, label: \"point 3\"}")
+        );
+        let keys = store
+            .list("points", Some("p"), None, None, None, None)
+            .unwrap()
+            .keys;
+        assert_eq!(keys, ["p0", "p1", "p2", "p3", "p4"]);
+        let ks = store.data_keyspace("points").unwrap();
+        assert_eq!(
+            ks.get("orphan.memory").unwrap().as_deref(),
+            Some(&b"kept"[..])
+        );
+        assert_eq!(
+            store.meta.get(META_CONFIG_KEY).unwrap().as_deref(),
+            Some(&STORE_VERSION.to_le_bytes()[..])
+        );
+        assert!(store.meta.get(META_UPGRADE_KEY).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_open_upgrades_v1_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = v1_store(&dir);
+        assert_upgraded(&path);
+    }
+
+    #[test]
+    fn test_interrupted_v1_upgrade_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = v1_store(&dir);
+
+        // Merge the types and the first two values, as an upgrade stopped
+        // after its first batch of values would have
+        {
+            let db = fjall::Database::builder(&path).open().unwrap();
+            let meta = db
+                .keyspace(META_KEYSPACE, KeyspaceCreateOptions::default)
+                .unwrap();
+            let ks = db
+                .keyspace("data_points", KeyspaceCreateOptions::default)
+                .unwrap();
+            let type_keys: Vec<String> = meta
+                .iter()
+                .map(|kv| String::from_utf8(kv.key().unwrap().to_vec()).unwrap())
+                .filter_map(|key| key.strip_suffix(V1_MEMORY_SUFFIX).map(str::to_string))
+                .collect();
+            let merged = type_keys
+                .iter()
+                .map(|key| (&meta, key.as_str()))
+                .chain([(&ks, "p0"), (&ks, "p1")]);
+            for (keyspace, key) in merged {
+                let memory_key = format!("{}{}", key, V1_MEMORY_SUFFIX);
+                let mut record = keyspace.get(key).unwrap().unwrap().to_vec();
+                record.extend_from_slice(&keyspace.get(&memory_key).unwrap().unwrap());
+                keyspace.insert(key, record).unwrap();
+                keyspace.remove(&memory_key).unwrap();
+            }
+            meta.insert(
+                META_UPGRADE_KEY,
+                upgrade_progress("data_points", b"p1.memory"),
+            )
+            .unwrap();
+            db.persist(PersistMode::SyncAll).unwrap();
+        }
+        assert_upgraded(&path);
+    }
+
+    #[test]
+    fn test_v1_upgrade_in_small_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = v1_store(&dir);
+        {
+            let db = fjall::Database::builder(&path).open().unwrap();
+            let meta = db
+                .keyspace(META_KEYSPACE, KeyspaceCreateOptions::default)
+                .unwrap();
+            KvStore::upgrade_from_v1(&db, &meta, 2).unwrap();
+        }
+        assert_upgraded(&path);
+    }
+
+    #[test]
+    fn test_batch_built_before_migration_is_refused() {
+        let (dir, store, v1) = store(POINT_V1);
+        store
+            .set_type("points", &v1, Some("point"), false, false)
+            .unwrap();
+        store.set("points", "p1", "{x: 1, y: 2}").unwrap();
+
+        let mut stale = store.batch();
+        stale.set("points", "p2", "{x: 3, y: 4}").unwrap();
+
+        // Same layout, new definition: every value is carried over as is
+        let documented = write_wit(
+            &dir,
+            "point-doc.wit",
+            &POINT_V1.replace("record point", "/// A point\n    record point"),
+        );
+        let metadata = store
+            .migrate("points", &documented, Some("point"), |_, value, _| {
+                Ok::<_, KvError>(value.clone())
+            })
+            .unwrap();
+
+        assert!(matches!(
+            stale.commit(),
+            Err(KvError::TypeVersionMismatch { current, .. }) if current == metadata.type_version
+        ));
+        assert_eq!(store.get("points", "p2").unwrap(), None);
+
+        let mut fresh = store.batch();
+        fresh.set("points", "p2", "{x: 3, y: 4}").unwrap();
+        fresh.commit().unwrap();
+        assert_eq!(
+            store.get("points", "p2").unwrap().as_deref(),
+            Some("{x: 3, y: 4}")
+        );
+    }
+
+    #[test]
+    fn test_conditional_writes() {
+        let (_dir, store, v1) = store(POINT_V1);
+        store
+            .set_type("points", &v1, Some("point"), false, false)
+            .unwrap();
+
+        let first = store.set_if_absent("points", "p1", "{x: 1, y: 2}").unwrap();
+        assert_eq!(
+            store.get_with_revision("points", "p1").unwrap(),
+            Some(("{x: 1, y: 2}".to_string(), first))
+        );
+        assert!(matches!(
+            store.set_if_absent("points", "p1", "{x: 0, y: 0}"),
+            Err(KvError::RevisionConflict { expected: None, actual: Some(actual), .. }) if actual == first
+        ));
+
+        let second = store.set_if("points", "p1", "{x: 3, y: 4}", first).unwrap();
+        assert!(second > first);
+        assert!(matches!(
+            store.set_if("points", "p1", "{x: 0, y: 0}", first),
+            Err(KvError::RevisionConflict { expected: Some(expected), actual: Some(actual), .. })
+                if expected == first && actual == second
+        ));
+        assert!(matches!(
+            store.delete_if("points", "p1", first),
+            Err(KvError::RevisionConflict { .. })
+        ));
+        assert_eq!(
+            store.get("points", "p1").unwrap().as_deref(),
+            Some("{x: 3, y: 4}")
+        );
+
+        let mut batch = store.batch();
+        batch.set_if_exists("points", "p2", "{x: 0, y: 0}").unwrap();
+        assert!(matches!(batch.commit(), Err(KvError::ValueMissing { key }) if key == "p2"));
+        assert_eq!(store.get("points", "p2").unwrap(), None);
+
+        let mut batch = store.batch();
+        batch.set_if_exists("points", "p1", "{x: 5, y: 6}").unwrap();
+        let third = batch.commit().unwrap();
+        assert!(third > second);
+
+        store.delete_if("points", "p1", third).unwrap();
+        let mut batch = store.batch();
+        batch.delete_if_exists("points", "p1").unwrap();
+        assert!(matches!(batch.commit(), Err(KvError::ValueMissing { .. })));
+    }
+
+    #[test]
+    fn test_set_raw_validates_against_type() {
+        let (_dir, store, labeled) = store(LABELED);
+        store
+            .set_type("points", &labeled, Some("point"), false, false)
+            .unwrap();
+
+        // x, y, then the label's pointer and length into memory
+        let encode = |len: u32, memory: &[u8]| BinaryExport {
+            buffer: [
+                1i32.to_le_bytes(),
+                2i32.to_le_bytes(),
+                0u32.to_le_bytes(),
+                len.to_le_bytes(),
+            ]
+            .concat(),
+            memory: Some(memory.to_vec()),
+        };
+
+        store.set_raw("points", "p1", &encode(2, b"hi")).unwrap();
+        assert_eq!(
+            store.get("points", "p1").unwrap().as_deref(),
+            Some("{x: 1, y: 2, label: \"hi\"}")
+        );
+
+        let truncated = BinaryExport {
+            buffer: vec![1, 0, 0],
+            memory: None,
+        };
+        for (key, value) in [
+            ("short", truncated),
+            ("out-of-bounds", encode(10, b"hi")),
+            ("not-utf8", encode(2, &[0xff, 0xfe])),
+        ] {
+            assert!(
+                matches!(
+                    store.set_raw("points", key, &value),
+                    Err(KvError::InvalidValue { .. })
+                ),
+                "{key} was accepted"
+            );
+            assert_eq!(store.get("points", key).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_indexes_follow_writes() {
+        let (_dir, store, v1) = store(POINT_V1);
+        // `a` is a prefix of `ab`, and both index the same field
+        for keyspace in ["a", "ab"] {
+            store
+                .set_type(keyspace, &v1, Some("point"), false, false)
+                .unwrap();
+            store.create_index(keyspace, "x").unwrap();
+        }
+        assert_eq!(store.list_indexes("a").unwrap(), vec!["x"]);
+
+        store.set("a", "p1", "{x: 1, y: 0}").unwrap();
+        store.set("a", "p2", "{x: 1, y: 1}").unwrap();
+        store.set("ab", "p3", "{x: 1, y: 2}").unwrap();
+        assert_eq!(
+            store.lookup_by_index("a", "x", "1").unwrap(),
+            vec!["p1", "p2"]
+        );
+        assert_eq!(store.lookup_by_index("ab", "x", "1").unwrap(), vec!["p3"]);
+
+        store.set("a", "p1", "{x: 2, y: 0}").unwrap();
+        assert_eq!(store.lookup_by_index("a", "x", "1").unwrap(), vec!["p2"]);
+        assert_eq!(store.lookup_by_index("a", "x", "2").unwrap(), vec!["p1"]);
+
+        store.delete("a", "p2").unwrap();
+        assert!(store.lookup_by_index("a", "x", "1").unwrap().is_empty());
+
+        // Deleting a type drops its indexes, and only its own
+        store.delete_type("a", true).unwrap();
+        store
+            .set_type("a", &v1, Some("point"), false, false)
+            .unwrap();
+        assert!(store.list_indexes("a").unwrap().is_empty());
+        assert!(store.index_keyspace("a").unwrap().is_empty().unwrap());
+        assert_eq!(store.list_indexes("ab").unwrap(), vec!["x"]);
+        assert_eq!(store.lookup_by_index("ab", "x", "1").unwrap(), vec!["p3"]);
+    }
 
-        let memory_key = format!("{}.memory", key);
-        let memory = keyspace
-            .get(&memory_key)?
-            .map(|v| v.to_vec())
-            .unwrap_or_default();
+    #[test]
+    fn test_expired_values_are_hidden_then_purged() {
+        let (_dir, store, v1) = store(POINT_V1);
+        store
+            .set_type("points", &v1, Some("point"), false, false)
+            .unwrap();
+        store.create_index("points", "x").unwrap();
+
+        store.set("points", "keep", "{x: 1, y: 0}").unwrap();
+        store
+            .set_with_ttl("points", "gone", "{x: 1, y: 1}", Duration::from_millis(1))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        // Hidden right away, before any purge
+        assert_eq!(store.get("points", "gone").unwrap(), None);
+        let keys: Vec<String> = store
+            .scan("points", &ScanOptions::default())
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec!["keep"]);
+        assert_eq!(
+            store.lookup_by_index("points", "x", "1").unwrap(),
+            vec!["keep"]
+        );
+        let index = store.index_keyspace("points").unwrap();
+        let expiry = store.expiry_keyspace("points").unwrap();
+        assert_eq!(index.len().unwrap(), 2);
+        assert_eq!(expiry.len().unwrap(), 1);
+
+        assert_eq!(store.purge_expired().unwrap(), 1);
+        assert_eq!(store.get_raw("points", "gone").unwrap(), None);
+        assert_eq!(index.len().unwrap(), 1);
+        assert!(expiry.is_empty().unwrap());
+        assert_eq!(
+            store.lookup_by_index("points", "x", "1").unwrap(),
+            vec!["keep"]
+        );
+        assert_eq!(store.purge_expired().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_change_log_is_trimmed_to_retention() {
+        let (_dir, store, v1) = store(POINT_V1);
+        let store = store.with_change_retention(3);
+        store
+            .set_type("points", &v1, Some("point"), false, false)
+            .unwrap();
+
+        for i in 0..10 {
+            store
+                .set("points", &format!("p{i}"), "{x: 1, y: 2}")
+                .unwrap();
+        }
+        let mut batch = store.batch();
+        batch.delete("points", "p0").unwrap();
+        batch.delete("points", "p1").unwrap();
+        batch.commit().unwrap();
+
+        let log = store.changes_keyspace("points").unwrap();
+        assert_eq!(changes::first_seq(&log).unwrap(), Some(10));
+        assert_eq!(log.len().unwrap(), 3);
+        assert!(matches!(
+            store.watch("points", Some(9)),
+            Err(KvError::ChangesTrimmed { oldest: 10, .. })
+        ));
+        assert!(matches!(
+            store.watch("points", Some(14)),
+            Err(KvError::ChangesAhead {
+                from: 14,
+                next: 13,
+                ..
+            })
+        ));
+        assert!(store.watch("points", Some(13)).is_ok());
+        let mut watch = store.watch("points", Some(10)).unwrap();
+        let keys: Vec<String> = (0..3)
+            .map(|_| watch.next_timeout(Duration::ZERO).unwrap().unwrap().key)
+            .collect();
+        assert_eq!(keys, vec!["p9", "p0", "p1"]);
+    }
+
+    #[test]
+    fn test_default_ttl_follows_the_current_type() {
+        let (dir, store, v1) = store(POINT_V1);
+        let v2 = write_wit(&dir, "point-v2.wit", POINT_V2);
+        store
+            .set_type("points", &v1, Some("point"), false, false)
+            .unwrap();
+        store
+            .set_type("points", &v2, Some("point"), true, false)
+            .unwrap();
+
+        let metadata = store
+            .set_default_ttl("points", Some(Duration::from_secs(60)))
+            .unwrap();
+        assert_eq!(metadata.wit_definition, POINT_V2);
+        assert_eq!(
+            store.get_type("points").unwrap().unwrap().type_version,
+            metadata.type_version
+        );
 
-        Ok(Some(StoredValue::decode(&buffer, &memory)?))
+        store.delete_type("points", true).unwrap();
+        assert!(matches!(
+            store.set_default_ttl("points", None),
+            Err(KvError::KeyspaceNotFound(_))
+        ));
+        assert!(store.get_type("points").unwrap().is_none());
     }
 }
@@ -0,0 +1,363 @@
+//! Bulk import and export of the values of a keyspace.
+//!
+//! Three formats are supported:
+//!
+//! - [`TransferFormat::Lines`]: one `key<TAB>wave-value` entry per line.
+//!   Empty lines are skipped on import, and keys containing a tab or line
+//!   break cannot be exported.
+//! - [`TransferFormat::Json`]: one `{"key": ..., "value": ...}` object per
+//!   line, with the value in the mapping of [`wave_to_json`](crate::wave_to_json).
+//!   Empty lines are skipped on import.
+//! - [`TransferFormat::Frames`]: the framing of binary scan responses, a
+//!   sequence of entries each made of a little-endian `u32` key length, the
+//!   UTF-8 key, a `u32` record length and a `binary-export` record.
+//!
+//! Imports validate every value against the keyspace type and commit in
+//! batches. A value that does not validate is reported and skipped, while
+//! I/O errors and broken framing stop the import.
+
+use std::io::{BufRead, ErrorKind, Read, Write};
+
+use crate::logging::{debug, info, trace};
+
+use super::batch::WriteBatch;
+use super::error::KvError;
+use super::format::BinaryExport;
+use super::scan::ScanOptions;
+use super::store::KvStore;
+
+/// Values committed per batch by an import unless configured otherwise.
+pub const DEFAULT_IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Failed entries kept in [`ImportStats::errors`]; later ones are only counted.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+/// Encoding of the entries read by [`KvStore::import`] and written by
+/// [`KvStore::export`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransferFormat {
+    /// One `key<TAB>wave-value` entry per line.
+    #[default]
+    Lines,
+    /// Length-prefixed keys and `binary-export` records.
+    Frames,
+    /// One `{"key": ..., "value": ...}` JSON object per line.
+    Json,
+}
+
+/// Outcome of [`KvStore::import`].
+#[derive(Debug, Clone, Default)]
+pub struct ImportStats {
+    /// Number of values written.
+    pub imported: usize,
+    /// Number of entries skipped because they were invalid.
+    pub failed: usize,
+    /// The first skipped entries, in input order.
+    pub errors: Vec<ImportError>,
+    /// Store revision of the last committed batch, if anything was written.
+    pub revision: Option<u64>,
+}
+
+impl ImportStats {
+    fn add_error(&mut self, entry: usize, key: Option<String>, message: String) {
+        trace!(entry = entry, key = key.as_deref(), error = %message, "import entry skipped");
+        self.failed += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(ImportError {
+                entry,
+                key,
+                message,
+            });
+        }
+    }
+}
+
+/// An entry skipped by [`KvStore::import`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+    /// Line number, or entry number in [`TransferFormat::Frames`], starting at 1.
+    pub entry: usize,
+    /// Key of the entry, if it could be read.
+    pub key: Option<String>,
+    /// Why the entry was skipped.
+    pub message: String,
+}
+
+/// Read entries from `reader` into `keyspace`, committing every `batch_size` values.
+pub(super) fn import(
+    store: &KvStore,
+    keyspace: &str,
+    format: TransferFormat,
+    mut reader: impl BufRead,
+    batch_size: usize,
+) -> Result<ImportStats, KvError> {
+    store.keyspace_type(keyspace)?;
+    let batch_size = batch_size.max(1);
+
+    let mut stats = ImportStats::default();
+    let mut batch = store.batch();
+    let mut entry = 0;
+    loop {
+        let next = match format {
+            TransferFormat::Lines | TransferFormat::Json => read_line(&mut reader)?,
+            TransferFormat::Frames => read_frame(&mut reader)?,
+        };
+        let Some(next) = next else {
+            break;
+        };
+        entry += 1;
+
+        let result = match next {
+            Entry::Line(line) if line.is_empty() => continue,
+            Entry::Line(line) if format == TransferFormat::Json => {
+                import_json(&mut batch, keyspace, &line)
+            }
+            Entry::Line(line) => match line.split_once('\t') {
+                Some((key, value)) => batch
+                    .set(keyspace, key, value)
+                    .map_err(|e| (Some(key.to_string()), e.to_string())),
+                None => Err((None, "expected 'key<TAB>value'".to_string())),
+            },
+            Entry::Frame(key, record) => match String::from_utf8(key) {
+                Ok(key) => BinaryExport::decode_from_bytes(&record)
+                    .and_then(|value| batch.set_raw(keyspace, &key, &value))
+                    .map_err(|e| (Some(key), e.to_string())),
+                Err(_) => Err((None, "key is not valid UTF-8".to_string())),
+            },
+        };
+        match result {
+            Ok(()) => stats.imported += 1,
+            Err((key, message)) => stats.add_error(entry, key, message),
+        }
+
+        if batch.len() >= batch_size {
+            let full = std::mem::replace(&mut batch, store.batch());
+            stats.revision = Some(full.commit()?);
+            debug!(
+                keyspace = keyspace,
+                imported = stats.imported,
+                "import batch committed"
+            );
+        }
+    }
+    if !batch.is_empty() {
+        stats.revision = Some(batch.commit()?);
+    }
+
+    info!(
+        keyspace = keyspace,
+        imported = stats.imported,
+        failed = stats.failed,
+        "import finished"
+    );
+    Ok(stats)
+}
+
+/// Write every live value of `keyspace` to `writer`, returning how many were written.
+pub(super) fn export(
+    store: &KvStore,
+    keyspace: &str,
+    format: TransferFormat,
+    mut writer: impl Write,
+) -> Result<usize, KvError> {
+    let scan = store.scan(keyspace, &ScanOptions::default())?;
+    let mut count = 0;
+    match format {
+        TransferFormat::Lines => {
+            for entry in scan.wave() {
+                let (key, value) = entry?;
+                if key.contains(['\t', '\n', '\r']) {
+                    return Err(KvError::InvalidFormat(format!(
+                        "Key {:?} cannot be exported as a line; use the binary format",
+                        key
+                    )));
+                }
+                writeln!(writer, "{}\t{}", key, value)?;
+                count += 1;
+            }
+        }
+        TransferFormat::Json => {
+            for entry in scan.json() {
+                let (key, value) = entry?;
+                let line = serde_json::json!({"key": key, "value": value});
+                writeln!(writer, "{}", line)?;
+                count += 1;
+            }
+        }
+        TransferFormat::Frames => {
+            for entry in scan {
+                let (key, stored) = entry?;
+                writer.write_all(&encode_frame(
+                    &key,
+                    &BinaryExport::from_stored_owned(stored),
+                )?)?;
+                count += 1;
+            }
+        }
+    }
+    writer.flush()?;
+
+    info!(keyspace = keyspace, count = count, "export finished");
+    Ok(count)
+}
+
+/// Queue one `{"key": ..., "value": ...}` line.
+fn import_json(
+    batch: &mut WriteBatch<'_>,
+    keyspace: &str,
+    line: &str,
+) -> Result<(), (Option<String>, String)> {
+    let entry: serde_json::Value =
+        serde_json::from_str(line).map_err(|e| (None, format!("invalid JSON: {}", e)))?;
+    let (Some(key), Some(value)) = (
+        entry.get("key").and_then(|k| k.as_str()),
+        entry.get("value"),
+    ) else {
+        return Err((
+            None,
+            "expected {\"key\": string, \"value\": ...}".to_string(),
+        ));
+    };
+    batch
+        .set_json(keyspace, key, value)
+        .map_err(|e| (Some(key.to_string()), e.to_string()))
+}
+
+/// One length-prefixed key and `binary-export` record, as written by
+/// [`TransferFormat::Frames`] and binary scan responses.
+pub fn encode_frame(key: &str, value: &BinaryExport) -> Result<Vec<u8>, KvError> {
+    let (buffer, memory) = value.encode()?;
+    let record_len = buffer.len() + memory.len();
+
+    let mut frame = Vec::with_capacity(8 + key.len() + record_len);
+    frame.extend(frame_len(key.len())?);
+    frame.extend(key.as_bytes());
+    frame.extend(frame_len(record_len)?);
+    frame.extend(buffer);
+    frame.extend(memory);
+    Ok(frame)
+}
+
+fn frame_len(len: usize) -> Result<[u8; 4], KvError> {
+    u32::try_from(len)
+        .map(u32::to_le_bytes)
+        .map_err(|_| KvError::InvalidFormat(format!("{} bytes do not fit a frame", len)))
+}
+
+/// An undecoded import entry.
+enum Entry {
+    Line(String),
+    Frame(Vec<u8>, Vec<u8>),
+}
+
+/// Next line without its line break, or `None` at the end of the input.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<Entry>, KvError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Some(Entry::Line(line)))
+}
+
+/// Next frame, or `None` if the input ends between frames.
+fn read_frame(reader: &mut impl Read) -> Result<Option<Entry>, KvError> {
+    // Only an input ending before the first byte of a frame ends cleanly
+    let mut first = [0; 1];
+    match reader.read_exact(&mut first) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut rest = [0; 3];
+    reader.read_exact(&mut rest).map_err(truncated)?;
+    let ([b0], [b1, b2, b3]) = (first, rest);
+    let key = read_chunk(reader, u32::from_le_bytes([b0, b1, b2, b3]))?;
+
+    let mut len = [0; 4];
+    reader.read_exact(&mut len).map_err(truncated)?;
+    let record = read_chunk(reader, u32::from_le_bytes(len))?;
+    Ok(Some(Entry::Frame(key, record)))
+}
+
+/// Read `len` bytes, without trusting `len` enough to allocate it up front.
+fn read_chunk(reader: &mut impl Read, len: u32) -> Result<Vec<u8>, KvError> {
+    let mut chunk = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut chunk)?;
+    if chunk.len() != len as usize {
+        return Err(truncated(ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(chunk)
+}
+
+fn truncated(e: std::io::Error) -> KvError {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        KvError::InvalidFormat("Input ends in the middle of a frame".to_string())
+    } else {
+        e.into()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_lines() {
+        let mut input = "a\t{x: 1}\r\n\nb\t{x: 2}".as_bytes();
+        let mut lines = Vec::new();
+        while let Some(entry) = read_line(&mut input).unwrap() {
+            if let Entry::Line(line) = entry {
+                lines.push(line);
+            }
+        }
+        assert_eq!(lines, vec!["a\t{x: 1}", "", "b\t{x: 2}"]);
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let value = BinaryExport {
+            buffer: vec![1, 2, 3, 4],
+            memory: Some(vec![5, 6]),
+        };
+        let mut input = encode_frame("key", &value).unwrap();
+        input.extend(encode_frame("", &value).unwrap());
+
+        let mut reader = input.as_slice();
+        let first = read_frame(&mut reader).unwrap();
+        assert!(matches!(&first, Some(Entry::Frame(key, _)) if key == b"key"));
+        if let Some(Entry::Frame(_, record)) = first {
+            let decoded = BinaryExport::decode_from_bytes(&record).unwrap();
+            assert_eq!(decoded.buffer, value.buffer);
+            assert_eq!(decoded.memory, value.memory);
+        }
+        assert!(
+            matches!(read_frame(&mut reader).unwrap(), Some(Entry::Frame(key, _)) if key.is_empty())
+        );
+        assert!(read_frame(&mut reader).unwrap().is_none());
+
+        // Ending inside a frame is an error, not the end of the input
+        let truncated = input.get(..input.len() - 1).unwrap();
+        let mut reader = truncated;
+        assert!(read_frame(&mut reader).unwrap().is_some());
+        assert!(matches!(
+            read_frame(&mut reader),
+            Err(KvError::InvalidFormat(_))
+        ));
+
+        // So is ending inside the length prefix of the next frame
+        let first_len = encode_frame("key", &value).unwrap().len();
+        let mut reader = input.get(..first_len + 2).unwrap();
+        assert!(read_frame(&mut reader).unwrap().is_some());
+        assert!(matches!(
+            read_frame(&mut reader),
+            Err(KvError::InvalidFormat(_))
+        ));
+    }
+}
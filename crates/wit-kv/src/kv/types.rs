@@ -1,10 +1,15 @@
 //! Data types for the KV store module.
 
+use std::time::Duration;
+
+use wit_parser::Type;
+
+use super::error::KvError;
 use super::version::SemanticVersion;
 
 /// Stored value envelope - wraps the actual value with metadata.
 /// This structure mirrors the `stored-value` WIT type in kv.wit.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StoredValue {
     /// Format version for future compatibility.
     /// Used for migration if the envelope structure changes.
@@ -19,22 +24,52 @@ pub struct StoredValue {
 
     /// Linear memory bytes (for variable-length types: strings, lists)
     pub memory: Option<Vec<u8>>,
+
+    /// Store revision of the write that produced this value.
+    ///
+    /// Revisions increase with every committed write to the store, so a key's
+    /// revision changes whenever it is rewritten, including after a delete.
+    /// Assigned by the store on write; values written before revisions were
+    /// tracked (format version 1) have revision 0.
+    pub revision: u64,
+
+    /// Unix time in milliseconds from which the value is expired, if it has
+    /// a time-to-live.
+    ///
+    /// Expired values are hidden from reads and removed by
+    /// [`KvStore::purge_expired`](super::KvStore::purge_expired). Values
+    /// written before expiry was tracked (format versions 1 and 2) never expire.
+    pub expires_at: Option<u64>,
 }
 
 impl StoredValue {
     /// Current format version.
     /// Increment this when changing the StoredValue structure itself.
-    pub const CURRENT_VERSION: u8 = 1;
+    ///
+    /// - 1: initial format
+    /// - 2: adds `revision`
+    /// - 3: adds `expires_at`
+    pub const CURRENT_VERSION: u8 = 3;
 
     /// Create a new StoredValue with the current format version.
+    ///
+    /// The revision is 0 until the value is written to a store, and the
+    /// value does not expire.
     pub fn new(type_version: SemanticVersion, value: Vec<u8>, memory: Option<Vec<u8>>) -> Self {
         Self {
             version: Self::CURRENT_VERSION,
             type_version,
             value,
             memory,
+            revision: 0,
+            expires_at: None,
         }
     }
+
+    /// Returns `true` if the value is expired at `now` (Unix time in milliseconds).
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
 }
 
 /// Keyspace type metadata.
@@ -62,6 +97,12 @@ pub struct KeyspaceMetadata {
 
     /// Unix timestamp of keyspace creation
     pub created_at: u64,
+
+    /// Time-to-live given to values written without an explicit one.
+    ///
+    /// Stored with millisecond precision. Changing it does not affect values
+    /// already written.
+    pub default_ttl: Option<Duration>,
 }
 
 impl KeyspaceMetadata {
@@ -86,6 +127,150 @@ impl KeyspaceMetadata {
             type_version: SemanticVersion::INITIAL,
             type_hash,
             created_at,
+            default_ttl: None,
+        }
+    }
+
+    /// Check that `name` can be used as a keyspace name: it must not be empty
+    /// or contain `@` or `/`, which separate keyspace names from versions and
+    /// field paths in metadata keys.
+    pub fn validate_name(name: &str) -> Result<(), KvError> {
+        if name.is_empty() || name.contains(['@', '/']) {
+            Err(KvError::InvalidKeyspaceName(name.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// JSON Schema (2020-12) of the JSON form of this keyspace's values.
+    ///
+    /// The schema is generated from `wit_definition` and `type_name`; see
+    /// [`json_schema`](crate::json_schema) for how WIT types are described.
+    pub fn json_schema(&self) -> Result<serde_json::Value, KvError> {
+        let (resolve, type_id, _) =
+            crate::load_wit_type_from_string(&self.wit_definition, Some(&self.type_name))
+                .map_err(|e| KvError::WaveParse(e.to_string()))?;
+        let mut schema = crate::json_schema(&resolve, &Type::Id(type_id))?;
+        if let Some(object) = schema.as_object_mut() {
+            object.insert(
+                "$schema".to_string(),
+                "https://json-schema.org/draft/2020-12/schema".into(),
+            );
+        }
+        Ok(schema)
+    }
+}
+
+/// Kind of a registered module.
+/// This mirrors the `module-kind` WIT type in kv.wit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleKind {
+    /// Map module, exporting `filter` and `transform`
+    Mapper,
+    /// Reduce module, exporting `init-state` and `reduce`
+    Reducer,
+}
+
+impl ModuleKind {
+    /// Name of the kind in kv.wit (`mapper` or `reducer`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModuleKind::Mapper => "mapper",
+            ModuleKind::Reducer => "reducer",
+        }
+    }
+
+    /// Functions a module of this kind must export.
+    pub fn exports(&self) -> &'static [&'static str] {
+        match self {
+            ModuleKind::Mapper => &["filter", "transform"],
+            ModuleKind::Reducer => &["init-state", "reduce"],
+        }
+    }
+}
+
+impl std::str::FromStr for ModuleKind {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mapper" => Ok(ModuleKind::Mapper),
+            "reducer" => Ok(ModuleKind::Reducer),
+            _ => Err(KvError::InvalidFormat(format!(
+                "Unknown module kind '{}', expected mapper or reducer",
+                s
+            ))),
+        }
+    }
+}
+
+/// A WebAssembly component registered in a store, so map and reduce
+/// operations can run it by id.
+/// This structure mirrors the `module-registration` WIT type in kv.wit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleRegistration {
+    /// Unique identifier, made of ASCII letters, digits, `-` and `_`
+    pub id: String,
+
+    /// Human-readable name
+    pub name: String,
+
+    /// WIT definition text of the component's types
+    pub wit_definition: String,
+
+    /// Input type name within the WIT definition
+    pub input_type: String,
+
+    /// Output type (mappers) or state type (reducers) name within the WIT definition
+    pub output_type: String,
+
+    /// Whether the component maps or reduces
+    pub kind: ModuleKind,
+
+    /// Unix timestamp of registration
+    pub created_at: u64,
+}
+
+impl ModuleRegistration {
+    /// Create a registration made now.
+    pub fn new(
+        id: String,
+        name: String,
+        wit_definition: String,
+        input_type: String,
+        output_type: String,
+        kind: ModuleKind,
+    ) -> Self {
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            id,
+            name,
+            wit_definition,
+            input_type,
+            output_type,
+            kind,
+            created_at,
+        }
+    }
+
+    /// Check that `id` can identify a module.
+    ///
+    /// Ids are used in file names, so they are limited to 1 to 128 ASCII
+    /// letters, digits, `-` and `_`.
+    pub fn validate_id(id: &str) -> Result<(), KvError> {
+        let valid = !id.is_empty()
+            && id.len() <= 128
+            && id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if valid {
+            Ok(())
+        } else {
+            Err(KvError::InvalidModuleId(id.to_string()))
         }
     }
 }
@@ -3,6 +3,8 @@
 use std::fmt;
 use std::str::FromStr;
 
+use super::evolution::ChangeKind;
+
 /// Error returned when parsing a [`SemanticVersion`] from a string fails.
 ///
 /// # Example
@@ -87,6 +89,23 @@ impl SemanticVersion {
         reader.can_read_from(self)
     }
 
+    /// Return the version that follows this one after a type change of `change`.
+    ///
+    /// Bumps follow the same rules as [`can_read_from`](Self::can_read_from):
+    /// pre-1.0, compatible changes bump the patch and breaking changes bump
+    /// the minor version; from 1.0 on, patch/minor/major map directly.
+    pub fn bump(&self, change: ChangeKind) -> Self {
+        match (self.major, change) {
+            (_, ChangeKind::Patch) | (0, ChangeKind::Minor) => {
+                Self::new(self.major, self.minor, self.patch.saturating_add(1))
+            }
+            (0, ChangeKind::Major) | (_, ChangeKind::Minor) => {
+                Self::new(self.major, self.minor.saturating_add(1), 0)
+            }
+            (_, ChangeKind::Major) => Self::new(self.major.saturating_add(1), 0, 0),
+        }
+    }
+
     /// Parse from string like "1.2.3" or "0.1.0".
     pub fn parse(s: &str) -> Option<Self> {
         let mut parts = s.split('.');
@@ -245,6 +264,26 @@ mod tests {
         assert!(!v100.can_read_from(&v200));
     }
 
+    #[test]
+    fn test_bump() {
+        let v010 = SemanticVersion::new(0, 1, 0);
+        assert_eq!(v010.bump(ChangeKind::Patch), SemanticVersion::new(0, 1, 1));
+        assert_eq!(v010.bump(ChangeKind::Minor), SemanticVersion::new(0, 1, 1));
+        assert_eq!(v010.bump(ChangeKind::Major), SemanticVersion::new(0, 2, 0));
+
+        let v123 = SemanticVersion::new(1, 2, 3);
+        assert_eq!(v123.bump(ChangeKind::Patch), SemanticVersion::new(1, 2, 4));
+        assert_eq!(v123.bump(ChangeKind::Minor), SemanticVersion::new(1, 3, 0));
+        assert_eq!(v123.bump(ChangeKind::Major), SemanticVersion::new(2, 0, 0));
+
+        // Compatible bumps stay readable, breaking bumps do not
+        for v in [v010, v123] {
+            assert!(v.bump(ChangeKind::Patch).can_read_from(&v));
+            assert!(v.bump(ChangeKind::Minor).can_read_from(&v));
+            assert!(!v.bump(ChangeKind::Major).can_read_from(&v));
+        }
+    }
+
     #[test]
     fn test_from_str() {
         // Valid versions
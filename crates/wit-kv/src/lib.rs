@@ -16,6 +16,7 @@
 //!
 //! - `kv` (default): Key-value store functionality
 //! - `wasm` (default): WASM execution for map/reduce operations
+//! - `json` (enabled by `kv`): Lossless conversion between WIT values and JSON
 //! - `logging`: Enable tracing-based logging
 //!
 //! # Example
@@ -27,7 +28,7 @@
 //! let store = KvStore::init(".wit-kv")?;
 //!
 //! // Register a type for a keyspace
-//! store.set_type("tasks", "types.wit", Some("task"), false)?;
+//! store.set_type("tasks", "types.wit", Some("task"), false, false)?;
 //!
 //! // Store and retrieve values
 //! store.set("tasks", "task-1", "{name: \"Build\", done: false}")?;
@@ -46,6 +47,10 @@ pub mod wasm;
 // Re-export from wit-kv-abi
 pub use wit_kv_abi::{CanonicalAbi, CanonicalAbiError, EncodedValue, LinearMemory};
 
+// Re-export JSON conversion (when feature enabled)
+#[cfg(feature = "json")]
+pub use wit_kv_abi::{JsonConvertError, json_schema, json_to_wave, wave_to_json};
+
 // Re-export from wit-parser and wasm-wave for convenience
 pub use wasm_wave::value::{Type as WaveType, Value, resolve_wit_type};
 pub use wasm_wave::{from_str as wave_from_str, to_string as wave_to_string};
@@ -57,13 +62,18 @@ pub use error::{Error, Result};
 // Re-export KV types (when feature enabled)
 #[cfg(feature = "kv")]
 pub use kv::{
-    BinaryExport, DatabaseInfo, DatabaseList, KeyList, KeyspaceList, KeyspaceMetadata, KvError,
-    KvStore, ParseVersionError, SemanticVersion, StoredValue,
+    BackupInfo, BinaryExport, ChangeEvent, ChangeKind, ChangeOp, DatabaseInfo, DatabaseList,
+    Durability, ImportError, ImportStats, KeyList, KeyspaceList, KeyspaceMetadata, KvError,
+    KvStore, ModuleKind, ModuleRegistration, ParseVersionError, Scan, ScanOptions, SemanticVersion,
+    StoredValue, TransferFormat, TypeChange, TypeDiff, Watch, WriteBatch,
 };
 
 // Re-export WASM types (when feature enabled)
 #[cfg(feature = "wasm")]
-pub use wasm::{TypedRunner, TypedRunnerBuilder, WasmError, create_placeholder_val};
+pub use wasm::{
+    ComponentCache, ExecutionLimits, LimitScope, TypedRunner, TypedRunnerBuilder, WasmError,
+    create_placeholder_val,
+};
 
 // Re-export Val conversion functions (when wasm feature enabled)
 #[cfg(feature = "wasm")]
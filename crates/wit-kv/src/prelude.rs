@@ -9,7 +9,7 @@
 //! use wit_kv::prelude::*;
 //!
 //! let store = KvStore::init(".wit-kv")?;
-//! store.set_type("points", "types.wit", Some("point"), false)?;
+//! store.set_type("points", "types.wit", Some("point"), false, false)?;
 //! store.set("points", "p1", "{x: 10, y: 20}")?;
 //! ```
 
@@ -22,14 +22,15 @@ pub use crate::{CanonicalAbi, CanonicalAbiError, EncodedValue, LinearMemory};
 // KV store types (requires "kv" feature)
 #[cfg(feature = "kv")]
 pub use crate::kv::{
-    BinaryExport, KeyspaceMetadata, KvError, KvStore, ParseVersionError, SemanticVersion,
-    StoredValue,
+    BinaryExport, Durability, KeyspaceMetadata, KvError, KvStore, ModuleKind, ModuleRegistration,
+    ParseVersionError, ScanOptions, SemanticVersion, StoredValue, WriteBatch,
 };
 
 // WASM execution types (requires "wasm" feature)
 #[cfg(feature = "wasm")]
 pub use crate::wasm::{
-    TypedRunner, TypedRunnerBuilder, WasmError, create_placeholder_val, val_to_wave, wave_to_val,
+    ComponentCache, ExecutionLimits, LimitScope, TypedRunner, TypedRunnerBuilder, WasmError,
+    create_placeholder_val, val_to_wave, wave_to_val,
 };
 
 // Dependency re-exports
@@ -0,0 +1,294 @@
+//! On-disk cache of compiled WebAssembly components.
+//!
+//! Compiling a component is by far the slowest part of creating a
+//! [`TypedRunner`](super::TypedRunner). A [`ComponentCache`] keeps the
+//! artifacts produced by [`Component::serialize`] in a directory, so a
+//! module that is run again is only deserialized.
+//!
+//! Artifacts are keyed by a name (such as a module id), a hash of the
+//! component bytes and a hash of the engine settings that affect compiled
+//! code, since the engine depends on the [`ExecutionLimits`](super::ExecutionLimits)
+//! of a runner. Only the latest component of a name is kept: caching new
+//! bytes under a name removes the artifacts of the previous ones.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use wasmtime::Engine;
+use wasmtime::component::Component;
+
+use super::error::WasmError;
+use crate::logging::{debug, trace, warn};
+
+/// Extension of cached artifacts.
+const ARTIFACT_EXTENSION: &str = "cwasm";
+
+/// Distinguishes the temporary files of artifacts written at once by this process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A directory of compiled components.
+///
+/// # Example
+///
+/// ```ignore
+/// let cache = ComponentCache::new(store.module_cache_dir());
+/// let runner = TypedRunner::builder()
+///     .component_bytes(bytes)
+///     .wit_text(wit)
+///     .input_type("point")
+///     .component_cache(cache, "filter-points")
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ComponentCache {
+    dir: PathBuf,
+}
+
+impl ComponentCache {
+    /// A cache in `dir`, which is created on the first write.
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The directory of the cache.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Remove every artifact cached under `name`.
+    ///
+    /// Returns the number of artifacts removed.
+    pub fn remove(&self, name: &str) -> Result<usize, WasmError> {
+        validate_name(name)?;
+        let mut removed = 0;
+        for path in self.artifacts(name)? {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+        debug!(name = name, removed = removed, "removed cached components");
+        Ok(removed)
+    }
+
+    /// Load the component `bytes` cached under `name` for `engine`,
+    /// compiling and caching it if needed.
+    ///
+    /// Failing to write the cache is not an error: the compiled component
+    /// is returned anyway.
+    // Errors are only logged, which does nothing without the logging feature
+    #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+    pub(super) fn load(
+        &self,
+        engine: &Engine,
+        name: &str,
+        bytes: &[u8],
+    ) -> Result<Component, WasmError> {
+        validate_name(name)?;
+        let path = self.artifact_path(engine, name, bytes);
+
+        if path.exists() {
+            // SAFETY: artifacts are only written by `store` below, from
+            // `Component::serialize` of an engine with the same settings, as
+            // the file name is keyed by the engine's compatibility hash. The
+            // cache directory must not be writable by untrusted parties.
+            match unsafe { Component::deserialize_file(engine, &path) } {
+                Ok(component) => {
+                    trace!(path = %path.display(), "loaded cached component");
+                    return Ok(component);
+                }
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "discarding unusable cached component");
+                }
+            }
+        }
+
+        let component = Component::new(engine, bytes)?;
+        if let Err(e) = self.store(&component, name, bytes, &path) {
+            warn!(path = %path.display(), error = %e, "failed to cache compiled component");
+        }
+        Ok(component)
+    }
+
+    /// Write the artifact of `component` to `path`, removing the artifacts
+    /// of other components cached under `name`.
+    fn store(
+        &self,
+        component: &Component,
+        name: &str,
+        bytes: &[u8],
+        path: &Path,
+    ) -> Result<(), WasmError> {
+        fs::create_dir_all(&self.dir)?;
+        let bytes_prefix = format!("{}.{:016x}.", name, hash(bytes));
+        for stale in self.artifacts(name)? {
+            let same_bytes = stale
+                .file_name()
+                .and_then(|file| file.to_str())
+                .is_some_and(|file| file.starts_with(&bytes_prefix));
+            if !same_bytes {
+                trace!(path = %stale.display(), "removing stale cached component");
+                fs::remove_file(&stale)?;
+            }
+        }
+
+        // Write to a temporary file first, so readers never see a partial
+        // artifact. Its name is unique, so concurrent writers of the same
+        // artifact (in this or another process) do not clobber each other.
+        let tmp = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = fs::write(&tmp, component.serialize()?).and_then(|()| fs::rename(&tmp, path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        debug!(path = %path.display(), "cached compiled component");
+        Ok(())
+    }
+
+    /// Path of the artifact of `bytes` under `name` for `engine`:
+    /// `{name}.{bytes hash}.{engine hash}.cwasm`.
+    fn artifact_path(&self, engine: &Engine, name: &str, bytes: &[u8]) -> PathBuf {
+        self.dir.join(format!(
+            "{}.{:016x}.{:016x}.{}",
+            name,
+            hash(bytes),
+            hash(engine.precompile_compatibility_hash()),
+            ARTIFACT_EXTENSION
+        ))
+    }
+
+    /// Paths of the artifacts cached under `name`.
+    ///
+    /// Names cannot contain `.`, so the `{name}.` prefix matches exactly.
+    fn artifacts(&self, name: &str) -> Result<Vec<PathBuf>, WasmError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let prefix = format!("{}.", name);
+        let suffix = format!(".{}", ARTIFACT_EXTENSION);
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .and_then(|file| file.to_str())
+                .is_some_and(|file| file.starts_with(&prefix) && file.ends_with(&suffix))
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Check that `name` can be part of a file name: 1 to 128 ASCII letters,
+/// digits, `-` and `_`.
+fn validate_name(name: &str) -> Result<(), WasmError> {
+    let valid = !name.is_empty()
+        && name.len() <= 128
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(WasmError::InvalidCacheName(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use tempfile::TempDir;
+    use wasmtime::Config;
+
+    use super::*;
+
+    const COMPONENT: &str = r#"
+        (component
+            (core module $m
+                (func (export "init-state") (result i32) (i32.const 7)))
+            (core instance $i (instantiate $m))
+            (func (export "init-state") (result u32)
+                (canon lift (core func $i "init-state"))))
+    "#;
+
+    fn engine() -> Engine {
+        let mut config = Config::new();
+        config.wasm_component_model(true);
+        Engine::new(&config).unwrap()
+    }
+
+    /// A cache in a subdirectory of `dir`, so its creation is exercised.
+    fn cache(dir: &TempDir) -> ComponentCache {
+        ComponentCache::new(dir.path().join("components"))
+    }
+
+    #[test]
+    fn test_load_caches_and_replaces_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(&dir);
+        let engine = engine();
+
+        cache.load(&engine, "sum", COMPONENT.as_bytes()).unwrap();
+        let first = cache.artifacts("sum").unwrap();
+        assert_eq!(first.len(), 1);
+
+        // Loading again deserializes the same artifact
+        cache.load(&engine, "sum", COMPONENT.as_bytes()).unwrap();
+        assert_eq!(cache.artifacts("sum").unwrap(), first);
+
+        // New bytes under the same name replace the artifact
+        let changed = COMPONENT.replace("i32.const 7", "i32.const 8");
+        cache.load(&engine, "sum", changed.as_bytes()).unwrap();
+        let second = cache.artifacts("sum").unwrap();
+        assert_eq!(second.len(), 1);
+        assert_ne!(second, first);
+
+        // Names are matched exactly, not by prefix
+        cache.load(&engine, "sum-2", COMPONENT.as_bytes()).unwrap();
+        assert_eq!(cache.remove("sum").unwrap(), 1);
+        assert_eq!(cache.artifacts("sum-2").unwrap().len(), 1);
+
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(cache.dir()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_invalid_names_and_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(&dir);
+        let engine = engine();
+
+        for name in ["", "../escape", "a.b", "a/b"] {
+            assert!(matches!(
+                cache.load(&engine, name, COMPONENT.as_bytes()),
+                Err(WasmError::InvalidCacheName(_))
+            ));
+        }
+
+        // A corrupt artifact is recompiled and overwritten
+        cache.load(&engine, "m", COMPONENT.as_bytes()).unwrap();
+        let path = cache.artifacts("m").unwrap().pop().unwrap();
+        fs::write(&path, b"not an artifact").unwrap();
+        cache.load(&engine, "m", COMPONENT.as_bytes()).unwrap();
+        assert_ne!(fs::read(&path).unwrap(), b"not an artifact");
+    }
+}
@@ -1,9 +1,13 @@
 //! Error types for WebAssembly module execution.
 
+use std::time::Duration;
+
 use thiserror::Error;
 
 use wit_kv_abi::{CanonicalAbiError, ValConvertError};
 
+use super::limits::LimitScope;
+
 /// Errors that can occur during WebAssembly module execution.
 #[derive(Error, Debug)]
 pub enum WasmError {
@@ -35,6 +39,30 @@ pub enum WasmError {
     #[error("Wasm execution trapped: {0}")]
     Trap(String),
 
+    /// Execution consumed all the fuel it was allowed.
+    #[error("Fuel exhausted: {scope} limit of {limit} reached")]
+    FuelExhausted { scope: LimitScope, limit: u64 },
+
+    /// Execution ran past its wall-clock deadline.
+    #[error("Deadline exceeded: {scope} limit of {}ms reached", limit.as_millis())]
+    DeadlineExceeded { scope: LimitScope, limit: Duration },
+
+    /// The module tried to grow a linear memory beyond the limit.
+    #[error("Memory limit exceeded: {requested} bytes requested, limit is {limit}")]
+    MemoryLimitExceeded { limit: usize, requested: usize },
+
+    /// The module tried to grow a table beyond the limit.
+    #[error("Table limit exceeded: {requested} elements requested, limit is {limit}")]
+    TableLimitExceeded { limit: usize, requested: usize },
+
+    /// The runner was cancelled through its cancel flag.
+    #[error("Execution cancelled")]
+    Cancelled,
+
+    /// A component cache name that cannot be used in file names.
+    #[error("Invalid component cache name '{0}': use 1-128 ASCII letters, digits, '-' and '_'")]
+    InvalidCacheName(String),
+
     /// Type mismatch between keyspace type and module expectations.
     #[error("Type mismatch: keyspace type '{keyspace_type}' incompatible with module")]
     TypeMismatch { keyspace_type: String },
@@ -55,3 +83,22 @@ pub enum WasmError {
     #[error("Val conversion error: {0}")]
     ValConvert(#[from] ValConvertError),
 }
+
+impl WasmError {
+    /// Whether a per-job limit was hit or the runner was cancelled, so no
+    /// further call of the runner can succeed.
+    pub fn ends_job(&self) -> bool {
+        matches!(
+            self,
+            WasmError::Cancelled
+                | WasmError::FuelExhausted {
+                    scope: LimitScope::Job,
+                    ..
+                }
+                | WasmError::DeadlineExceeded {
+                    scope: LimitScope::Job,
+                    ..
+                }
+        )
+    }
+}
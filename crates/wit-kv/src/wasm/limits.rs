@@ -0,0 +1,373 @@
+//! Resource limits for WebAssembly executions.
+//!
+//! A [`TypedRunner`](super::TypedRunner) runs untrusted code, so it can be
+//! bounded in fuel (a deterministic count of executed instructions),
+//! wall-clock time, linear memory and table size. Fuel and time are limited
+//! per call and per job, where a job is everything a runner does from its
+//! creation on, such as one map or reduce over a keyspace.
+//!
+//! Time limits use wasmtime's epoch interruption: a background thread bumps
+//! the engine epoch every [`EPOCH_TICK`], and the running code checks its
+//! deadlines on each tick. The same check stops a runner whose cancel flag
+//! was set.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use wasmtime::{Engine, ResourceLimiter, Store, Trap, UpdateDeadline};
+
+use super::error::WasmError;
+use crate::logging::trace;
+
+/// How often running code checks its wall-clock deadlines and cancel flag.
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Limits on the resources a [`TypedRunner`](super::TypedRunner) may use.
+///
+/// Every limit is optional, and the default is unlimited.
+///
+/// # Example
+///
+/// ```ignore
+/// let runner = TypedRunner::builder()
+///     .component("filter.wasm")
+///     .wit("types.wit")
+///     .input_type("point")
+///     .limits(ExecutionLimits {
+///         call_fuel: Some(10_000_000),
+///         job_timeout: Some(Duration::from_secs(60)),
+///         max_memory: Some(64 << 20),
+///         ..ExecutionLimits::default()
+///     })
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    /// Fuel a single call may consume.
+    pub call_fuel: Option<u64>,
+    /// Fuel all calls of the runner may consume together.
+    pub job_fuel: Option<u64>,
+    /// Wall-clock time a single call may take.
+    pub call_timeout: Option<Duration>,
+    /// Wall-clock time from the creation of the runner after which no call may run.
+    pub job_timeout: Option<Duration>,
+    /// Maximum size in bytes of each linear memory.
+    pub max_memory: Option<usize>,
+    /// Maximum number of elements of each table.
+    pub max_table_elements: Option<usize>,
+}
+
+impl ExecutionLimits {
+    /// These limits without the per-job ones.
+    ///
+    /// Used for operations that must either finish or fail as a whole, such
+    /// as migrations, where stopping halfway is not an option.
+    pub fn per_call(self) -> Self {
+        Self {
+            job_fuel: None,
+            job_timeout: None,
+            ..self
+        }
+    }
+
+    /// Whether fuel metering must be enabled.
+    pub(super) fn uses_fuel(&self) -> bool {
+        self.call_fuel.is_some() || self.job_fuel.is_some()
+    }
+
+    /// Whether epoch interruption must be enabled.
+    pub(super) fn uses_epochs(&self) -> bool {
+        self.call_timeout.is_some() || self.job_timeout.is_some()
+    }
+}
+
+/// Whether a limit applies to a single call or to the whole job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitScope {
+    /// The limit of a single call.
+    Call,
+    /// The limit of all calls of a runner together.
+    Job,
+}
+
+impl fmt::Display for LimitScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitScope::Call => write!(f, "per-call"),
+            LimitScope::Job => write!(f, "per-job"),
+        }
+    }
+}
+
+/// Data of a runner's store: its limits and what is left of the job budget.
+#[derive(Debug, Clone)]
+pub(super) struct RunnerState {
+    limits: ExecutionLimits,
+    /// When the job runs out of time, `None` if never (or too far off to
+    /// represent).
+    job_deadline: Option<Instant>,
+    /// When the current call runs out of time, `None` like `job_deadline`.
+    call_deadline: Option<Instant>,
+    /// Fuel left for the job, if it is limited.
+    job_fuel_left: Option<u64>,
+    /// Fuel given to the store by the last [`begin_call`].
+    fuel_granted: u64,
+    /// Which limit bounded the fuel given by the last [`begin_call`].
+    fuel_scope: LimitScope,
+    /// Stops the job once set.
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl RunnerState {
+    /// State of a job starting now.
+    pub(super) fn new(limits: ExecutionLimits, cancel: Option<Arc<AtomicBool>>) -> Self {
+        Self {
+            limits,
+            job_deadline: limits.job_timeout.and_then(deadline_after),
+            call_deadline: None,
+            job_fuel_left: limits.job_fuel,
+            fuel_granted: 0,
+            fuel_scope: LimitScope::Call,
+            cancel,
+        }
+    }
+
+    pub(super) fn limits(&self) -> &ExecutionLimits {
+        &self.limits
+    }
+
+    /// Whether epoch interruption must be enabled.
+    pub(super) fn uses_epochs(&self) -> bool {
+        self.limits.uses_epochs() || self.cancel.is_some()
+    }
+
+    /// The error of cancellation or of the first deadline that has passed, if any.
+    fn check_deadlines(&self) -> Result<(), WasmError> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+        {
+            return Err(WasmError::Cancelled);
+        }
+        let now = Instant::now();
+        if let (Some(deadline), Some(limit)) = (self.job_deadline, self.limits.job_timeout)
+            && now >= deadline
+        {
+            return Err(WasmError::DeadlineExceeded {
+                scope: LimitScope::Job,
+                limit,
+            });
+        }
+        if let (Some(deadline), Some(limit)) = (self.call_deadline, self.limits.call_timeout)
+            && now >= deadline
+        {
+            return Err(WasmError::DeadlineExceeded {
+                scope: LimitScope::Call,
+                limit,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl ResourceLimiter for RunnerState {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        match self.limits.max_memory {
+            // Trap instead of failing memory.grow, so the cause is reported
+            // rather than the module's own out-of-memory handling
+            Some(limit) if desired > limit => Err(WasmError::MemoryLimitExceeded {
+                limit,
+                requested: desired,
+            }
+            .into()),
+            _ => Ok(true),
+        }
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        match self.limits.max_table_elements {
+            Some(limit) if desired > limit => Err(WasmError::TableLimitExceeded {
+                limit,
+                requested: desired,
+            }
+            .into()),
+            _ => Ok(true),
+        }
+    }
+}
+
+/// Create a store for a job, with its limiter and deadline checks installed.
+pub(super) fn new_store(engine: &Engine, state: RunnerState) -> Store<RunnerState> {
+    let mut store = Store::new(engine, state);
+    store.limiter(|state| state);
+    if store.data().uses_epochs() {
+        store.epoch_deadline_callback(|ctx| {
+            ctx.data().check_deadlines()?;
+            Ok(UpdateDeadline::Continue(1))
+        });
+    }
+    store
+}
+
+/// Start bumping the epoch of `engine` every [`EPOCH_TICK`].
+///
+/// The thread ends once the engine is dropped.
+pub(super) fn spawn_epoch_ticker(engine: &Engine) {
+    let engine = engine.weak();
+    thread::spawn(move || {
+        loop {
+            thread::sleep(EPOCH_TICK);
+            match engine.upgrade() {
+                Some(engine) => engine.increment_epoch(),
+                None => break,
+            }
+        }
+        trace!("epoch ticker stopped");
+    });
+}
+
+/// The instant `timeout` from now, or `None` if it cannot be represented.
+fn deadline_after(timeout: Duration) -> Option<Instant> {
+    Instant::now().checked_add(timeout)
+}
+
+/// Prepare the store for a call: account for the fuel used so far, and set
+/// the fuel and deadline of the call.
+///
+/// Fails without running anything if the job is out of fuel or time.
+pub(super) fn begin_call(store: &mut Store<RunnerState>) -> Result<(), WasmError> {
+    settle_fuel(store)?;
+
+    let state = store.data_mut();
+    let limits = state.limits;
+    let uses_epochs = state.uses_epochs();
+    state.call_deadline = limits.call_timeout.and_then(deadline_after);
+    state.check_deadlines()?;
+
+    if limits.uses_fuel() {
+        let (grant, scope) = match (limits.call_fuel, state.job_fuel_left) {
+            (_, Some(0)) => {
+                return Err(WasmError::FuelExhausted {
+                    scope: LimitScope::Job,
+                    limit: limits.job_fuel.unwrap_or_default(),
+                });
+            }
+            (Some(call), Some(job)) if job < call => (job, LimitScope::Job),
+            (Some(call), _) => (call, LimitScope::Call),
+            (None, Some(job)) => (job, LimitScope::Job),
+            (None, None) => (u64::MAX, LimitScope::Call),
+        };
+        state.fuel_granted = grant;
+        state.fuel_scope = scope;
+        store.set_fuel(grant)?;
+    }
+    if uses_epochs {
+        store.set_epoch_deadline(1);
+    }
+    Ok(())
+}
+
+/// Deduct the fuel used since the last [`begin_call`] from the job budget.
+pub(super) fn settle_fuel(store: &mut Store<RunnerState>) -> Result<(), WasmError> {
+    if !store.data().limits.uses_fuel() {
+        return Ok(());
+    }
+    let left = store.get_fuel()?;
+    let state = store.data_mut();
+    let used = state.fuel_granted.saturating_sub(left);
+    state.fuel_granted = left;
+    if let Some(job_fuel_left) = &mut state.job_fuel_left {
+        *job_fuel_left = job_fuel_left.saturating_sub(used);
+    }
+    Ok(())
+}
+
+/// The limit that stopped a call or instantiation, or the original error if
+/// no limit was hit.
+pub(super) fn limit_error(
+    store: &Store<RunnerState>,
+    error: wasmtime::Error,
+) -> Result<WasmError, wasmtime::Error> {
+    if error.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+        let state = store.data();
+        let limit = match state.fuel_scope {
+            LimitScope::Call => state.limits.call_fuel,
+            LimitScope::Job => state.limits.job_fuel,
+        };
+        return Ok(WasmError::FuelExhausted {
+            scope: state.fuel_scope,
+            limit: limit.unwrap_or_default(),
+        });
+    }
+    error.downcast::<WasmError>()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_and_table_limits() {
+        let limits = ExecutionLimits {
+            max_memory: Some(1 << 20),
+            max_table_elements: Some(10),
+            ..ExecutionLimits::default()
+        };
+        let mut state = RunnerState::new(limits, None);
+        assert!(state.memory_growing(0, 1 << 20, None).unwrap());
+        let err = state.memory_growing(1 << 20, 2 << 20, None).unwrap_err();
+        assert!(matches!(
+            err.downcast::<WasmError>(),
+            Ok(WasmError::MemoryLimitExceeded { limit: 1048576, .. })
+        ));
+        assert!(state.table_growing(0, 10, None).unwrap());
+        assert!(state.table_growing(10, 11, None).is_err());
+
+        // Unlimited by default
+        let mut state = RunnerState::new(ExecutionLimits::default(), None);
+        assert!(state.memory_growing(0, usize::MAX, None).unwrap());
+    }
+
+    #[test]
+    fn test_job_deadline() {
+        let limits = ExecutionLimits {
+            call_timeout: Some(Duration::from_secs(60)),
+            job_timeout: Some(Duration::ZERO),
+            ..ExecutionLimits::default()
+        };
+        let state = RunnerState::new(limits, None);
+        assert!(matches!(
+            state.check_deadlines(),
+            Err(WasmError::DeadlineExceeded {
+                scope: LimitScope::Job,
+                ..
+            })
+        ));
+
+        let state = RunnerState::new(limits.per_call(), None);
+        assert!(state.check_deadlines().is_ok());
+
+        // A timeout too long to represent never expires
+        let limits = ExecutionLimits {
+            job_timeout: Some(Duration::MAX),
+            ..ExecutionLimits::default()
+        };
+        let state = RunnerState::new(limits, None);
+        assert!(state.check_deadlines().is_ok());
+    }
+}
@@ -7,10 +7,14 @@
 //! The `TypedRunner` handles type conversion between stored values and
 //! component interfaces, used by the `map` and `reduce` commands.
 
+mod cache;
 mod error;
+mod limits;
 mod typed_runner;
 
+pub use cache::ComponentCache;
 pub use error::WasmError;
+pub use limits::{EPOCH_TICK, ExecutionLimits, LimitScope};
 pub use typed_runner::{
     TypedRunner, TypedRunnerBuilder, create_placeholder_val, val_to_wave, wave_to_val,
 };
@@ -17,15 +17,19 @@
 //! ```
 
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
 use wasmtime::component::types;
 use wasmtime::component::{Component, Func, Instance, Linker, Val};
 use wasmtime::{Config, Engine, Store};
 use wit_parser::{Resolve, TypeId};
 
+use super::cache::ComponentCache;
 use super::error::WasmError;
+use super::limits::{self, ExecutionLimits, RunnerState};
 use crate::find_type_by_name;
-use crate::kv::{SemanticVersion, StoredValue};
+use crate::kv::{ChangeKind, SemanticVersion, StoredValue, TypeDiff};
 use crate::logging::{debug, error, info, trace};
 use wit_kv_abi::{CanonicalAbi, LinearMemory};
 
@@ -73,6 +77,18 @@ pub fn create_placeholder_val(ty: &types::Type) -> Result<Val, WasmError> {
     }
 }
 
+/// Instantiate `component` in `store`, within the limits of the store.
+fn instantiate(
+    linker: &Linker<RunnerState>,
+    store: &mut Store<RunnerState>,
+    component: &Component,
+) -> Result<Instance, WasmError> {
+    limits::begin_call(store)?;
+    linker
+        .instantiate(&mut *store, component)
+        .map_err(|e| limits::limit_error(store, e).unwrap_or_else(WasmError::Wasmtime))
+}
+
 /// Builder for creating [`TypedRunner`] instances with a fluent API.
 ///
 /// # Example
@@ -94,6 +110,17 @@ pub fn create_placeholder_val(ty: &types::Type) -> Result<Val, WasmError> {
 ///     .input_type("point")
 ///     .output_type("magnitude")
 ///     .build()?;
+///
+/// // With a time limit on every call
+/// let runner = TypedRunner::builder()
+///     .component("filter.wasm")
+///     .wit("types.wit")
+///     .input_type("point")
+///     .limits(ExecutionLimits {
+///         call_timeout: Some(Duration::from_secs(1)),
+///         ..ExecutionLimits::default()
+///     })
+///     .build()?;
 /// ```
 #[derive(Default)]
 pub struct TypedRunnerBuilder {
@@ -103,6 +130,9 @@ pub struct TypedRunnerBuilder {
     wit_text: Option<String>,
     input_type_name: Option<String>,
     output_type_name: Option<String>,
+    limits: ExecutionLimits,
+    cancel: Option<Arc<AtomicBool>>,
+    cache: Option<(ComponentCache, String)>,
 }
 
 impl TypedRunnerBuilder {
@@ -163,6 +193,33 @@ impl TypedRunnerBuilder {
         self
     }
 
+    /// Set the resource limits of the runner's calls.
+    ///
+    /// Unlimited if not specified. See [`ExecutionLimits`].
+    pub fn limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set a flag that cancels the runner once set.
+    ///
+    /// Running calls stop within [`EPOCH_TICK`](super::EPOCH_TICK), and
+    /// later calls fail right away, with [`WasmError::Cancelled`].
+    pub fn cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Load the compiled component from `cache` under `name`, compiling and
+    /// caching it only when it is not cached yet.
+    ///
+    /// `name` identifies the component within the cache, such as the id of
+    /// a registered module. See [`ComponentCache`].
+    pub fn component_cache(mut self, cache: ComponentCache, name: impl Into<String>) -> Self {
+        self.cache = Some((cache, name.into()));
+        self
+    }
+
     /// Build the [`TypedRunner`] with the configured options.
     ///
     /// # Errors
@@ -218,11 +275,13 @@ impl TypedRunnerBuilder {
 
         let output_type_name = self.output_type_name;
 
-        TypedRunner::from_parts(
+        TypedRunner::create(
             component_bytes,
             resolve,
             &input_type_name,
             output_type_name.as_deref(),
+            RunnerState::new(self.limits, self.cancel),
+            self.cache.as_ref(),
         )
     }
 }
@@ -261,8 +320,12 @@ impl TypedRunnerBuilder {
 /// ```
 pub struct TypedRunner {
     engine: Engine,
-    store: Store<()>,
+    store: Store<RunnerState>,
+    linker: Linker<RunnerState>,
+    component: Component,
     instance: Instance,
+    /// Set when a call trapped, leaving `instance` unusable.
+    trapped: bool,
     resolve: Resolve,
     input_type_id: TypeId,
     output_type_id: TypeId,
@@ -306,45 +369,10 @@ impl TypedRunner {
         let mut resolve = Resolve::new();
         resolve.push_path(wit_path)?;
 
-        // Find input type
-        let input_type_id = find_type_by_name(&resolve, input_type_name).ok_or_else(|| {
-            WasmError::TypeMismatch {
-                keyspace_type: format!("input type '{}' not found in WIT", input_type_name),
-            }
-        })?;
-
-        // Find output type (defaults to input type)
-        let output_type_name = output_type_name.unwrap_or(input_type_name);
-        let output_type_id = find_type_by_name(&resolve, output_type_name).ok_or_else(|| {
-            WasmError::TypeMismatch {
-                keyspace_type: format!("output type '{}' not found in WIT", output_type_name),
-            }
-        })?;
-
-        // Create wasmtime engine
-        let mut config = Config::new();
-        config.wasm_component_model(true);
-        let engine = Engine::new(&config)?;
-
         // Load component
         let component_bytes = std::fs::read(module_path)?;
-        let component = Component::new(&engine, &component_bytes)?;
-
-        // Create linker and store
-        let linker: Linker<()> = Linker::new(&engine);
-        let mut store = Store::new(&engine, ());
 
-        // Instantiate the component
-        let instance = linker.instantiate(&mut store, &component)?;
-
-        Ok(Self {
-            engine,
-            store,
-            instance,
-            resolve,
-            input_type_id,
-            output_type_id,
-        })
+        Self::from_parts(component_bytes, resolve, input_type_name, output_type_name)
     }
 
     /// Create a TypedRunner from pre-loaded parts.
@@ -361,11 +389,53 @@ impl TypedRunner {
         resolve: Resolve,
         input_type_name: &str,
         output_type_name: Option<&str>,
+    ) -> Result<Self, WasmError> {
+        Self::from_parts_with_limits(
+            component_bytes,
+            resolve,
+            input_type_name,
+            output_type_name,
+            ExecutionLimits::default(),
+        )
+    }
+
+    /// Create a TypedRunner from pre-loaded parts, with resource limits.
+    ///
+    /// The per-job limits start counting now, and instantiating the
+    /// component counts as a call.
+    pub fn from_parts_with_limits(
+        component_bytes: Vec<u8>,
+        resolve: Resolve,
+        input_type_name: &str,
+        output_type_name: Option<&str>,
+        limits: ExecutionLimits,
+    ) -> Result<Self, WasmError> {
+        Self::create(
+            component_bytes,
+            resolve,
+            input_type_name,
+            output_type_name,
+            RunnerState::new(limits, None),
+            None,
+        )
+    }
+
+    /// Compile and instantiate the component, with the job state `state`.
+    ///
+    /// The compiled component is taken from `cache`, under the given name, if set.
+    fn create(
+        component_bytes: Vec<u8>,
+        resolve: Resolve,
+        input_type_name: &str,
+        output_type_name: Option<&str>,
+        state: RunnerState,
+        cache: Option<&(ComponentCache, String)>,
     ) -> Result<Self, WasmError> {
         debug!(
             component_size = component_bytes.len(),
             input_type = input_type_name,
             output_type = output_type_name,
+            limits = ?state.limits(),
             "creating TypedRunner"
         );
 
@@ -388,22 +458,30 @@ impl TypedRunner {
 
         trace!("creating wasmtime engine with component model");
 
-        // Create wasmtime engine
+        // Create wasmtime engine, metering fuel and time only when limited
         let mut config = Config::new();
         config.wasm_component_model(true);
+        config.consume_fuel(state.limits().uses_fuel());
+        config.epoch_interruption(state.uses_epochs());
         let engine = Engine::new(&config)?;
+        if state.uses_epochs() {
+            limits::spawn_epoch_ticker(&engine);
+        }
 
         // Load component from bytes
         trace!(bytes = component_bytes.len(), "loading WASM component");
-        let component = Component::new(&engine, &component_bytes)?;
+        let component = match cache {
+            Some((cache, name)) => cache.load(&engine, name, &component_bytes)?,
+            None => Component::new(&engine, &component_bytes)?,
+        };
 
         // Create linker and store
-        let linker: Linker<()> = Linker::new(&engine);
-        let mut store = Store::new(&engine, ());
+        let linker: Linker<RunnerState> = Linker::new(&engine);
+        let mut store = limits::new_store(&engine, state);
 
         // Instantiate the component
         trace!("instantiating component");
-        let instance = linker.instantiate(&mut store, &component)?;
+        let instance = instantiate(&linker, &mut store, &component)?;
 
         info!(
             input_type = input_type_name,
@@ -414,7 +492,10 @@ impl TypedRunner {
         Ok(Self {
             engine,
             store,
+            linker,
+            component,
             instance,
+            trapped: false,
             resolve,
             input_type_id,
             output_type_id,
@@ -509,13 +590,57 @@ impl TypedRunner {
         ))
     }
 
+    /// Check that the component exports every function in `names`.
+    ///
+    /// Fails with [`WasmError::FunctionNotFound`] for the first missing one.
+    /// Used to validate a module before it is registered, rather than on
+    /// its first call.
+    pub fn check_exports(&mut self, names: &[&str]) -> Result<(), WasmError> {
+        for name in names {
+            self.get_func(name)?;
+        }
+        Ok(())
+    }
+
     /// Get a function by name from the component instance.
+    ///
+    /// A component instance that trapped cannot be entered again, so the
+    /// component is first instantiated afresh if the last call trapped.
     fn get_func(&mut self, name: &str) -> Result<Func, WasmError> {
+        if self.trapped {
+            self.reinstantiate()?;
+        }
         self.instance
             .get_func(&mut self.store, name)
             .ok_or_else(|| WasmError::FunctionNotFound(name.to_string()))
     }
 
+    /// Call `func` within the limits of the runner.
+    fn call_func(
+        &mut self,
+        func: Func,
+        params: &[Val],
+        results: &mut [Val],
+    ) -> Result<(), WasmError> {
+        limits::begin_call(&mut self.store)?;
+        func.call(&mut self.store, params, results).map_err(|e| {
+            self.trapped = true;
+            limits::limit_error(&self.store, e).unwrap_or_else(|e| WasmError::Trap(e.to_string()))
+        })
+    }
+
+    /// Replace the instance with a fresh one, keeping the job budget.
+    fn reinstantiate(&mut self) -> Result<(), WasmError> {
+        trace!("re-instantiating component after trap");
+        limits::settle_fuel(&mut self.store)?;
+        let mut store = limits::new_store(&self.engine, self.store.data().clone());
+        let instance = instantiate(&self.linker, &mut store, &self.component)?;
+        self.store = store;
+        self.instance = instance;
+        self.trapped = false;
+        Ok(())
+    }
+
     /// Call the `filter` function with a typed value.
     ///
     /// The filter function should have signature: `filter(value: T) -> bool`
@@ -539,10 +664,10 @@ impl TypedRunner {
 
         // Call function
         let mut results = vec![Val::Bool(false)];
-        func.call(&mut self.store, &[input_val], &mut results)
+        self.call_func(func, &[input_val], &mut results)
             .map_err(|e| {
                 error!(error = %e, "filter function trap");
-                WasmError::Trap(e.to_string())
+                e
             })?;
 
         func.post_return(&mut self.store).map_err(|e| {
@@ -578,8 +703,63 @@ impl TypedRunner {
         stored: &StoredValue,
         type_version: SemanticVersion,
     ) -> Result<StoredValue, WasmError> {
-        debug!("calling transform function");
-        let func = self.get_func("transform")?;
+        self.call_unary("transform", stored, type_version)
+    }
+
+    /// Call the `migrate` function with a typed value.
+    ///
+    /// The migrate function should have signature: `migrate(old: T0) -> T1`,
+    /// where `T0` is the keyspace's current type and `T1` the type it is
+    /// being migrated to.
+    pub fn call_migrate(
+        &mut self,
+        stored: &StoredValue,
+        type_version: SemanticVersion,
+    ) -> Result<StoredValue, WasmError> {
+        self.call_unary("migrate", stored, type_version)
+    }
+
+    /// Check that the runner's input type is structurally identical to `expected`.
+    ///
+    /// Used before operations that rewrite stored data, where decoding values
+    /// with a merely similar type would silently corrupt them.
+    pub fn check_input_type(&self, expected: &wasm_wave::value::Type) -> Result<(), WasmError> {
+        let input = self.input_wave_type()?;
+        let diff = TypeDiff::between(expected, &input);
+        if diff.kind() == ChangeKind::Patch {
+            Ok(())
+        } else {
+            Err(WasmError::TypeMismatch {
+                keyspace_type: format!("input type does not match keyspace type: {}", diff),
+            })
+        }
+    }
+
+    /// Check that the runner's output type is structurally identical to `expected`.
+    ///
+    /// Used before writing transformed values into a keyspace of type
+    /// `expected`, so their canonical ABI bytes can be stored as they are.
+    pub fn check_output_type(&self, expected: &wasm_wave::value::Type) -> Result<(), WasmError> {
+        let output = self.output_wave_type()?;
+        let diff = TypeDiff::between(expected, &output);
+        if diff.kind() == ChangeKind::Patch {
+            Ok(())
+        } else {
+            Err(WasmError::TypeMismatch {
+                keyspace_type: format!("output type does not match keyspace type: {}", diff),
+            })
+        }
+    }
+
+    /// Call a `func(value: T) -> T1` export with a typed value.
+    fn call_unary(
+        &mut self,
+        name: &str,
+        stored: &StoredValue,
+        type_version: SemanticVersion,
+    ) -> Result<StoredValue, WasmError> {
+        debug!(function = name, "calling unary function");
+        let func = self.get_func(name)?;
 
         // Get function type
         let func_type = func.ty(&self.store);
@@ -588,7 +768,7 @@ impl TypedRunner {
                 .params()
                 .next()
                 .ok_or_else(|| WasmError::InvalidReturnType {
-                    expected: "transform function should have 1 parameter".to_string(),
+                    expected: format!("{} function should have 1 parameter", name),
                 })?;
 
         let result_type =
@@ -596,7 +776,7 @@ impl TypedRunner {
                 .results()
                 .next()
                 .ok_or_else(|| WasmError::InvalidReturnType {
-                    expected: "transform function should have 1 result".to_string(),
+                    expected: format!("{} function should have 1 result", name),
                 })?;
 
         // Convert input
@@ -607,28 +787,28 @@ impl TypedRunner {
         let mut results = vec![create_placeholder_val(&result_type)?];
 
         // Call function
-        func.call(&mut self.store, &[input_val], &mut results)
+        self.call_func(func, &[input_val], &mut results)
             .map_err(|e| {
-                error!(error = %e, "transform function trap");
-                WasmError::Trap(e.to_string())
+                error!(function = name, error = %e, "function trap");
+                e
             })?;
 
         // Convert result to StoredValue
         let result_val = results.first().ok_or_else(|| {
-            error!("transform returned no result");
+            error!(function = name, "function returned no result");
             WasmError::InvalidReturnType {
-                expected: "transform function should return a value".to_string(),
+                expected: format!("{} function should return a value", name),
             }
         })?;
         trace!("converting result Val to StoredValue");
         let output = self.val_to_stored(result_val, type_version)?;
 
         func.post_return(&mut self.store).map_err(|e| {
-            error!(error = %e, "transform post_return failed");
+            error!(function = name, error = %e, "post_return failed");
             WasmError::Trap(format!("post_return failed: {}", e))
         })?;
 
-        debug!("transform function completed");
+        debug!(function = name, "function completed");
         Ok(output)
     }
 
@@ -661,9 +841,9 @@ impl TypedRunner {
         let mut results = vec![create_placeholder_val(&result_type)?];
 
         // Call function (no parameters)
-        func.call(&mut self.store, &[], &mut results).map_err(|e| {
+        self.call_func(func, &[], &mut results).map_err(|e| {
             error!(error = %e, "init-state function trap");
-            WasmError::Trap(e.to_string())
+            e
         })?;
 
         // Convert result to StoredValue using output_type (which is the state type)
@@ -748,10 +928,10 @@ impl TypedRunner {
         let mut results = vec![create_placeholder_val(&result_type)?];
 
         // Call function
-        func.call(&mut self.store, &[state_val, value_val], &mut results)
+        self.call_func(func, &[state_val, value_val], &mut results)
             .map_err(|e| {
                 error!(error = %e, "reduce function trap");
-                WasmError::Trap(e.to_string())
+                e
             })?;
 
         // Convert result to StoredValue
@@ -773,3 +953,149 @@ impl TypedRunner {
         Ok(output)
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::wasm::LimitScope;
+
+    const WIT: &str = r#"
+        package test:limits;
+        interface types {
+            type counter = u32;
+        }
+    "#;
+
+    /// A component whose `init-state` loops forever, with `pages` pages of memory.
+    fn looping_component(pages: u32) -> Vec<u8> {
+        format!(
+            r#"
+            (component
+                (core module $m
+                    (memory (export "memory") {pages})
+                    (func (export "init-state") (result i32)
+                        (loop $spin (br $spin))
+                        (i32.const 0)))
+                (core instance $i (instantiate $m))
+                (func (export "init-state") (result u32)
+                    (canon lift (core func $i "init-state"))))
+            "#
+        )
+        .into_bytes()
+    }
+
+    fn build(pages: u32, limits: ExecutionLimits) -> Result<TypedRunner, WasmError> {
+        TypedRunner::builder()
+            .component_bytes(looping_component(pages))
+            .wit_text(WIT)
+            .input_type("counter")
+            .limits(limits)
+            .build()
+    }
+
+    #[test]
+    fn test_fuel_limits() {
+        let mut runner = build(
+            1,
+            ExecutionLimits {
+                call_fuel: Some(10_000),
+                job_fuel: Some(25_000),
+                ..ExecutionLimits::default()
+            },
+        )
+        .unwrap();
+        let version = SemanticVersion::INITIAL;
+
+        // The instance is replaced after a trap, so the next call runs again
+        for _ in 0..2 {
+            assert!(matches!(
+                runner.call_init_state(version),
+                Err(WasmError::FuelExhausted {
+                    scope: LimitScope::Call,
+                    limit: 10_000
+                })
+            ));
+        }
+        let err = runner.call_init_state(version).unwrap_err();
+        assert!(err.ends_job());
+        assert!(matches!(
+            runner.call_init_state(version),
+            Err(WasmError::FuelExhausted {
+                scope: LimitScope::Job,
+                limit: 25_000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_deadline_cancel_and_memory_limits() {
+        let mut runner = build(
+            1,
+            ExecutionLimits {
+                call_timeout: Some(Duration::from_millis(50)),
+                ..ExecutionLimits::default()
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            runner.call_init_state(SemanticVersion::INITIAL),
+            Err(WasmError::DeadlineExceeded {
+                scope: LimitScope::Call,
+                ..
+            })
+        ));
+
+        // Cancelling stops the running call, and every later one
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut runner = TypedRunner::builder()
+            .component_bytes(looping_component(1))
+            .wit_text(WIT)
+            .input_type("counter")
+            .cancel_flag(Arc::clone(&cancel))
+            .build()
+            .unwrap();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+        assert!(matches!(
+            runner.call_init_state(SemanticVersion::INITIAL),
+            Err(WasmError::Cancelled)
+        ));
+        canceller.join().unwrap();
+        let err = runner
+            .call_init_state(SemanticVersion::INITIAL)
+            .unwrap_err();
+        assert!(err.ends_job());
+
+        let limits = ExecutionLimits {
+            max_memory: Some(65536),
+            ..ExecutionLimits::default()
+        };
+        assert!(build(1, limits).is_ok());
+        assert!(matches!(
+            build(2, limits),
+            Err(WasmError::MemoryLimitExceeded {
+                limit: 65536,
+                requested: 131072
+            })
+        ));
+    }
+
+    #[test]
+    fn test_check_input_and_output_types() {
+        use wasm_wave::value::Type as WaveType;
+
+        let runner = build(1, ExecutionLimits::default()).unwrap();
+        assert!(runner.check_input_type(&WaveType::U32).is_ok());
+        assert!(runner.check_output_type(&WaveType::U32).is_ok());
+        assert!(matches!(
+            runner.check_output_type(&WaveType::STRING),
+            Err(WasmError::TypeMismatch { .. })
+        ));
+        assert!(runner.check_output_type(&WaveType::U64).is_err());
+    }
+}